@@ -0,0 +1,635 @@
+use std::io::{IsTerminal, Read, Write};
+use std::ops::RangeInclusive;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::time::Duration;
+#[cfg(feature = "native")]
+use std::{env, process};
+use std::{fs, io};
+
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use potterscript_codegen::wasm;
+use potterscript_parser::Program;
+use potterscript_runtime::golden::{self, GoldenOutcome};
+use potterscript_runtime::optimizer;
+#[cfg(feature = "native")]
+use potterscript_runtime::vm::aot;
+use potterscript_runtime::vm::{self, Chunk, Vm};
+use potterscript_runtime::vows;
+use potterscript_runtime::{
+    Choice, DefaultRuntimeAdapter, Recorder, Replayer, Runtime, RuntimeAdapter, RuntimeError,
+    Seeded,
+};
+
+const EXIT_PARSE_ERROR: u8 = 65;
+const EXIT_IO_ERROR: u8 = 66;
+const EXIT_BUILD_ERROR: u8 = 69;
+const EXIT_RUNTIME_ERROR: u8 = 70;
+const EXIT_TEST_FAILURE: u8 = 1;
+
+/// The Wizarding World Programming Language
+#[derive(Parser)]
+#[command(name = "potter", version)]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Parse and run a script, or run a script precompiled with `compile`
+    Run {
+        /// Script to run, `-` or nothing reads from stdin
+        file: Option<PathBuf>,
+        #[command(flatten)]
+        options: RunOptions,
+    },
+    /// Print the AST of a script
+    Ast {
+        /// Script to parse, `-` or nothing reads from stdin
+        file: Option<PathBuf>,
+        /// Output format
+        #[arg(long, value_enum, default_value_t = AstFormat::Json)]
+        format: AstFormat,
+        /// Print the AST after folding constants and removing dead code
+        #[arg(long)]
+        optimize: bool,
+    },
+    /// Compile a script to a bytecode file that `run` runs without parsing
+    Compile {
+        /// Script to compile, `-` or nothing reads from stdin
+        file: Option<PathBuf>,
+        /// Where to write the bytecode, the script with a `.potterc`
+        /// extension by default, or stdout when reading from stdin
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+        /// Leave out the debug info mapping code back to the source
+        #[arg(long)]
+        strip: bool,
+    },
+    /// Compile a script to a standalone native executable
+    Build {
+        /// Script to compile, `-` or nothing reads from stdin
+        file: Option<PathBuf>,
+        /// Where to write the executable, the script without its extension
+        /// by default (with `.out` if it has none), or `a.out` when reading
+        /// from stdin
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+        /// The `potterscript-rt` support library to link, by default the
+        /// one built next to `potter`
+        #[arg(long, conflicts_with = "wasm")]
+        runtime: Option<PathBuf>,
+        /// Compile to a WebAssembly module instead, the script with a
+        /// `.wasm` extension by default
+        #[arg(long)]
+        wasm: bool,
+    },
+    /// Check a script for syntax errors without running it
+    Check {
+        /// Script to check, `-` or nothing reads from stdin
+        file: Option<PathBuf>,
+    },
+    /// Run the `vow` blocks of a script and report which ones hold
+    Test {
+        /// Script to test, `-` or nothing reads from stdin
+        file: Option<PathBuf>,
+        /// Only run the vows whose name contains this text
+        #[arg(long)]
+        filter: Option<String>,
+    },
+    /// Run every script in a directory and compare its output with the
+    /// sibling `.out` file
+    Golden {
+        /// Directory with the `.potter` scripts
+        dir: PathBuf,
+        /// Write the `.out` files from the actual output instead
+        #[arg(long)]
+        bless: bool,
+    },
+}
+
+#[derive(Args)]
+struct RunOptions {
+    /// Seed for the Sorting Hat, random (and reported) if not given
+    #[arg(long)]
+    seed: Option<u64>,
+    /// Write every random choice and input line to this JSON file
+    #[arg(long)]
+    record: Option<PathBuf>,
+    /// Make the choices recorded with `--record` again
+    #[arg(long)]
+    replay: Option<PathBuf>,
+    /// Maximum number of statements to evaluate before aborting
+    #[arg(long)]
+    max_steps: Option<usize>,
+    /// When to print `~Lumos` output with colors
+    #[arg(long, value_enum, default_value_t = ColorChoice::Auto)]
+    color: ColorChoice,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum AstFormat {
+    Json,
+    Debug,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum ColorChoice {
+    Auto,
+    Always,
+    Never,
+}
+
+impl ColorChoice {
+    fn enabled(self) -> bool {
+        match self {
+            ColorChoice::Auto => io::stdout().is_terminal(),
+            ColorChoice::Always => true,
+            ColorChoice::Never => false,
+        }
+    }
+}
+
+/// Prints to the terminal. Random choices come from the [`Seeded`] wrapper.
+struct CliRuntimeAdapter {
+    color: bool,
+}
+
+impl RuntimeAdapter for CliRuntimeAdapter {
+    fn random_integer(&self, range: RangeInclusive<i64>) -> i64 {
+        DefaultRuntimeAdapter.random_integer(range)
+    }
+
+    fn random_double(&self) -> f64 {
+        DefaultRuntimeAdapter.random_double()
+    }
+
+    fn lumos(&self, string: String) -> String {
+        if self.color {
+            DefaultRuntimeAdapter.lumos(string)
+        } else {
+            string
+        }
+    }
+
+    fn log(&self, string: &str) {
+        DefaultRuntimeAdapter.log(string)
+    }
+
+    fn read_line(&self, prompt: Option<&str>) -> Option<String> {
+        DefaultRuntimeAdapter.read_line(prompt)
+    }
+
+    fn sleep(&self, duration: Duration) {
+        DefaultRuntimeAdapter.sleep(duration)
+    }
+}
+
+struct Source {
+    name: String,
+    code: String,
+}
+
+/// A script read by `run`, either source code or precompiled bytecode.
+enum Script {
+    Source(Source),
+    Bytecode { name: String, chunk: Chunk },
+}
+
+/// Reads `file`, or stdin for `-` or nothing, returning its name and bytes.
+fn read_file(file: Option<PathBuf>) -> Result<(String, Vec<u8>), ExitCode> {
+    let result = match &file {
+        Some(path) if path.as_os_str() != "-" => fs::read(path),
+        _ => {
+            let mut bytes = vec![];
+            io::stdin().read_to_end(&mut bytes).map(|_| bytes)
+        }
+    };
+    let name = match file {
+        Some(path) if path.as_os_str() != "-" => path.display().to_string(),
+        _ => "<stdin>".to_string(),
+    };
+
+    match result {
+        Ok(bytes) => Ok((name, bytes)),
+        Err(error) => {
+            eprintln!("error: cannot read {}: {}", name, error);
+            Err(ExitCode::from(EXIT_IO_ERROR))
+        }
+    }
+}
+
+impl Script {
+    fn read(file: Option<PathBuf>) -> Result<Self, ExitCode> {
+        let (name, bytes) = read_file(file)?;
+        if !bytes.starts_with(&vm::MAGIC) {
+            return Source::from_bytes(name, bytes).map(Script::Source);
+        }
+
+        match Chunk::from_bytes(&bytes) {
+            Ok(chunk) => Ok(Script::Bytecode { name, chunk }),
+            Err(error) => {
+                eprintln!("error: {}: {}", name, error);
+                Err(ExitCode::from(EXIT_IO_ERROR))
+            }
+        }
+    }
+}
+
+impl Source {
+    fn read(file: Option<PathBuf>) -> Result<Self, ExitCode> {
+        let (name, bytes) = read_file(file)?;
+        Self::from_bytes(name, bytes)
+    }
+
+    fn from_bytes(name: String, bytes: Vec<u8>) -> Result<Self, ExitCode> {
+        match String::from_utf8(bytes) {
+            Ok(code) => Ok(Self { name, code }),
+            Err(error) => {
+                eprintln!("error: cannot read {}: {}", name, error);
+                Err(ExitCode::from(EXIT_IO_ERROR))
+            }
+        }
+    }
+
+    fn parse(&self) -> Result<Program, ExitCode> {
+        potterscript_parser::parse(&self.code).map_err(|error| {
+            eprintln!("error: {}: {}", self.name, error);
+            ExitCode::from(EXIT_PARSE_ERROR)
+        })
+    }
+}
+
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+
+    let result = match cli.command {
+        Command::Run { file, options } => run(file, options),
+        Command::Ast {
+            file,
+            format,
+            optimize,
+        } => ast(file, format, optimize),
+        Command::Compile {
+            file,
+            output,
+            strip,
+        } => compile(file, output, strip),
+        #[cfg(feature = "native")]
+        Command::Build {
+            file,
+            output,
+            runtime,
+            wasm: false,
+        } => build(file, output, runtime),
+        #[cfg(not(feature = "native"))]
+        Command::Build { wasm: false, .. } => {
+            eprintln!(
+                "error: this potter was built without the native feature, only `build --wasm` works"
+            );
+            Err(ExitCode::from(EXIT_BUILD_ERROR))
+        }
+        Command::Build {
+            file,
+            output,
+            wasm: true,
+            ..
+        } => build_wasm(file, output),
+        Command::Check { file } => check(file),
+        Command::Test { file, filter } => test(file, filter.as_deref()),
+        Command::Golden { dir, bless } => golden(&dir, bless),
+    };
+
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(code) => code,
+    }
+}
+
+fn run(file: Option<PathBuf>, options: RunOptions) -> Result<(), ExitCode> {
+    let script = Script::read(file)?;
+
+    let choices = match &options.replay {
+        Some(path) => read_choices(path)?,
+        None => vec![],
+    };
+
+    let seed = options.seed.unwrap_or_else(rand::random);
+    let adapter = CliRuntimeAdapter {
+        color: options.color.enabled(),
+    };
+    let adapter = Recorder::new(Replayer::new(Seeded::new(adapter, seed), choices));
+
+    let (result, adapter) = match script {
+        Script::Source(source) => {
+            let program = source.parse()?;
+            let mut runtime = Runtime::new(adapter);
+            runtime.set_max_steps(options.max_steps);
+            let result = match runtime.eval(&program) {
+                Err(RuntimeError::Exit) => Ok(()),
+                result => result.map_err(|error| {
+                    eprintln!("error: {}: {}", source.name, error);
+                }),
+            };
+            (result, runtime.into_adapter())
+        }
+        Script::Bytecode { name, chunk } => {
+            let mut vm = Vm::new(chunk, adapter);
+            vm.set_max_steps(options.max_steps);
+            let result = match vm.run() {
+                Err(RuntimeError::Exit) => Ok(()),
+                result => result.map_err(|error| match vm.chunk().span_at(vm.pc()) {
+                    Some(span) => eprintln!(
+                        "error: {}: {} (source bytes {}..{})",
+                        name, error, span.start, span.end
+                    ),
+                    None => eprintln!("error: {}: {}", name, error),
+                }),
+            };
+            (result, vm.into_adapter())
+        }
+    };
+    let result = result.map_err(|_| ExitCode::from(EXIT_RUNTIME_ERROR));
+
+    let replayer = adapter.inner();
+    if options.replay.is_some() && replayer.diverged() {
+        eprintln!("warning: the program made choices that are not in the replay");
+    }
+    if options.seed.is_none() && replayer.inner().was_used() {
+        eprintln!(
+            "note: the Sorting Hat was seeded with {0}, run with `--seed {0}` to replay",
+            seed
+        );
+    }
+    if let Some(path) = &options.record {
+        let choices = serde_json::to_string_pretty(&adapter.choices()).unwrap();
+        fs::write(path, choices + "\n").map_err(|error| {
+            eprintln!("error: cannot write {}: {}", path.display(), error);
+            ExitCode::from(EXIT_IO_ERROR)
+        })?;
+    }
+
+    result
+}
+
+fn read_choices(path: &Path) -> Result<Vec<Choice>, ExitCode> {
+    let choices = fs::read_to_string(path).map_err(|error| {
+        eprintln!("error: cannot read {}: {}", path.display(), error);
+        ExitCode::from(EXIT_IO_ERROR)
+    })?;
+    serde_json::from_str(&choices).map_err(|error| {
+        eprintln!("error: {}: {}", path.display(), error);
+        ExitCode::from(EXIT_IO_ERROR)
+    })
+}
+
+fn ast(file: Option<PathBuf>, format: AstFormat, optimize: bool) -> Result<(), ExitCode> {
+    let source = Source::read(file)?;
+    let mut program = source.parse()?;
+    if optimize {
+        program = optimizer::optimize(program);
+    }
+
+    match format {
+        AstFormat::Json => println!("{}", serde_json::to_string_pretty(&program).unwrap()),
+        AstFormat::Debug => println!("{:#?}", program),
+    }
+    Ok(())
+}
+
+fn compile(file: Option<PathBuf>, output: Option<PathBuf>, strip: bool) -> Result<(), ExitCode> {
+    let output = output.or_else(|| {
+        file.as_ref()
+            .filter(|path| path.as_os_str() != "-")
+            .map(|path| path.with_extension("potterc"))
+    });
+    let source = Source::read(file)?;
+    let statements = potterscript_parser::parse_spanned(&source.code).map_err(|error| {
+        eprintln!("error: {}: {}", source.name, error);
+        ExitCode::from(EXIT_PARSE_ERROR)
+    })?;
+
+    let mut chunk = vm::compile_spanned(&statements);
+    if strip {
+        chunk.spans.clear();
+    }
+    let bytes = chunk.to_bytes();
+
+    let result = match &output {
+        Some(path) => fs::write(path, bytes),
+        None => io::stdout().write_all(&bytes),
+    };
+    result.map_err(|error| {
+        let name = output.map_or("<stdout>".to_string(), |path| path.display().to_string());
+        eprintln!("error: cannot write {}: {}", name, error);
+        ExitCode::from(EXIT_IO_ERROR)
+    })
+}
+
+/// Libraries the Rust standard library in the support library needs.
+#[cfg(all(feature = "native", target_os = "macos"))]
+const NATIVE_LIBS: &[&str] = &["-lSystem", "-lc", "-lm"];
+#[cfg(all(feature = "native", not(target_os = "macos")))]
+const NATIVE_LIBS: &[&str] = &[
+    "-lgcc_s",
+    "-lutil",
+    "-lrt",
+    "-lpthread",
+    "-lm",
+    "-ldl",
+    "-lc",
+];
+
+#[cfg(feature = "native")]
+fn build(
+    file: Option<PathBuf>,
+    output: Option<PathBuf>,
+    runtime: Option<PathBuf>,
+) -> Result<(), ExitCode> {
+    let output = output.unwrap_or_else(|| match &file {
+        Some(path) if path.as_os_str() != "-" && path.extension().is_some() => {
+            path.with_extension("")
+        }
+        Some(path) if path.as_os_str() != "-" => path.with_extension("out"),
+        _ => PathBuf::from("a.out"),
+    });
+    check_not_source(file.as_deref(), &output)?;
+    let runtime = match runtime {
+        Some(runtime) => runtime,
+        None => env::current_exe()
+            .ok()
+            .and_then(|potter| Some(potter.parent()?.join("libpotterscript_rt.a")))
+            .filter(|runtime| runtime.exists())
+            .ok_or_else(|| {
+                eprintln!(
+                    "error: cannot find the support library, build it with \
+                     `cargo build -p potterscript-rt` and pass it with --runtime"
+                );
+                ExitCode::from(EXIT_BUILD_ERROR)
+            })?,
+    };
+
+    let source = Source::read(file)?;
+    let statements = potterscript_parser::parse_spanned(&source.code).map_err(|error| {
+        eprintln!("error: {}: {}", source.name, error);
+        ExitCode::from(EXIT_PARSE_ERROR)
+    })?;
+    let object = aot::compile_object(&vm::compile_spanned(&statements)).map_err(|error| {
+        eprintln!("error: cannot compile {}: {}", source.name, error);
+        ExitCode::from(EXIT_BUILD_ERROR)
+    })?;
+
+    let object_path = env::temp_dir().join(format!("potter-{}.o", process::id()));
+    fs::write(&object_path, object).map_err(|error| {
+        eprintln!("error: cannot write {}: {}", object_path.display(), error);
+        ExitCode::from(EXIT_IO_ERROR)
+    })?;
+    let linker = env::var_os("CC").unwrap_or_else(|| "cc".into());
+    let status = process::Command::new(&linker)
+        .arg(&object_path)
+        .arg(&runtime)
+        .args(NATIVE_LIBS)
+        .arg("-o")
+        .arg(&output)
+        .status();
+    let _ = fs::remove_file(&object_path);
+
+    match status {
+        Ok(status) if status.success() => Ok(()),
+        Ok(status) => {
+            eprintln!("error: cannot link {}: {}", output.display(), status);
+            Err(ExitCode::from(EXIT_BUILD_ERROR))
+        }
+        Err(error) => {
+            eprintln!(
+                "error: cannot run the linker {}: {}",
+                linker.to_string_lossy(),
+                error
+            );
+            Err(ExitCode::from(EXIT_BUILD_ERROR))
+        }
+    }
+}
+
+fn build_wasm(file: Option<PathBuf>, output: Option<PathBuf>) -> Result<(), ExitCode> {
+    let output = output.unwrap_or_else(|| match &file {
+        Some(path) if path.as_os_str() != "-" => path.with_extension("wasm"),
+        _ => PathBuf::from("a.wasm"),
+    });
+    check_not_source(file.as_deref(), &output)?;
+
+    let source = Source::read(file)?;
+    let program = source.parse()?;
+    let module = wasm::compile(&program).map_err(|error| {
+        eprintln!("error: cannot compile {}: {}", source.name, error);
+        ExitCode::from(EXIT_BUILD_ERROR)
+    })?;
+
+    fs::write(&output, module).map_err(|error| {
+        eprintln!("error: cannot write {}: {}", output.display(), error);
+        ExitCode::from(EXIT_IO_ERROR)
+    })
+}
+
+/// Fails if `output` is the script being built, which would be lost.
+fn check_not_source(file: Option<&Path>, output: &Path) -> Result<(), ExitCode> {
+    let same = file.is_some_and(
+        |file| match (fs::canonicalize(file), fs::canonicalize(output)) {
+            (Ok(file), Ok(output)) => file == output,
+            _ => false,
+        },
+    );
+    if same {
+        eprintln!(
+            "error: {} is the script being built, pass another one with --output",
+            output.display()
+        );
+        return Err(ExitCode::from(EXIT_BUILD_ERROR));
+    }
+    Ok(())
+}
+
+fn check(file: Option<PathBuf>) -> Result<(), ExitCode> {
+    let source = Source::read(file)?;
+    let program = source.parse()?;
+    // Only warnings, a `protego` block may expect the error
+    for name in potterscript_runtime::undefined_variables(&program) {
+        eprintln!(
+            "warning: {}: variable {} may be read before it is assigned",
+            source.name, name
+        );
+    }
+    println!("{}: ok", source.name);
+    Ok(())
+}
+
+fn test(file: Option<PathBuf>, filter: Option<&str>) -> Result<(), ExitCode> {
+    let source = Source::read(file)?;
+    let program = source.parse()?;
+
+    let results = vows::run_vows(&program, filter);
+    let mut failed = 0;
+    for result in &results {
+        match &result.error {
+            None => println!("vow {:?} ... ok", result.name),
+            Some(error) => {
+                failed += 1;
+                println!("vow {:?} ... FAILED", result.name);
+                for line in result.output.lines() {
+                    println!("  {}", line);
+                }
+                println!("  error: {}", error);
+            }
+        }
+    }
+
+    println!(
+        "\n{}: {} passed, {} failed",
+        source.name,
+        results.len() - failed,
+        failed
+    );
+    if failed > 0 {
+        return Err(ExitCode::from(EXIT_TEST_FAILURE));
+    }
+    Ok(())
+}
+
+fn golden(dir: &Path, bless: bool) -> Result<(), ExitCode> {
+    let cases = golden::run_golden(dir, bless).map_err(|error| {
+        eprintln!("error: cannot run {}: {}", dir.display(), error);
+        ExitCode::from(EXIT_IO_ERROR)
+    })?;
+
+    let (mut passed, mut failed) = (0, 0);
+    for case in &cases {
+        let script = case.script.display();
+        match &case.outcome {
+            GoldenOutcome::Passed => {
+                passed += 1;
+                println!("ok      {}", script);
+            }
+            GoldenOutcome::Blessed => println!("blessed {}", script),
+            GoldenOutcome::Missing => {
+                failed += 1;
+                println!("MISSING {} (run with --bless to create it)", script);
+            }
+            GoldenOutcome::Failed { expected, actual } => {
+                failed += 1;
+                println!("FAILED  {}", script);
+                print!("{}", golden::diff(expected, actual));
+            }
+        }
+    }
+
+    if bless {
+        println!("\n{} blessed", cases.len());
+    } else {
+        println!("\n{} passed, {} failed", passed, failed);
+    }
+    if failed > 0 {
+        return Err(ExitCode::from(EXIT_TEST_FAILURE));
+    }
+    Ok(())
+}
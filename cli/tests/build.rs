@@ -0,0 +1,118 @@
+//! Runs executables built with `potter build`, which links the support
+//! library built by `cargo build -p potterscript-rt`.
+
+#![cfg(feature = "native")]
+
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{self, Command, Output};
+
+const POTTER: &str = env!("CARGO_BIN_EXE_potter");
+
+fn golden(name: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("../runtime/tests/golden")
+        .join(name)
+}
+
+/// A fresh directory for the files a test writes.
+fn temp_dir(name: &str) -> PathBuf {
+    let dir = env::temp_dir().join(format!("potter-{}-{}", name, process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// The support library next to `potter`, built first if needed with the
+/// profile `potter` was built with.
+fn runtime() -> PathBuf {
+    let dir = Path::new(POTTER).parent().unwrap();
+    // Profiles build into a directory of the same name, except `dev`
+    let profile = match dir.file_name().unwrap().to_str().unwrap() {
+        "debug" => "dev",
+        profile => profile,
+    };
+    let status = Command::new(env!("CARGO"))
+        .args([
+            "build",
+            "--quiet",
+            "-p",
+            "potterscript-rt",
+            "--profile",
+            profile,
+        ])
+        .status()
+        .unwrap();
+    assert!(status.success(), "cannot build potterscript-rt");
+    dir.join("libpotterscript_rt.a")
+}
+
+fn potter(args: &[&str]) -> Output {
+    Command::new(POTTER).args(args).output().unwrap()
+}
+
+#[test]
+fn test_built_executable_prints_like_run() {
+    let dir = temp_dir("build");
+    let executable = dir.join("example");
+    let script = golden("example.potter");
+    let built = potter(&[
+        "build",
+        script.to_str().unwrap(),
+        "--output",
+        executable.to_str().unwrap(),
+        "--runtime",
+        runtime().to_str().unwrap(),
+    ]);
+    assert!(
+        built.status.success(),
+        "{}",
+        String::from_utf8_lossy(&built.stderr)
+    );
+
+    let expected = potter(&["run", script.to_str().unwrap(), "--seed", "7"]);
+    let actual = Command::new(&executable)
+        .args(["--seed", "7"])
+        .output()
+        .unwrap();
+    fs::remove_dir_all(&dir).unwrap();
+    assert!(actual.status.success());
+    assert_eq!(
+        String::from_utf8_lossy(&expected.stdout),
+        String::from_utf8_lossy(&actual.stdout)
+    );
+}
+
+#[test]
+fn test_build_keeps_script_without_extension() {
+    let dir = temp_dir("noext");
+    let script = dir.join("noext");
+    fs::copy(golden("example.potter"), &script).unwrap();
+    let code = fs::read_to_string(&script).unwrap();
+
+    let built = potter(&[
+        "build",
+        script.to_str().unwrap(),
+        "--runtime",
+        runtime().to_str().unwrap(),
+    ]);
+    assert!(
+        built.status.success(),
+        "{}",
+        String::from_utf8_lossy(&built.stderr)
+    );
+    assert!(dir.join("noext.out").exists());
+    assert_eq!(code, fs::read_to_string(&script).unwrap());
+
+    let refused = potter(&[
+        "build",
+        script.to_str().unwrap(),
+        "--output",
+        script.to_str().unwrap(),
+        "--runtime",
+        runtime().to_str().unwrap(),
+    ]);
+    assert!(!refused.status.success());
+    assert_eq!(code, fs::read_to_string(&script).unwrap());
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,159 @@
+//! What the backends need to know about a program before generating code
+//! for it.
+
+use std::collections::HashSet;
+
+use potterscript_parser::{Atom, Expression, Spell, Statement};
+
+/// The loop a statement is in, which decides what `snitch` becomes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Loop {
+    None,
+    /// A loop whose every `snitch` ends an iteration, so it can `break`.
+    Break,
+    /// A loop running while the `quidditch` flag is set. A `snitch` clears
+    /// it, and every loop it is in ends once its iteration does.
+    Flag,
+}
+
+impl Loop {
+    /// The loop a `quidditch` with `block` as its body is, inside of this
+    /// one.
+    pub(crate) fn nested(self, block: &[Statement]) -> Loop {
+        if self == Loop::Flag || !snitches_end_iteration(block, true) {
+            Loop::Flag
+        } else {
+            Loop::Break
+        }
+    }
+}
+
+/// Code for an expression, depending on whether it leaves a value.
+pub(crate) enum Cast {
+    /// An expression evaluating to a value.
+    Value(String),
+    /// An expression that returns or fails before finishing.
+    Never(String),
+    /// An expression evaluated for its effect, or `None` when the cast does
+    /// nothing.
+    Nothing(Option<String>),
+}
+
+/// The variable a spell is cast on, for the spells that change it.
+pub(crate) fn target_variable(target: Option<&Expression>) -> Option<&str> {
+    match target {
+        Some(Expression::Atom(Atom::Variable(name))) => Some(name.as_str()),
+        _ => None,
+    }
+}
+
+/// `name` with a `_` appended if it is `taken`, e.g. by a keyword of the
+/// generated language. PotterScript names can't contain `_`, so it never
+/// clashes with another variable.
+pub(crate) fn variable_name(name: &str, taken: bool) -> String {
+    if taken {
+        format!("{}_", name)
+    } else {
+        name.to_string()
+    }
+}
+
+/// Variables some `~PetrificusTotalus` may make constant, which every
+/// assignment checks.
+pub(crate) fn petrified(block: &[Statement]) -> HashSet<&str> {
+    let mut petrified = HashSet::new();
+    visit_spells(block, &mut |spell, target| {
+        if let (Spell::PetrificusTotalus, Some(Expression::Atom(Atom::Variable(name)))) =
+            (spell, target)
+        {
+            petrified.insert(name.as_str());
+        }
+    });
+    petrified
+}
+
+/// Calls `visit` with every spell cast in `block`, including the ones in
+/// spell targets.
+pub(crate) fn visit_spells<'p>(
+    block: &'p [Statement],
+    visit: &mut impl FnMut(&'p Spell, Option<&'p Expression>),
+) {
+    for statement in block {
+        match statement {
+            Statement::VariableAssignment(_, expression)
+            | Statement::ExpressionStatement(expression) => {
+                visit_expression_spells(expression, visit)
+            }
+            Statement::If(condition, true_block, else_block) => {
+                visit_expression_spells(condition, visit);
+                visit_spells(true_block, visit);
+                visit_spells(else_block, visit);
+            }
+            Statement::Quidditch(block) => visit_spells(block, visit),
+            Statement::Protego(block, _, catch_block) => {
+                visit_spells(block, visit);
+                visit_spells(catch_block, visit);
+            }
+            Statement::Snitch | Statement::Vow(_, _) => {}
+        }
+    }
+}
+
+pub(crate) fn visit_expression_spells<'p>(
+    expression: &'p Expression,
+    visit: &mut impl FnMut(&'p Spell, Option<&'p Expression>),
+) {
+    match expression {
+        Expression::SpellCast(spell, target) => {
+            let target = target.as_ref().as_ref();
+            visit(spell, target);
+            if let Some(target) = target {
+                visit_expression_spells(target, visit);
+            }
+        }
+        Expression::BinaryOperation(_, left, right) => {
+            visit_expression_spells(left, visit);
+            visit_expression_spells(right, visit);
+        }
+        Expression::Field(expression, _) => visit_expression_spells(expression, visit),
+        Expression::Atom(_) | Expression::Comment(_) | Expression::SortingHat => {}
+    }
+}
+
+/// Whether every `snitch` in `block`, the body of a loop, is the last thing
+/// an iteration does, so it can `break`. A `snitch` in a nested loop also
+/// ends this one once its iteration is done, which a `break` can't do.
+fn snitches_end_iteration(block: &[Statement], last: bool) -> bool {
+    block.iter().enumerate().all(|(index, statement)| {
+        let last = last && block[index + 1..].iter().all(does_nothing);
+        match statement {
+            Statement::Snitch => last,
+            Statement::If(_, true_block, else_block) => {
+                snitches_end_iteration(true_block, last) && snitches_end_iteration(else_block, last)
+            }
+            Statement::Protego(block, _, catch_block) => {
+                snitches_end_iteration(block, last) && snitches_end_iteration(catch_block, last)
+            }
+            Statement::Quidditch(block) => snitches_end_iteration(block, false),
+            _ => true,
+        }
+    })
+}
+
+/// Whether `block` has a `quidditch` loop, which leaves the flag set when an
+/// error escapes it.
+pub(crate) fn has_loop(block: &[Statement]) -> bool {
+    block.iter().any(|statement| match statement {
+        Statement::Quidditch(_) => true,
+        Statement::If(_, true_block, else_block) => has_loop(true_block) || has_loop(else_block),
+        Statement::Protego(block, _, catch_block) => has_loop(block) || has_loop(catch_block),
+        _ => false,
+    })
+}
+
+pub(crate) fn does_nothing(statement: &Statement) -> bool {
+    matches!(
+        statement,
+        Statement::ExpressionStatement(Expression::Comment(_)) | Statement::Vow(_, _)
+    )
+}
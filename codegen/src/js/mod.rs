@@ -0,0 +1,835 @@
+//! Transpiles a [`Program`] to readable JavaScript that runs in browsers and
+//! Node.js alike.
+//!
+//! Integers become `BigInt`s wrapped to 64 bits, doubles, booleans and
+//! strings their JavaScript counterparts and houses `House` objects. Spells
+//! and operations call helpers written out before the program, only the ones
+//! it uses, which fail with the interpreter's error messages. The program
+//! itself becomes an `async function main()`, so that `~Inmobolus` can
+//! `await`, and a failure prints `error: ` and its message like `potter run`.
+
+mod prelude;
+
+use std::collections::HashSet;
+use std::fmt::Write;
+
+use potterscript_parser::{Atom, BinaryOperation, Expression, Program, Spell, Statement};
+use potterscript_runtime::{evaluates_target, RuntimeError};
+
+use crate::analysis::{
+    has_loop, petrified, target_variable, variable_name, visit_expression_spells, visit_spells,
+    Cast, Loop,
+};
+use prelude::HELPERS;
+
+/// JavaScript keywords and globals, and the names the script gives its own
+/// variables.
+const RESERVED: &[&str] = &[
+    "arguments",
+    "async",
+    "await",
+    "break",
+    "case",
+    "catch",
+    "class",
+    "const",
+    "continue",
+    "debugger",
+    "default",
+    "delete",
+    "do",
+    "else",
+    "enum",
+    "eval",
+    "export",
+    "extends",
+    "false",
+    "finally",
+    "for",
+    "function",
+    "if",
+    "implements",
+    "import",
+    "in",
+    "instanceof",
+    "interface",
+    "let",
+    "new",
+    "null",
+    "package",
+    "private",
+    "protected",
+    "public",
+    "return",
+    "static",
+    "super",
+    "switch",
+    "this",
+    "throw",
+    "true",
+    "try",
+    "typeof",
+    "undefined",
+    "var",
+    "void",
+    "while",
+    "with",
+    "yield",
+    "BigInt",
+    "Error",
+    "Infinity",
+    "Map",
+    "Math",
+    "NaN",
+    "Number",
+    "Object",
+    "Promise",
+    "Set",
+    "String",
+    "console",
+    "globalThis",
+    "prompt",
+    "setTimeout",
+    "Gryffindor",
+    "Hufflepuff",
+    "Ravenclaw",
+    "Slytherin",
+    "error",
+    "main",
+    "quidditch",
+];
+
+/// Transpiles `program` to a JavaScript script.
+///
+/// ```
+/// let program = potterscript_parser::parse("x = 1 + 2\n~Revelio x").unwrap();
+/// let script = potterscript_codegen::js::transpile(&program);
+/// assert!(script.contains("x = add(1n, 2n);\n  revelio(x);"));
+/// ```
+pub fn transpile(program: &Program) -> String {
+    let mut transpiler = Transpiler {
+        petrified: petrified(&program.0),
+        ..Transpiler::default()
+    };
+    transpiler.indent = 1;
+    transpiler.block(&program.0, &mut Assigned::new(), Loop::None);
+
+    let mut script = String::from("// Transpiled from PotterScript\n\n");
+    transpiler.called.push("host");
+    for helper in prelude::resolve(&transpiler.called) {
+        script += helper.code;
+        script += "\n\n";
+    }
+
+    script += "async function main() {\n";
+    if !transpiler.variables.is_empty() {
+        let names: Vec<_> = transpiler
+            .variables
+            .iter()
+            .map(|name| js_name(name))
+            .collect();
+        writeln!(script, "  let {};", names.join(", ")).unwrap();
+    }
+    if transpiler.quidditch {
+        script += "  // Cleared by `snitch` to end the loops running\n  let quidditch;\n";
+    }
+    if !transpiler.variables.is_empty() || transpiler.quidditch {
+        script += "\n";
+    }
+    script += &transpiler.body;
+    script += "}\n\n";
+
+    if transpiler.called.contains(&"avadaKedabra") {
+        script += "main().catch((error) => {\n  if (!(error instanceof Exit)) host.error(error.message);\n});\n";
+    } else {
+        script += "main().catch((error) => host.error(error.message));\n";
+    }
+    script
+}
+
+/// Variables certainly assigned at some point of the program, which are
+/// read without checking.
+type Assigned<'p> = HashSet<&'p str>;
+
+#[derive(Default)]
+struct Transpiler<'p> {
+    /// Every variable, in the order they first appear.
+    variables: Vec<&'p str>,
+    /// Variables the program may make constant.
+    petrified: HashSet<&'p str>,
+    /// Helpers the program calls.
+    called: Vec<&'static str>,
+    /// Whether a loop uses the `quidditch` flag.
+    quidditch: bool,
+    /// Copies of the flag made so far, to put it back after a `protego`
+    /// block catches an error from a loop.
+    saved_flags: usize,
+    body: String,
+    indent: usize,
+}
+
+impl<'p> Transpiler<'p> {
+    fn line(&mut self, line: &str) {
+        self.body += &"  ".repeat(self.indent);
+        self.body += line;
+        self.body += "\n";
+    }
+
+    fn call(&mut self, helper: &'static str) -> &'static str {
+        if !self.called.contains(&helper) {
+            self.called.push(helper);
+        }
+        helper
+    }
+
+    fn variable(&mut self, name: &'p str) -> String {
+        if !self.variables.contains(&name) {
+            self.variables.push(name);
+        }
+        js_name(name)
+    }
+
+    /// Reads a variable, checking that it is assigned unless it certainly is.
+    fn read(&mut self, name: &'p str, assigned: &Assigned<'p>) -> String {
+        let js_name = self.variable(name);
+        if assigned.contains(name) {
+            js_name
+        } else {
+            format!("{}({}, {})", self.call("assigned"), js_name, string(name))
+        }
+    }
+
+    fn block(&mut self, block: &'p [Statement], assigned: &mut Assigned<'p>, in_loop: Loop) {
+        for statement in block {
+            self.statement(statement, assigned, in_loop);
+            *assigned = assigned_after(statement, assigned);
+        }
+    }
+
+    fn nested_block(&mut self, block: &'p [Statement], mut assigned: Assigned<'p>, in_loop: Loop) {
+        self.indent += 1;
+        self.block(block, &mut assigned, in_loop);
+        self.indent -= 1;
+    }
+
+    fn statement(&mut self, statement: &'p Statement, assigned: &Assigned<'p>, in_loop: Loop) {
+        match statement {
+            Statement::VariableAssignment(name, value) => {
+                let js_name = self.variable(name);
+                if self.petrified.contains(name.as_str()) {
+                    let check = format!("{}({});", self.call("checkNotConstant"), string(name));
+                    self.line(&check);
+                }
+                match self.cast(value, assigned) {
+                    Cast::Value(value) => self.line(&format!("{} = {};", js_name, value)),
+                    Cast::Never(code) => self.line(&format!("{};", code)),
+                    Cast::Nothing(effect) => {
+                        if let Some(effect) = effect {
+                            self.line(&format!("{};", effect));
+                        }
+                        let error = RuntimeError::NoneAssignment(name.clone());
+                        let throw = format!(
+                            "throw new {}({}, {});",
+                            self.call("PotterError"),
+                            string(error.kind()),
+                            string(&error.to_string())
+                        );
+                        self.line(&throw);
+                    }
+                }
+            }
+            Statement::ExpressionStatement(Expression::Comment(comment)) => {
+                self.line(&format!("//{}", comment));
+            }
+            Statement::ExpressionStatement(expression) => match self.cast(expression, assigned) {
+                Cast::Value(code) | Cast::Never(code) | Cast::Nothing(Some(code)) => {
+                    self.line(&format!("{};", code))
+                }
+                Cast::Nothing(None) => {}
+            },
+            Statement::If(condition, true_block, else_block) => {
+                // Only `true` runs the first block, comparisons are always booleans
+                let test = match condition {
+                    Expression::BinaryOperation(
+                        BinaryOperation::Equal | BinaryOperation::NotEqual,
+                        _,
+                        _,
+                    ) => self.expression(condition, assigned),
+                    _ => format!("{} === true", self.expression(condition, assigned)),
+                };
+                let mut assigned = assigned.clone();
+                forget(expression_obliviated(condition), &mut assigned);
+
+                self.line(&format!("if ({}) {{", test));
+                self.nested_block(true_block, assigned.clone(), in_loop);
+                if !else_block.is_empty() {
+                    self.line("} else {");
+                    self.nested_block(else_block, assigned, in_loop);
+                }
+                self.line("}");
+            }
+            Statement::Quidditch(block) => {
+                let entry = loop_entry(block, assigned);
+                match in_loop.nested(block) {
+                    Loop::Flag => {
+                        self.quidditch = true;
+                        self.line("quidditch = true;");
+                        self.line("do {");
+                        self.nested_block(block, entry, Loop::Flag);
+                        self.line("} while (quidditch);");
+                    }
+                    nested => {
+                        self.line("while (true) {");
+                        self.nested_block(block, entry, nested);
+                        self.line("}");
+                    }
+                }
+            }
+            Statement::Snitch => match in_loop {
+                Loop::None => {}
+                Loop::Break => self.line("break;"),
+                Loop::Flag => self.line("quidditch = false;"),
+            },
+            Statement::Vow(_, _) => {}
+            Statement::Protego(block, error_name, catch_block) => {
+                // The loop around only sees the flag a failed loop left
+                // when it runs on the flag
+                let saved_flag = (in_loop == Loop::Flag && has_loop(block)).then(|| {
+                    self.saved_flags += 1;
+                    format!("quidditch_{}", self.saved_flags)
+                });
+                if let Some(saved_flag) = &saved_flag {
+                    self.line(&format!("const {} = quidditch;", saved_flag));
+                }
+                self.line("try {");
+                self.nested_block(block, assigned.clone(), in_loop);
+                self.line("} catch (error) {");
+
+                self.indent += 1;
+                if let Some(saved_flag) = &saved_flag {
+                    self.line(&format!("quidditch = {};", saved_flag));
+                }
+                let js_name = self.variable(error_name);
+                let mut error = format!("{}(error)", self.call("caught"));
+                if self.petrified.contains(error_name.as_str()) {
+                    error = format!(
+                        "{}({}, {})",
+                        self.call("reassign"),
+                        string(error_name),
+                        error
+                    );
+                }
+                self.line(&format!("{} = {};", js_name, error));
+                self.indent -= 1;
+
+                let assigned = catch_entry(block, error_name, assigned);
+                self.nested_block(catch_block, assigned, in_loop);
+                self.line("}");
+            }
+        }
+    }
+
+    /// JavaScript for `expression` where its value is used.
+    fn expression(&mut self, expression: &'p Expression, assigned: &Assigned<'p>) -> String {
+        match self.cast(expression, assigned) {
+            Cast::Value(code) | Cast::Never(code) => code,
+            Cast::Nothing(Some(code)) => format!("void ({})", code),
+            Cast::Nothing(None) => "undefined".to_string(),
+        }
+    }
+
+    fn cast(&mut self, expression: &'p Expression, assigned: &Assigned<'p>) -> Cast {
+        match expression {
+            Expression::SpellCast(spell, target) => {
+                return self.spell(spell, target.as_ref().as_ref(), assigned)
+            }
+            Expression::Comment(_) => return Cast::Nothing(None),
+            _ => {}
+        }
+
+        Cast::Value(match expression {
+            Expression::BinaryOperation(operation, left, right) => {
+                let left = self.expression(left, assigned);
+                let right = self.expression(right, assigned);
+                format!(
+                    "{}({}, {})",
+                    self.call(operation_helper(*operation)),
+                    left,
+                    right
+                )
+            }
+            Expression::Atom(Atom::Variable(name)) => self.read(name, assigned),
+            Expression::Atom(Atom::HogwartsHouse(house)) => {
+                self.call("House");
+                house.to_string()
+            }
+            Expression::Atom(atom) => literal(atom),
+            Expression::Field(expression, name) => {
+                let expression = self.expression(expression, assigned);
+                format!("{}({}, {})", self.call("field"), expression, string(name))
+            }
+            Expression::SortingHat => format!("{}()", self.call("sortingHat")),
+            Expression::SpellCast(_, _) | Expression::Comment(_) => unreachable!(),
+        })
+    }
+
+    fn spell(
+        &mut self,
+        spell: &Spell,
+        target: Option<&'p Expression>,
+        assigned: &Assigned<'p>,
+    ) -> Cast {
+        let variable = target_variable(target);
+        let argument = match target {
+            Some(Expression::BinaryOperation(_, _, _)) if *spell == Spell::UnbreakableVow => {
+                String::new()
+            }
+            Some(target) if evaluates_target(spell) => self.expression(target, assigned),
+            _ => String::new(),
+        };
+
+        match spell {
+            Spell::Custom(name) => {
+                let name = string(name);
+                let arguments = match target {
+                    Some(_) => format!("{}, {}", name, argument),
+                    None => name,
+                };
+                Cast::Never(format!("{}({})", self.call("unknownSpell"), arguments))
+            }
+            Spell::Accio => Cast::Value(format!("{}({})", self.call("accio"), argument)),
+            Spell::UnbreakableVow => Cast::Nothing(Some(match target {
+                Some(Expression::BinaryOperation(operation, left, right)) => {
+                    let left = self.expression(left, assigned);
+                    let right = self.expression(right, assigned);
+                    format!(
+                        "{}({}, {}, {}, {})",
+                        self.call("unbreakableVowOperation"),
+                        string(&target.unwrap().to_string()),
+                        string(&operation.to_string()),
+                        left,
+                        right
+                    )
+                }
+                Some(target) => format!(
+                    "{}({}, {})",
+                    self.call("unbreakableVow"),
+                    string(&target.to_string()),
+                    argument
+                ),
+                None => format!("{}()", self.call("unbreakableVow")),
+            })),
+            Spell::FelixFelicis => {
+                Cast::Value(format!("{}({})", self.call("felixFelicis"), argument))
+            }
+            Spell::AvadaKedabra => Cast::Never(format!("{}()", self.call("avadaKedabra"))),
+            Spell::Confringo => Cast::Never(format!("{}({})", self.call("confringo"), argument)),
+            Spell::Inmobolus => Cast::Nothing(Some(format!(
+                "await {}({})",
+                self.call("inmobolus"),
+                argument
+            ))),
+            Spell::Incendio | Spell::WingardiumLeviosa => {
+                let helper = if *spell == Spell::Incendio {
+                    "incendio"
+                } else {
+                    "wingardiumLeviosa"
+                };
+                match (variable, target) {
+                    (Some(name), _) => self.change(name, helper, assigned),
+                    (None, Some(Expression::Atom(Atom::String(value)))) => {
+                        Cast::Value(format!("{}({})", self.call(helper), string(value)))
+                    }
+                    (None, _) => Cast::Nothing(None),
+                }
+            }
+            Spell::Aguamenti => Cast::Value(format!("{}()", self.call("aguamenti"))),
+            Spell::OculusReparo => Cast::Value(format!("{}()", self.call("oculusReparo"))),
+            Spell::Serpensortia => Cast::Value(format!("{}()", self.call("serpensortia"))),
+            Spell::Periculum => Cast::Nothing(Some(format!("{}()", self.call("periculum")))),
+            Spell::Lumos | Spell::Nox => {
+                let value = *spell == Spell::Lumos;
+                Cast::Nothing(Some(format!("{} = {}", self.call("lumos"), value)))
+            }
+            Spell::Engorgio | Spell::Reducio => match variable {
+                Some(name) => {
+                    let helper = if *spell == Spell::Engorgio {
+                        "engorgio"
+                    } else {
+                        "reducio"
+                    };
+                    self.change(name, helper, assigned)
+                }
+                None => Cast::Nothing(None),
+            },
+            Spell::Obliviate => Cast::Nothing(variable.map(|name| {
+                let js_name = self.variable(name);
+                if self.petrified.contains(name) {
+                    let obliviate = self.call("obliviate");
+                    format!("{0} = {1}({2}, {0})", js_name, obliviate, string(name))
+                } else {
+                    format!("{} = undefined", js_name)
+                }
+            })),
+            Spell::Revelio => {
+                Cast::Nothing(target.map(|_| format!("{}({})", self.call("revelio"), argument)))
+            }
+            Spell::PetrificusTotalus => Cast::Nothing(variable.map(|name| {
+                let js_name = self.variable(name);
+                let petrify = self.call("petrificusTotalus");
+                format!("{}({}, {})", petrify, string(name), js_name)
+            })),
+        }
+    }
+
+    /// A spell changing the value of a variable with `helper`.
+    fn change(&mut self, name: &'p str, helper: &'static str, assigned: &Assigned<'p>) -> Cast {
+        let js_name = self.variable(name);
+        let mut value = format!("{}({})", self.call(helper), self.read(name, assigned));
+        if self.petrified.contains(name) {
+            value = format!("{}({}, {})", self.call("reassign"), string(name), value);
+        }
+        Cast::Nothing(Some(format!("{} = {}", js_name, value)))
+    }
+}
+
+fn js_name(name: &str) -> String {
+    let taken = RESERVED.contains(&name) || HELPERS.iter().any(|helper| helper.name == name);
+    variable_name(name, taken)
+}
+
+fn operation_helper(operation: BinaryOperation) -> &'static str {
+    match operation {
+        BinaryOperation::Plus => "add",
+        BinaryOperation::Minus => "subtract",
+        BinaryOperation::Times => "multiply",
+        BinaryOperation::Divide => "divide",
+        BinaryOperation::Equal => "equals",
+        BinaryOperation::NotEqual => "notEquals",
+    }
+}
+
+fn literal(atom: &Atom) -> String {
+    match atom {
+        Atom::String(value) => string(value),
+        Atom::Boolean(value) => value.to_string(),
+        Atom::Integer(value) => format!("{}n", value),
+        Atom::Double(value) if value.is_nan() => "NaN".to_string(),
+        Atom::Double(value) if value.is_infinite() => if *value > 0.0 {
+            "Infinity"
+        } else {
+            "-Infinity"
+        }
+        .to_string(),
+        // Always has a `.` or an exponent, which JavaScript reads the same
+        Atom::Double(value) => format!("{:?}", value),
+        Atom::Variable(_) | Atom::HogwartsHouse(_) => unreachable!(),
+    }
+}
+
+/// A JavaScript string literal.
+fn string(value: &str) -> String {
+    let mut literal = String::from("\"");
+    for char in value.chars() {
+        match char {
+            '"' => literal += "\\\"",
+            '\\' => literal += "\\\\",
+            '\n' => literal += "\\n",
+            '\r' => literal += "\\r",
+            '\t' => literal += "\\t",
+            '\u{2028}' | '\u{2029}' => write!(literal, "\\u{:04x}", char as u32).unwrap(),
+            char if char.is_control() => write!(literal, "\\u{:04x}", char as u32).unwrap(),
+            char => literal.push(char),
+        }
+    }
+    literal.push('"');
+    literal
+}
+
+/// Variables the `~Obliviate`s in `block` may forget.
+fn obliviated(block: &[Statement]) -> Vec<&str> {
+    let mut obliviated = vec![];
+    visit_spells(block, &mut |spell, target| {
+        obliviated.extend(obliviate_target(spell, target))
+    });
+    obliviated
+}
+
+/// Variables the `~Obliviate`s in `expression` may forget.
+fn expression_obliviated(expression: &Expression) -> Vec<&str> {
+    let mut obliviated = vec![];
+    visit_expression_spells(expression, &mut |spell, target| {
+        obliviated.extend(obliviate_target(spell, target))
+    });
+    obliviated
+}
+
+fn obliviate_target<'p>(spell: &Spell, target: Option<&'p Expression>) -> Option<&'p str> {
+    match (spell, target) {
+        (Spell::Obliviate, Some(Expression::Atom(Atom::Variable(name)))) => Some(name),
+        _ => None,
+    }
+}
+
+fn forget<'p>(names: Vec<&'p str>, assigned: &mut Assigned<'p>) {
+    for name in names {
+        assigned.remove(name);
+    }
+}
+
+/// Variables certainly assigned after `statement` runs from `assigned`.
+fn assigned_after<'p>(statement: &'p Statement, assigned: &Assigned<'p>) -> Assigned<'p> {
+    let mut after = assigned.clone();
+    match statement {
+        Statement::VariableAssignment(name, value) => {
+            forget(expression_obliviated(value), &mut after);
+            after.insert(name);
+        }
+        Statement::ExpressionStatement(expression) => {
+            forget(expression_obliviated(expression), &mut after)
+        }
+        Statement::If(condition, true_block, else_block) => {
+            forget(expression_obliviated(condition), &mut after);
+            let true_after = block_assigned_after(true_block, &after);
+            let else_after = block_assigned_after(else_block, &after);
+            after = true_after.intersection(&else_after).copied().collect();
+        }
+        Statement::Quidditch(block) => {
+            after = block_assigned_after(block, &loop_entry(block, assigned));
+        }
+        Statement::Snitch | Statement::Vow(_, _) => {}
+        Statement::Protego(block, error_name, catch_block) => {
+            let caught =
+                block_assigned_after(catch_block, &catch_entry(block, error_name, assigned));
+            after = block_assigned_after(block, assigned)
+                .intersection(&caught)
+                .copied()
+                .collect();
+        }
+    }
+    after
+}
+
+fn block_assigned_after<'p>(block: &'p [Statement], assigned: &Assigned<'p>) -> Assigned<'p> {
+    block.iter().fold(assigned.clone(), |assigned, statement| {
+        assigned_after(statement, &assigned)
+    })
+}
+
+/// Variables certainly assigned at the start of every iteration of a loop
+/// over `block` entered with `assigned`.
+fn loop_entry<'p>(block: &'p [Statement], assigned: &Assigned<'p>) -> Assigned<'p> {
+    let mut entry = assigned.clone();
+    loop {
+        let next: Assigned = entry
+            .intersection(&block_assigned_after(block, &entry))
+            .copied()
+            .collect();
+        if next == entry {
+            return entry;
+        }
+        entry = next;
+    }
+}
+
+/// Variables certainly assigned when `protego` catches an error from
+/// `block`, which may have failed anywhere in it.
+fn catch_entry<'p>(
+    block: &'p [Statement],
+    error_name: &'p str,
+    assigned: &Assigned<'p>,
+) -> Assigned<'p> {
+    let mut entry = assigned.clone();
+    forget(obliviated(block), &mut entry);
+    entry.insert(error_name);
+    entry
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::Path;
+
+    use potterscript_runtime::golden::golden_output;
+    use rquickjs::{Context, Runtime};
+
+    use super::*;
+
+    /// Globals standing in for the browser's. What the script logs is
+    /// collected without the colors of `~Lumos`, the Sorting Hat goes round
+    /// the houses in order like in golden tests, sleeping only adds up the
+    /// time and `prompt` answers from `inputs`.
+    const BROWSER: &str = r#"
+        var output = "";
+        var slept = 0;
+        var inputs = [];
+        var prompts = [];
+        var draws = 0;
+        var console = {
+          log: (text) => {
+            output += text.replaceAll("\x1b[30;47m", "").replaceAll("\x1b[0m", "") + "\n";
+          },
+          error: (text) => {
+            output += text + "\n";
+          },
+        };
+        var setTimeout = (resolve, ms) => {
+          slept += ms;
+          resolve();
+        };
+        var prompt = (text) => {
+          prompts.push(text);
+          return inputs.length > 0 ? inputs.shift() : null;
+        };
+        Math.random = () => ((draws++ % 4) + 0.5) / 4;
+    "#;
+
+    /// Runs `setup` and then `script` in [`BROWSER`] until it is done,
+    /// returning the value of `result` afterwards.
+    fn run(setup: &str, script: &str, result: &str) -> String {
+        let runtime = Runtime::new().unwrap();
+        let context = Context::full(&runtime).unwrap();
+        context.with(|ctx| {
+            ctx.eval::<(), _>(BROWSER).unwrap();
+            ctx.eval::<(), _>(setup).unwrap();
+            ctx.eval::<(), _>(script).unwrap();
+        });
+        while runtime.execute_pending_job().unwrap() {}
+        context.with(|ctx| ctx.eval::<String, _>(result).unwrap())
+    }
+
+    /// Runs `code` transpiled to JavaScript the way golden tests run it.
+    fn js_output(code: &str, inputs: &[String]) -> String {
+        let script = transpile(&potterscript_parser::parse(code).unwrap());
+        let inputs = inputs.iter().map(|input| string(input)).collect::<Vec<_>>();
+        let setup = format!("inputs = [{}];", inputs.join(", "));
+        run(&setup, &script, "output")
+    }
+
+    fn assert_same_output(code: &str) {
+        assert_eq!(golden_output(code, &[]), js_output(code, &[]), "{}", code);
+    }
+
+    #[test]
+    fn test_examples() {
+        let root = Path::new(env!("CARGO_MANIFEST_DIR")).join("..");
+        assert_same_output(&fs::read_to_string(root.join("example/input.potter")).unwrap());
+
+        for name in ["accio", "constant", "example", "protego", "strings"] {
+            let golden = root.join("runtime/tests/golden").join(name);
+            let code = fs::read_to_string(golden.with_extension("potter")).unwrap();
+            let inputs: Vec<String> = fs::read_to_string(golden.with_extension("in"))
+                .map(|inputs| inputs.lines().map(str::to_string).collect())
+                .unwrap_or_default();
+            assert_eq!(
+                golden_output(&code, &inputs),
+                js_output(&code, &inputs),
+                "{}",
+                name
+            );
+        }
+    }
+
+    #[test]
+    fn test_values() {
+        assert_same_output("~Revelio 0\n~Revelio -42\n~Revelio 9223372036854775807");
+        assert_same_output("x = 1.5\ny = x * 2.0\n~Revelio y\n~Revelio x / 0.0");
+        assert_same_output("~Revelio 1e300\n~Revelio 0.0000001\n~Revelio 0.0 - 1e-20");
+        assert_same_output("~Revelio true\n~Revelio false\n~Revelio true == false");
+        assert_same_output("a = SortingHat\nb = SortingHat\n~Revelio a\n~Revelio b == a");
+        assert_same_output("~Revelio Slytherin\n~Revelio ~Lumos\n~Revelio # nothing");
+        // Only some spells evaluate their target
+        assert_same_output("~Periculum ~Revelio 1\n~Engorgio ~Revelio 2\n~Lumos x");
+        assert_same_output("x = \"Hogwarts\"\n~Reducio x\n~Revelio x + \"!\"");
+        assert_same_output("~Revelio \"back\\slash\"\n~Revelio \"tab\tnew\nline\"");
+        // Values of different types are never equal
+        assert_same_output("~Revelio 1 == 1.0\n~Revelio \"1\" != 1");
+    }
+
+    #[test]
+    fn test_arithmetic() {
+        assert_same_output("x = 7 / 2\n~Revelio x\ny = -7 / 2\n~Revelio y");
+        assert_same_output("x = 9223372036854775807\n~Engorgio x\n~Revelio x");
+        assert_same_output("x = -9223372036854775807 - 1\ny = x / -1\n~Revelio y");
+        assert_same_output("x = 10\ny = x - 3\nz = y * y\n~Revelio z");
+        assert_same_output("x = 0\ny = 1 / x");
+        assert_same_output("x = 1.5\n~Reducio x\n~Revelio x");
+    }
+
+    #[test]
+    fn test_loops() {
+        assert_same_output(
+            "i = 0\nquidditch {\n~Engorgio i\nj = 0\nquidditch {\n~Engorgio j\n~Revelio j\nif j == 2 {\nsnitch\n}\n}\n~Revelio i\n}",
+        );
+        assert_same_output(
+            "s = \"\"\nquidditch {\ns = s + \"a\"\nif s == \"aaa\" {\nsnitch\n}\n}\n~Revelio s",
+        );
+        // The iteration goes on after a `snitch`
+        assert_same_output("quidditch {\nsnitch\n~Revelio \"still here\"\n}");
+        assert_same_output(
+            "i = 0\nquidditch {\n~Engorgio i\nif i == 3 {\nsnitch\n}\n~Revelio i\n}",
+        );
+        // A `snitch` outside of any loop does nothing
+        assert_same_output("snitch\n~Revelio 1");
+    }
+
+    #[test]
+    fn test_snitch_becomes_break() {
+        let script = |code| transpile(&potterscript_parser::parse(code).unwrap());
+
+        let script = script("i = 0\nquidditch {\n~Engorgio i\nif i == 3 {\nsnitch # done\n}\n}");
+        assert!(script.contains("while (true) {"), "{}", script);
+        assert!(script.contains("break;\n      // done"), "{}", script);
+        assert!(!script.contains("quidditch"), "{}", script);
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_same_output("~Revelio x");
+        assert_same_output("x = 1\n~Obliviate x\n~Revelio x");
+        assert_same_output("x = 1\n~PetrificusTotalus x\n~Obliviate x\nx = 2");
+        assert_same_output("x = 1\n~PetrificusTotalus x\n~Engorgio x");
+        assert_same_output("x = ~Lumos");
+        assert_same_output("~Expecto 1");
+        assert_same_output("~Confringo 42");
+        assert_same_output("~Confringo");
+        assert_same_output("~Inmobolus");
+        assert_same_output("x = true\n~Engorgio x");
+        assert_same_output("~Revelio 1 + \"1\"\n");
+        assert_same_output("~Revelio 1.5 * true");
+        assert_same_output("x = \"a\\b\tc\nd\"\n~UnbreakableVow x == 1.0");
+        assert_same_output("x = \"a\"\n~UnbreakableVow x");
+        assert_same_output(
+            "protego {\n~Revelio 1 / 0\n} expelliarmus err {\n~Revelio err\n~Revelio err.kind\n~Revelio err.nope\n}",
+        );
+        // Broken vows can't be caught
+        assert_same_output(
+            "protego {\n~UnbreakableVow 1 == 2\n} expelliarmus err {\n~Revelio err\n}",
+        );
+    }
+
+    #[test]
+    fn test_variables() {
+        // Variables named like JavaScript keywords or helpers still work
+        assert_same_output("let = 1\nadd = let + 1\nerror = add\n~Revelio error");
+        assert_same_output("x = 1\nif x == 1 {\ny = 2\n}\n~Revelio y");
+        assert_same_output("x = 1\nif x == 2 {\ny = 2\n}\n~Revelio y");
+        assert_same_output("i = 0\nquidditch {\n~Engorgio i\nif i == 2 {\n~Revelio x\n}\nx = i\n~Obliviate x\nif i == 3 {\nsnitch\n}\n}");
+    }
+
+    #[test]
+    fn test_host_calls() {
+        let program = potterscript_parser::parse(
+            "~Inmobolus 1500\n~Inmobolus -5\nx = ~FelixFelicis\n~Revelio x\nname = ~Accio \"Name?\"\n~Revelio name\n~Revelio ~Accio\n~AvadaKedabra\n~Revelio \"unreachable\"",
+        )
+        .unwrap();
+        let script = transpile(&program);
+
+        let result = run(
+            "inputs = [\"Hedwig\"];",
+            &script,
+            "JSON.stringify([output, slept, prompts])",
+        );
+        assert_eq!(r#"["0.125\nHedwig\nfalse\n",1500,["Name?",""]]"#, result);
+    }
+}
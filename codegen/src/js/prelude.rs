@@ -0,0 +1,522 @@
+//! The helpers transpiled programs call, each included only if the program
+//! needs it.
+
+pub(super) struct Helper {
+    pub(super) name: &'static str,
+    /// Helpers this one calls.
+    pub(super) uses: &'static [&'static str],
+    pub(super) code: &'static str,
+}
+
+const fn helper(name: &'static str, uses: &'static [&'static str], code: &'static str) -> Helper {
+    Helper { name, uses, code }
+}
+
+/// Every helper, in the order they are written out.
+pub(super) const HELPERS: &[Helper] = &[
+    helper(
+        "host",
+        &[],
+        r#"// How the program talks to the world, replace these to run it elsewhere
+const host = {
+  log: (text) => console.log(text),
+  lumos: (text) => `\x1b[30;47m${text}\x1b[0m`,
+  random: () => Math.random(),
+  sleep: (ms) => new Promise((resolve) => setTimeout(resolve, ms)),
+  readLine: (prompt) => globalThis.prompt?.(prompt ?? "") ?? null,
+  error: (message) => console.error(`error: ${message}`),
+};"#,
+    ),
+    helper(
+        "PotterError",
+        &[],
+        r#"class PotterError extends Error {
+  constructor(kind, message) {
+    super(message);
+    this.kind = kind;
+  }
+}"#,
+    ),
+    helper("Exit", &[], "// Thrown by `~AvadaKedabra`\nclass Exit {}"),
+    helper(
+        "House",
+        &[],
+        r#"class House {
+  constructor(name) {
+    this.name = name;
+  }
+}
+
+const Gryffindor = new House("Gryffindor");
+const Hufflepuff = new House("Hufflepuff");
+const Ravenclaw = new House("Ravenclaw");
+const Slytherin = new House("Slytherin");"#,
+    ),
+    helper(
+        "quote",
+        &[],
+        r#"// Quotes a string the way Rust's `Debug` does
+function quote(string) {
+  const escapes = { "\\": "\\\\", '"': '\\"', "\n": "\\n", "\r": "\\r", "\t": "\\t", "\0": "\\0" };
+  const escaped = string.replace(
+    /[\\"\0-\x1f\x7f]/g,
+    (char) => escapes[char] ?? `\\u{${char.charCodeAt(0).toString(16)}}`
+  );
+  return `"${escaped}"`;
+}"#,
+    ),
+    helper(
+        "showDouble",
+        &[],
+        r#"// Formats a double the way Rust does, never with an exponent
+function showDouble(double) {
+  if (Number.isNaN(double)) return "NaN";
+  if (!Number.isFinite(double)) return double > 0 ? "inf" : "-inf";
+  if (Object.is(double, -0)) return "-0";
+  const [mantissa, exponent] = String(double).split("e");
+  if (exponent === undefined) return mantissa;
+
+  const sign = double < 0 ? "-" : "";
+  const [whole, fraction = ""] = mantissa.replace("-", "").split(".");
+  const digits = whole + fraction;
+  const point = whole.length + Number(exponent);
+  if (point <= 0) return `${sign}0.${"0".repeat(-point)}${digits}`;
+  return `${sign}${digits}${"0".repeat(point - digits.length)}`;
+}"#,
+    ),
+    helper(
+        "debugDouble",
+        &["showDouble"],
+        r#"// Formats a double the way Rust's `Debug` does
+function debugDouble(double) {
+  const magnitude = Math.abs(double);
+  if (Number.isFinite(double) && magnitude !== 0 && (magnitude < 1e-4 || magnitude >= 1e16)) {
+    return double.toExponential().replace("+", "");
+  }
+  const shown = showDouble(double);
+  return Number.isFinite(double) && !shown.includes(".") ? `${shown}.0` : shown;
+}"#,
+    ),
+    helper(
+        "entries",
+        &[],
+        r#"// Entries of a map, ordered by key
+function entries(map) {
+  return [...map].sort(([a], [b]) => (a < b ? -1 : a > b ? 1 : 0));
+}"#,
+    ),
+    helper(
+        "show",
+        &["showDouble", "House", "entries", "quote", "literal"],
+        r#"// What `~Revelio` prints for a value
+function show(value) {
+  switch (typeof value) {
+    case "undefined":
+      return "";
+    case "number":
+      return showDouble(value);
+    case "object":
+      if (value instanceof House) return value.name;
+      return `{${entries(value)
+        .map(([key, value]) => `${quote(key)}: ${literal(value)}`)
+        .join(", ")}}`;
+    default:
+      return String(value);
+  }
+}"#,
+    ),
+    helper(
+        "literal",
+        &["quote", "show"],
+        r#"// A value the way it is written in a script
+function literal(value) {
+  return typeof value === "string" ? quote(value) : show(value);
+}"#,
+    ),
+    helper(
+        "debug",
+        &["debugDouble", "quote", "House", "entries"],
+        r#"// A value the way Rust's `Debug` shows it, for error messages
+function debug(value) {
+  switch (typeof value) {
+    case "bigint":
+      return `Integer(${value})`;
+    case "number":
+      return `Double(${debugDouble(value)})`;
+    case "boolean":
+      return `Boolean(${value})`;
+    case "string":
+      return `String(${quote(value)})`;
+  }
+  if (value instanceof House) return `HogwartsHouse(${value.name})`;
+  return `Map({${entries(value)
+    .map(([key, value]) => `${quote(key)}: ${debug(value)}`)
+    .join(", ")}})`;
+}"#,
+    ),
+    helper(
+        "assigned",
+        &["PotterError"],
+        r#"// The value of a variable that may not be assigned
+function assigned(value, name) {
+  if (value === undefined) {
+    throw new PotterError("VariableNotFound", `Variable ${name} not found`);
+  }
+  return value;
+}"#,
+    ),
+    helper(
+        "constants",
+        &[],
+        "// Variables frozen with `~PetrificusTotalus`\nconst constants = new Set();",
+    ),
+    helper(
+        "checkNotConstant",
+        &["constants", "PotterError"],
+        r#"function checkNotConstant(name) {
+  if (constants.has(name)) {
+    throw new PotterError("ConstantReassignment", `Cannot re-assign constant ${name}`);
+  }
+}"#,
+    ),
+    helper(
+        "reassign",
+        &["checkNotConstant"],
+        r#"// The new value of a variable a spell changes, unless it is a constant
+function reassign(name, value) {
+  checkNotConstant(name);
+  return value;
+}"#,
+    ),
+    helper(
+        "petrificusTotalus",
+        &["constants"],
+        r#"function petrificusTotalus(name, value) {
+  if (value !== undefined) constants.add(name);
+}"#,
+    ),
+    helper(
+        "obliviate",
+        &["constants"],
+        r#"// Constants cannot be forgotten
+function obliviate(name, value) {
+  return constants.has(name) ? value : undefined;
+}"#,
+    ),
+    helper(
+        "operate",
+        &["PotterError", "debug"],
+        r#"function operate(verb, left, right, operation) {
+  if (typeof left !== typeof right || !["bigint", "number"].includes(typeof left)) {
+    throw new PotterError(
+      "InvalidBinaryOperation",
+      `Cannot ${verb} ${debug(left)} and ${debug(right)}`
+    );
+  }
+  const result = operation(left, right);
+  // Integers have 64 bits and wrap around
+  return typeof result === "bigint" ? BigInt.asIntN(64, result) : result;
+}"#,
+    ),
+    helper(
+        "add",
+        &["operate"],
+        r#"function add(left, right) {
+  if (typeof left === "string" && typeof right === "string") return left + right;
+  return operate("add", left, right, (left, right) => left + right);
+}"#,
+    ),
+    helper(
+        "subtract",
+        &["operate"],
+        r#"function subtract(left, right) {
+  return operate("subtract", left, right, (left, right) => left - right);
+}"#,
+    ),
+    helper(
+        "multiply",
+        &["operate"],
+        r#"function multiply(left, right) {
+  return operate("multiply", left, right, (left, right) => left * right);
+}"#,
+    ),
+    helper(
+        "divide",
+        &["operate", "PotterError"],
+        r#"function divide(left, right) {
+  if (typeof left === "bigint" && right === 0n) {
+    throw new PotterError("DivisionByZero", "Cannot divide by zero");
+  }
+  return operate("divide", left, right, (left, right) => left / right);
+}"#,
+    ),
+    helper(
+        "equals",
+        &["show"],
+        r#"// Values of different types are never equal
+function equals(left, right) {
+  if (left instanceof Map && right instanceof Map) return show(left) === show(right);
+  return left === right;
+}"#,
+    ),
+    helper(
+        "notEquals",
+        &["equals"],
+        r#"function notEquals(left, right) {
+  return !equals(left, right);
+}"#,
+    ),
+    helper(
+        "operations",
+        &[
+            "add",
+            "subtract",
+            "multiply",
+            "divide",
+            "equals",
+            "notEquals",
+        ],
+        r#"const operations = {
+  "+": add,
+  "-": subtract,
+  "*": multiply,
+  "/": divide,
+  "==": equals,
+  "!=": notEquals,
+};"#,
+    ),
+    helper(
+        "invalidSpellTarget",
+        &["PotterError", "debug"],
+        r#"function invalidSpellTarget(spell, value) {
+  return new PotterError("InvalidSpellTarget", `Cannot ${spell} ${debug(value)}`);
+}"#,
+    ),
+    helper(
+        "missingSpellTarget",
+        &["PotterError"],
+        r#"function missingSpellTarget(spell) {
+  return new PotterError("MissingSpellTarget", `${spell} needs a target`);
+}"#,
+    ),
+    helper(
+        "lumos",
+        &[],
+        "// Set by `~Lumos`, cleared by `~Nox`\nlet lumos = false;",
+    ),
+    helper(
+        "revelio",
+        &["host", "show", "lumos"],
+        r#"function revelio(value) {
+  const text = show(value);
+  host.log(lumos ? host.lumos(text) : text);
+}"#,
+    ),
+    helper(
+        "periculum",
+        &["host"],
+        r#"function periculum() {
+  host.log("🔥🔥🔥🔥🔥🔥🔥🔥🔥");
+}"#,
+    ),
+    helper(
+        "aguamenti",
+        &[],
+        r#"function aguamenti() {
+  return "💦";
+}"#,
+    ),
+    helper(
+        "oculusReparo",
+        &[],
+        r#"function oculusReparo() {
+  return "👓";
+}"#,
+    ),
+    helper(
+        "serpensortia",
+        &[],
+        r#"function serpensortia() {
+  return "🐍";
+}"#,
+    ),
+    helper(
+        "engorgio",
+        &["invalidSpellTarget"],
+        r#"function engorgio(value) {
+  switch (typeof value) {
+    case "bigint":
+      return BigInt.asIntN(64, value + 1n);
+    case "number":
+      return value + 1;
+    case "string":
+      return value.replace(/[a-z]/g, (letter) => letter.toUpperCase());
+  }
+  throw invalidSpellTarget("Engorgio", value);
+}"#,
+    ),
+    helper(
+        "reducio",
+        &["invalidSpellTarget"],
+        r#"function reducio(value) {
+  switch (typeof value) {
+    case "bigint":
+      return BigInt.asIntN(64, value - 1n);
+    case "number":
+      return value - 1;
+    case "string":
+      return value.replace(/[A-Z]/g, (letter) => letter.toLowerCase());
+  }
+  throw invalidSpellTarget("Reducio", value);
+}"#,
+    ),
+    helper(
+        "incendio",
+        &["invalidSpellTarget"],
+        r#"function incendio(string) {
+  if (typeof string !== "string") throw invalidSpellTarget("Incendio", string);
+  return `${string}🔥`;
+}"#,
+    ),
+    helper(
+        "wingardiumLeviosa",
+        &["invalidSpellTarget"],
+        r#"function wingardiumLeviosa(string) {
+  if (typeof string !== "string") throw invalidSpellTarget("WingardiumLeviosa", string);
+  return `${string}\n`;
+}"#,
+    ),
+    helper(
+        "felixFelicis",
+        &["host", "invalidSpellTarget"],
+        r#"function felixFelicis(max) {
+  if (max === undefined) return host.random();
+  if (typeof max === "bigint" && max > 0n) {
+    return BigInt(Math.floor(host.random() * Number(max)));
+  }
+  if (typeof max === "number" && max >= 0 && Number.isFinite(max)) {
+    return host.random() * max;
+  }
+  throw invalidSpellTarget("FelixFelicis", max);
+}"#,
+    ),
+    helper(
+        "sortingHat",
+        &["host", "House"],
+        r#"function sortingHat() {
+  const houses = [Gryffindor, Hufflepuff, Ravenclaw, Slytherin];
+  return houses[Math.floor(host.random() * houses.length)];
+}"#,
+    ),
+    helper(
+        "inmobolus",
+        &["host"],
+        r#"async function inmobolus(ms) {
+  if (typeof ms !== "bigint") return;
+  await host.sleep(ms > 0n ? Number(ms) : 0);
+}"#,
+    ),
+    helper(
+        "confringo",
+        &["missingSpellTarget", "PotterError", "show"],
+        r#"function confringo(value) {
+  if (value === undefined) throw missingSpellTarget("Confringo");
+  throw new PotterError("Raised", show(value));
+}"#,
+    ),
+    helper(
+        "accio",
+        &["host", "show"],
+        r#"function accio(prompt) {
+  const line = host.readLine(prompt === undefined ? undefined : show(prompt));
+  // No more input reads as `false`
+  return line ?? false;
+}"#,
+    ),
+    helper(
+        "avadaKedabra",
+        &["Exit"],
+        r#"function avadaKedabra() {
+  throw new Exit();
+}"#,
+    ),
+    helper(
+        "unknownSpell",
+        &["PotterError"],
+        r#"// Fails once the target, if any, is evaluated
+function unknownSpell(name) {
+  throw new PotterError("UnknownSpell", `Wand broken: Unknown spell ${name}`);
+}"#,
+    ),
+    helper(
+        "keepVow",
+        &["PotterError", "missingSpellTarget", "invalidSpellTarget"],
+        r#"function keepVow(expression, value, actual) {
+  if (value === true) return;
+  if (value === false) {
+    throw new PotterError("BrokenVow", `Unbreakable Vow broken: \`${expression}\` was \`${actual()}\``);
+  }
+  if (value === undefined) throw missingSpellTarget("UnbreakableVow");
+  throw invalidSpellTarget("UnbreakableVow", value);
+}"#,
+    ),
+    helper(
+        "unbreakableVow",
+        &["keepVow", "literal"],
+        r#"function unbreakableVow(expression, value) {
+  keepVow(expression, value, () => literal(value));
+}"#,
+    ),
+    helper(
+        "unbreakableVowOperation",
+        &["operations", "keepVow", "literal"],
+        r#"// A vow on an operation, which reports both operands if it breaks
+function unbreakableVowOperation(expression, operator, left, right) {
+  const value = operations[operator](left, right);
+  keepVow(expression, value, () => `${literal(left)} ${operator} ${literal(right)}`);
+}"#,
+    ),
+    helper(
+        "field",
+        &["PotterError", "debug"],
+        r#"function field(value, name) {
+  if (value instanceof Map && value.has(name)) return value.get(name);
+  throw new PotterError("FieldNotFound", `Field ${name} not found in ${debug(value)}`);
+}"#,
+    ),
+    helper(
+        "caught",
+        &["PotterError"],
+        r#"// The error `expelliarmus` binds, unless `protego` cannot catch it
+function caught(error) {
+  if (!(error instanceof PotterError) || error.kind === "BrokenVow") throw error;
+  return new Map([
+    ["kind", error.kind],
+    ["message", error.message],
+  ]);
+}"#,
+    ),
+];
+
+/// The helpers called, and the ones they call, in the order to write them
+/// out.
+pub(super) fn resolve(called: &[&str]) -> Vec<&'static Helper> {
+    let mut included = vec![false; HELPERS.len()];
+    let mut pending = called.to_vec();
+    while let Some(name) = pending.pop() {
+        let index = HELPERS
+            .iter()
+            .position(|helper| helper.name == name)
+            .unwrap_or_else(|| panic!("no helper called {}", name));
+        if !included[index] {
+            included[index] = true;
+            pending.extend(HELPERS[index].uses);
+        }
+    }
+
+    HELPERS
+        .iter()
+        .zip(included)
+        .filter_map(|(helper, included)| included.then_some(helper))
+        .collect()
+}
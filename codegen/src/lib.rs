@@ -0,0 +1,16 @@
+//! Compiles PotterScript programs ahead of time for hosts that don't run the
+//! [`potterscript_runtime`] interpreter.
+//!
+//! - [`wasm`] compiles a [`Program`](potterscript_parser::Program) to a
+//!   standalone WebAssembly module.
+//! - [`js`] transpiles a [`Program`](potterscript_parser::Program) to readable
+//!   JavaScript.
+//! - [`rust`] generates a Rust module running a
+//!   [`Program`](potterscript_parser::Program) on top of
+//!   [`potterscript_runtime`].
+
+mod analysis;
+pub mod js;
+pub mod rust;
+#[cfg(feature = "wasm")]
+pub mod wasm;
@@ -0,0 +1,893 @@
+//! Generates a Rust module from a [`Program`], to embed a script in a Rust
+//! project without the interpreter.
+//!
+//! The module has a single `pub fn run(adapter: &impl RuntimeAdapter) ->
+//! Result<(), RuntimeError>` that does what [`Runtime::eval`] would, calling
+//! the [`spells`](potterscript_runtime::spells) of `potterscript_runtime` and
+//! going through `adapter` for logs, random picks, sleeps and input. It
+//! depends on `potterscript-parser` and `potterscript-runtime`, and can be a
+//! module file or be `include!`d from a build script's output:
+//!
+//! ```ignore
+//! mod sorting {
+//!     include!(concat!(env!("OUT_DIR"), "/sorting.rs"));
+//! }
+//!
+//! sorting::run(&DefaultRuntimeAdapter)?;
+//! ```
+//!
+//! Variables become `Option<RuntimeValue>`s, `quidditch` loops Rust loops and
+//! `protego` blocks labeled blocks that an error breaks out of. Unlike in
+//! the interpreter, where it fails with `RuntimeError::Exit`,
+//! `~AvadaKedabra` returns `Ok(())` from `run`.
+//!
+//! [`Runtime::eval`]: potterscript_runtime::Runtime::eval
+
+use std::collections::{BTreeSet, HashSet};
+use std::fmt::Write;
+use std::mem;
+
+use potterscript_parser::{
+    Atom, BinaryOperation, Expression, HogwartsHouse, Program, Spell, Statement,
+};
+use potterscript_runtime::evaluates_target;
+
+use crate::analysis::{has_loop, petrified, target_variable, variable_name, Cast, Loop};
+
+/// Rust keywords and prelude names, and the names `run` gives its own
+/// bindings.
+const RESERVED: &[&str] = &[
+    "abstract",
+    "as",
+    "async",
+    "await",
+    "become",
+    "box",
+    "break",
+    "const",
+    "continue",
+    "crate",
+    "do",
+    "dyn",
+    "else",
+    "enum",
+    "extern",
+    "false",
+    "final",
+    "fn",
+    "for",
+    "gen",
+    "if",
+    "impl",
+    "in",
+    "let",
+    "loop",
+    "macro",
+    "match",
+    "mod",
+    "move",
+    "mut",
+    "override",
+    "priv",
+    "pub",
+    "ref",
+    "return",
+    "self",
+    "static",
+    "struct",
+    "super",
+    "trait",
+    "true",
+    "try",
+    "type",
+    "typeof",
+    "unsafe",
+    "unsized",
+    "use",
+    "virtual",
+    "where",
+    "while",
+    "yield",
+    "Err",
+    "None",
+    "Ok",
+    "Self",
+    "Some",
+    "adapter",
+    "error",
+    "lumos",
+    "operands",
+    "quidditch",
+    "result",
+    "run",
+    "spells",
+    "value",
+];
+
+/// Functions and macros the generated code calls, each written out only if
+/// it is used.
+struct Helper {
+    name: &'static str,
+    /// Names it needs imported.
+    imports: &'static [&'static str],
+    code: &'static str,
+}
+
+const HELPERS: &[Helper] = &[
+    Helper {
+        name: "protego",
+        imports: &[],
+        code: r#"/// `?` inside a `protego` block, which goes on to its `expelliarmus` block
+/// instead of returning.
+macro_rules! protego {
+    ($block:lifetime, $result:expr) => {
+        match $result {
+            Ok(value) => value,
+            Err(error) => break $block Err(error),
+        }
+    };
+}"#,
+    },
+    Helper {
+        name: "get",
+        imports: &["RuntimeValue"],
+        code: r#"fn get(variable: &Option<RuntimeValue>, name: &str) -> Result<RuntimeValue, RuntimeError> {
+    variable
+        .clone()
+        .ok_or_else(|| RuntimeError::VariableNotFound(name.to_string()))
+}"#,
+    },
+    Helper {
+        name: "reassign",
+        imports: &["RuntimeValue"],
+        code: r#"/// The new value of a variable a spell changes, unless it is a constant.
+fn reassign(name: &str, constant: bool, value: RuntimeValue) -> Result<RuntimeValue, RuntimeError> {
+    if constant {
+        return Err(RuntimeError::ConstantReassignment(name.to_string()));
+    }
+    Ok(value)
+}"#,
+    },
+    Helper {
+        name: "revelio",
+        imports: &["RuntimeValue"],
+        code: r#"fn revelio(adapter: &impl RuntimeAdapter, lumos: bool, value: Option<RuntimeValue>) {
+    let text = value.map(|value| value.to_string()).unwrap_or_default();
+    adapter.log(&if lumos { adapter.lumos(text) } else { text });
+}"#,
+    },
+    Helper {
+        name: "field",
+        imports: &["RuntimeValue"],
+        code: r#"fn field(value: RuntimeValue, name: &str) -> Result<RuntimeValue, RuntimeError> {
+    match value {
+        RuntimeValue::Map(mut entries) => match entries.remove(name) {
+            Some(value) => Ok(value),
+            None => Err(RuntimeError::FieldNotFound(RuntimeValue::Map(entries), name.to_string())),
+        },
+        value => Err(RuntimeError::FieldNotFound(value, name.to_string())),
+    }
+}"#,
+    },
+    Helper {
+        name: "unbreakable_vow",
+        imports: &["Expression", "RuntimeValue", "spells"],
+        code: r#"fn unbreakable_vow(expression: &Expression, value: Option<RuntimeValue>) -> Result<(), RuntimeError> {
+    spells::unbreakable_vow(expression, value.clone(), value.into_iter().collect())
+}"#,
+    },
+    Helper {
+        name: "unbreakable_vow_operation",
+        imports: &["BinaryOperation", "Expression", "RuntimeValue", "spells"],
+        code: r#"/// A vow on an operation, which reports both operands if it breaks.
+fn unbreakable_vow_operation(
+    expression: &Expression,
+    operation: BinaryOperation,
+    left: Option<RuntimeValue>,
+    right: Option<RuntimeValue>,
+) -> Result<(), RuntimeError> {
+    let (value, operands) = spells::vow_operation(operation, left, right)?;
+    spells::unbreakable_vow(expression, value, operands)
+}"#,
+    },
+];
+
+/// Generates a Rust module running `program`.
+///
+/// ```
+/// let program = potterscript_parser::parse("x = 1 + 2\n~Revelio x").unwrap();
+/// let module = potterscript_codegen::rust::transpile(&program);
+/// assert!(module.contains("pub fn run(adapter: &impl RuntimeAdapter)"));
+/// ```
+pub fn transpile(program: &Program) -> String {
+    let mut transpiler = Transpiler {
+        petrified: petrified(&program.0),
+        imports: BTreeSet::from(["RuntimeAdapter", "RuntimeError"]),
+        indent: 1,
+        ..Transpiler::default()
+    };
+    transpiler.block(&program.0, Loop::None);
+
+    let mut helpers = vec![];
+    for helper in HELPERS {
+        if transpiler.called.contains(helper.name) {
+            transpiler.imports.extend(helper.imports);
+            helpers.push(helper.code);
+        }
+    }
+    if !transpiler.variables.is_empty() {
+        transpiler.imports.insert("RuntimeValue");
+    }
+
+    let mut module =
+        String::from("// Generated from PotterScript by potterscript-codegen, do not edit.\n\n");
+    let imports = |names: &[&str]| {
+        let used: Vec<_> = names
+            .iter()
+            .filter(|name| transpiler.imports.contains(*name))
+            .copied()
+            .collect();
+        match used.as_slice() {
+            [] => None,
+            [name] => Some(name.to_string()),
+            names => Some(format!("{{{}}}", names.join(", "))),
+        }
+    };
+    let parser = [
+        "Atom",
+        "BinaryOperation",
+        "Expression",
+        "HogwartsHouse",
+        "Spell",
+    ];
+    if let Some(names) = imports(&parser) {
+        writeln!(module, "use potterscript_parser::{};", names).unwrap();
+    }
+    let runtime = ["spells", "RuntimeAdapter", "RuntimeError", "RuntimeValue"];
+    writeln!(
+        module,
+        "use potterscript_runtime::{};\n",
+        imports(&runtime).unwrap()
+    )
+    .unwrap();
+    for helper in helpers {
+        module += helper;
+        module += "\n\n";
+    }
+
+    module += "/// Runs the program, going through `adapter` for logs, random picks, sleeps\n";
+    module += "/// and input.\n";
+    module += "#[allow(\n    unused_mut,\n    unused_assignments,\n    unused_variables,\n    unused_labels,\n    unreachable_code,\n    non_snake_case,\n    clippy::all\n)]\n";
+    module += "pub fn run(adapter: &impl RuntimeAdapter) -> Result<(), RuntimeError> {\n";
+    if transpiler.lumos {
+        module += "    let mut lumos = false;\n";
+    }
+    if transpiler.quidditch {
+        module += "    // Cleared by `snitch` to end the loops running\n";
+        module += "    let mut quidditch = false;\n";
+    }
+    for name in &transpiler.variables {
+        let name = rust_name(name);
+        writeln!(module, "    let mut {}: Option<RuntimeValue> = None;", name).unwrap();
+    }
+    for name in &transpiler.variables {
+        if transpiler.petrified.contains(name) {
+            writeln!(module, "    let mut {}_constant = false;", rust_name(name)).unwrap();
+        }
+    }
+    if transpiler.lumos || transpiler.quidditch || !transpiler.variables.is_empty() {
+        module += "\n";
+    }
+    module += &transpiler.body;
+    module += "    Ok(())\n}\n";
+    module
+}
+
+#[derive(Default)]
+struct Transpiler<'p> {
+    /// Every variable, in the order they first appear.
+    variables: Vec<&'p str>,
+    /// Variables the program may make constant, which have a `_constant`
+    /// flag.
+    petrified: HashSet<&'p str>,
+    /// Helpers the program calls.
+    called: HashSet<&'static str>,
+    imports: BTreeSet<&'static str>,
+    /// Whether the program casts `~Lumos`, `~Nox` or `~Revelio`.
+    lumos: bool,
+    /// Whether a loop uses the `quidditch` flag.
+    quidditch: bool,
+    /// Label of the innermost `protego` block being generated, which errors
+    /// break out of instead of returning.
+    protego: Option<String>,
+    /// Counts the labels of loops and `protego` blocks.
+    labels: usize,
+    /// Number of the label of the innermost loop a `snitch` breaks out of.
+    loop_label: usize,
+    body: String,
+    indent: usize,
+}
+
+impl<'p> Transpiler<'p> {
+    fn line(&mut self, line: &str) {
+        self.body += &"    ".repeat(self.indent);
+        self.body += line;
+        self.body += "\n";
+    }
+
+    /// `code` as a statement, which needs a `;` unless it ends with a block.
+    fn statement_line(&mut self, code: &str) {
+        if code.ends_with('}') {
+            self.line(code);
+        } else {
+            self.line(&format!("{};", code));
+        }
+    }
+
+    fn call(&mut self, helper: &'static str) -> &'static str {
+        self.called.insert(helper);
+        helper
+    }
+
+    fn import(&mut self, name: &'static str) -> &'static str {
+        self.imports.insert(name);
+        name
+    }
+
+    fn variable(&mut self, name: &'p str) -> String {
+        if !self.variables.contains(&name) {
+            self.variables.push(name);
+        }
+        rust_name(name)
+    }
+
+    fn label(&mut self, kind: &str) -> String {
+        self.labels += 1;
+        format!("'{}_{}", kind, self.labels)
+    }
+
+    /// `result` with `?`, breaking out of the `protego` block it is in if
+    /// any.
+    fn fallible(&mut self, result: String) -> String {
+        match self.protego.clone() {
+            Some(label) => format!("{}!({}, {})", self.call("protego"), label, result),
+            None => format!("{}?", result),
+        }
+    }
+
+    /// An expression failing with `error`.
+    fn raise(&self, error: String) -> String {
+        match &self.protego {
+            Some(label) => format!("break {} Err({})", label, error),
+            None => format!("return Err({})", error),
+        }
+    }
+
+    fn block(&mut self, block: &'p [Statement], in_loop: Loop) {
+        for statement in block {
+            self.statement(statement, in_loop);
+        }
+    }
+
+    fn nested_block(&mut self, block: &'p [Statement], in_loop: Loop) {
+        self.indent += 1;
+        self.block(block, in_loop);
+        self.indent -= 1;
+    }
+
+    fn statement(&mut self, statement: &'p Statement, in_loop: Loop) {
+        match statement {
+            Statement::VariableAssignment(name, value) => {
+                let rust_name = self.variable(name);
+                if self.petrified.contains(name.as_str()) {
+                    let error =
+                        format!("RuntimeError::ConstantReassignment({:?}.to_string())", name);
+                    let check = format!("if {}_constant {{ {}; }}", rust_name, self.raise(error));
+                    self.line(&check);
+                }
+                match self.cast(value) {
+                    Cast::Value(value) => self.line(&format!("{} = Some({});", rust_name, value)),
+                    Cast::Never(value) => self.statement_line(&value),
+                    Cast::Nothing(effect) => {
+                        if let Some(effect) = effect {
+                            self.statement_line(&effect);
+                        }
+                        let error = format!("RuntimeError::NoneAssignment({:?}.to_string())", name);
+                        let raise = self.raise(error);
+                        self.statement_line(&raise);
+                    }
+                }
+            }
+            Statement::ExpressionStatement(Expression::Comment(comment)) => {
+                self.line(&format!("//{}", comment));
+            }
+            Statement::ExpressionStatement(expression) => match self.cast(expression) {
+                Cast::Value(code) => self.line(&format!("let _ = {};", code)),
+                Cast::Never(code) | Cast::Nothing(Some(code)) => self.statement_line(&code),
+                Cast::Nothing(None) => {}
+            },
+            Statement::If(condition, true_block, else_block) => match self.cast(condition) {
+                // Only `true` runs the first block
+                Cast::Value(condition) => {
+                    self.line(&format!(
+                        "if {} == RuntimeValue::Boolean(true) {{",
+                        condition
+                    ));
+                    self.nested_block(true_block, in_loop);
+                    if !else_block.is_empty() {
+                        self.line("} else {");
+                        self.nested_block(else_block, in_loop);
+                    }
+                    self.line("}");
+                }
+                Cast::Never(condition) => self.statement_line(&condition),
+                Cast::Nothing(effect) => {
+                    if let Some(effect) = effect {
+                        self.statement_line(&effect);
+                    }
+                    self.block(else_block, in_loop);
+                }
+            },
+            Statement::Quidditch(block) => {
+                match in_loop.nested(block) {
+                    Loop::Flag => {
+                        self.quidditch = true;
+                        self.line("quidditch = true;");
+                        self.line("while quidditch {");
+                        self.nested_block(block, Loop::Flag);
+                    }
+                    nested => {
+                        let label = self.label("quidditch");
+                        self.line(&format!("{}: loop {{", label));
+                        let outer = mem::replace(&mut self.loop_label, self.labels);
+                        self.nested_block(block, nested);
+                        self.loop_label = outer;
+                    }
+                }
+                self.line("}");
+            }
+            Statement::Snitch => match in_loop {
+                Loop::None => {}
+                Loop::Break => {
+                    let label = self.loop_label;
+                    self.line(&format!("break 'quidditch_{};", label));
+                }
+                Loop::Flag => self.line("quidditch = false;"),
+            },
+            Statement::Vow(_, _) => {}
+            Statement::Protego(block, error_name, catch_block) => {
+                let label = self.label("protego");
+                // The loop around only sees the flag a failed loop left
+                // when it runs on the flag
+                let saved_flag = in_loop == Loop::Flag && has_loop(block);
+                if saved_flag {
+                    self.line("let quidditch_before = quidditch;");
+                }
+                self.line(&format!(
+                    "let result: Result<(), RuntimeError> = {}: {{",
+                    label
+                ));
+                let outer = self.protego.replace(label);
+                self.nested_block(block, in_loop);
+                self.protego = outer;
+                self.line("    Ok(())");
+                self.line("};");
+
+                self.line("if let Err(error) = result {");
+                self.indent += 1;
+                let raise = self.raise("error".to_string());
+                self.line(&format!("if !error.is_catchable() {{ {}; }}", raise));
+                if saved_flag {
+                    self.line("quidditch = quidditch_before;");
+                }
+                let rust_name = self.variable(error_name);
+                if self.petrified.contains(error_name.as_str()) {
+                    let error = format!(
+                        "RuntimeError::ConstantReassignment({:?}.to_string())",
+                        error_name
+                    );
+                    let check = format!("if {}_constant {{ {}; }}", rust_name, self.raise(error));
+                    self.line(&check);
+                }
+                self.line(&format!("{} = Some(error.to_value());", rust_name));
+                self.indent -= 1;
+                self.nested_block(catch_block, in_loop);
+                self.line("}");
+            }
+        }
+    }
+
+    /// Rust for `expression` as an `Option<RuntimeValue>`, like the
+    /// interpreter evaluates it.
+    fn option(&mut self, expression: &'p Expression) -> String {
+        match self.cast(expression) {
+            Cast::Value(code) => format!("Some({})", code),
+            Cast::Never(code) => code,
+            Cast::Nothing(Some(code)) => format!("{{ {}; None }}", code),
+            Cast::Nothing(None) => "None".to_string(),
+        }
+    }
+
+    fn cast(&mut self, expression: &'p Expression) -> Cast {
+        match expression {
+            Expression::SpellCast(spell, target) => self.spell(spell, target.as_ref().as_ref()),
+            Expression::BinaryOperation(operation, left, right) => {
+                match (self.cast(left), self.cast(right)) {
+                    (Cast::Never(left), _) => Cast::Never(left),
+                    (Cast::Value(left), Cast::Value(right)) => {
+                        let operation = self.operation(*operation);
+                        let result = format!("{}.binary_operation({}, {})", left, operation, right);
+                        Cast::Value(self.fallible(result))
+                    }
+                    (Cast::Value(left), Cast::Never(right)) => {
+                        Cast::Never(format!("{{ let _ = {}; {} }}", left, right))
+                    }
+                    // An operation on nothing is nothing
+                    (left, right) => {
+                        let effects: Vec<_> =
+                            [left, right].into_iter().filter_map(effect).collect();
+                        Cast::Nothing(Some(format!("{{ {}; }}", effects.join("; "))))
+                    }
+                }
+            }
+            Expression::Atom(Atom::Variable(name)) => {
+                let rust_name = self.variable(name);
+                let get = format!("{}(&{}, {:?})", self.call("get"), rust_name, name);
+                Cast::Value(self.fallible(get))
+            }
+            Expression::Atom(atom) => Cast::Value(self.value(atom)),
+            Expression::Field(expression, name) => match self.cast(expression) {
+                Cast::Value(value) => {
+                    let field = format!("{}({}, {:?})", self.call("field"), value, name);
+                    Cast::Value(self.fallible(field))
+                }
+                nothing => nothing,
+            },
+            Expression::Comment(_) => Cast::Nothing(None),
+            Expression::SortingHat => {
+                self.import("HogwartsHouse");
+                Cast::Value(
+                    format!(
+                        "RuntimeValue::HogwartsHouse(HogwartsHouse::ALL[adapter.random_integer(0..={}) as usize])",
+                        HogwartsHouse::ALL.len() - 1
+                    ),
+                )
+            }
+        }
+    }
+
+    fn spell(&mut self, spell: &'p Spell, target: Option<&'p Expression>) -> Cast {
+        let variable = target_variable(target);
+        let argument = match target {
+            // Cast on their own below
+            Some(_) if matches!(spell, Spell::Custom(_)) => String::new(),
+            Some(Expression::BinaryOperation(_, _, _)) if *spell == Spell::UnbreakableVow => {
+                String::new()
+            }
+            Some(target) if evaluates_target(spell) => self.option(target),
+            _ => "None".to_string(),
+        };
+
+        match spell {
+            Spell::Custom(name) => {
+                let error = format!("RuntimeError::UnknownSpell({:?}.to_string())", name);
+                let raise = self.raise(error);
+                match target.map(|target| self.cast(target)) {
+                    Some(Cast::Never(target)) => Cast::Never(target),
+                    Some(target) => match effect(target) {
+                        Some(effect) => Cast::Never(format!("{{ {}; {} }}", effect, raise)),
+                        None => Cast::Never(raise),
+                    },
+                    None => Cast::Never(raise),
+                }
+            }
+            Spell::Accio => {
+                self.import("spells");
+                Cast::Value(format!("spells::accio(adapter, {})", argument))
+            }
+            Spell::UnbreakableVow => Cast::Nothing(Some(match target {
+                Some(Expression::BinaryOperation(operation, left, right)) => {
+                    let expression = self.expression(target.unwrap());
+                    let operation = self.operation(*operation);
+                    let left = self.option(left);
+                    let right = self.option(right);
+                    let vow = format!(
+                        "{}(&{}, {}, {}, {})",
+                        self.call("unbreakable_vow_operation"),
+                        expression,
+                        operation,
+                        left,
+                        right
+                    );
+                    self.fallible(vow)
+                }
+                Some(target) => {
+                    let expression = self.expression(target);
+                    let vow = format!(
+                        "{}(&{}, {})",
+                        self.call("unbreakable_vow"),
+                        expression,
+                        argument
+                    );
+                    self.fallible(vow)
+                }
+                None => {
+                    let spell = self.spell_value(spell);
+                    self.raise(format!("RuntimeError::MissingSpellTarget({})", spell))
+                }
+            })),
+            Spell::FelixFelicis => {
+                self.import("spells");
+                let random = format!("spells::felix_felicis(adapter, {})", argument);
+                Cast::Value(self.fallible(random))
+            }
+            Spell::AvadaKedabra => Cast::Never("return Ok(())".to_string()),
+            Spell::Confringo => {
+                self.import("spells");
+                Cast::Never(self.raise(format!("spells::confringo({})", argument)))
+            }
+            Spell::Inmobolus => {
+                self.import("spells");
+                let sleep = format!("spells::inmobolus(adapter, {})", argument);
+                Cast::Nothing(Some(sleep))
+            }
+            Spell::Incendio | Spell::WingardiumLeviosa => {
+                let suffix = if *spell == Spell::Incendio {
+                    "🔥"
+                } else {
+                    "\n"
+                };
+                match (variable, target) {
+                    (Some(name), _) => {
+                        self.import("spells");
+                        let spell = self.spell_value(spell);
+                        let append =
+                            format!("|value| spells::append({}, value, {:?})", spell, suffix);
+                        self.change(name, &append)
+                    }
+                    (None, Some(Expression::Atom(Atom::String(string)))) => Cast::Value(format!(
+                        "RuntimeValue::from({:?})",
+                        format!("{}{}", string, suffix)
+                    )),
+                    (None, _) => Cast::Nothing(None),
+                }
+            }
+            Spell::Aguamenti => Cast::Value("RuntimeValue::from(\"💦\")".to_string()),
+            Spell::OculusReparo => Cast::Value("RuntimeValue::from(\"👓\")".to_string()),
+            Spell::Serpensortia => Cast::Value("RuntimeValue::from(\"🐍\")".to_string()),
+            Spell::Periculum => {
+                Cast::Nothing(Some("adapter.log(\"🔥🔥🔥🔥🔥🔥🔥🔥🔥\")".to_string()))
+            }
+            Spell::Lumos | Spell::Nox => {
+                self.lumos = true;
+                Cast::Nothing(Some(format!("lumos = {}", *spell == Spell::Lumos)))
+            }
+            Spell::Engorgio | Spell::Reducio => match variable {
+                Some(name) => {
+                    self.import("spells");
+                    let function = if *spell == Spell::Engorgio {
+                        "spells::engorgio"
+                    } else {
+                        "spells::reducio"
+                    };
+                    self.change(name, function)
+                }
+                None => Cast::Nothing(None),
+            },
+            Spell::Obliviate => Cast::Nothing(variable.map(|name| {
+                let rust_name = self.variable(name);
+                if self.petrified.contains(name) {
+                    // Constants can't be forgotten
+                    format!("if !{0}_constant {{ {0} = None }}", rust_name)
+                } else {
+                    format!("{} = None", rust_name)
+                }
+            })),
+            Spell::Revelio => match target {
+                Some(_) => {
+                    self.lumos = true;
+                    let revelio = self.call("revelio");
+                    Cast::Nothing(Some(format!("{}(adapter, lumos, {})", revelio, argument)))
+                }
+                None => Cast::Nothing(None),
+            },
+            Spell::PetrificusTotalus => Cast::Nothing(variable.map(|name| {
+                let rust_name = self.variable(name);
+                format!("if {0}.is_some() {{ {0}_constant = true }}", rust_name)
+            })),
+        }
+    }
+
+    /// A spell changing the value of a variable with `function`.
+    fn change(&mut self, name: &'p str, function: &str) -> Cast {
+        let rust_name = self.variable(name);
+        let mut value = format!(
+            "{}(&{}, {:?}).and_then({})",
+            self.call("get"),
+            rust_name,
+            name,
+            function
+        );
+        if self.petrified.contains(name) {
+            let reassign = self.call("reassign");
+            write!(
+                value,
+                ".and_then(|value| {}({:?}, {}_constant, value))",
+                reassign, name, rust_name
+            )
+            .unwrap();
+        }
+        let value = self.fallible(value);
+        Cast::Nothing(Some(format!("{} = Some({})", rust_name, value)))
+    }
+
+    fn value(&mut self, atom: &Atom) -> String {
+        match atom {
+            Atom::String(value) => format!("RuntimeValue::from({:?})", value),
+            Atom::Boolean(value) => format!("RuntimeValue::Boolean({})", value),
+            Atom::Integer(i64::MIN) => "RuntimeValue::Integer(i64::MIN)".to_string(),
+            Atom::Integer(value) => format!("RuntimeValue::Integer({})", value),
+            Atom::Double(value) => format!("RuntimeValue::Double({})", double(*value)),
+            Atom::HogwartsHouse(house) => {
+                self.import("HogwartsHouse");
+                format!("RuntimeValue::HogwartsHouse(HogwartsHouse::{})", house)
+            }
+            Atom::Variable(_) => unreachable!(),
+        }
+    }
+
+    fn operation(&mut self, operation: BinaryOperation) -> String {
+        format!("{}::{:?}", self.import("BinaryOperation"), operation)
+    }
+
+    fn spell_value(&mut self, spell: &Spell) -> String {
+        self.import("Spell");
+        match spell {
+            Spell::Custom(name) => format!("Spell::Custom({:?}.to_string())", name),
+            spell => format!("Spell::{:?}", spell),
+        }
+    }
+
+    /// Rust building `expression`, for the message of a broken vow.
+    fn expression(&mut self, expression: &Expression) -> String {
+        self.import("Expression");
+        match expression {
+            Expression::SpellCast(spell, target) => {
+                let spell = self.spell_value(spell);
+                let target = match target.as_ref() {
+                    Some(target) => format!("Some({})", self.expression(target)),
+                    None => "None".to_string(),
+                };
+                format!("Expression::SpellCast({}, Box::new({}))", spell, target)
+            }
+            Expression::BinaryOperation(operation, left, right) => {
+                let operation = self.operation(*operation);
+                let left = self.expression(left);
+                let right = self.expression(right);
+                format!(
+                    "Expression::BinaryOperation({}, Box::new({}), Box::new({}))",
+                    operation, left, right
+                )
+            }
+            Expression::Atom(atom) => {
+                self.import("Atom");
+                let atom = match atom {
+                    Atom::String(value) => format!("Atom::String({:?}.to_string())", value),
+                    Atom::Variable(name) => format!("Atom::Variable({:?}.to_string())", name),
+                    Atom::Boolean(value) => format!("Atom::Boolean({})", value),
+                    Atom::Integer(i64::MIN) => "Atom::Integer(i64::MIN)".to_string(),
+                    Atom::Integer(value) => format!("Atom::Integer({})", value),
+                    Atom::Double(value) => format!("Atom::Double({})", double(*value)),
+                    Atom::HogwartsHouse(house) => {
+                        self.import("HogwartsHouse");
+                        format!("Atom::HogwartsHouse(HogwartsHouse::{})", house)
+                    }
+                };
+                format!("Expression::Atom({})", atom)
+            }
+            Expression::Field(expression, name) => format!(
+                "Expression::Field(Box::new({}), {:?}.to_string())",
+                self.expression(expression),
+                name
+            ),
+            Expression::Comment(comment) => {
+                format!("Expression::Comment({:?}.to_string())", comment)
+            }
+            Expression::SortingHat => "Expression::SortingHat".to_string(),
+        }
+    }
+}
+
+/// A statement evaluating a cast only for its effect, if it has any.
+fn effect(cast: Cast) -> Option<String> {
+    match cast {
+        Cast::Value(code) => Some(format!("let _ = {}", code)),
+        Cast::Never(code) => Some(code),
+        Cast::Nothing(effect) => effect,
+    }
+}
+
+fn rust_name(name: &str) -> String {
+    let taken = RESERVED.contains(&name) || HELPERS.iter().any(|helper| helper.name == name);
+    variable_name(name, taken)
+}
+
+fn double(value: f64) -> String {
+    if value.is_nan() {
+        "f64::NAN".to_string()
+    } else if value.is_infinite() {
+        if value > 0.0 {
+            "f64::INFINITY"
+        } else {
+            "f64::NEG_INFINITY"
+        }
+        .to_string()
+    } else {
+        // Always has a `.` or an exponent, which makes it an `f64` literal
+        format!("{:?}", value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn generate(code: &str) -> String {
+        transpile(&potterscript_parser::parse(code).unwrap())
+    }
+
+    #[test]
+    fn test_snitch_becomes_break() {
+        let script = generate("i = 0\nquidditch {\n~Engorgio i\nif i == 3 {\nsnitch # done\n}\n}");
+        assert!(script.contains("'quidditch_1: loop {"), "{}", script);
+        assert!(
+            script.contains("break 'quidditch_1;\n            // done"),
+            "{}",
+            script
+        );
+        assert!(!script.contains("quidditch = "), "{}", script);
+
+        // The rest of the iteration still runs
+        let script = generate("quidditch {\nsnitch\n~Revelio 1\n}");
+        assert!(script.contains("quidditch = false;\n"), "{}", script);
+        assert!(script.contains("while quidditch {"), "{}", script);
+    }
+
+    #[test]
+    fn test_reserved_names() {
+        let script = generate("loop = 1\nget = loop + 1\n~Revelio get");
+        assert!(
+            script.contains("let mut loop_: Option<RuntimeValue> = None;"),
+            "{}",
+            script
+        );
+        assert!(
+            script.contains("get_ = Some(get(&loop_, \"loop\")?"),
+            "{}",
+            script
+        );
+        assert!(
+            script.contains("revelio(adapter, lumos, Some(get(&get_, \"get\")?));"),
+            "{}",
+            script
+        );
+    }
+
+    #[test]
+    fn test_only_what_is_used() {
+        let script = generate("~Revelio 1");
+        assert!(script
+            .contains("use potterscript_runtime::{RuntimeAdapter, RuntimeError, RuntimeValue};"));
+        assert!(!script.contains("potterscript_parser"), "{}", script);
+        assert!(!script.contains("fn get("), "{}", script);
+        assert!(!script.contains("quidditch"), "{}", script);
+
+        let script = generate("protego {\n~Revelio x\n} expelliarmus e {\n~Revelio e\n}");
+        assert!(script.contains("macro_rules! protego"), "{}", script);
+        assert!(
+            script.contains("protego!('protego_1, get(&x, \"x\"))"),
+            "{}",
+            script
+        );
+    }
+}
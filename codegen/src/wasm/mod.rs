@@ -0,0 +1,1331 @@
+//! Compiles a [`Program`] to a standalone WebAssembly module, so scripts run
+//! in any WebAssembly engine given a few host functions.
+//!
+//! Every variable holds values of a single type, found before compiling, and
+//! values are plain WebAssembly values: integers are `i64`, doubles `f64`,
+//! booleans and houses (by their index in [`HogwartsHouse::ALL`]) `i32`, and
+//! strings a pointer and a length into the memory of the module, where they
+//! are never freed. Programs where a variable changes type or an operation
+//! always fails on the types it is given are rejected with a
+//! [`CompileError`], as are `protego`, fields, `~Accio` and
+//! `~UnbreakableVow`. Everything else runs with the same semantics and error
+//! messages as the interpreter.
+//!
+//! The module imports from `env`:
+//!
+//! - `log(ptr: i32, len: i32, lumos: i32)`, called with the UTF-8 string to
+//!   log and whether `~Lumos` is on.
+//! - `random_integer(min: i64, max: i64) -> i64`, in `min..=max`.
+//! - `random_double() -> f64`, in `0.0..1.0`.
+//! - `sleep(ms: i64)`, for `~Inmobolus`.
+//! - `format_double(value: f64, debug: i32, ptr: i32) -> i32`, which writes
+//!   the double the way Rust displays it at `ptr`, or debug-formats it if
+//!   `debug` is 1, in at most [`DOUBLE_CAPACITY`] bytes, and returns its
+//!   length.
+//! - `error(ptr: i32, len: i32)`, called with the message of the error that
+//!   stops the program.
+//!
+//! It exports its `memory` and `run() -> i32`, which runs the program and
+//! returns 1 if it failed, or 0.
+//!
+//! ```
+//! let program = potterscript_parser::parse("~Revelio \"Hello, world!\"").unwrap();
+//! let module = potterscript_codegen::wasm::compile(&program).unwrap();
+//! assert_eq!(b"\0asm", &module[..4]);
+//! ```
+
+mod types;
+
+use std::collections::HashMap;
+use std::{error, fmt};
+
+use potterscript_parser::{Atom, BinaryOperation, Expression, HogwartsHouse, Program};
+use potterscript_parser::{Spell, Statement};
+use potterscript_runtime::{RuntimeError, RuntimeValue, ValueType};
+use wasm_encoder::{
+    BlockType, CodeSection, ConstExpr, DataSection, EntityType, ExportKind, ExportSection,
+    Function, FunctionSection, GlobalSection, GlobalType, ImportSection, Instruction, MemArg,
+    MemorySection, MemoryType, Module, TypeSection, ValType,
+};
+
+use self::types::{Type, Variables};
+
+/// Room `format_double` has to write a double in.
+pub const DOUBLE_CAPACITY: usize = 512;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum CompileError {
+    /// Something the backend cannot compile, e.g. `protego`.
+    Unsupported(&'static str),
+    /// A variable assigned values of different types.
+    TypeChange(String, ValueType, ValueType),
+    InvalidOperation(BinaryOperation, ValueType, ValueType),
+    InvalidSpellTarget(Spell, ValueType),
+}
+
+impl fmt::Display for CompileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CompileError::Unsupported(what) => write!(f, "{} is not supported", what),
+            CompileError::TypeChange(name, from, to) => {
+                write!(f, "variable {} changes type from {} to {}", name, from, to)
+            }
+            CompileError::InvalidOperation(operation, left, right) => {
+                write!(f, "cannot apply {} to {} and {}", operation, left, right)
+            }
+            CompileError::InvalidSpellTarget(spell, value_type) => {
+                write!(f, "cannot cast ~{} on {}", spell, value_type)
+            }
+        }
+    }
+}
+
+impl error::Error for CompileError {}
+
+/// Compiles `program` to the bytes of a WebAssembly module.
+pub fn compile(program: &Program) -> Result<Vec<u8>, CompileError> {
+    let mut compiler = Compiler::new(types::infer(program)?);
+    compiler.block(&program.0)?;
+    compiler.emit(Instruction::I32Const(0));
+    Ok(compiler.finish())
+}
+
+// Imported functions, then the ones the module defines
+const LOG: u32 = 0;
+const RANDOM_INTEGER: u32 = 1;
+const RANDOM_DOUBLE: u32 = 2;
+const SLEEP: u32 = 3;
+const FORMAT_DOUBLE: u32 = 4;
+const ERROR: u32 = 5;
+const ALLOC: u32 = 6;
+const CONCAT: u32 = 7;
+const STRING_EQ: u32 = 8;
+const INTEGER_TO_STRING: u32 = 9;
+const CHANGE_CASE: u32 = 10;
+const DOUBLE_TO_STRING: u32 = 11;
+const RUN: u32 = 12;
+
+/// The global with the start of the free memory.
+const HEAP: u32 = 0;
+/// Where the pointer and length of the name of every house start.
+const HOUSE_NAMES: u64 = 0;
+
+// States of a variable
+const UNBOUND: i32 = 0;
+const BOUND: i32 = 1;
+const CONSTANT: i32 = 2;
+
+#[derive(Clone, Copy)]
+struct Variable {
+    /// Local with the state of the variable, [`UNBOUND`], [`BOUND`] or
+    /// [`CONSTANT`].
+    state: u32,
+    /// First local with the value, strings take two, and its type. Variables
+    /// never assigned a value have none.
+    value: Option<(u32, ValueType)>,
+}
+
+struct Compiler<'p> {
+    types: Variables<'p>,
+    variables: HashMap<&'p str, Variable>,
+    /// Locals of `run`.
+    locals: Vec<ValType>,
+    /// Code of `run`.
+    code: Vec<Instruction<'static>>,
+    /// The data segment, at the start of the memory.
+    data: Vec<u8>,
+    strings: HashMap<String, i32>,
+    /// Local set while `~Lumos` is on.
+    lumos: u32,
+    /// Local cleared by `snitch`, shared by every `quidditch` loop like in
+    /// the interpreter.
+    quidditch: u32,
+}
+
+impl<'p> Compiler<'p> {
+    fn new(types: Variables<'p>) -> Self {
+        let mut compiler = Self {
+            types,
+            variables: HashMap::new(),
+            locals: vec![ValType::I32, ValType::I32],
+            code: vec![],
+            data: vec![0; HogwartsHouse::ALL.len() * 8],
+            strings: HashMap::new(),
+            lumos: 0,
+            quidditch: 1,
+        };
+        for (index, house) in HogwartsHouse::ALL.iter().enumerate() {
+            let name = house.to_string();
+            let ptr = compiler.intern(&name);
+            let entry = HOUSE_NAMES as usize + index * 8;
+            compiler.data[entry..entry + 4].copy_from_slice(&ptr.to_le_bytes());
+            compiler.data[entry + 4..entry + 8].copy_from_slice(&(name.len() as i32).to_le_bytes());
+        }
+        compiler
+    }
+
+    fn emit(&mut self, instruction: Instruction<'static>) {
+        self.code.push(instruction);
+    }
+
+    fn local(&mut self, value_type: ValType) -> u32 {
+        self.locals.push(value_type);
+        self.locals.len() as u32 - 1
+    }
+
+    /// Adds `string` to the data segment, once, returning where it is.
+    fn intern(&mut self, string: &str) -> i32 {
+        if let Some(&ptr) = self.strings.get(string) {
+            return ptr;
+        }
+        let ptr = self.data.len() as i32;
+        self.data.extend_from_slice(string.as_bytes());
+        self.strings.insert(string.to_string(), ptr);
+        ptr
+    }
+
+    /// Pushes the pointer and length of a string literal.
+    fn string(&mut self, string: &str) {
+        let ptr = self.intern(string);
+        self.emit(Instruction::I32Const(ptr));
+        self.emit(Instruction::I32Const(string.len() as i32));
+    }
+
+    /// Stops the program with the message on the stack.
+    fn raise(&mut self) {
+        self.emit(Instruction::Call(ERROR));
+        self.emit(Instruction::I32Const(1));
+        self.emit(Instruction::Return);
+    }
+
+    fn fail(&mut self, error: RuntimeError) {
+        self.string(&error.to_string());
+        self.raise();
+    }
+
+    /// Fails with `error` if the condition on the stack is true.
+    fn fail_if(&mut self, error: RuntimeError) {
+        self.emit(Instruction::If(BlockType::Empty));
+        self.fail(error);
+        self.emit(Instruction::End);
+    }
+
+    /// Drops a value of type `value_type` from the stack.
+    fn drop(&mut self, value_type: Type) {
+        match value_type {
+            Type::Value(ValueType::String) => {
+                self.emit(Instruction::Drop);
+                self.emit(Instruction::Drop);
+            }
+            Type::Value(_) => self.emit(Instruction::Drop),
+            Type::Nothing | Type::Never => {}
+        }
+    }
+
+    fn variable(&mut self, name: &'p str) -> Variable {
+        if let Some(&variable) = self.variables.get(name) {
+            return variable;
+        }
+
+        let state = self.local(ValType::I32);
+        let value_type = self.types.get(name).copied();
+        let value = value_type.map(|value_type| {
+            let local = match value_type {
+                ValueType::Integer => self.local(ValType::I64),
+                ValueType::Double => self.local(ValType::F64),
+                ValueType::String => {
+                    let ptr = self.local(ValType::I32);
+                    self.local(ValType::I32);
+                    ptr
+                }
+                _ => self.local(ValType::I32),
+            };
+            (local, value_type)
+        });
+        let variable = Variable { state, value };
+        self.variables.insert(name, variable);
+        variable
+    }
+
+    /// Pushes the value of the variable called `name`, failing if it is
+    /// unbound.
+    fn read(&mut self, name: &'p str) -> Type {
+        let variable = self.variable(name);
+        let not_found = RuntimeError::VariableNotFound(name.to_string());
+        let Some((local, value_type)) = variable.value else {
+            self.fail(not_found);
+            return Type::Never;
+        };
+
+        self.emit(Instruction::LocalGet(variable.state));
+        self.emit(Instruction::I32Eqz);
+        self.fail_if(not_found);
+        self.emit(Instruction::LocalGet(local));
+        if value_type == ValueType::String {
+            self.emit(Instruction::LocalGet(local + 1));
+        }
+        Type::Value(value_type)
+    }
+
+    fn check_not_constant(&mut self, name: &'p str) {
+        let variable = self.variable(name);
+        self.emit(Instruction::LocalGet(variable.state));
+        self.emit(Instruction::I32Const(CONSTANT));
+        self.emit(Instruction::I32Eq);
+        self.fail_if(RuntimeError::ConstantReassignment(name.to_string()));
+    }
+
+    /// Assigns the value on the stack to the variable called `name`.
+    fn assign(&mut self, name: &'p str) {
+        self.check_not_constant(name);
+        let variable = self.variable(name);
+        let (local, value_type) = variable
+            .value
+            .expect("variables assigned a value have a type");
+        if value_type == ValueType::String {
+            self.emit(Instruction::LocalSet(local + 1));
+        }
+        self.emit(Instruction::LocalSet(local));
+        self.emit(Instruction::I32Const(BOUND));
+        self.emit(Instruction::LocalSet(variable.state));
+    }
+
+    fn block(&mut self, block: &'p [Statement]) -> Result<(), CompileError> {
+        block
+            .iter()
+            .try_for_each(|statement| self.statement(statement))
+    }
+
+    fn statement(&mut self, statement: &'p Statement) -> Result<(), CompileError> {
+        match statement {
+            Statement::VariableAssignment(name, value) => {
+                self.check_not_constant(name);
+                match self.expression(value)? {
+                    Type::Value(_) => self.assign(name),
+                    Type::Nothing => self.fail(RuntimeError::NoneAssignment(name.clone())),
+                    Type::Never => {}
+                }
+            }
+            Statement::ExpressionStatement(expression) => {
+                let value_type = self.expression(expression)?;
+                self.drop(value_type);
+            }
+            Statement::If(condition, true_block, else_block) => {
+                match self.expression(condition)? {
+                    Type::Value(ValueType::Boolean) => {
+                        self.emit(Instruction::If(BlockType::Empty));
+                        self.block(true_block)?;
+                        self.emit(Instruction::Else);
+                        self.block(else_block)?;
+                        self.emit(Instruction::End);
+                    }
+                    // Anything but `true` runs the else block
+                    value_type => {
+                        self.drop(value_type);
+                        self.block(else_block)?;
+                    }
+                }
+            }
+            Statement::Quidditch(block) => {
+                self.emit(Instruction::I32Const(1));
+                self.emit(Instruction::LocalSet(self.quidditch));
+                self.emit(Instruction::Loop(BlockType::Empty));
+                self.block(block)?;
+                self.emit(Instruction::LocalGet(self.quidditch));
+                self.emit(Instruction::BrIf(0));
+                self.emit(Instruction::End);
+            }
+            Statement::Snitch => {
+                self.emit(Instruction::I32Const(0));
+                self.emit(Instruction::LocalSet(self.quidditch));
+            }
+            Statement::Vow(_, _) => {}
+            Statement::Protego(_, _, _) => return Err(CompileError::Unsupported("protego")),
+        }
+        Ok(())
+    }
+
+    fn expression(&mut self, expression: &'p Expression) -> Result<Type, CompileError> {
+        match expression {
+            Expression::SpellCast(spell, target) => self.spell(spell, target.as_ref().as_ref()),
+            Expression::BinaryOperation(operation, left, right) => {
+                self.binary_operation(*operation, left, right)
+            }
+            Expression::Atom(Atom::Variable(name)) => Ok(self.read(name)),
+            Expression::Atom(atom) => {
+                match atom {
+                    Atom::String(string) => self.string(string),
+                    Atom::Integer(integer) => self.emit(Instruction::I64Const(*integer)),
+                    Atom::Double(double) => self.emit(Instruction::F64Const(*double)),
+                    Atom::Boolean(boolean) => self.emit(Instruction::I32Const(*boolean as i32)),
+                    Atom::HogwartsHouse(house) => {
+                        let index = HogwartsHouse::ALL.iter().position(|h| h == house);
+                        self.emit(Instruction::I32Const(index.unwrap() as i32));
+                    }
+                    Atom::Variable(_) => unreachable!(),
+                }
+                Ok(types::atom_type(atom, &self.types))
+            }
+            Expression::Field(_, _) => Err(CompileError::Unsupported("field access")),
+            Expression::Comment(_) => Ok(Type::Nothing),
+            Expression::SortingHat => {
+                self.emit(Instruction::I64Const(0));
+                self.emit(Instruction::I64Const(HogwartsHouse::ALL.len() as i64 - 1));
+                self.emit(Instruction::Call(RANDOM_INTEGER));
+                self.emit(Instruction::I32WrapI64);
+                Ok(Type::Value(ValueType::HogwartsHouse))
+            }
+        }
+    }
+
+    fn binary_operation(
+        &mut self,
+        operation: BinaryOperation,
+        left: &'p Expression,
+        right: &'p Expression,
+    ) -> Result<Type, CompileError> {
+        let left = self.expression(left)?;
+        if left == Type::Never {
+            return Ok(Type::Never);
+        }
+        let right = self.expression(right)?;
+        let result = types::binary_type(operation, left, right)?;
+        let (Type::Value(left), Type::Value(right)) = (left, right) else {
+            self.drop(right);
+            self.drop(left);
+            return Ok(result);
+        };
+
+        let not = operation == BinaryOperation::NotEqual;
+        match (operation, left) {
+            // Values of different types are never equal
+            (BinaryOperation::Equal | BinaryOperation::NotEqual, _) if left != right => {
+                self.drop(Type::Value(right));
+                self.drop(Type::Value(left));
+                self.emit(Instruction::I32Const(not as i32));
+            }
+            (BinaryOperation::Equal | BinaryOperation::NotEqual, ValueType::String) => {
+                self.emit(Instruction::Call(STRING_EQ));
+                if not {
+                    self.emit(Instruction::I32Eqz);
+                }
+            }
+            (BinaryOperation::Equal, ValueType::Integer) => self.emit(Instruction::I64Eq),
+            (BinaryOperation::NotEqual, ValueType::Integer) => self.emit(Instruction::I64Ne),
+            (BinaryOperation::Equal, ValueType::Double) => self.emit(Instruction::F64Eq),
+            (BinaryOperation::NotEqual, ValueType::Double) => self.emit(Instruction::F64Ne),
+            (BinaryOperation::Equal, _) => self.emit(Instruction::I32Eq),
+            (BinaryOperation::NotEqual, _) => self.emit(Instruction::I32Ne),
+            (BinaryOperation::Plus, ValueType::String) => self.emit(Instruction::Call(CONCAT)),
+            (BinaryOperation::Plus, ValueType::Integer) => self.emit(Instruction::I64Add),
+            (BinaryOperation::Minus, ValueType::Integer) => self.emit(Instruction::I64Sub),
+            (BinaryOperation::Times, ValueType::Integer) => self.emit(Instruction::I64Mul),
+            (BinaryOperation::Divide, ValueType::Integer) => self.divide(),
+            (BinaryOperation::Plus, _) => self.emit(Instruction::F64Add),
+            (BinaryOperation::Minus, _) => self.emit(Instruction::F64Sub),
+            (BinaryOperation::Times, _) => self.emit(Instruction::F64Mul),
+            (BinaryOperation::Divide, _) => self.emit(Instruction::F64Div),
+        }
+        Ok(result)
+    }
+
+    /// Divides integers with wrapping, where `i64.div_s` would trap.
+    fn divide(&mut self) {
+        let right = self.local(ValType::I64);
+        let left = self.local(ValType::I64);
+        self.emit(Instruction::LocalSet(right));
+        self.emit(Instruction::LocalSet(left));
+        self.emit(Instruction::LocalGet(right));
+        self.emit(Instruction::I64Eqz);
+        self.fail_if(RuntimeError::DivisionByZero);
+
+        self.emit(Instruction::LocalGet(right));
+        self.emit(Instruction::I64Const(-1));
+        self.emit(Instruction::I64Eq);
+        self.emit(Instruction::If(BlockType::Result(ValType::I64)));
+        self.emit(Instruction::I64Const(0));
+        self.emit(Instruction::LocalGet(left));
+        self.emit(Instruction::I64Sub);
+        self.emit(Instruction::Else);
+        self.emit(Instruction::LocalGet(left));
+        self.emit(Instruction::LocalGet(right));
+        self.emit(Instruction::I64DivS);
+        self.emit(Instruction::End);
+    }
+
+    /// Turns the value on the stack into the string `~Revelio` logs.
+    fn display(&mut self, value_type: ValueType) {
+        match value_type {
+            ValueType::Integer => self.emit(Instruction::Call(INTEGER_TO_STRING)),
+            ValueType::Double => {
+                self.emit(Instruction::I32Const(0));
+                self.emit(Instruction::Call(DOUBLE_TO_STRING));
+            }
+            ValueType::Boolean => {
+                let boolean = self.local(ValType::I32);
+                let (true_ptr, false_ptr) = (self.intern("true"), self.intern("false"));
+                self.emit(Instruction::LocalSet(boolean));
+                for (if_true, if_false) in [(true_ptr, false_ptr), (4, 5)] {
+                    self.emit(Instruction::I32Const(if_true));
+                    self.emit(Instruction::I32Const(if_false));
+                    self.emit(Instruction::LocalGet(boolean));
+                    self.emit(Instruction::Select);
+                }
+            }
+            ValueType::HogwartsHouse => {
+                let entry = self.local(ValType::I32);
+                self.emit(Instruction::I32Const(8));
+                self.emit(Instruction::I32Mul);
+                self.emit(Instruction::LocalTee(entry));
+                self.emit(Instruction::I32Load(mem_arg(HOUSE_NAMES, 2)));
+                self.emit(Instruction::LocalGet(entry));
+                self.emit(Instruction::I32Load(mem_arg(HOUSE_NAMES + 4, 2)));
+            }
+            ValueType::String => {}
+            ValueType::List | ValueType::Map => unreachable!("scripts cannot build {}", value_type),
+        }
+    }
+
+    /// Compiles the target of a spell that evaluates it, if any.
+    fn target(&mut self, target: Option<&'p Expression>) -> Result<Option<Type>, CompileError> {
+        target.map(|target| self.expression(target)).transpose()
+    }
+
+    fn spell(
+        &mut self,
+        spell: &'p Spell,
+        target: Option<&'p Expression>,
+    ) -> Result<Type, CompileError> {
+        let result = types::spell_type(spell, target, &self.types)?;
+        let variable = match target {
+            Some(Expression::Atom(Atom::Variable(name))) => Some(name.as_str()),
+            _ => None,
+        };
+
+        match spell {
+            Spell::Accio | Spell::UnbreakableVow => unreachable!("rejected by `spell_type`"),
+            Spell::Custom(name) => {
+                if self.target(target)? != Some(Type::Never) {
+                    self.fail(RuntimeError::UnknownSpell(name.clone()));
+                }
+            }
+            Spell::Confringo => match self.target(target)? {
+                Some(Type::Value(value_type)) => {
+                    self.display(value_type);
+                    self.raise();
+                }
+                Some(Type::Never) => {}
+                None | Some(Type::Nothing) => {
+                    self.fail(RuntimeError::MissingSpellTarget(Spell::Confringo))
+                }
+            },
+            Spell::AvadaKedabra => {
+                self.emit(Instruction::I32Const(0));
+                self.emit(Instruction::Return);
+            }
+            Spell::FelixFelicis => match self.target(target)? {
+                Some(Type::Value(ValueType::Integer)) => {
+                    let max = self.local(ValType::I64);
+                    self.emit(Instruction::LocalTee(max));
+                    self.emit(Instruction::I64Const(0));
+                    self.emit(Instruction::I64LeS);
+                    self.emit(Instruction::If(BlockType::Empty));
+                    // The runtime's message, with the integer written in
+                    // where a placeholder one is
+                    let placeholder = RuntimeValue::Integer(i64::MIN);
+                    let message =
+                        RuntimeError::InvalidSpellTarget(Spell::FelixFelicis, placeholder)
+                            .to_string();
+                    let (before, after) = message
+                        .split_once(&i64::MIN.to_string())
+                        .expect("the message shows the integer");
+                    self.string(before);
+                    self.emit(Instruction::LocalGet(max));
+                    self.emit(Instruction::Call(INTEGER_TO_STRING));
+                    self.emit(Instruction::Call(CONCAT));
+                    self.string(after);
+                    self.emit(Instruction::Call(CONCAT));
+                    self.raise();
+                    self.emit(Instruction::End);
+
+                    self.emit(Instruction::I64Const(0));
+                    self.emit(Instruction::LocalGet(max));
+                    self.emit(Instruction::I64Const(1));
+                    self.emit(Instruction::I64Sub);
+                    self.emit(Instruction::Call(RANDOM_INTEGER));
+                }
+                Some(Type::Value(_)) => {
+                    // Fails unless `0.0 <= max` and `max - max` is 0, which
+                    // it isn't for infinities and NaN
+                    let max = self.local(ValType::F64);
+                    self.emit(Instruction::LocalTee(max));
+                    self.emit(Instruction::F64Const(0.0));
+                    self.emit(Instruction::F64Ge);
+                    self.emit(Instruction::LocalGet(max));
+                    self.emit(Instruction::LocalGet(max));
+                    self.emit(Instruction::F64Sub);
+                    self.emit(Instruction::F64Const(0.0));
+                    self.emit(Instruction::F64Eq);
+                    self.emit(Instruction::I32And);
+                    self.emit(Instruction::I32Eqz);
+                    self.emit(Instruction::If(BlockType::Empty));
+                    let placeholder = RuntimeValue::Double(f64::NAN);
+                    let message =
+                        RuntimeError::InvalidSpellTarget(Spell::FelixFelicis, placeholder)
+                            .to_string();
+                    let (before, after) = message
+                        .split_once(&format!("{:?}", f64::NAN))
+                        .expect("the message shows the double");
+                    self.string(before);
+                    self.emit(Instruction::LocalGet(max));
+                    self.emit(Instruction::I32Const(1));
+                    self.emit(Instruction::Call(DOUBLE_TO_STRING));
+                    self.emit(Instruction::Call(CONCAT));
+                    self.string(after);
+                    self.emit(Instruction::Call(CONCAT));
+                    self.raise();
+                    self.emit(Instruction::End);
+
+                    self.emit(Instruction::LocalGet(max));
+                    self.emit(Instruction::Call(RANDOM_DOUBLE));
+                    self.emit(Instruction::F64Mul);
+                }
+                Some(Type::Never) => {}
+                None | Some(Type::Nothing) => self.emit(Instruction::Call(RANDOM_DOUBLE)),
+            },
+            Spell::Inmobolus => match self.target(target)? {
+                Some(Type::Value(ValueType::Integer)) => {
+                    // Negative durations don't sleep
+                    let ms = self.local(ValType::I64);
+                    self.emit(Instruction::LocalTee(ms));
+                    self.emit(Instruction::I64Const(0));
+                    self.emit(Instruction::LocalGet(ms));
+                    self.emit(Instruction::I64Const(0));
+                    self.emit(Instruction::I64GtS);
+                    self.emit(Instruction::Select);
+                    self.emit(Instruction::Call(SLEEP));
+                }
+                // Other targets do nothing, like in the interpreter
+                Some(value_type) => self.drop(value_type),
+                None => {}
+            },
+            Spell::Revelio => {
+                match self.target(target)? {
+                    Some(Type::Value(value_type)) => self.display(value_type),
+                    Some(Type::Nothing) => self.string(""),
+                    None | Some(Type::Never) => return Ok(result),
+                }
+                self.emit(Instruction::LocalGet(self.lumos));
+                self.emit(Instruction::Call(LOG));
+            }
+            Spell::Incendio | Spell::WingardiumLeviosa => {
+                let suffix = match spell {
+                    Spell::Incendio => "🔥",
+                    _ => "\n",
+                };
+                match (variable, target) {
+                    (Some(name), _) => {
+                        if self.read(name) != Type::Never {
+                            self.string(suffix);
+                            self.emit(Instruction::Call(CONCAT));
+                            self.assign(name);
+                        }
+                    }
+                    (None, Some(Expression::Atom(Atom::String(string)))) => {
+                        self.string(&format!("{}{}", string, suffix))
+                    }
+                    (None, _) => {}
+                }
+            }
+            Spell::Engorgio | Spell::Reducio => {
+                if let Some(name) = variable {
+                    let grow = *spell == Spell::Engorgio;
+                    match self.read(name) {
+                        Type::Value(ValueType::Integer) => {
+                            self.emit(Instruction::I64Const(1));
+                            self.emit(if grow {
+                                Instruction::I64Add
+                            } else {
+                                Instruction::I64Sub
+                            });
+                        }
+                        Type::Value(ValueType::Double) => {
+                            self.emit(Instruction::F64Const(1.0));
+                            self.emit(if grow {
+                                Instruction::F64Add
+                            } else {
+                                Instruction::F64Sub
+                            });
+                        }
+                        Type::Value(_) => {
+                            self.emit(Instruction::I32Const(grow as i32));
+                            self.emit(Instruction::Call(CHANGE_CASE));
+                        }
+                        Type::Nothing | Type::Never => return Ok(result),
+                    }
+                    self.assign(name);
+                }
+            }
+            Spell::Obliviate => {
+                // Constants cannot be forgotten
+                if let Some(name) = variable {
+                    let state = self.variable(name).state;
+                    self.emit(Instruction::LocalGet(state));
+                    self.emit(Instruction::I32Const(BOUND));
+                    self.emit(Instruction::I32Eq);
+                    self.emit(Instruction::If(BlockType::Empty));
+                    self.emit(Instruction::I32Const(UNBOUND));
+                    self.emit(Instruction::LocalSet(state));
+                    self.emit(Instruction::End);
+                }
+            }
+            Spell::PetrificusTotalus => {
+                if let Some(name) = variable {
+                    let state = self.variable(name).state;
+                    self.emit(Instruction::LocalGet(state));
+                    self.emit(Instruction::If(BlockType::Empty));
+                    self.emit(Instruction::I32Const(CONSTANT));
+                    self.emit(Instruction::LocalSet(state));
+                    self.emit(Instruction::End);
+                }
+            }
+            Spell::Aguamenti => self.string("💦"),
+            Spell::OculusReparo => self.string("👓"),
+            Spell::Serpensortia => self.string("🐍"),
+            Spell::Periculum => {
+                self.string("🔥🔥🔥🔥🔥🔥🔥🔥🔥");
+                self.emit(Instruction::I32Const(0));
+                self.emit(Instruction::Call(LOG));
+            }
+            Spell::Lumos | Spell::Nox => {
+                self.emit(Instruction::I32Const((*spell == Spell::Lumos) as i32));
+                self.emit(Instruction::LocalSet(self.lumos));
+            }
+        }
+        Ok(result)
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        use ValType::{F64, I32, I64};
+
+        self.emit(Instruction::End);
+        let run = function(&self.locals, &self.code);
+
+        let imports: [(&str, &[ValType], &[ValType]); 6] = [
+            ("log", &[I32, I32, I32], &[]),
+            ("random_integer", &[I64, I64], &[I64]),
+            ("random_double", &[], &[F64]),
+            ("sleep", &[I64], &[]),
+            ("format_double", &[F64, I32, I32], &[I32]),
+            ("error", &[I32, I32], &[]),
+        ];
+        let functions: [(&[ValType], &[ValType], Function); 7] = [
+            (&[I32], &[I32], alloc()),
+            (&[I32, I32, I32, I32], &[I32, I32], concat()),
+            (&[I32, I32, I32, I32], &[I32], string_eq()),
+            (&[I64], &[I32, I32], integer_to_string()),
+            (&[I32, I32, I32], &[I32, I32], change_case()),
+            (&[F64, I32], &[I32, I32], double_to_string()),
+            (&[], &[I32], run),
+        ];
+
+        // A type per function, in order
+        let mut type_section = TypeSection::new();
+        let mut import_section = ImportSection::new();
+        for (index, (name, params, results)) in imports.iter().enumerate() {
+            type_section
+                .ty()
+                .function(params.iter().copied(), results.iter().copied());
+            import_section.import("env", name, EntityType::Function(index as u32));
+        }
+        let mut function_section = FunctionSection::new();
+        let mut code_section = CodeSection::new();
+        for (index, (params, results, function)) in functions.iter().enumerate() {
+            type_section
+                .ty()
+                .function(params.iter().copied(), results.iter().copied());
+            function_section.function((imports.len() + index) as u32);
+            code_section.function(function);
+        }
+
+        let heap = self.data.len().next_multiple_of(8);
+        let mut memory_section = MemorySection::new();
+        memory_section.memory(MemoryType {
+            minimum: (heap / 65536 + 1) as u64,
+            maximum: None,
+            memory64: false,
+            shared: false,
+            page_size_log2: None,
+        });
+        let mut global_section = GlobalSection::new();
+        global_section.global(
+            GlobalType {
+                val_type: I32,
+                mutable: true,
+                shared: false,
+            },
+            &ConstExpr::i32_const(heap as i32),
+        );
+        let mut export_section = ExportSection::new();
+        export_section.export("memory", ExportKind::Memory, 0);
+        export_section.export("run", ExportKind::Func, RUN);
+        let mut data_section = DataSection::new();
+        data_section.active(0, &ConstExpr::i32_const(0), self.data);
+
+        let mut module = Module::new();
+        module
+            .section(&type_section)
+            .section(&import_section)
+            .section(&function_section)
+            .section(&memory_section)
+            .section(&global_section)
+            .section(&export_section)
+            .section(&code_section)
+            .section(&data_section);
+        module.finish()
+    }
+}
+
+fn mem_arg(offset: u64, align: u32) -> MemArg {
+    MemArg {
+        offset,
+        align,
+        memory_index: 0,
+    }
+}
+
+fn function(locals: &[ValType], code: &[Instruction]) -> Function {
+    let mut function = Function::new(locals.iter().map(|&local| (1, local)));
+    for instruction in code {
+        function.instruction(instruction);
+    }
+    function
+}
+
+/// `alloc(len) -> ptr`: takes `len` bytes off the heap, growing the memory
+/// if needed.
+fn alloc() -> Function {
+    use Instruction::*;
+    let (len, ptr) = (0, 1);
+    function(
+        &[ValType::I32],
+        &[
+            GlobalGet(HEAP),
+            LocalTee(ptr),
+            LocalGet(len),
+            I32Add,
+            GlobalSet(HEAP),
+            // Bytes missing past the end of the memory
+            GlobalGet(HEAP),
+            MemorySize(0),
+            I32Const(16),
+            I32Shl,
+            I32Sub,
+            LocalTee(len),
+            I32Const(0),
+            I32GtS,
+            If(BlockType::Empty),
+            LocalGet(len),
+            I32Const(65535),
+            I32Add,
+            I32Const(16),
+            I32ShrU,
+            MemoryGrow(0),
+            I32Const(-1),
+            I32Eq,
+            If(BlockType::Empty),
+            Unreachable,
+            End,
+            End,
+            LocalGet(ptr),
+            End,
+        ],
+    )
+}
+
+/// `concat(ptr, len, ptr, len) -> (ptr, len)`
+fn concat() -> Function {
+    use Instruction::*;
+    let (left, left_len, right, right_len, ptr) = (0, 1, 2, 3, 4);
+    let copy = MemoryCopy {
+        src_mem: 0,
+        dst_mem: 0,
+    };
+    function(
+        &[ValType::I32],
+        &[
+            LocalGet(left_len),
+            LocalGet(right_len),
+            I32Add,
+            Call(ALLOC),
+            LocalSet(ptr),
+            LocalGet(ptr),
+            LocalGet(left),
+            LocalGet(left_len),
+            copy.clone(),
+            LocalGet(ptr),
+            LocalGet(left_len),
+            I32Add,
+            LocalGet(right),
+            LocalGet(right_len),
+            copy,
+            LocalGet(ptr),
+            LocalGet(left_len),
+            LocalGet(right_len),
+            I32Add,
+            End,
+        ],
+    )
+}
+
+/// `string_eq(ptr, len, ptr, len) -> i32`
+fn string_eq() -> Function {
+    use Instruction::*;
+    let (left, left_len, right, right_len, index) = (0, 1, 2, 3, 4);
+    function(
+        &[ValType::I32],
+        &[
+            LocalGet(left_len),
+            LocalGet(right_len),
+            I32Ne,
+            If(BlockType::Empty),
+            I32Const(0),
+            Return,
+            End,
+            Block(BlockType::Empty),
+            Loop(BlockType::Empty),
+            LocalGet(index),
+            LocalGet(left_len),
+            I32GeU,
+            BrIf(1),
+            LocalGet(left),
+            LocalGet(index),
+            I32Add,
+            I32Load8U(mem_arg(0, 0)),
+            LocalGet(right),
+            LocalGet(index),
+            I32Add,
+            I32Load8U(mem_arg(0, 0)),
+            I32Ne,
+            If(BlockType::Empty),
+            I32Const(0),
+            Return,
+            End,
+            LocalGet(index),
+            I32Const(1),
+            I32Add,
+            LocalSet(index),
+            Br(0),
+            End,
+            End,
+            I32Const(1),
+            End,
+        ],
+    )
+}
+
+/// `integer_to_string(i64) -> (ptr, len)`, in decimal.
+fn integer_to_string() -> Function {
+    use Instruction::*;
+    let (value, end, ptr, rest, digit) = (0, 1, 2, 3, 4);
+    function(
+        &[ValType::I32, ValType::I32, ValType::I64, ValType::I64],
+        &[
+            // Written backwards from the end of the longest one,
+            // `-9223372036854775808`
+            I32Const(20),
+            Call(ALLOC),
+            I32Const(20),
+            I32Add,
+            LocalTee(end),
+            LocalSet(ptr),
+            LocalGet(value),
+            LocalSet(rest),
+            Loop(BlockType::Empty),
+            LocalGet(ptr),
+            I32Const(1),
+            I32Sub,
+            LocalSet(ptr),
+            // The remainder of a negative number is negative
+            LocalGet(rest),
+            I64Const(10),
+            I64RemS,
+            LocalSet(digit),
+            LocalGet(ptr),
+            I64Const(0),
+            LocalGet(digit),
+            I64Sub,
+            LocalGet(digit),
+            LocalGet(digit),
+            I64Const(0),
+            I64LtS,
+            Select,
+            I32WrapI64,
+            I32Const(b'0' as i32),
+            I32Add,
+            I32Store8(mem_arg(0, 0)),
+            LocalGet(rest),
+            I64Const(10),
+            I64DivS,
+            LocalTee(rest),
+            I64Const(0),
+            I64Ne,
+            BrIf(0),
+            End,
+            LocalGet(value),
+            I64Const(0),
+            I64LtS,
+            If(BlockType::Empty),
+            LocalGet(ptr),
+            I32Const(1),
+            I32Sub,
+            LocalTee(ptr),
+            I32Const(b'-' as i32),
+            I32Store8(mem_arg(0, 0)),
+            End,
+            LocalGet(ptr),
+            LocalGet(end),
+            LocalGet(ptr),
+            I32Sub,
+            End,
+        ],
+    )
+}
+
+/// `change_case(ptr, len, upper: i32) -> (ptr, len)`, a copy of the string
+/// in ASCII upper or lower case.
+fn change_case() -> Function {
+    use Instruction::*;
+    let (string, len, upper, ptr, index, byte) = (0, 1, 2, 3, 4, 5);
+    // `byte` shifted by 32 if it is within 26 letters of `from`
+    let shift = |from: u8, shift: Instruction<'static>| {
+        [
+            LocalGet(byte),
+            I32Const(32),
+            shift,
+            LocalGet(byte),
+            LocalGet(byte),
+            I32Const(from as i32),
+            I32Sub,
+            I32Const(26),
+            I32LtU,
+            Select,
+        ]
+    };
+    let mut code = vec![
+        LocalGet(len),
+        Call(ALLOC),
+        LocalSet(ptr),
+        Block(BlockType::Empty),
+        Loop(BlockType::Empty),
+        LocalGet(index),
+        LocalGet(len),
+        I32GeU,
+        BrIf(1),
+        LocalGet(string),
+        LocalGet(index),
+        I32Add,
+        I32Load8U(mem_arg(0, 0)),
+        LocalSet(byte),
+        LocalGet(ptr),
+        LocalGet(index),
+        I32Add,
+        LocalGet(upper),
+        If(BlockType::Result(ValType::I32)),
+    ];
+    code.extend(shift(b'a', I32Sub));
+    code.push(Else);
+    code.extend(shift(b'A', I32Add));
+    code.extend([
+        End,
+        I32Store8(mem_arg(0, 0)),
+        LocalGet(index),
+        I32Const(1),
+        I32Add,
+        LocalSet(index),
+        Br(0),
+        End,
+        End,
+        LocalGet(ptr),
+        LocalGet(len),
+        End,
+    ]);
+    function(&[ValType::I32; 3], &code)
+}
+
+/// `double_to_string(f64, debug: i32) -> (ptr, len)`, formatted by the host.
+fn double_to_string() -> Function {
+    use Instruction::*;
+    let (value, debug, ptr, len) = (0, 1, 2, 3);
+    function(
+        &[ValType::I32, ValType::I32],
+        &[
+            I32Const(DOUBLE_CAPACITY as i32),
+            Call(ALLOC),
+            LocalSet(ptr),
+            LocalGet(value),
+            LocalGet(debug),
+            LocalGet(ptr),
+            Call(FORMAT_DOUBLE),
+            LocalSet(len),
+            // Gives back the bytes it didn't use
+            LocalGet(ptr),
+            LocalGet(len),
+            I32Add,
+            GlobalSet(HEAP),
+            LocalGet(ptr),
+            LocalGet(len),
+            End,
+        ],
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::Path;
+    use std::time::Duration;
+
+    use potterscript_runtime::golden::golden_output;
+    use potterscript_runtime::{RuntimeAdapter, RuntimeValue, TestAdapter};
+    use wasmi::{Caller, Engine, Extern, Linker, Memory, Store};
+
+    use super::*;
+
+    struct Host {
+        adapter: TestAdapter,
+        error: Option<String>,
+    }
+
+    fn memory(caller: &Caller<'_, Host>) -> Memory {
+        caller
+            .get_export("memory")
+            .and_then(Extern::into_memory)
+            .unwrap()
+    }
+
+    fn read_string(caller: &Caller<'_, Host>, ptr: i32, len: i32) -> String {
+        let bytes = &memory(caller).data(caller)[ptr as usize..][..len as usize];
+        String::from_utf8(bytes.to_vec()).unwrap()
+    }
+
+    /// Runs `module` with the host functions backed by `adapter`, returning
+    /// what `run` returned.
+    fn run(module: &[u8], adapter: TestAdapter) -> (i32, Host) {
+        let engine = Engine::default();
+        let module = wasmi::Module::new(&engine, module).unwrap();
+        let host = Host {
+            adapter,
+            error: None,
+        };
+        let mut store = Store::new(&engine, host);
+
+        let mut linker = Linker::new(&engine);
+        linker
+            .func_wrap(
+                "env",
+                "log",
+                |caller: Caller<'_, Host>, ptr: i32, len: i32, lumos: i32| {
+                    let mut string = read_string(&caller, ptr, len);
+                    let adapter = &caller.data().adapter;
+                    if lumos != 0 {
+                        string = adapter.lumos(string);
+                    }
+                    adapter.log(&string);
+                },
+            )
+            .unwrap();
+        linker
+            .func_wrap(
+                "env",
+                "random_integer",
+                |caller: Caller<'_, Host>, min: i64, max: i64| {
+                    caller.data().adapter.random_integer(min..=max)
+                },
+            )
+            .unwrap();
+        linker
+            .func_wrap("env", "random_double", |caller: Caller<'_, Host>| {
+                caller.data().adapter.random_double()
+            })
+            .unwrap();
+        linker
+            .func_wrap("env", "sleep", |caller: Caller<'_, Host>, ms: i64| {
+                assert!(ms >= 0);
+                caller
+                    .data()
+                    .adapter
+                    .sleep(Duration::from_millis(ms as u64));
+            })
+            .unwrap();
+        linker
+            .func_wrap(
+                "env",
+                "format_double",
+                |mut caller: Caller<'_, Host>, value: f64, debug: i32, ptr: i32| {
+                    let string = if debug == 1 {
+                        format!("{:?}", value)
+                    } else {
+                        RuntimeValue::Double(value).to_string()
+                    };
+                    assert!(string.len() <= DOUBLE_CAPACITY);
+                    memory(&caller)
+                        .write(&mut caller, ptr as usize, string.as_bytes())
+                        .unwrap();
+                    string.len() as i32
+                },
+            )
+            .unwrap();
+        linker
+            .func_wrap(
+                "env",
+                "error",
+                |mut caller: Caller<'_, Host>, ptr: i32, len: i32| {
+                    caller.data_mut().error = Some(read_string(&caller, ptr, len));
+                },
+            )
+            .unwrap();
+
+        let instance = linker
+            .instantiate(&mut store, &module)
+            .unwrap()
+            .ensure_no_start(&mut store)
+            .unwrap();
+        let run = instance.get_typed_func::<(), i32>(&store, "run").unwrap();
+        let status = run.call(&mut store, ()).unwrap();
+        (status, store.into_data())
+    }
+
+    /// Runs `code` compiled to WebAssembly the way golden tests run it.
+    fn wasm_output(code: &str) -> String {
+        let program = potterscript_parser::parse(code).unwrap();
+        let module = compile(&program).unwrap();
+        let (status, host) = run(&module, TestAdapter::new().with_integers((0..4).cycle()));
+        assert_eq!(host.error.is_some() as i32, status);
+
+        let mut output = host.adapter.output();
+        if let Some(error) = host.error {
+            output += &format!("error: {}\n", error);
+        }
+        output
+    }
+
+    fn assert_same_output(code: &str) {
+        assert_eq!(golden_output(code, &[]), wasm_output(code), "{}", code);
+    }
+
+    #[test]
+    fn test_examples() {
+        let root = Path::new(env!("CARGO_MANIFEST_DIR")).join("..");
+        for script in [
+            "example/input.potter",
+            "runtime/tests/golden/constant.potter",
+            "runtime/tests/golden/example.potter",
+            "runtime/tests/golden/strings.potter",
+        ] {
+            assert_same_output(&fs::read_to_string(root.join(script)).unwrap());
+        }
+    }
+
+    #[test]
+    fn test_values() {
+        assert_same_output("~Revelio 0\n~Revelio -42\n~Revelio 9223372036854775807");
+        assert_same_output("x = 1.5\ny = x * 2.0\n~Revelio y\n~Revelio x / 0.0");
+        assert_same_output("~Revelio true\n~Revelio false\n~Revelio true == false");
+        assert_same_output("a = SortingHat\nb = SortingHat\n~Revelio a\n~Revelio b == a");
+        assert_same_output("~Revelio Slytherin\n~Revelio ~Lumos\n~Revelio # nothing");
+        assert_same_output("x = \"Hogwarts\"\n~Reducio x\n~Revelio x + \"!\"");
+        assert_same_output("~Revelio \"a\" == \"a\"\n~Revelio \"a\" != \"ab\"");
+        // Values of different types are never equal
+        assert_same_output("~Revelio 1 == 1.0\n~Revelio \"1\" != 1");
+    }
+
+    #[test]
+    fn test_arithmetic() {
+        assert_same_output("x = 7 / 2\n~Revelio x\ny = -7 / 2\n~Revelio y");
+        assert_same_output("x = 9223372036854775807\n~Engorgio x\n~Revelio x");
+        assert_same_output("x = -9223372036854775807 - 1\ny = x / -1\n~Revelio y");
+        assert_same_output("x = 10\ny = x - 3\nz = y * y\n~Revelio z");
+        assert_same_output("x = 0\ny = 1 / x");
+    }
+
+    #[test]
+    fn test_loops() {
+        assert_same_output(
+            "i = 0\nquidditch {\n~Engorgio i\nj = 0\nquidditch {\n~Engorgio j\n~Revelio j\nif j == 2 {\nsnitch\n}\n}\n~Revelio i\n}",
+        );
+        assert_same_output(
+            "s = \"\"\nquidditch {\ns = s + \"a\"\nif s == \"aaa\" {\nsnitch\n}\n}\n~Revelio s",
+        );
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_same_output("~Revelio x");
+        assert_same_output("x = 1\n~Obliviate x\n~Revelio x");
+        assert_same_output("x = 1\n~PetrificusTotalus x\n~Obliviate x\nx = 2");
+        assert_same_output("x = ~Lumos");
+        assert_same_output("~Expecto 1");
+        assert_same_output("~Confringo 42");
+        assert_same_output("~Confringo");
+        assert_same_output("~Inmobolus");
+        assert_same_output("x = ~FelixFelicis 10\n~Revelio x\ny = ~FelixFelicis -3");
+        assert_same_output("~Revelio 1.5\ny = ~FelixFelicis -2.0");
+    }
+
+    #[test]
+    fn test_host_calls() {
+        let program = potterscript_parser::parse(
+            "~Inmobolus 1500\n~Inmobolus -5\nx = ~FelixFelicis\n~Revelio x\n~AvadaKedabra\n~Revelio \"unreachable\"",
+        )
+        .unwrap();
+        let module = compile(&program).unwrap();
+        let (status, host) = run(&module, TestAdapter::new().with_doubles([0.25]));
+
+        assert_eq!(0, status);
+        assert_eq!(Duration::from_millis(1500), host.adapter.elapsed());
+        host.adapter.assert_logs(&["0.25"]);
+    }
+
+    #[test]
+    fn test_memory_grows() {
+        assert_same_output(
+            "i = 0\nquidditch {\nx = ~Serpensortia\ns = x + x\n~Engorgio i\nif i == 20000 {\nsnitch\n}\n}\n~Revelio s",
+        );
+    }
+
+    #[test]
+    fn test_compile_errors() {
+        let compile_error = |code| compile(&potterscript_parser::parse(code).unwrap()).unwrap_err();
+
+        assert_eq!(
+            CompileError::Unsupported("protego"),
+            compile_error("protego {\n~Revelio 1\n} expelliarmus e {\n~Revelio e\n}")
+        );
+        assert_eq!(
+            CompileError::TypeChange("x".to_string(), ValueType::Integer, ValueType::String),
+            compile_error("x = 1\nx = \"one\"")
+        );
+        // Types flow from assignments further down a loop
+        assert_eq!(
+            CompileError::TypeChange("y".to_string(), ValueType::Integer, ValueType::Double),
+            compile_error("y = 1\nquidditch {\ny = x\nx = 1.0\n}")
+        );
+        assert_eq!(
+            CompileError::InvalidOperation(
+                BinaryOperation::Plus,
+                ValueType::Integer,
+                ValueType::String
+            ),
+            compile_error("~Revelio 1 + \"1\"")
+        );
+        assert_eq!(
+            CompileError::InvalidSpellTarget(Spell::Engorgio, ValueType::Boolean),
+            compile_error("x = true\n~Engorgio x")
+        );
+        assert_eq!(
+            "~Accio is not supported",
+            compile_error("name = ~Accio").to_string()
+        );
+    }
+}
@@ -0,0 +1,205 @@
+//! Static types of what a program computes. A WebAssembly local holds values
+//! of a single type, so every variable must be assigned values of one type.
+
+use std::collections::HashMap;
+
+use potterscript_parser::{Atom, BinaryOperation, Expression, Program, Spell, Statement};
+use potterscript_runtime::ValueType;
+
+use super::CompileError;
+
+/// What evaluating an expression leaves, if it doesn't fail.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum Type {
+    Value(ValueType),
+    /// No value, e.g. a comment or `~Lumos`.
+    Nothing,
+    /// Always fails or ends the program, e.g. reading a variable that is
+    /// never assigned.
+    Never,
+}
+
+/// Type of every variable the program assigns a value to.
+pub(super) type Variables<'p> = HashMap<&'p str, ValueType>;
+
+/// Finds the type of every variable, going over the program until no
+/// assignment teaches anything new, since a variable may be assigned from
+/// one assigned further down in a loop.
+pub(super) fn infer(program: &Program) -> Result<Variables<'_>, CompileError> {
+    let mut variables = Variables::new();
+    while infer_block(&program.0, &mut variables)? {}
+    Ok(variables)
+}
+
+/// Whether the block taught the type of a variable.
+fn infer_block<'p>(
+    block: &'p [Statement],
+    variables: &mut Variables<'p>,
+) -> Result<bool, CompileError> {
+    let mut learned = false;
+    for statement in block {
+        learned |= match statement {
+            Statement::VariableAssignment(name, value) => {
+                match expression_type(value, variables)? {
+                    Type::Value(value_type) => match variables.get(name.as_str()) {
+                        None => {
+                            variables.insert(name, value_type);
+                            true
+                        }
+                        Some(&old) if old == value_type => false,
+                        Some(&old) => {
+                            return Err(CompileError::TypeChange(name.clone(), old, value_type))
+                        }
+                    },
+                    Type::Nothing | Type::Never => false,
+                }
+            }
+            Statement::ExpressionStatement(expression) => {
+                expression_type(expression, variables)?;
+                false
+            }
+            Statement::If(condition, true_block, else_block) => {
+                expression_type(condition, variables)?;
+                let learned = infer_block(true_block, variables)?;
+                infer_block(else_block, variables)? || learned
+            }
+            Statement::Quidditch(block) => infer_block(block, variables)?,
+            Statement::Snitch | Statement::Vow(_, _) => false,
+            Statement::Protego(_, _, _) => return Err(CompileError::Unsupported("protego")),
+        };
+    }
+    Ok(learned)
+}
+
+pub(super) fn variable_type(name: &str, variables: &Variables) -> Type {
+    match variables.get(name) {
+        Some(&value_type) => Type::Value(value_type),
+        None => Type::Never,
+    }
+}
+
+pub(super) fn atom_type(atom: &Atom, variables: &Variables) -> Type {
+    match atom {
+        Atom::String(_) => Type::Value(ValueType::String),
+        Atom::Variable(name) => variable_type(name, variables),
+        Atom::Boolean(_) => Type::Value(ValueType::Boolean),
+        Atom::Integer(_) => Type::Value(ValueType::Integer),
+        Atom::Double(_) => Type::Value(ValueType::Double),
+        Atom::HogwartsHouse(_) => Type::Value(ValueType::HogwartsHouse),
+    }
+}
+
+pub(super) fn expression_type(
+    expression: &Expression,
+    variables: &Variables,
+) -> Result<Type, CompileError> {
+    match expression {
+        Expression::SpellCast(spell, target) => {
+            spell_type(spell, target.as_ref().as_ref(), variables)
+        }
+        Expression::BinaryOperation(operation, left, right) => binary_type(
+            *operation,
+            expression_type(left, variables)?,
+            expression_type(right, variables)?,
+        ),
+        Expression::Atom(atom) => Ok(atom_type(atom, variables)),
+        Expression::Field(_, _) => Err(CompileError::Unsupported("field access")),
+        Expression::Comment(_) => Ok(Type::Nothing),
+        Expression::SortingHat => Ok(Type::Value(ValueType::HogwartsHouse)),
+    }
+}
+
+/// Type of a binary operation on operands of the given types. Operations
+/// that always fail, e.g. adding an integer and a string, are rejected.
+pub(super) fn binary_type(
+    operation: BinaryOperation,
+    left: Type,
+    right: Type,
+) -> Result<Type, CompileError> {
+    let (left, right) = match (left, right) {
+        (Type::Never, _) | (_, Type::Never) => return Ok(Type::Never),
+        (Type::Value(left), Type::Value(right)) => (left, right),
+        _ => return Ok(Type::Nothing),
+    };
+
+    match (operation, left, right) {
+        (BinaryOperation::Equal | BinaryOperation::NotEqual, _, _) => {
+            Ok(Type::Value(ValueType::Boolean))
+        }
+        (BinaryOperation::Plus, ValueType::String, ValueType::String) => {
+            Ok(Type::Value(ValueType::String))
+        }
+        (_, ValueType::Integer, ValueType::Integer) | (_, ValueType::Double, ValueType::Double) => {
+            Ok(Type::Value(left))
+        }
+        _ => Err(CompileError::InvalidOperation(operation, left, right)),
+    }
+}
+
+/// Type of casting `spell` on `target`. Only the spells that evaluate their
+/// target check its type, the others only look at a variable or string
+/// literal written as the target.
+pub(super) fn spell_type(
+    spell: &Spell,
+    target: Option<&Expression>,
+    variables: &Variables,
+) -> Result<Type, CompileError> {
+    let target_type = |target: Option<&Expression>| match target {
+        Some(target) => expression_type(target, variables).map(Some),
+        None => Ok(None),
+    };
+    let variable = match target {
+        Some(Expression::Atom(Atom::Variable(name))) => Some(variable_type(name, variables)),
+        _ => None,
+    };
+    let invalid = |value_type| Err(CompileError::InvalidSpellTarget(spell.clone(), value_type));
+
+    match spell {
+        Spell::Accio => Err(CompileError::Unsupported("~Accio")),
+        Spell::UnbreakableVow => Err(CompileError::Unsupported("~UnbreakableVow")),
+        Spell::Custom(_) | Spell::Confringo => {
+            target_type(target)?;
+            Ok(Type::Never)
+        }
+        Spell::AvadaKedabra => Ok(Type::Never),
+        Spell::FelixFelicis => match target_type(target)? {
+            None | Some(Type::Nothing) => Ok(Type::Value(ValueType::Double)),
+            Some(Type::Value(value_type @ (ValueType::Integer | ValueType::Double))) => {
+                Ok(Type::Value(value_type))
+            }
+            Some(Type::Value(value_type)) => invalid(value_type),
+            Some(Type::Never) => Ok(Type::Never),
+        },
+        Spell::Inmobolus => match target_type(target)? {
+            Some(Type::Never) => Ok(Type::Never),
+            _ => Ok(Type::Nothing),
+        },
+        Spell::Revelio => match target_type(target)? {
+            Some(Type::Never) => Ok(Type::Never),
+            _ => Ok(Type::Nothing),
+        },
+        Spell::Incendio | Spell::WingardiumLeviosa => match (variable, target) {
+            (Some(Type::Value(ValueType::String)), _) => Ok(Type::Nothing),
+            (Some(Type::Value(value_type)), _) => invalid(value_type),
+            (Some(_), _) => Ok(Type::Never),
+            (None, Some(Expression::Atom(Atom::String(_)))) => Ok(Type::Value(ValueType::String)),
+            (None, _) => Ok(Type::Nothing),
+        },
+        Spell::Engorgio | Spell::Reducio => match variable {
+            Some(Type::Value(ValueType::Integer | ValueType::Double | ValueType::String)) => {
+                Ok(Type::Nothing)
+            }
+            Some(Type::Value(value_type)) => invalid(value_type),
+            Some(_) => Ok(Type::Never),
+            None => Ok(Type::Nothing),
+        },
+        Spell::Aguamenti | Spell::OculusReparo | Spell::Serpensortia => {
+            Ok(Type::Value(ValueType::String))
+        }
+        Spell::Periculum
+        | Spell::Lumos
+        | Spell::Nox
+        | Spell::Obliviate
+        | Spell::PetrificusTotalus => Ok(Type::Nothing),
+    }
+}
@@ -0,0 +1,109 @@
+//! The Rust modules generated from the examples are checked in under
+//! `tests/rust`, so they are compiled with the tests. Run with
+//! `POTTERSCRIPT_BLESS=1` to regenerate them.
+
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use potterscript_runtime::golden::golden_output;
+use potterscript_runtime::{RuntimeError, TestAdapter};
+
+#[rustfmt::skip]
+#[path = "rust/accio.rs"]
+mod accio;
+#[rustfmt::skip]
+#[path = "rust/constant.rs"]
+mod constant;
+#[rustfmt::skip]
+#[path = "rust/example.rs"]
+mod example;
+#[rustfmt::skip]
+#[path = "rust/input.rs"]
+mod input;
+#[rustfmt::skip]
+#[path = "rust/protego.rs"]
+mod protego;
+#[rustfmt::skip]
+#[path = "rust/spells.rs"]
+mod spells;
+#[rustfmt::skip]
+#[path = "rust/strings.rs"]
+mod strings;
+
+type Run = fn(&TestAdapter) -> Result<(), RuntimeError>;
+
+/// Every generated module, by name.
+const MODULES: &[(&str, Run)] = &[
+    ("accio", accio::run),
+    ("constant", constant::run),
+    ("example", example::run),
+    ("input", input::run),
+    ("protego", protego::run),
+    ("spells", spells::run),
+    ("strings", strings::run),
+];
+
+/// The script a module is generated from, and the lines its `~Accio`s read.
+fn script(name: &str) -> (PathBuf, Vec<String>) {
+    let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let script = match name {
+        "input" => root.join("../example/input.potter"),
+        "spells" => root.join("tests/rust/spells.potter"),
+        name => root
+            .join("../runtime/tests/golden")
+            .join(name)
+            .with_extension("potter"),
+    };
+    let inputs = fs::read_to_string(script.with_extension("in"))
+        .map(|inputs| inputs.lines().map(str::to_string).collect())
+        .unwrap_or_default();
+    (script, inputs)
+}
+
+#[test]
+fn test_modules_up_to_date() {
+    let bless = env::var_os("POTTERSCRIPT_BLESS").is_some();
+    for (name, _) in MODULES {
+        let (script, _) = script(name);
+        let program = potterscript_parser::parse(&fs::read_to_string(script).unwrap()).unwrap();
+        let module = potterscript_codegen::rust::transpile(&program);
+
+        let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/rust")
+            .join(name)
+            .with_extension("rs");
+        if bless {
+            fs::write(path, module).unwrap();
+        } else {
+            assert_eq!(
+                fs::read_to_string(path).unwrap(),
+                module,
+                "tests/rust/{}.rs is out of date, run the tests with POTTERSCRIPT_BLESS=1",
+                name
+            );
+        }
+    }
+}
+
+#[test]
+fn test_same_output() {
+    for (name, run) in MODULES {
+        let (script, inputs) = script(name);
+        let adapter = TestAdapter::new()
+            .with_integers((0..4).cycle())
+            .with_inputs(inputs.iter().cloned());
+        let result = run(&adapter);
+
+        let mut output = adapter.output();
+        if let Err(error) = result {
+            output += &format!("error: {}\n", error);
+        }
+        assert_eq!(
+            golden_output(&fs::read_to_string(script).unwrap(), &inputs),
+            output,
+            "{}",
+            name
+        );
+    }
+}
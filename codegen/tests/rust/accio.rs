@@ -0,0 +1,43 @@
+// Generated from PotterScript by potterscript-codegen, do not edit.
+
+use potterscript_parser::BinaryOperation;
+use potterscript_runtime::{spells, RuntimeAdapter, RuntimeError, RuntimeValue};
+
+fn get(variable: &Option<RuntimeValue>, name: &str) -> Result<RuntimeValue, RuntimeError> {
+    variable
+        .clone()
+        .ok_or_else(|| RuntimeError::VariableNotFound(name.to_string()))
+}
+
+fn revelio(adapter: &impl RuntimeAdapter, lumos: bool, value: Option<RuntimeValue>) {
+    let text = value.map(|value| value.to_string()).unwrap_or_default();
+    adapter.log(&if lumos { adapter.lumos(text) } else { text });
+}
+
+/// Runs the program, going through `adapter` for logs, random picks, sleeps
+/// and input.
+#[allow(
+    unused_mut,
+    unused_assignments,
+    unused_variables,
+    unused_labels,
+    unreachable_code,
+    non_snake_case,
+    clippy::all
+)]
+pub fn run(adapter: &impl RuntimeAdapter) -> Result<(), RuntimeError> {
+    let mut lumos = false;
+    let mut name: Option<RuntimeValue> = None;
+    let mut owl: Option<RuntimeValue> = None;
+    let mut rest: Option<RuntimeValue> = None;
+
+    name = Some(spells::accio(adapter, Some(RuntimeValue::from("What is your name? "))));
+    revelio(adapter, lumos, Some(RuntimeValue::from("Welcome, ").binary_operation(BinaryOperation::Plus, get(&name, "name")?)?));
+    owl = Some(spells::accio(adapter, None));
+    revelio(adapter, lumos, Some(get(&owl, "owl")?));
+    rest = Some(spells::accio(adapter, None));
+    if get(&rest, "rest")?.binary_operation(BinaryOperation::Equal, RuntimeValue::Boolean(false))? == RuntimeValue::Boolean(true) {
+        revelio(adapter, lumos, Some(RuntimeValue::from("No more input")));
+    }
+    Ok(())
+}
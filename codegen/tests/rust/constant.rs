@@ -0,0 +1,47 @@
+// Generated from PotterScript by potterscript-codegen, do not edit.
+
+use potterscript_runtime::{spells, RuntimeAdapter, RuntimeError, RuntimeValue};
+
+fn get(variable: &Option<RuntimeValue>, name: &str) -> Result<RuntimeValue, RuntimeError> {
+    variable
+        .clone()
+        .ok_or_else(|| RuntimeError::VariableNotFound(name.to_string()))
+}
+
+/// The new value of a variable a spell changes, unless it is a constant.
+fn reassign(name: &str, constant: bool, value: RuntimeValue) -> Result<RuntimeValue, RuntimeError> {
+    if constant {
+        return Err(RuntimeError::ConstantReassignment(name.to_string()));
+    }
+    Ok(value)
+}
+
+fn revelio(adapter: &impl RuntimeAdapter, lumos: bool, value: Option<RuntimeValue>) {
+    let text = value.map(|value| value.to_string()).unwrap_or_default();
+    adapter.log(&if lumos { adapter.lumos(text) } else { text });
+}
+
+/// Runs the program, going through `adapter` for logs, random picks, sleeps
+/// and input.
+#[allow(
+    unused_mut,
+    unused_assignments,
+    unused_variables,
+    unused_labels,
+    unreachable_code,
+    non_snake_case,
+    clippy::all
+)]
+pub fn run(adapter: &impl RuntimeAdapter) -> Result<(), RuntimeError> {
+    let mut lumos = false;
+    let mut answer: Option<RuntimeValue> = None;
+    let mut answer_constant = false;
+
+    if answer_constant { return Err(RuntimeError::ConstantReassignment("answer".to_string())); }
+    answer = Some(RuntimeValue::Integer(42));
+    if answer.is_some() { answer_constant = true }
+    revelio(adapter, lumos, Some(get(&answer, "answer")?));
+    answer = Some(get(&answer, "answer").and_then(spells::engorgio).and_then(|value| reassign("answer", answer_constant, value))?);
+    revelio(adapter, lumos, Some(RuntimeValue::from("unreachable")));
+    Ok(())
+}
@@ -0,0 +1,100 @@
+// Generated from PotterScript by potterscript-codegen, do not edit.
+
+use potterscript_parser::{BinaryOperation, HogwartsHouse, Spell};
+use potterscript_runtime::{spells, RuntimeAdapter, RuntimeError, RuntimeValue};
+
+fn get(variable: &Option<RuntimeValue>, name: &str) -> Result<RuntimeValue, RuntimeError> {
+    variable
+        .clone()
+        .ok_or_else(|| RuntimeError::VariableNotFound(name.to_string()))
+}
+
+/// The new value of a variable a spell changes, unless it is a constant.
+fn reassign(name: &str, constant: bool, value: RuntimeValue) -> Result<RuntimeValue, RuntimeError> {
+    if constant {
+        return Err(RuntimeError::ConstantReassignment(name.to_string()));
+    }
+    Ok(value)
+}
+
+fn revelio(adapter: &impl RuntimeAdapter, lumos: bool, value: Option<RuntimeValue>) {
+    let text = value.map(|value| value.to_string()).unwrap_or_default();
+    adapter.log(&if lumos { adapter.lumos(text) } else { text });
+}
+
+/// Runs the program, going through `adapter` for logs, random picks, sleeps
+/// and input.
+#[allow(
+    unused_mut,
+    unused_assignments,
+    unused_variables,
+    unused_labels,
+    unreachable_code,
+    non_snake_case,
+    clippy::all
+)]
+pub fn run(adapter: &impl RuntimeAdapter) -> Result<(), RuntimeError> {
+    let mut lumos = false;
+    let mut house: Option<RuntimeValue> = None;
+    let mut water: Option<RuntimeValue> = None;
+    let mut x: Option<RuntimeValue> = None;
+    let mut z: Option<RuntimeValue> = None;
+    let mut index: Option<RuntimeValue> = None;
+    let mut snake: Option<RuntimeValue> = None;
+    let mut y: Option<RuntimeValue> = None;
+    let mut y_constant = false;
+
+    adapter.log("🔥🔥🔥🔥🔥🔥🔥🔥🔥");
+    revelio(adapter, lumos, Some(RuntimeValue::from("Hello, world!")));
+    revelio(adapter, lumos, Some(RuntimeValue::Integer(4)));
+    house = Some(RuntimeValue::HogwartsHouse(HogwartsHouse::ALL[adapter.random_integer(0..=3) as usize]));
+    revelio(adapter, lumos, Some(get(&house, "house")?));
+    if get(&house, "house")?.binary_operation(BinaryOperation::Equal, RuntimeValue::HogwartsHouse(HogwartsHouse::Gryffindor))? == RuntimeValue::Boolean(true) {
+        adapter.log("🔥🔥🔥🔥🔥🔥🔥🔥🔥");
+    }
+    water = Some(RuntimeValue::from("💦"));
+    x = Some(RuntimeValue::from("Agua: ").binary_operation(BinaryOperation::Plus, get(&water, "water")?)?);
+    x = Some(get(&x, "x").and_then(spells::engorgio)?);
+    x = Some(get(&x, "x").and_then(|value| spells::append(Spell::WingardiumLeviosa, value, "\n"))?);
+    revelio(adapter, lumos, Some(get(&x, "x")?));
+    x = None;
+    z = Some(RuntimeValue::from("hola\n"));
+    revelio(adapter, lumos, Some(get(&z, "z")?));
+    z = Some(RuntimeValue::from("hola🔥"));
+    // This is a comment
+    revelio(adapter, lumos, Some(get(&z, "z")?));
+    index = Some(RuntimeValue::Integer(0));
+    'quidditch_1: loop {
+        snake = Some(RuntimeValue::from("🐍"));
+        revelio(adapter, lumos, Some(get(&snake, "snake")?));
+        snake = Some(get(&snake, "snake").and_then(|value| spells::append(Spell::Incendio, value, "🔥"))?);
+        revelio(adapter, lumos, Some(get(&snake, "snake")?));
+        index = Some(get(&index, "index").and_then(spells::engorgio)?);
+        if get(&index, "index")?.binary_operation(BinaryOperation::Equal, RuntimeValue::Integer(4))? == RuntimeValue::Boolean(true) {
+            break 'quidditch_1;
+            // Break loop
+        }
+    }
+    revelio(adapter, lumos, Some(RuntimeValue::from("Waiting 2s")));
+    spells::inmobolus(adapter, Some(RuntimeValue::Integer(2000)));
+    if y_constant { return Err(RuntimeError::ConstantReassignment("y".to_string())); }
+    y = Some(RuntimeValue::Integer(3).binary_operation(BinaryOperation::Plus, RuntimeValue::Integer(7))?);
+    y = Some(get(&y, "y").and_then(spells::engorgio).and_then(|value| reassign("y", y_constant, value))?);
+    revelio(adapter, lumos, Some(get(&y, "y")?));
+    if get(&y, "y")?.binary_operation(BinaryOperation::NotEqual, RuntimeValue::Integer(11))? == RuntimeValue::Boolean(true) {
+        revelio(adapter, lumos, Some(RuntimeValue::from("y is not 11")));
+    } else {
+        lumos = true;
+        revelio(adapter, lumos, Some(RuntimeValue::from("y is 11")));
+    }
+    if y_constant { return Err(RuntimeError::ConstantReassignment("y".to_string())); }
+    y = Some(RuntimeValue::Integer(8));
+    lumos = false;
+    y = Some(get(&y, "y").and_then(spells::engorgio).and_then(|value| reassign("y", y_constant, value))?);
+    y = Some(get(&y, "y").and_then(spells::engorgio).and_then(|value| reassign("y", y_constant, value))?);
+    y = Some(get(&y, "y").and_then(spells::engorgio).and_then(|value| reassign("y", y_constant, value))?);
+    y = Some(get(&y, "y").and_then(spells::reducio).and_then(|value| reassign("y", y_constant, value))?);
+    revelio(adapter, lumos, Some(get(&y, "y")?));
+    if y.is_some() { y_constant = true }
+    Ok(())
+}
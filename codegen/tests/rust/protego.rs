@@ -0,0 +1,105 @@
+// Generated from PotterScript by potterscript-codegen, do not edit.
+
+use potterscript_parser::BinaryOperation;
+use potterscript_runtime::{spells, RuntimeAdapter, RuntimeError, RuntimeValue};
+
+/// `?` inside a `protego` block, which goes on to its `expelliarmus` block
+/// instead of returning.
+macro_rules! protego {
+    ($block:lifetime, $result:expr) => {
+        match $result {
+            Ok(value) => value,
+            Err(error) => break $block Err(error),
+        }
+    };
+}
+
+fn get(variable: &Option<RuntimeValue>, name: &str) -> Result<RuntimeValue, RuntimeError> {
+    variable
+        .clone()
+        .ok_or_else(|| RuntimeError::VariableNotFound(name.to_string()))
+}
+
+fn revelio(adapter: &impl RuntimeAdapter, lumos: bool, value: Option<RuntimeValue>) {
+    let text = value.map(|value| value.to_string()).unwrap_or_default();
+    adapter.log(&if lumos { adapter.lumos(text) } else { text });
+}
+
+fn field(value: RuntimeValue, name: &str) -> Result<RuntimeValue, RuntimeError> {
+    match value {
+        RuntimeValue::Map(mut entries) => match entries.remove(name) {
+            Some(value) => Ok(value),
+            None => Err(RuntimeError::FieldNotFound(RuntimeValue::Map(entries), name.to_string())),
+        },
+        value => Err(RuntimeError::FieldNotFound(value, name.to_string())),
+    }
+}
+
+/// Runs the program, going through `adapter` for logs, random picks, sleeps
+/// and input.
+#[allow(
+    unused_mut,
+    unused_assignments,
+    unused_variables,
+    unused_labels,
+    unreachable_code,
+    non_snake_case,
+    clippy::all
+)]
+pub fn run(adapter: &impl RuntimeAdapter) -> Result<(), RuntimeError> {
+    let mut lumos = false;
+    // Cleared by `snitch` to end the loops running
+    let mut quidditch = false;
+    let mut err: Option<RuntimeValue> = None;
+    let mut i: Option<RuntimeValue> = None;
+
+    let result: Result<(), RuntimeError> = 'protego_1: {
+        revelio(adapter, lumos, Some(protego!('protego_1, RuntimeValue::Integer(1).binary_operation(BinaryOperation::Divide, RuntimeValue::Integer(0)))));
+        Ok(())
+    };
+    if let Err(error) = result {
+        if !error.is_catchable() { return Err(error); }
+        err = Some(error.to_value());
+        revelio(adapter, lumos, Some(field(get(&err, "err")?, "kind")?));
+        revelio(adapter, lumos, Some(field(get(&err, "err")?, "message")?));
+    }
+    let result: Result<(), RuntimeError> = 'protego_2: {
+        break 'protego_2 Err(spells::confringo(Some(RuntimeValue::from("Mischief managed"))));
+        revelio(adapter, lumos, Some(RuntimeValue::from("unreachable")));
+        Ok(())
+    };
+    if let Err(error) = result {
+        if !error.is_catchable() { return Err(error); }
+        err = Some(error.to_value());
+        revelio(adapter, lumos, Some(get(&err, "err")?));
+    }
+    i = Some(RuntimeValue::Integer(0));
+    quidditch = true;
+    while quidditch {
+        i = Some(get(&i, "i").and_then(spells::engorgio)?);
+        quidditch = false;
+        let quidditch_before = quidditch;
+        let result: Result<(), RuntimeError> = 'protego_3: {
+            quidditch = true;
+            while quidditch {
+                break 'protego_3 Err(spells::confringo(Some(RuntimeValue::from("Lost the snitch"))));
+            }
+            Ok(())
+        };
+        if let Err(error) = result {
+            if !error.is_catchable() { return Err(error); }
+            quidditch = quidditch_before;
+            err = Some(error.to_value());
+            revelio(adapter, lumos, Some(field(get(&err, "err")?, "message")?));
+        }
+    }
+    'quidditch_4: loop {
+        i = Some(get(&i, "i").and_then(spells::engorgio)?);
+        if get(&i, "i")?.binary_operation(BinaryOperation::Equal, RuntimeValue::Integer(3))? == RuntimeValue::Boolean(true) {
+            break 'quidditch_4;
+        }
+    }
+    revelio(adapter, lumos, Some(get(&i, "i")?));
+    return Err(spells::confringo(Some(RuntimeValue::from("Uncaught"))));
+    Ok(())
+}
@@ -0,0 +1,173 @@
+// Generated from PotterScript by potterscript-codegen, do not edit.
+
+use potterscript_parser::{Atom, BinaryOperation, Expression, HogwartsHouse, Spell};
+use potterscript_runtime::{spells, RuntimeAdapter, RuntimeError, RuntimeValue};
+
+/// `?` inside a `protego` block, which goes on to its `expelliarmus` block
+/// instead of returning.
+macro_rules! protego {
+    ($block:lifetime, $result:expr) => {
+        match $result {
+            Ok(value) => value,
+            Err(error) => break $block Err(error),
+        }
+    };
+}
+
+fn get(variable: &Option<RuntimeValue>, name: &str) -> Result<RuntimeValue, RuntimeError> {
+    variable
+        .clone()
+        .ok_or_else(|| RuntimeError::VariableNotFound(name.to_string()))
+}
+
+/// The new value of a variable a spell changes, unless it is a constant.
+fn reassign(name: &str, constant: bool, value: RuntimeValue) -> Result<RuntimeValue, RuntimeError> {
+    if constant {
+        return Err(RuntimeError::ConstantReassignment(name.to_string()));
+    }
+    Ok(value)
+}
+
+fn revelio(adapter: &impl RuntimeAdapter, lumos: bool, value: Option<RuntimeValue>) {
+    let text = value.map(|value| value.to_string()).unwrap_or_default();
+    adapter.log(&if lumos { adapter.lumos(text) } else { text });
+}
+
+fn field(value: RuntimeValue, name: &str) -> Result<RuntimeValue, RuntimeError> {
+    match value {
+        RuntimeValue::Map(mut entries) => match entries.remove(name) {
+            Some(value) => Ok(value),
+            None => Err(RuntimeError::FieldNotFound(RuntimeValue::Map(entries), name.to_string())),
+        },
+        value => Err(RuntimeError::FieldNotFound(value, name.to_string())),
+    }
+}
+
+fn unbreakable_vow(expression: &Expression, value: Option<RuntimeValue>) -> Result<(), RuntimeError> {
+    spells::unbreakable_vow(expression, value.clone(), value.into_iter().collect())
+}
+
+/// A vow on an operation, which reports both operands if it breaks.
+fn unbreakable_vow_operation(
+    expression: &Expression,
+    operation: BinaryOperation,
+    left: Option<RuntimeValue>,
+    right: Option<RuntimeValue>,
+) -> Result<(), RuntimeError> {
+    let (value, operands) = spells::vow_operation(operation, left, right)?;
+    spells::unbreakable_vow(expression, value, operands)
+}
+
+/// Runs the program, going through `adapter` for logs, random picks, sleeps
+/// and input.
+#[allow(
+    unused_mut,
+    unused_assignments,
+    unused_variables,
+    unused_labels,
+    unreachable_code,
+    non_snake_case,
+    clippy::all
+)]
+pub fn run(adapter: &impl RuntimeAdapter) -> Result<(), RuntimeError> {
+    let mut lumos = false;
+    // Cleared by `snitch` to end the loops running
+    let mut quidditch = false;
+    let mut count: Option<RuntimeValue> = None;
+    let mut house: Option<RuntimeValue> = None;
+    let mut divisor: Option<RuntimeValue> = None;
+    let mut error_: Option<RuntimeValue> = None;
+    let mut round: Option<RuntimeValue> = None;
+    let mut laps: Option<RuntimeValue> = None;
+    let mut lucky: Option<RuntimeValue> = None;
+    let mut name: Option<RuntimeValue> = None;
+    let mut missing: Option<RuntimeValue> = None;
+    let mut name_constant = false;
+
+    // Covers what the examples don't
+    lumos = true;
+    count = Some(RuntimeValue::Integer(0));
+    quidditch = true;
+    while quidditch {
+        count = Some(get(&count, "count").and_then(spells::engorgio)?);
+        house = Some(RuntimeValue::HogwartsHouse(HogwartsHouse::ALL[adapter.random_integer(0..=3) as usize]));
+        revelio(adapter, lumos, Some(get(&house, "house")?));
+        let result: Result<(), RuntimeError> = 'protego_1: {
+            if protego!('protego_1, protego!('protego_1, get(&count, "count")).binary_operation(BinaryOperation::Equal, RuntimeValue::Integer(3))) == RuntimeValue::Boolean(true) {
+                quidditch = false;
+            }
+            divisor = Some(protego!('protego_1, protego!('protego_1, get(&count, "count")).binary_operation(BinaryOperation::Minus, RuntimeValue::Integer(2))));
+            revelio(adapter, lumos, Some(protego!('protego_1, RuntimeValue::Integer(10).binary_operation(BinaryOperation::Divide, protego!('protego_1, get(&divisor, "divisor"))))));
+            Ok(())
+        };
+        if let Err(error) = result {
+            if !error.is_catchable() { return Err(error); }
+            error_ = Some(error.to_value());
+            revelio(adapter, lumos, Some(field(get(&error_, "error")?, "kind")?));
+        }
+    }
+    lumos = false;
+    round = Some(RuntimeValue::Integer(0));
+    quidditch = true;
+    while quidditch {
+        round = Some(get(&round, "round").and_then(spells::engorgio)?);
+        laps = Some(RuntimeValue::Integer(0));
+        quidditch = true;
+        while quidditch {
+            laps = Some(get(&laps, "laps").and_then(spells::engorgio)?);
+            if get(&laps, "laps")?.binary_operation(BinaryOperation::Equal, RuntimeValue::Integer(2))? == RuntimeValue::Boolean(true) {
+                quidditch = false;
+                revelio(adapter, lumos, Some(RuntimeValue::from("the lap ends")));
+            }
+        }
+        revelio(adapter, lumos, Some(get(&round, "round")?));
+    }
+    unbreakable_vow_operation(&Expression::BinaryOperation(BinaryOperation::Equal, Box::new(Expression::Atom(Atom::Variable("round".to_string()))), Box::new(Expression::Atom(Atom::Integer(1)))), BinaryOperation::Equal, Some(get(&round, "round")?), Some(RuntimeValue::Integer(1)))?;
+    unbreakable_vow(&Expression::Atom(Atom::Boolean(true)), Some(RuntimeValue::Boolean(true)))?;
+    lucky = Some(spells::felix_felicis(adapter, Some(RuntimeValue::Integer(10)))?);
+    revelio(adapter, lumos, Some(get(&lucky, "lucky")?));
+    if name_constant { return Err(RuntimeError::ConstantReassignment("name".to_string())); }
+    name = Some(RuntimeValue::from("Harry"));
+    if name.is_some() { name_constant = true }
+    if !name_constant { name = None }
+    let result: Result<(), RuntimeError> = 'protego_2: {
+        name = Some(protego!('protego_2, get(&name, "name").and_then(|value| spells::append(Spell::Incendio, value, "🔥")).and_then(|value| reassign("name", name_constant, value))));
+        Ok(())
+    };
+    if let Err(error) = result {
+        if !error.is_catchable() { return Err(error); }
+        error_ = Some(error.to_value());
+        revelio(adapter, lumos, Some(field(get(&error_, "error")?, "message")?));
+    }
+    let result: Result<(), RuntimeError> = 'protego_3: {
+        if name_constant { break 'protego_3 Err(RuntimeError::ConstantReassignment("name".to_string())); }
+        name = Some(RuntimeValue::from("Ron"));
+        Ok(())
+    };
+    if let Err(error) = result {
+        if !error.is_catchable() { return Err(error); }
+        error_ = Some(error.to_value());
+        revelio(adapter, lumos, Some(field(get(&error_, "error")?, "message")?));
+    }
+    revelio(adapter, lumos, Some(get(&name, "name")?));
+    let result: Result<(), RuntimeError> = 'protego_4: {
+        revelio(adapter, lumos, Some(protego!('protego_4, get(&missing, "missing"))));
+        Ok(())
+    };
+    if let Err(error) = result {
+        if !error.is_catchable() { return Err(error); }
+        error_ = Some(error.to_value());
+        revelio(adapter, lumos, Some(field(get(&error_, "error")?, "message")?));
+    }
+    let result: Result<(), RuntimeError> = 'protego_5: {
+        { revelio(adapter, lumos, Some(RuntimeValue::from("evaluated first"))); break 'protego_5 Err(RuntimeError::UnknownSpell("Expecto".to_string())) }
+        Ok(())
+    };
+    if let Err(error) = result {
+        if !error.is_catchable() { return Err(error); }
+        error_ = Some(error.to_value());
+        revelio(adapter, lumos, Some(field(get(&error_, "error")?, "kind")?));
+    }
+    unbreakable_vow_operation(&Expression::BinaryOperation(BinaryOperation::Equal, Box::new(Expression::Atom(Atom::Variable("name".to_string()))), Box::new(Expression::Atom(Atom::String("Draco".to_string())))), BinaryOperation::Equal, Some(get(&name, "name")?), Some(RuntimeValue::from("Draco")))?;
+    Ok(())
+}
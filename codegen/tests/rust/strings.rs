@@ -0,0 +1,46 @@
+// Generated from PotterScript by potterscript-codegen, do not edit.
+
+use potterscript_parser::BinaryOperation;
+use potterscript_runtime::{spells, RuntimeAdapter, RuntimeError, RuntimeValue};
+
+fn get(variable: &Option<RuntimeValue>, name: &str) -> Result<RuntimeValue, RuntimeError> {
+    variable
+        .clone()
+        .ok_or_else(|| RuntimeError::VariableNotFound(name.to_string()))
+}
+
+fn revelio(adapter: &impl RuntimeAdapter, lumos: bool, value: Option<RuntimeValue>) {
+    let text = value.map(|value| value.to_string()).unwrap_or_default();
+    adapter.log(&if lumos { adapter.lumos(text) } else { text });
+}
+
+/// Runs the program, going through `adapter` for logs, random picks, sleeps
+/// and input.
+#[allow(
+    unused_mut,
+    unused_assignments,
+    unused_variables,
+    unused_labels,
+    unreachable_code,
+    non_snake_case,
+    clippy::all
+)]
+pub fn run(adapter: &impl RuntimeAdapter) -> Result<(), RuntimeError> {
+    let mut lumos = false;
+    let mut spell: Option<RuntimeValue> = None;
+    let mut fire: Option<RuntimeValue> = None;
+    let mut water: Option<RuntimeValue> = None;
+    let mut glasses: Option<RuntimeValue> = None;
+
+    spell = Some(RuntimeValue::from("lumos"));
+    spell = Some(get(&spell, "spell").and_then(spells::engorgio)?);
+    revelio(adapter, lumos, Some(get(&spell, "spell")?));
+    spell = Some(get(&spell, "spell").and_then(spells::reducio)?);
+    revelio(adapter, lumos, Some(get(&spell, "spell")?));
+    fire = Some(RuntimeValue::from("hot🔥"));
+    revelio(adapter, lumos, Some(get(&fire, "fire")?));
+    water = Some(RuntimeValue::from("💦"));
+    glasses = Some(RuntimeValue::from("👓"));
+    revelio(adapter, lumos, Some(get(&water, "water")?.binary_operation(BinaryOperation::Plus, get(&glasses, "glasses")?)?));
+    Ok(())
+}
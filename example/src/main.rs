@@ -9,6 +9,6 @@ fn main() {
     let mut runtime = potterscript_runtime::Runtime::new(DefaultRuntimeAdapter);
 
     println!("\nRunning PotterScript program...");
-    runtime.eval(program);
+    runtime.eval(&program).unwrap();
     println!("PotterScript program finished running.");
 }
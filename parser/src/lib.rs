@@ -5,7 +5,7 @@ use nom::{
     bytes::complete::{tag, take_till, take_until},
     character::complete::{alpha0, alpha1, char, i64, multispace0},
     combinator::{map, opt},
-    multi::many1,
+    multi::{fold_many0, many1},
     number::complete::double,
     sequence::{delimited, preceded, terminated, tuple},
     IResult,
@@ -32,6 +32,15 @@ pub enum HogwartsHouse {
     Slytherin,
 }
 
+impl HogwartsHouse {
+    pub const ALL: [HogwartsHouse; 4] = [
+        HogwartsHouse::Gryffindor,
+        HogwartsHouse::Hufflepuff,
+        HogwartsHouse::Ravenclaw,
+        HogwartsHouse::Slytherin,
+    ];
+}
+
 impl fmt::Display for HogwartsHouse {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
@@ -43,15 +52,15 @@ impl fmt::Display for HogwartsHouse {
     }
 }
 
-impl Atom {
-    pub fn to_string(&self) -> String {
+impl fmt::Display for Atom {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
-            Atom::Boolean(boolean) => boolean.to_string(),
-            Atom::Integer(integer) => integer.to_string(),
-            Atom::Double(float) => float.to_string(),
-            Atom::String(string) => string.to_string(),
-            Atom::Variable(var) => var.to_string(),
-            Atom::HogwartsHouse(house) => house.to_string(),
+            Atom::Boolean(boolean) => write!(f, "{}", boolean),
+            Atom::Integer(integer) => write!(f, "{}", integer),
+            Atom::Double(float) => write!(f, "{}", float),
+            Atom::String(string) => write!(f, "{}", string),
+            Atom::Variable(var) => write!(f, "{}", var),
+            Atom::HogwartsHouse(house) => write!(f, "{}", house),
         }
     }
 }
@@ -71,7 +80,14 @@ fn parse_atom(input: &str) -> IResult<&str, Expression> {
         parse_string,
         parse_variable,
     ));
-    map(parser, |atom| atom.into())(input)
+    let (rest, atom) = map(parser, Expression::from)(input)?;
+
+    // Fields of a map, e.g. `err.message`
+    fold_many0(
+        preceded(char('.'), alpha1),
+        move || atom.clone(),
+        |expression, field: &str| Expression::Field(Box::new(expression), field.to_string()),
+    )(rest)
 }
 
 fn parse_boolean(input: &str) -> IResult<&str, Atom> {
@@ -80,20 +96,21 @@ fn parse_boolean(input: &str) -> IResult<&str, Atom> {
 }
 
 fn parse_double(input: &str) -> IResult<&str, Atom> {
-    if !input.contains(".") {
+    let (rest, value) = double(input)?;
+    // Only numbers written with a dot are doubles, `6` is an integer
+    if !input[..input.len() - rest.len()].contains('.') {
         return Err(nom::Err::Error(nom::error::Error::new(
             input,
             nom::error::ErrorKind::Digit,
         )));
     }
 
-    let parser = double;
-    map(parser, |float| Atom::Double(float))(input)
+    Ok((rest, Atom::Double(value)))
 }
 
 fn parse_integer(input: &str) -> IResult<&str, Atom> {
     let parser = i64;
-    map(parser, |integer| Atom::Integer(integer))(input)
+    map(parser, Atom::Integer)(input)
 }
 
 fn parse_string(input: &str) -> IResult<&str, Atom> {
@@ -128,15 +145,20 @@ pub enum Expression {
     SpellCast(Spell, Box<Option<Expression>>),
     BinaryOperation(BinaryOperation, Box<Expression>, Box<Expression>),
     Atom(Atom),
+    /// A field of a map, e.g. `err.kind`.
+    Field(Box<Expression>, String),
     Comment(String),
     SortingHat,
 }
 
 #[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
 pub enum Spell {
+    Accio,
     Aguamenti,
     AvadaKedabra,
+    Confringo,
     Engorgio,
+    FelixFelicis,
     Incendio,
     Inmobolus,
     Lumos,
@@ -148,10 +170,46 @@ pub enum Spell {
     PetrificusTotalus,
     Revelio,
     Serpensortia,
+    UnbreakableVow,
     WingardiumLeviosa,
+    /// Any other spell, registered by the host running the program.
+    Custom(String),
+}
+
+impl Spell {
+    pub const ALL: [Spell; 19] = [
+        Spell::Accio,
+        Spell::Aguamenti,
+        Spell::AvadaKedabra,
+        Spell::Confringo,
+        Spell::Engorgio,
+        Spell::FelixFelicis,
+        Spell::Incendio,
+        Spell::Inmobolus,
+        Spell::Lumos,
+        Spell::Nox,
+        Spell::Obliviate,
+        Spell::OculusReparo,
+        Spell::Periculum,
+        Spell::Reducio,
+        Spell::PetrificusTotalus,
+        Spell::Revelio,
+        Spell::Serpensortia,
+        Spell::UnbreakableVow,
+        Spell::WingardiumLeviosa,
+    ];
+}
+
+impl fmt::Display for Spell {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Spell::Custom(name) => write!(f, "{}", name),
+            _ => write!(f, "{:?}", self),
+        }
+    }
 }
 
-#[derive(Debug, Eq, PartialEq, Clone, Serialize, Deserialize)]
+#[derive(Debug, Eq, PartialEq, Clone, Copy, Serialize, Deserialize)]
 pub enum BinaryOperation {
     Plus,
     Minus,
@@ -161,6 +219,39 @@ pub enum BinaryOperation {
     NotEqual,
 }
 
+impl fmt::Display for BinaryOperation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BinaryOperation::Plus => write!(f, "+"),
+            BinaryOperation::Minus => write!(f, "-"),
+            BinaryOperation::Times => write!(f, "*"),
+            BinaryOperation::Divide => write!(f, "/"),
+            BinaryOperation::Equal => write!(f, "=="),
+            BinaryOperation::NotEqual => write!(f, "!="),
+        }
+    }
+}
+
+/// Formats the expression the way it is written in a script.
+impl fmt::Display for Expression {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Expression::SpellCast(spell, target) => match target.as_ref() {
+                Some(target) => write!(f, "~{} {}", spell, target),
+                None => write!(f, "~{}", spell),
+            },
+            Expression::BinaryOperation(operation, left, right) => {
+                write!(f, "{} {} {}", left, operation, right)
+            }
+            Expression::Atom(Atom::String(string)) => write!(f, "\"{}\"", string),
+            Expression::Atom(atom) => write!(f, "{}", atom),
+            Expression::Field(expression, field) => write!(f, "{}.{}", expression, field),
+            Expression::Comment(comment) => write!(f, "#{}", comment),
+            Expression::SortingHat => write!(f, "SortingHat"),
+        }
+    }
+}
+
 pub fn parse_expression(input: &str) -> IResult<&str, Expression> {
     // dbg!("parse_expression");
     // dbg!(input);
@@ -181,28 +272,39 @@ pub fn parse_sorting_hat(input: &str) -> IResult<&str, Expression> {
 
 pub fn parse_spell_cast(input: &str) -> IResult<&str, Expression> {
     // take until ; or ->
-    let spell_parser = delimited(tag("~"), alpha0, opt(tag(" ")));
-    let target_parser = parse_expression;
-    let parser = tuple((spell_parser, opt(target_parser)));
-
-    map(parser, |(spell, target)| match spell {
-        "AvadaKedabra" => Expression::SpellCast(Spell::AvadaKedabra, Box::new(target)),
-        "Aguamenti" => Expression::SpellCast(Spell::Aguamenti, Box::new(target)),
-        "Engorgio" => Expression::SpellCast(Spell::Engorgio, Box::new(target)),
-        "Incendio" => Expression::SpellCast(Spell::Incendio, Box::new(target)),
-        "Inmobolus" => Expression::SpellCast(Spell::Inmobolus, Box::new(target)),
-        "Lumos" => Expression::SpellCast(Spell::Lumos, Box::new(target)),
-        "Nox" => Expression::SpellCast(Spell::Nox, Box::new(target)),
-        "Obliviate" => Expression::SpellCast(Spell::Obliviate, Box::new(target)),
-        "OculusReparo" => Expression::SpellCast(Spell::OculusReparo, Box::new(target)),
-        "Periculum" => Expression::SpellCast(Spell::Periculum, Box::new(target)),
-        "Reducio" => Expression::SpellCast(Spell::Reducio, Box::new(target)),
-        "PetrificusTotalus" => Expression::SpellCast(Spell::PetrificusTotalus, Box::new(target)),
-        "Revelio" => Expression::SpellCast(Spell::Revelio, Box::new(target)),
-        "Serpensortia" => Expression::SpellCast(Spell::Serpensortia, Box::new(target)),
-        "WingardiumLeviosa" => Expression::SpellCast(Spell::WingardiumLeviosa, Box::new(target)),
-        _ => panic!("Wand broken: Unknown spell: {}", spell),
-    })(input)
+    let (rest, spell) = delimited(tag("~"), alpha0, opt(tag(" ")))(input)?;
+    let spell = match spell {
+        "Accio" => Spell::Accio,
+        "AvadaKedabra" => Spell::AvadaKedabra,
+        "Aguamenti" => Spell::Aguamenti,
+        "Confringo" => Spell::Confringo,
+        "Engorgio" => Spell::Engorgio,
+        "FelixFelicis" => Spell::FelixFelicis,
+        "Incendio" => Spell::Incendio,
+        "Inmobolus" => Spell::Inmobolus,
+        "Lumos" => Spell::Lumos,
+        "Nox" => Spell::Nox,
+        "Obliviate" => Spell::Obliviate,
+        "OculusReparo" => Spell::OculusReparo,
+        "Periculum" => Spell::Periculum,
+        "Reducio" => Spell::Reducio,
+        "PetrificusTotalus" => Spell::PetrificusTotalus,
+        "Revelio" => Spell::Revelio,
+        "Serpensortia" => Spell::Serpensortia,
+        "UnbreakableVow" => Spell::UnbreakableVow,
+        "WingardiumLeviosa" => Spell::WingardiumLeviosa,
+        // Wand broken: No spell
+        "" => {
+            return Err(nom::Err::Failure(nom::error::Error::new(
+                input,
+                nom::error::ErrorKind::Alpha,
+            )))
+        }
+        _ => Spell::Custom(spell.to_string()),
+    };
+    let (rest, target) = opt(parse_expression)(rest)?;
+
+    Ok((rest, Expression::SpellCast(spell, Box::new(target))))
 }
 
 pub fn parse_binary_operation(input: &str) -> IResult<&str, Expression> {
@@ -245,13 +347,37 @@ pub enum Statement {
     If(Expression, Vec<Statement>, Vec<Statement>),
     Quidditch(Vec<Statement>),
     Snitch,
+    /// A named test, only run by a test runner.
+    Vow(String, Vec<Statement>),
+    /// `protego { ... } expelliarmus err { ... }`: runs the second block with
+    /// the error bound to `err` if the first one fails.
+    Protego(Vec<Statement>, String, Vec<Statement>),
 }
 
+/// A statement at the top level of a program, the only place `vow` blocks
+/// can go.
 fn parse_statement(input: &str) -> IResult<&str, Statement> {
+    alt((
+        preceded(multispace0, terminated(parse_vow_statement, multispace0)),
+        parse_block_statement,
+    ))(input)
+}
+
+/// A statement inside a block. A `vow` block there would never run, so it
+/// fails the parse.
+fn parse_block_statement(input: &str) -> IResult<&str, Statement> {
+    if parse_vow_statement(input).is_ok() {
+        return Err(nom::Err::Failure(nom::error::Error::new(
+            input.trim_start(),
+            nom::error::ErrorKind::Verify,
+        )));
+    }
+
     let parser_content = alt((
         parse_if_statement,
         parse_snitch_statement,
         parse_quidditch_statement,
+        parse_protego_statement,
         parse_variable_assignment,
         parse_expression_statement,
     ));
@@ -281,9 +407,9 @@ fn parse_expression_statement(input: &str) -> IResult<&str, Statement> {
 fn parse_if_statement(input: &str) -> IResult<&str, Statement> {
     let parse_if = preceded(multispace0, terminated(tag("if"), multispace0));
     let parse_condition = preceded(multispace0, terminated(parse_expression, multispace0));
-    let parse_true_block = delimited(char('{'), many1(parse_statement), char('}'));
+    let parse_true_block = delimited(char('{'), many1(parse_block_statement), char('}'));
     let parse_else = preceded(multispace0, terminated(tag("else"), multispace0));
-    let parse_false_block = delimited(char('{'), many1(parse_statement), char('}'));
+    let parse_false_block = delimited(char('{'), many1(parse_block_statement), char('}'));
 
     map(
         tuple((
@@ -299,13 +425,47 @@ fn parse_if_statement(input: &str) -> IResult<&str, Statement> {
 
 fn parse_quidditch_statement(input: &str) -> IResult<&str, Statement> {
     let parse_quidditch = preceded(multispace0, terminated(tag("quidditch"), multispace0));
-    let parse_block = delimited(char('{'), many1(parse_statement), char('}'));
+    let parse_block = delimited(char('{'), many1(parse_block_statement), char('}'));
 
     map(preceded(parse_quidditch, parse_block), |block| {
         Statement::Quidditch(block)
     })(input)
 }
 
+fn parse_vow_statement(input: &str) -> IResult<&str, Statement> {
+    let parse_vow = preceded(multispace0, terminated(tag("vow"), multispace0));
+    let parse_name = terminated(parse_string, multispace0);
+    let parse_block = delimited(char('{'), many1(parse_block_statement), char('}'));
+
+    map(
+        tuple((preceded(parse_vow, parse_name), parse_block)),
+        |(name, block)| match name {
+            Atom::String(name) => Statement::Vow(name, block),
+            _ => unreachable!(),
+        },
+    )(input)
+}
+
+fn parse_protego_statement(input: &str) -> IResult<&str, Statement> {
+    let parse_protego = preceded(multispace0, terminated(tag("protego"), multispace0));
+    let parse_block = delimited(char('{'), many1(parse_block_statement), char('}'));
+    let parse_expelliarmus = preceded(multispace0, terminated(tag("expelliarmus"), multispace0));
+    let parse_name = terminated(parse_variable, multispace0);
+    let parse_catch_block = delimited(char('{'), many1(parse_block_statement), char('}'));
+
+    map(
+        tuple((
+            preceded(parse_protego, parse_block),
+            preceded(parse_expelliarmus, parse_name),
+            parse_catch_block,
+        )),
+        |(block, name, catch_block)| match name {
+            Atom::Variable(name) => Statement::Protego(block, name, catch_block),
+            _ => unreachable!(),
+        },
+    )(input)
+}
+
 fn parse_snitch_statement(input: &str) -> IResult<&str, Statement> {
     let parse_snitch = preceded(multispace0, terminated(tag("snitch"), multispace0));
 
@@ -321,6 +481,104 @@ pub fn parse_program(input: &str) -> IResult<&str, Program> {
     map(many1(terminated(parse_statement, multispace0)), Program)(input)
 }
 
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseError {
+    pub offset: usize,
+    pub line: usize,
+    pub column: usize,
+    pub token: String,
+}
+
+impl ParseError {
+    fn new(input: &str, rest: &str) -> Self {
+        let offset = input.len() - rest.len();
+        let consumed = &input[..offset];
+        let line = consumed.matches('\n').count() + 1;
+        let column = consumed.chars().rev().take_while(|&c| c != '\n').count() + 1;
+        let token = rest
+            .split_whitespace()
+            .next()
+            .unwrap_or_default()
+            .to_string();
+
+        Self {
+            offset,
+            line,
+            column,
+            token,
+        }
+    }
+
+    pub fn span(&self) -> Span {
+        Span {
+            start: self.offset,
+            end: self.offset + self.token.len(),
+        }
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.token.is_empty() {
+            write!(
+                f,
+                "Unexpected end of input at line {}, column {}",
+                self.line, self.column
+            )
+        } else {
+            write!(
+                f,
+                "Unexpected `{}` at line {}, column {}",
+                self.token, self.line, self.column
+            )
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+/// Byte range of the source code a statement was parsed from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+/// Parses a whole source file, failing if any input is left unparsed.
+pub fn parse(input: &str) -> Result<Program, ParseError> {
+    let statements = parse_spanned(input)?;
+    Ok(Program(
+        statements
+            .into_iter()
+            .map(|(statement, _)| statement)
+            .collect(),
+    ))
+}
+
+/// Like [`parse`], but also returns the span of every top-level statement.
+pub fn parse_spanned(input: &str) -> Result<Vec<(Statement, Span)>, ParseError> {
+    let mut statements = vec![];
+    let mut rest = input.trim_start();
+
+    while !rest.is_empty() {
+        let start = input.len() - rest.len();
+        let next = match parse_statement(rest) {
+            Ok((next, _)) if next.len() == rest.len() => return Err(ParseError::new(input, rest)),
+            Ok((next, statement)) => {
+                let end = start + rest[..rest.len() - next.len()].trim_end().len();
+                statements.push((statement, Span { start, end }));
+                next
+            }
+            Err(nom::Err::Error(_)) => return Err(ParseError::new(input, rest)),
+            Err(nom::Err::Failure(error)) => return Err(ParseError::new(input, error.input)),
+            Err(nom::Err::Incomplete(_)) => return Err(ParseError::new(input, "")),
+        };
+        rest = next.trim_start();
+    }
+
+    Ok(statements)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -365,6 +623,9 @@ mod tests {
         let expected = Atom::Double(123.456);
         let (_, actual) = parse_double(input).unwrap();
         assert_eq!(expected, actual);
+
+        let (_, actual) = parse_atom("6\nx = 1.5").unwrap();
+        assert_eq!(Expression::Atom(Atom::Integer(6)), actual);
     }
 
     #[test]
@@ -644,6 +905,66 @@ mod tests {
         assert_eq!(expected, actual);
     }
 
+    #[test]
+    fn test_parse_vow() {
+        let input = "vow \"counts\" {
+  ~UnbreakableVow x == 1
+}";
+        let expected = Statement::Vow(
+            "counts".to_string(),
+            vec![Statement::ExpressionStatement(Expression::SpellCast(
+                Spell::UnbreakableVow,
+                Box::new(Some(Expression::BinaryOperation(
+                    BinaryOperation::Equal,
+                    Box::new(Atom::Variable("x".to_string()).into()),
+                    Box::new(Atom::Integer(1).into()),
+                ))),
+            ))],
+        );
+        let (_, actual) = parse_vow_statement(input).unwrap();
+        assert_eq!(expected, actual);
+    }
+
+    #[test]
+    fn test_parse_protego() {
+        let input = "protego {
+  ~Confringo \"Boom\"
+} expelliarmus err {
+  ~Revelio err.message
+}";
+        let expected = Statement::Protego(
+            vec![Statement::ExpressionStatement(Expression::SpellCast(
+                Spell::Confringo,
+                Box::new(Some(Atom::String("Boom".to_string()).into())),
+            ))],
+            "err".to_string(),
+            vec![Statement::ExpressionStatement(Expression::SpellCast(
+                Spell::Revelio,
+                Box::new(Some(Expression::Field(
+                    Box::new(Atom::Variable("err".to_string()).into()),
+                    "message".to_string(),
+                ))),
+            ))],
+        );
+        let (_, actual) = parse_protego_statement(input).unwrap();
+        assert_eq!(expected, actual);
+    }
+
+    #[test]
+    fn test_display_expression() {
+        let input = "~Revelio x";
+        let (_, expression) = parse_expression(input).unwrap();
+        assert_eq!(input, expression.to_string());
+
+        let input = "\"a\" != Gryffindor";
+        let (_, expression) = parse_expression(input).unwrap();
+        assert_eq!(input, expression.to_string());
+
+        let input = "err.kind == \"DivisionByZero\"";
+        let (_, expression) = parse_expression(input).unwrap();
+        assert_eq!(input, expression.to_string());
+    }
+
     // Program
 
     #[test]
@@ -663,6 +984,77 @@ mod tests {
         assert_eq!(expected, actual);
     }
 
+    #[test]
+    fn test_parse() {
+        let input = "x = 1\n~Revelio x\n";
+        let expected = Program(vec![
+            Statement::VariableAssignment("x".to_string(), Atom::Integer(1).into()),
+            Statement::ExpressionStatement(Expression::SpellCast(
+                Spell::Revelio,
+                Box::new(Some(Atom::Variable("x".to_string()).into())),
+            )),
+        ]);
+        let actual = parse(input).unwrap();
+        assert_eq!(expected, actual);
+    }
+
+    #[test]
+    fn test_parse_empty() {
+        let actual = parse(" \n").unwrap();
+        assert_eq!(Program(vec![]), actual);
+    }
+
+    #[test]
+    fn test_parse_spell_cast_custom() {
+        let input = "~Expecto \"patronum\"";
+        let expected = Expression::SpellCast(
+            Spell::Custom("Expecto".to_string()),
+            Box::new(Some(Atom::String("patronum".to_string()).into())),
+        );
+        let (_, actual) = parse_spell_cast(input).unwrap();
+        assert_eq!(expected, actual);
+    }
+
+    #[test]
+    fn test_parse_nested_vow() {
+        let input = "quidditch {\n  vow \"inside\" {\n    ~Revelio 1\n  }\n}";
+        let expected = ParseError {
+            offset: 14,
+            line: 2,
+            column: 3,
+            token: "vow".to_string(),
+        };
+        assert_eq!(expected, parse(input).unwrap_err());
+    }
+
+    #[test]
+    fn test_parse_missing_spell() {
+        let input = "~Revelio 4\n  ~ \"patronum\"";
+        let expected = ParseError {
+            offset: 13,
+            line: 2,
+            column: 3,
+            token: "~".to_string(),
+        };
+        let actual = parse(input).unwrap_err();
+        assert_eq!(expected, actual);
+        assert_eq!("Unexpected `~` at line 2, column 3", actual.to_string());
+    }
+
+    #[test]
+    fn test_parse_spanned() {
+        let input = "x = 1\n\nif x == 1 {\n  ~Revelio x\n}  # done\n";
+        let statements = parse_spanned(input).unwrap();
+        let spans: Vec<&str> = statements
+            .iter()
+            .map(|(_, span)| &input[span.start..span.end])
+            .collect();
+        assert_eq!(
+            vec!["x = 1", "if x == 1 {\n  ~Revelio x\n}", "# done"],
+            spans
+        );
+    }
+
     #[test]
     fn test_parse_program_serialized() {
         let code = r#"index = 0
@@ -24,9 +24,20 @@ quidditch {
   }
 }`
 
+type Output = 'ast' | 'javascript'
+
+const outputTitles: Record<Output, string> = {
+  ast: 'Abstract Syntax Tree (AST)',
+  javascript: 'JavaScript'
+}
+
 export function Playground() {
   const [code, setCode] = useState(defaultCode)
-  const [result, setResult] = useState('')
+  const [output, setOutput] = useState<Output>('ast')
+  const [results, setResults] = useState<Record<Output, string>>({ ast: '', javascript: '' })
+  const [seed, setSeed] = useState('')
+  const [lastSeed, setLastSeed] = useState('')
+  const result = results[output]
   const { wasm, loading } = usePotterScript()
   const { toast } = useToast()
 
@@ -36,11 +47,20 @@ export function Playground() {
       if (loading || !wasm) return
 
       const ast = JSON.parse(wasm.parse(code))
-      wasm.parse_and_run(code)
+      setLastSeed(
+        wasm.parse_and_run(
+          code,
+          (prompt?: string) => window.prompt(prompt ?? ''),
+          seed.trim() || undefined
+        )
+      )
 
-      setResult(JSON.stringify(ast, null, 2))
+      setResults({
+        ast: JSON.stringify(ast, null, 2),
+        javascript: wasm.to_javascript(code)
+      })
     },
-    [wasm, code, loading]
+    [wasm, code, seed, loading]
   )
 
   const handleCopy = useCallback(() => {
@@ -80,7 +100,14 @@ export function Playground() {
             onChange={(e) => setCode(e.target.value)}
             className="flex-grow bg-muted font-mono"
           />
-          <div className="flex items-center justify-end">
+          <div className="flex items-center justify-between space-x-2">
+            <input
+              placeholder={lastSeed ? `Seed (last: ${lastSeed})` : 'Seed'}
+              value={seed}
+              onChange={(e) => setSeed(e.target.value)}
+              inputMode="numeric"
+              className="h-9 w-56 rounded-md border border-input bg-transparent px-3 py-1 text-sm shadow-sm font-mono placeholder:text-muted-foreground focus-visible:outline-none focus-visible:ring-1 focus-visible:ring-ring"
+            />
             <div className="flex items-center space-x-2">
               <Button className="block lg:hidden" type="submit">
                 Parse
@@ -94,10 +121,22 @@ export function Playground() {
         </form>
 
         <form className="flex flex-col flex-grow space-y-5">
-          <h2 className="text-lg font-semibold">Abstract Syntax Tree (AST)</h2>
+          <h2 className="text-lg font-semibold">{outputTitles[output]}</h2>
           <Textarea className="flex-grow bg-muted font-mono" readOnly value={result} />
-          <div className="flex items-center justify-end">
-            <Button onClick={handleCopy}>Copy</Button>
+          <div className="flex items-center justify-end space-x-2">
+            {(Object.keys(outputTitles) as Output[]).map((name) => (
+              <Button
+                key={name}
+                type="button"
+                variant={name === output ? 'secondary' : 'ghost'}
+                onClick={() => setOutput(name)}
+              >
+                {name === 'ast' ? 'AST' : 'JavaScript'}
+              </Button>
+            ))}
+            <Button type="button" onClick={handleCopy}>
+              Copy
+            </Button>
           </div>
         </form>
       </div>
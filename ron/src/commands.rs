@@ -0,0 +1,91 @@
+pub const NAMES: [&str; 9] = [
+    ":help", ":vars", ":reset", ":load", ":ast", ":seed", ":save", ":jit", ":quit",
+];
+
+pub const HELP: &str = "\
+:help           Show this help
+:vars           List variables and constants with their types
+:reset          Forget every variable and constant
+:load <file>    Run a .potter file in the current session
+:ast <code>     Print the AST of <code> without running it
+:seed [n]       Seed the Sorting Hat, or print its seed
+:save [file]    Write the session as a script, or print it
+:jit            Toggle running input compiled to native code
+:quit           Exit the REPL";
+
+/// REPL meta-commands, entered as `:name [argument]`.
+#[derive(Debug, PartialEq)]
+pub enum Command<'a> {
+    Help,
+    Vars,
+    Reset,
+    Load(&'a str),
+    Ast(&'a str),
+    Seed(Option<u64>),
+    Save(Option<&'a str>),
+    Jit,
+    Quit,
+}
+
+impl<'a> Command<'a> {
+    pub fn parse(line: &'a str) -> Result<Self, String> {
+        let line = line.trim();
+        let (name, argument) = match line.split_once(char::is_whitespace) {
+            Some((name, argument)) => (name, Some(argument.trim())),
+            None => (line, None),
+        };
+
+        match (name, argument) {
+            (":help", None) => Ok(Command::Help),
+            (":vars", None) => Ok(Command::Vars),
+            (":reset", None) => Ok(Command::Reset),
+            (":load", Some(file)) => Ok(Command::Load(file)),
+            (":ast", Some(code)) => Ok(Command::Ast(code)),
+            (":seed", Some(seed)) => seed
+                .parse()
+                .map(|seed| Command::Seed(Some(seed)))
+                .map_err(|_| format!("Invalid seed: {}", seed)),
+            (":seed", None) => Ok(Command::Seed(None)),
+            (":save", file) => Ok(Command::Save(file)),
+            (":jit", None) => Ok(Command::Jit),
+            (":quit", None) => Ok(Command::Quit),
+            (":load" | ":ast", None) => Err(format!("Missing argument for {}", name)),
+            _ if NAMES.contains(&name) => Err(format!("Unexpected argument for {}", name)),
+            _ => Err(format!("Unknown command {}, try :help", name)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_command() {
+        assert_eq!(Ok(Command::Vars), Command::parse(":vars"));
+        assert_eq!(
+            Ok(Command::Load("a.potter")),
+            Command::parse(":load  a.potter ")
+        );
+        assert_eq!(
+            Ok(Command::Ast("x = 1 + 2")),
+            Command::parse(":ast x = 1 + 2")
+        );
+        assert_eq!(Ok(Command::Seed(Some(42))), Command::parse(":seed 42"));
+        assert_eq!(Ok(Command::Seed(None)), Command::parse(":seed"));
+        assert_eq!(Ok(Command::Save(None)), Command::parse(":save"));
+        assert_eq!(Ok(Command::Jit), Command::parse(":jit"));
+        assert_eq!(
+            Ok(Command::Save(Some("s.potter"))),
+            Command::parse(":save s.potter")
+        );
+    }
+
+    #[test]
+    fn test_parse_invalid_command() {
+        assert!(Command::parse(":seed hat").is_err());
+        assert!(Command::parse(":load").is_err());
+        assert!(Command::parse(":vars x").is_err());
+        assert!(Command::parse(":expecto").is_err());
+    }
+}
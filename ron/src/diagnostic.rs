@@ -0,0 +1,60 @@
+use potterscript_parser::Span;
+
+/// Renders an error `message` followed by the line of `source` where `span`
+/// starts, underlining the spanned code:
+///
+/// ```text
+/// error: Cannot subtract String("a") and Integer(1)
+///   |
+/// 2 | "a" - 1
+///   | ^^^^^^^
+/// ```
+pub fn render(source: &str, span: Span, message: &str) -> String {
+    let line_start = source[..span.start]
+        .rfind('\n')
+        .map_or(0, |index| index + 1);
+    let line_end = source[span.start..]
+        .find('\n')
+        .map_or(source.len(), |index| span.start + index);
+    let line = &source[line_start..line_end];
+    let line_number = (source[..span.start].matches('\n').count() + 1).to_string();
+
+    let padding = " ".repeat(line_number.len());
+    let column = source[line_start..span.start].chars().count();
+    let width = source[span.start..span.end.clamp(span.start, line_end)]
+        .chars()
+        .count()
+        .max(1);
+
+    format!(
+        "error: {}\n{} |\n{} | {}\n{} | {}{}",
+        message,
+        padding,
+        line_number,
+        line,
+        padding,
+        " ".repeat(column),
+        "^".repeat(width)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let source = "x = 1\ny = \"a\" - 1";
+        let span = Span { start: 10, end: 17 };
+        let expected = "error: oops\n  |\n2 | y = \"a\" - 1\n  |     ^^^^^^^";
+        assert_eq!(expected, render(source, span, "oops"));
+    }
+
+    #[test]
+    fn test_render_multiline_span() {
+        let source = "quidditch {\n  x = 1\n}";
+        let span = Span { start: 0, end: 21 };
+        let expected = "error: oops\n  |\n1 | quidditch {\n  | ^^^^^^^^^^^";
+        assert_eq!(expected, render(source, span, "oops"));
+    }
+}
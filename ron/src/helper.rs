@@ -0,0 +1,136 @@
+use potterscript_parser::{HogwartsHouse, Spell};
+use rustyline::completion::Completer;
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::validate::Validator;
+use rustyline::{Context, Helper};
+
+use crate::commands;
+
+const KEYWORDS: [&str; 10] = [
+    "if",
+    "else",
+    "quidditch",
+    "snitch",
+    "vow",
+    "protego",
+    "expelliarmus",
+    "true",
+    "false",
+    "SortingHat",
+];
+
+/// Tab-completes meta-commands after a leading `:`, spells after a `~`, and
+/// keywords, houses and the variables defined in the runtime everywhere else.
+#[derive(Default)]
+pub struct RonHelper {
+    pub variables: Vec<String>,
+}
+
+impl Completer for RonHelper {
+    type Candidate = String;
+
+    fn complete(
+        &self,
+        line: &str,
+        pos: usize,
+        _ctx: &Context<'_>,
+    ) -> rustyline::Result<(usize, Vec<String>)> {
+        let start = line[..pos]
+            .rfind(|c: char| !c.is_ascii_alphanumeric())
+            .map_or(0, |index| index + 1);
+        let word = &line[start..pos];
+
+        let candidates: Vec<String> = if line[..start] == *":" {
+            commands::NAMES
+                .iter()
+                .map(|name| name[1..].to_string())
+                .collect()
+        } else if line[..start].ends_with('~') {
+            Spell::ALL.iter().map(Spell::to_string).collect()
+        } else {
+            KEYWORDS
+                .iter()
+                .map(|keyword| keyword.to_string())
+                .chain(HogwartsHouse::ALL.iter().map(HogwartsHouse::to_string))
+                .chain(self.variables.iter().cloned())
+                .collect()
+        };
+
+        let mut matches: Vec<String> = candidates
+            .into_iter()
+            .filter(|candidate| candidate.starts_with(word))
+            .collect();
+        matches.sort();
+        matches.dedup();
+
+        Ok((start, matches))
+    }
+}
+
+impl Hinter for RonHelper {
+    type Hint = String;
+}
+
+impl Highlighter for RonHelper {}
+
+impl Validator for RonHelper {}
+
+impl Helper for RonHelper {}
+
+/// Whether `input` opened more `{` blocks than it closed, ignoring braces in
+/// strings and comments.
+pub fn is_incomplete(input: &str) -> bool {
+    let mut depth = 0;
+
+    for line in input.lines() {
+        let mut in_string = false;
+        for c in line.chars() {
+            match c {
+                '"' => in_string = !in_string,
+                '#' if !in_string => break,
+                '{' if !in_string => depth += 1,
+                '}' if !in_string => depth -= 1,
+                _ => {}
+            }
+        }
+    }
+
+    depth > 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_incomplete() {
+        assert!(is_incomplete("quidditch {"));
+        assert!(is_incomplete("if x == 1 {\n  ~Revelio x\n"));
+        assert!(!is_incomplete("if x == 1 {\n  ~Revelio x\n}"));
+        assert!(!is_incomplete("~Revelio \"{\""));
+        assert!(!is_incomplete("x = 1 # {"));
+    }
+
+    #[test]
+    fn test_complete_spell() {
+        let helper = RonHelper::default();
+        let history = rustyline::history::DefaultHistory::new();
+        let ctx = Context::new(&history);
+        let (start, candidates) = helper.complete("~Re", 3, &ctx).unwrap();
+        assert_eq!(1, start);
+        assert_eq!(vec!["Reducio", "Revelio"], candidates);
+    }
+
+    #[test]
+    fn test_complete_variable() {
+        let helper = RonHelper {
+            variables: vec!["house".to_string(), "index".to_string()],
+        };
+        let history = rustyline::history::DefaultHistory::new();
+        let ctx = Context::new(&history);
+        let (start, candidates) = helper.complete("if ho", 5, &ctx).unwrap();
+        assert_eq!(3, start);
+        assert_eq!(vec!["house"], candidates);
+    }
+}
@@ -1,17 +1,280 @@
-use std::io::Write;
+mod commands;
+mod diagnostic;
+mod helper;
 
-use potterscript_runtime::DefaultRuntimeAdapter;
+use std::env;
+use std::fs;
+use std::io::{self, IsTerminal};
+use std::path::PathBuf;
 
-fn main() {
-    let mut runtime = potterscript_runtime::Runtime::new(DefaultRuntimeAdapter);
-    println!("PotterScript REPL (Cast `~AvadaKedabra` to exit)");
+use potterscript_parser::Program;
+#[cfg(feature = "jit")]
+use potterscript_parser::{Span, Statement};
+#[cfg(feature = "jit")]
+use potterscript_runtime::vm::{self, Vm};
+use potterscript_runtime::{
+    BindingKind, DefaultRuntimeAdapter, Runtime, RuntimeAdapter, RuntimeError, RuntimeValue, Seeded,
+};
+use rustyline::error::ReadlineError;
+use rustyline::history::DefaultHistory;
+use rustyline::Editor;
 
+use crate::commands::Command;
+use crate::helper::RonHelper;
+
+const HISTORY_FILE: &str = ".potterscript_history";
+
+fn history_path() -> Option<PathBuf> {
+    env::var_os("HOME").map(|home| PathBuf::from(home).join(HISTORY_FILE))
+}
+
+struct Session {
+    runtime: Runtime<Seeded<DefaultRuntimeAdapter>>,
+    /// Code evaluated since the last `:reset`, dumped by `:save`.
+    script: Vec<String>,
+    /// Whether input runs compiled to native code, toggled by `:jit`.
+    #[cfg(feature = "jit")]
+    jit: bool,
+}
+
+impl Session {
+    fn new() -> Self {
+        let adapter = Seeded::new(DefaultRuntimeAdapter, rand::random());
+
+        Self {
+            runtime: Runtime::new(adapter),
+            script: vec![],
+            #[cfg(feature = "jit")]
+            jit: false,
+        }
+    }
+
+    /// Evaluates `code` statement by statement, reporting the first parse or
+    /// runtime error. Statements evaluated before the error are kept.
+    /// Returns `false` when `~AvadaKedabra` was cast and the REPL should exit.
+    fn eval(&mut self, code: &str) -> bool {
+        let statements = match potterscript_parser::parse_spanned(code) {
+            Ok(statements) => statements,
+            Err(error) => {
+                eprintln!(
+                    "{}",
+                    diagnostic::render(code, error.span(), &error.to_string())
+                );
+                return true;
+            }
+        };
+
+        #[cfg(feature = "jit")]
+        if self.jit {
+            return self.eval_jit(code, statements);
+        }
+
+        for (statement, span) in statements {
+            match self.runtime.eval(&Program(vec![statement])) {
+                Ok(()) => {}
+                Err(RuntimeError::Exit) => return false,
+                Err(error) => {
+                    eprintln!("{}", diagnostic::render(code, span, &error.to_string()));
+                    return true;
+                }
+            }
+            self.script.push(code[span.start..span.end].to_string());
+        }
+
+        let value = self.runtime.last_value().cloned();
+        self.show(value);
+        true
+    }
+
+    /// Runs `code` on the bytecode VM compiled to native code, with the
+    /// bindings of the session. Like [`eval`](Self::eval), bindings changed
+    /// before an error are kept.
+    #[cfg(feature = "jit")]
+    fn eval_jit(&mut self, code: &str, statements: Vec<(Statement, Span)>) -> bool {
+        let mut vm = Vm::new(vm::compile_spanned(&statements), self.runtime.adapter());
+        vm.set_jit_threshold(Some(0));
+        for (name, value, kind) in self.runtime.bindings() {
+            // Only names the code uses have a slot
+            let _ = match kind {
+                BindingKind::Variable => vm.set(name, value.clone()),
+                BindingKind::Constant => vm.declare_constant(name, value.clone()),
+            };
+        }
+
+        let result = vm.run();
+        let failed_at = vm.chunk().span_at(vm.pc());
+        let names = vm.chunk().names.clone();
+        let bindings: Vec<_> = vm
+            .bindings()
+            .map(|(name, value, kind)| (name.to_string(), value.clone(), kind))
+            .collect();
+        let value = vm.last_value().cloned();
+        drop(vm);
+
+        // Obliviated variables have no binding left
+        for name in &names {
+            self.runtime.remove(name);
+        }
+        for (name, value, kind) in bindings {
+            // Cannot fail, every binding left is new or has the same kind
+            let _ = match kind {
+                BindingKind::Variable => self.runtime.set(&name, value),
+                BindingKind::Constant => self.runtime.declare_constant(&name, value),
+            };
+        }
+
+        for (_, span) in &statements {
+            match (&result, failed_at) {
+                (Err(_), Some(failed_at)) if span.start >= failed_at.start => break,
+                _ => self.script.push(code[span.start..span.end].to_string()),
+            }
+        }
+        match (result, failed_at) {
+            (Ok(()), _) => self.show(value),
+            (Err(RuntimeError::Exit), _) => return false,
+            (Err(error), Some(span)) => {
+                eprintln!("{}", diagnostic::render(code, span, &error.to_string()))
+            }
+            (Err(error), None) => eprintln!("{}", error),
+        }
+        true
+    }
+
+    /// Prints the value of the last expression statement, if any.
+    fn show(&self, value: Option<RuntimeValue>) {
+        if let Some(value) = value {
+            let mut output = value.literal();
+            if io::stdout().is_terminal() {
+                output = self.runtime.adapter().lumos(output);
+            }
+            println!("{} # {}", output, value.type_name());
+        }
+    }
+
+    /// Runs a meta-command, returning `false` when the REPL should exit.
+    fn run_command(&mut self, command: Command) -> bool {
+        match command {
+            Command::Help => println!("{}", commands::HELP),
+            Command::Vars => {
+                let mut bindings: Vec<_> = self.runtime.bindings().collect();
+                bindings.sort_by_key(|(name, _, _)| *name);
+
+                for (name, value, kind) in bindings {
+                    let constant = match kind {
+                        BindingKind::Variable => "",
+                        BindingKind::Constant => " (constant)",
+                    };
+                    println!(
+                        "{}: {} = {}{}",
+                        name,
+                        value.type_name(),
+                        value.literal(),
+                        constant
+                    );
+                }
+            }
+            Command::Reset => {
+                self.runtime.reset();
+                self.script.clear();
+            }
+            Command::Load(file) => match fs::read_to_string(file) {
+                Ok(code) => return self.eval(code.trim_end()),
+                Err(error) => eprintln!("Cannot read {}: {}", file, error),
+            },
+            Command::Ast(code) => match potterscript_parser::parse(code) {
+                Ok(program) => println!("{:#?}", program),
+                Err(error) => eprintln!("{}", error),
+            },
+            Command::Seed(Some(seed)) => self.runtime.adapter().reseed(seed),
+            Command::Seed(None) => println!("{}", self.runtime.adapter().seed()),
+            Command::Save(file) => {
+                let script = self.script.join("\n") + "\n";
+                match file {
+                    Some(file) => {
+                        if let Err(error) = fs::write(file, script) {
+                            eprintln!("Cannot write {}: {}", file, error);
+                        }
+                    }
+                    None => print!("{}", script),
+                }
+            }
+            #[cfg(feature = "jit")]
+            Command::Jit => {
+                self.jit = !self.jit;
+                println!("JIT {}", if self.jit { "on" } else { "off" });
+            }
+            #[cfg(not(feature = "jit"))]
+            Command::Jit => eprintln!("This REPL was built without the jit feature"),
+            Command::Quit => return false,
+        }
+
+        true
+    }
+}
+
+fn main() -> rustyline::Result<()> {
+    let mut session = Session::new();
+    let mut editor: Editor<RonHelper, DefaultHistory> = Editor::new()?;
+    editor.set_helper(Some(RonHelper::default()));
+
+    let history_path = history_path();
+    if let Some(path) = &history_path {
+        // There is no history yet on the first run
+        let _ = editor.load_history(path);
+    }
+
+    println!("PotterScript REPL (Cast `~AvadaKedabra` or type :quit to exit, :help for commands)");
+
+    let mut input = String::new();
     loop {
-        let mut input = String::new();
-        print!("> ");
-        std::io::stdout().flush().unwrap();
-        std::io::stdin().read_line(&mut input).unwrap();
-        let (_, program) = potterscript_parser::parse_program(&input).unwrap();
-        runtime.eval(program);
+        let prompt = if input.is_empty() { "> " } else { "... " };
+        match editor.readline(prompt) {
+            Ok(line) => {
+                input.push_str(&line);
+                input.push('\n');
+            }
+            Err(ReadlineError::Interrupted) => {
+                input.clear();
+                continue;
+            }
+            Err(ReadlineError::Eof) => break,
+            Err(error) => return Err(error),
+        }
+
+        if helper::is_incomplete(&input) {
+            continue;
+        }
+
+        let code = input.trim_end().to_string();
+        input.clear();
+        if code.trim().is_empty() {
+            continue;
+        }
+
+        editor.add_history_entry(code.as_str())?;
+        if let Some(path) = &history_path {
+            editor.save_history(path)?;
+        }
+
+        if code.starts_with(':') {
+            match Command::parse(&code) {
+                Ok(command) => {
+                    if !session.run_command(command) {
+                        break;
+                    }
+                }
+                Err(error) => eprintln!("{}", error),
+            }
+        } else {
+            if !session.eval(&code) {
+                break;
+            }
+        }
+
+        if let Some(helper) = editor.helper_mut() {
+            helper.variables = session.runtime.variable_names().map(String::from).collect();
+        }
     }
+
+    Ok(())
 }
@@ -0,0 +1,88 @@
+//! The `main` of built executables.
+
+use std::ffi::{c_char, c_int, c_void};
+use std::io::{self, IsTerminal};
+use std::{env, slice};
+use std::{mem, ptr};
+
+use potterscript_runtime::vm::aot::NativeCode;
+use potterscript_runtime::vm::{Chunk, Vm};
+use potterscript_runtime::{RuntimeError, Seeded};
+
+use crate::Adapter;
+
+const EXIT_USAGE: c_int = 64;
+const EXIT_RUNTIME_ERROR: c_int = 70;
+
+extern "C-unwind" {
+    static potter_chunk: u8;
+    static potter_chunk_len: usize;
+    /// The [`NativeCode`] of the chunk, opaque to C.
+    fn potter_code(context: *mut c_void, pc: usize) -> usize;
+}
+
+#[no_mangle]
+extern "C" fn main(_argc: c_int, _argv: *const *const c_char) -> c_int {
+    let mut args = env::args();
+    let name = args.next().unwrap_or_else(|| "potter".to_string());
+    let seed = match (args.next().as_deref(), args.next()) {
+        (None, _) => None,
+        (Some("--seed"), Some(seed)) => match seed.parse() {
+            Ok(seed) => Some(seed),
+            Err(_) => {
+                eprintln!("error: invalid seed {}", seed);
+                return EXIT_USAGE;
+            }
+        },
+        _ => {
+            eprintln!("usage: {} [--seed <SEED>]", name);
+            return EXIT_USAGE;
+        }
+    };
+
+    // SAFETY: `potter build` linked the executable with both symbols
+    let bytes = unsafe { slice::from_raw_parts(ptr::addr_of!(potter_chunk), potter_chunk_len) };
+    let chunk = Chunk::from_bytes(bytes).expect("`potter build` compiled a valid chunk");
+
+    let adapter = Adapter {
+        color: io::stdout().is_terminal(),
+    };
+    let mut vm = Vm::new(
+        chunk,
+        Seeded::new(adapter, seed.unwrap_or_else(rand::random)),
+    );
+    // SAFETY: `potter_code` was compiled from the chunk, calling
+    // `potter_execute`
+    unsafe {
+        let code = mem::transmute::<
+            unsafe extern "C-unwind" fn(*mut c_void, usize) -> usize,
+            NativeCode<Seeded<Adapter>>,
+        >(potter_code);
+        vm.set_native_code(code);
+    }
+
+    let result = match vm.run() {
+        Err(RuntimeError::Exit) => Ok(()),
+        result => result,
+    };
+    if let Err(error) = &result {
+        match vm.chunk().span_at(vm.pc()) {
+            Some(span) => eprintln!(
+                "error: {}: {} (source bytes {}..{})",
+                name, error, span.start, span.end
+            ),
+            None => eprintln!("error: {}: {}", name, error),
+        }
+    }
+    if seed.is_none() && vm.adapter().was_used() {
+        eprintln!(
+            "note: the Sorting Hat was seeded with {0}, run with `--seed {0}` to replay",
+            vm.adapter().seed()
+        );
+    }
+
+    match result {
+        Ok(()) => 0,
+        Err(_) => EXIT_RUNTIME_ERROR,
+    }
+}
@@ -0,0 +1,60 @@
+//! Support library of the executables `potter build` links. It defines
+//! `main`, which runs the chunk compiled into the executable on a
+//! [`Vm`](potterscript_runtime::vm::Vm) with its native code, like
+//! `potter run` would run the script.
+
+// The test harness defines its own `main`, e.g. with `cargo test
+// --all-targets`
+#[cfg(not(test))]
+mod entry;
+
+use std::ops::RangeInclusive;
+use std::time::Duration;
+
+use potterscript_runtime::vm::aot::{self, Context};
+use potterscript_runtime::{DefaultRuntimeAdapter, RuntimeAdapter, Seeded};
+
+/// Prints to the terminal, with `~Lumos` colors only on a terminal like
+/// `potter run`.
+struct Adapter {
+    color: bool,
+}
+
+impl RuntimeAdapter for Adapter {
+    fn random_integer(&self, range: RangeInclusive<i64>) -> i64 {
+        DefaultRuntimeAdapter.random_integer(range)
+    }
+
+    fn random_double(&self) -> f64 {
+        DefaultRuntimeAdapter.random_double()
+    }
+
+    fn lumos(&self, string: String) -> String {
+        if self.color {
+            DefaultRuntimeAdapter.lumos(string)
+        } else {
+            string
+        }
+    }
+
+    fn log(&self, string: &str) {
+        DefaultRuntimeAdapter.log(string)
+    }
+
+    fn read_line(&self, prompt: Option<&str>) -> Option<String> {
+        DefaultRuntimeAdapter.read_line(prompt)
+    }
+
+    fn sleep(&self, duration: Duration) {
+        DefaultRuntimeAdapter.sleep(duration)
+    }
+}
+
+#[no_mangle]
+unsafe extern "C-unwind" fn potter_execute(
+    context: *mut Context<Seeded<Adapter>>,
+    pc: usize,
+) -> usize {
+    // SAFETY: called by `potter_code` with the context it was given
+    unsafe { aot::execute(context, pc) }
+}
@@ -0,0 +1,49 @@
+use criterion::{criterion_group, criterion_main, Criterion};
+use potterscript_runtime::vm::{self, Vm};
+use potterscript_runtime::{Runtime, TestAdapter};
+
+/// A loop long enough for the cost of walking its body to dominate.
+const LOOP: &str = r#"
+index = 0
+total = 0
+name = "Hedwig"
+quidditch {
+  total = total + index
+  name = name + "!"
+  name = "Hedwig"
+  if index == 10000 {
+    snitch
+  } else {
+    ~Engorgio index
+  }
+}
+"#;
+
+fn quidditch(c: &mut Criterion) {
+    let program = potterscript_parser::parse(LOOP).unwrap();
+
+    c.bench_function("quidditch 10k iterations", |b| {
+        b.iter(|| {
+            let mut runtime = Runtime::new(TestAdapter::new());
+            runtime.eval(&program).unwrap();
+            runtime
+        })
+    });
+
+    let chunk = vm::compile(&program);
+    // The same VM runs it again, so the JIT compiles it only once
+    let mut vm = Vm::new(chunk.clone(), TestAdapter::new());
+    c.bench_function("quidditch 10k iterations (vm)", |b| {
+        b.iter(|| vm.run().unwrap())
+    });
+
+    #[cfg(feature = "jit")]
+    c.bench_function("quidditch 10k iterations (jit)", |b| {
+        let mut vm = Vm::new(chunk.clone(), TestAdapter::new());
+        vm.set_jit_threshold(Some(0));
+        b.iter(|| vm.run().unwrap())
+    });
+}
+
+criterion_group!(benches, quidditch);
+criterion_main!(benches);
@@ -0,0 +1,215 @@
+use std::collections::BTreeMap;
+use std::fmt;
+
+use serde::de::value::{MapDeserializer, SeqDeserializer};
+use serde::de::{self, Deserialize, IntoDeserializer, Unexpected, Visitor};
+
+use super::ValueError;
+use crate::RuntimeValue;
+
+impl<'de> Deserialize<'de> for RuntimeValue {
+    fn deserialize<D: de::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_any(RuntimeValueVisitor)
+    }
+}
+
+struct RuntimeValueVisitor;
+
+impl<'de> Visitor<'de> for RuntimeValueVisitor {
+    type Value = RuntimeValue;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "a PotterScript value")
+    }
+
+    fn visit_bool<E: de::Error>(self, value: bool) -> Result<Self::Value, E> {
+        Ok(RuntimeValue::Boolean(value))
+    }
+
+    fn visit_i64<E: de::Error>(self, value: i64) -> Result<Self::Value, E> {
+        Ok(RuntimeValue::Integer(value))
+    }
+
+    fn visit_u64<E: de::Error>(self, value: u64) -> Result<Self::Value, E> {
+        i64::try_from(value)
+            .map(RuntimeValue::Integer)
+            .map_err(|_| E::invalid_value(Unexpected::Unsigned(value), &self))
+    }
+
+    fn visit_f64<E: de::Error>(self, value: f64) -> Result<Self::Value, E> {
+        Ok(RuntimeValue::Double(value))
+    }
+
+    fn visit_str<E: de::Error>(self, value: &str) -> Result<Self::Value, E> {
+        Ok(RuntimeValue::String(value.into()))
+    }
+
+    fn visit_string<E: de::Error>(self, value: String) -> Result<Self::Value, E> {
+        Ok(RuntimeValue::String(value.into()))
+    }
+
+    fn visit_some<D: de::Deserializer<'de>>(
+        self,
+        deserializer: D,
+    ) -> Result<Self::Value, D::Error> {
+        deserializer.deserialize_any(self)
+    }
+
+    fn visit_seq<A: de::SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+        let mut values = Vec::with_capacity(seq.size_hint().unwrap_or(0));
+        while let Some(value) = seq.next_element()? {
+            values.push(value);
+        }
+        Ok(RuntimeValue::List(values))
+    }
+
+    fn visit_map<A: de::MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+        let mut entries = BTreeMap::new();
+        while let Some((key, value)) = map.next_entry()? {
+            entries.insert(key, value);
+        }
+        Ok(RuntimeValue::Map(entries))
+    }
+}
+
+impl<'de> IntoDeserializer<'de, ValueError> for RuntimeValue {
+    type Deserializer = Deserializer;
+
+    fn into_deserializer(self) -> Self::Deserializer {
+        Deserializer(self)
+    }
+}
+
+/// Reads a [`RuntimeValue`] as any serde data type. Houses read as strings,
+/// unless an enum asks for them.
+pub struct Deserializer(pub(super) RuntimeValue);
+
+impl<'de> de::Deserializer<'de> for Deserializer {
+    type Error = ValueError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        match self.0 {
+            RuntimeValue::Integer(value) => visitor.visit_i64(value),
+            RuntimeValue::Double(value) => visitor.visit_f64(value),
+            RuntimeValue::Boolean(value) => visitor.visit_bool(value),
+            RuntimeValue::String(value) => visitor.visit_str(&value),
+            RuntimeValue::HogwartsHouse(house) => visitor.visit_string(house.to_string()),
+            RuntimeValue::List(values) => {
+                let mut seq = SeqDeserializer::new(values.into_iter());
+                let value = visitor.visit_seq(&mut seq)?;
+                seq.end()?;
+                Ok(value)
+            }
+            RuntimeValue::Map(entries) => {
+                let mut map = MapDeserializer::new(entries.into_iter());
+                let value = visitor.visit_map(&mut map)?;
+                map.end()?;
+                Ok(value)
+            }
+        }
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        visitor.visit_some(self)
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        match self.0 {
+            RuntimeValue::String(variant) => {
+                visitor.visit_enum(variant.to_string().into_deserializer())
+            }
+            RuntimeValue::HogwartsHouse(house) => {
+                visitor.visit_enum(house.to_string().into_deserializer())
+            }
+            RuntimeValue::Map(entries) if entries.len() == 1 => {
+                let (variant, value) = entries.into_iter().next().unwrap();
+                visitor.visit_enum(Enum { variant, value })
+            }
+            value => Err(de::Error::invalid_type(unexpected(&value), &"an enum")),
+        }
+    }
+
+    serde::forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf unit unit_struct seq tuple tuple_struct map struct
+        identifier ignored_any
+    }
+}
+
+fn unexpected(value: &RuntimeValue) -> Unexpected<'_> {
+    match value {
+        RuntimeValue::Integer(value) => Unexpected::Signed(*value),
+        RuntimeValue::Double(value) => Unexpected::Float(*value),
+        RuntimeValue::Boolean(value) => Unexpected::Bool(*value),
+        RuntimeValue::String(value) => Unexpected::Str(value),
+        RuntimeValue::HogwartsHouse(_) => Unexpected::Other("house"),
+        RuntimeValue::List(_) => Unexpected::Seq,
+        RuntimeValue::Map(_) => Unexpected::Map,
+    }
+}
+
+/// An enum variant with content, stored as a single-entry map.
+struct Enum {
+    variant: String,
+    value: RuntimeValue,
+}
+
+impl<'de> de::EnumAccess<'de> for Enum {
+    type Error = ValueError;
+    type Variant = Deserializer;
+
+    fn variant_seed<V: de::DeserializeSeed<'de>>(
+        self,
+        seed: V,
+    ) -> Result<(V::Value, Self::Variant), Self::Error> {
+        let variant = seed.deserialize(self.variant.into_deserializer())?;
+        Ok((variant, Deserializer(self.value)))
+    }
+}
+
+impl<'de> de::VariantAccess<'de> for Deserializer {
+    type Error = ValueError;
+
+    fn unit_variant(self) -> Result<(), Self::Error> {
+        Err(de::Error::invalid_type(
+            unexpected(&self.0),
+            &"a unit variant",
+        ))
+    }
+
+    fn newtype_variant_seed<T: de::DeserializeSeed<'de>>(
+        self,
+        seed: T,
+    ) -> Result<T::Value, Self::Error> {
+        seed.deserialize(self)
+    }
+
+    fn tuple_variant<V: Visitor<'de>>(
+        self,
+        _len: usize,
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        de::Deserializer::deserialize_any(self, visitor)
+    }
+
+    fn struct_variant<V: Visitor<'de>>(
+        self,
+        _fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        de::Deserializer::deserialize_any(self, visitor)
+    }
+}
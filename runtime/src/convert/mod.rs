@@ -0,0 +1,269 @@
+//! Conversions between [`RuntimeValue`] and Rust types, either directly with
+//! `From`/`TryFrom` or through serde with [`to_value`] and [`from_value`].
+
+mod de;
+mod ser;
+
+use std::collections::BTreeMap;
+use std::rc::Rc;
+use std::{error, fmt};
+
+use potterscript_parser::HogwartsHouse;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use crate::{RuntimeError, RuntimeValue, ValueType};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ValueError {
+    /// The value does not have the type the Rust side asked for.
+    UnexpectedType(ValueType, RuntimeValue),
+    /// The number does not fit in the Rust type named by the second field.
+    OutOfRange(String, &'static str),
+    /// Rust data without a PotterScript counterpart, like `()` or `None`.
+    Unsupported(&'static str),
+    /// Reported by a `Serialize` or `Deserialize` implementation.
+    Custom(String),
+}
+
+impl fmt::Display for ValueError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ValueError::UnexpectedType(expected, value) => {
+                write!(f, "Expected {}, found {:?}", expected, value)
+            }
+            ValueError::OutOfRange(number, target) => {
+                write!(f, "{} does not fit in {}", number, target)
+            }
+            ValueError::Unsupported(what) => write!(f, "PotterScript has no value for {}", what),
+            ValueError::Custom(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl error::Error for ValueError {}
+
+impl serde::ser::Error for ValueError {
+    fn custom<T: fmt::Display>(message: T) -> Self {
+        ValueError::Custom(message.to_string())
+    }
+}
+
+impl serde::de::Error for ValueError {
+    fn custom<T: fmt::Display>(message: T) -> Self {
+        ValueError::Custom(message.to_string())
+    }
+}
+
+/// Lets native spells convert their target with `?`.
+impl From<ValueError> for RuntimeError {
+    fn from(error: ValueError) -> Self {
+        RuntimeError::Custom(error.to_string())
+    }
+}
+
+/// Converts any serializable Rust value into a [`RuntimeValue`]. Structs and
+/// maps become maps, sequences and tuples become lists, and unit enum variants
+/// become houses when named after one, strings otherwise. `None` fields are
+/// left out of maps.
+pub fn to_value<T: Serialize + ?Sized>(value: &T) -> Result<RuntimeValue, ValueError> {
+    value
+        .serialize(ser::Serializer)?
+        .ok_or(ValueError::Unsupported("None"))
+}
+
+/// Converts a [`RuntimeValue`] into any deserializable Rust value, the
+/// inverse of [`to_value`].
+pub fn from_value<T: DeserializeOwned>(value: RuntimeValue) -> Result<T, ValueError> {
+    T::deserialize(de::Deserializer(value))
+}
+
+macro_rules! impl_from {
+    ($($type:ty => $variant:ident),*) => {
+        $(
+            impl From<$type> for RuntimeValue {
+                fn from(value: $type) -> Self {
+                    RuntimeValue::$variant(value.into())
+                }
+            }
+        )*
+    };
+}
+
+impl_from! {
+    i64 => Integer,
+    i32 => Integer,
+    u32 => Integer,
+    f64 => Double,
+    bool => Boolean,
+    String => String,
+    &str => String,
+    HogwartsHouse => HogwartsHouse,
+    Vec<RuntimeValue> => List,
+    BTreeMap<String, RuntimeValue> => Map
+}
+
+macro_rules! impl_try_from {
+    ($($type:ty => $variant:ident),*) => {
+        $(
+            impl TryFrom<RuntimeValue> for $type {
+                type Error = ValueError;
+
+                fn try_from(value: RuntimeValue) -> Result<Self, Self::Error> {
+                    match value {
+                        RuntimeValue::$variant(value) => Ok(value),
+                        _ => Err(ValueError::UnexpectedType(ValueType::$variant, value)),
+                    }
+                }
+            }
+        )*
+    };
+}
+
+impl_try_from! {
+    i64 => Integer,
+    f64 => Double,
+    bool => Boolean,
+    Rc<str> => String,
+    HogwartsHouse => HogwartsHouse,
+    Vec<RuntimeValue> => List,
+    BTreeMap<String, RuntimeValue> => Map
+}
+
+impl TryFrom<RuntimeValue> for String {
+    type Error = ValueError;
+
+    fn try_from(value: RuntimeValue) -> Result<Self, Self::Error> {
+        Rc::<str>::try_from(value).map(|value| value.to_string())
+    }
+}
+
+macro_rules! impl_try_from_integer {
+    ($($type:ty),*) => {
+        $(
+            impl TryFrom<RuntimeValue> for $type {
+                type Error = ValueError;
+
+                fn try_from(value: RuntimeValue) -> Result<Self, Self::Error> {
+                    let integer = i64::try_from(value)?;
+                    <$type>::try_from(integer).map_err(|_| {
+                        ValueError::OutOfRange(integer.to_string(), stringify!($type))
+                    })
+                }
+            }
+        )*
+    };
+}
+
+impl_try_from_integer!(i8, i16, i32, u8, u16, u32, u64, usize);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Deserialize;
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    enum Role {
+        Student,
+        Gryffindor,
+        Prefect { since: u32 },
+    }
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Wizard {
+        name: String,
+        age: u8,
+        house: HogwartsHouse,
+        roles: Vec<Role>,
+        wand: Option<String>,
+    }
+
+    #[test]
+    fn test_to_value() {
+        let wizard = Wizard {
+            name: "Hermione".to_string(),
+            age: 11,
+            house: HogwartsHouse::Gryffindor,
+            roles: vec![Role::Student, Role::Prefect { since: 1995 }],
+            wand: None,
+        };
+
+        let mut prefect = BTreeMap::new();
+        prefect.insert("since".to_string(), RuntimeValue::Integer(1995));
+        let mut roles = BTreeMap::new();
+        roles.insert("Prefect".to_string(), RuntimeValue::Map(prefect));
+        let mut expected = BTreeMap::new();
+        expected.insert("name".to_string(), RuntimeValue::from("Hermione"));
+        expected.insert("age".to_string(), RuntimeValue::Integer(11));
+        expected.insert(
+            "house".to_string(),
+            RuntimeValue::HogwartsHouse(HogwartsHouse::Gryffindor),
+        );
+        expected.insert(
+            "roles".to_string(),
+            RuntimeValue::List(vec![
+                RuntimeValue::from("Student"),
+                RuntimeValue::Map(roles),
+            ]),
+        );
+
+        let value = to_value(&wizard).unwrap();
+        assert_eq!(RuntimeValue::Map(expected), value);
+        assert_eq!(wizard, from_value(value).unwrap());
+    }
+
+    #[test]
+    fn test_house_enum() {
+        assert_eq!(
+            RuntimeValue::HogwartsHouse(HogwartsHouse::Gryffindor),
+            to_value(&Role::Gryffindor).unwrap()
+        );
+        assert_eq!(
+            Role::Gryffindor,
+            from_value(RuntimeValue::HogwartsHouse(HogwartsHouse::Gryffindor)).unwrap()
+        );
+        assert_eq!(
+            "Gryffindor",
+            from_value::<String>(RuntimeValue::HogwartsHouse(HogwartsHouse::Gryffindor)).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_value_round_trip() {
+        let value = RuntimeValue::List(vec![
+            RuntimeValue::Integer(1),
+            RuntimeValue::Double(1.5),
+            RuntimeValue::from(true),
+            RuntimeValue::from("a"),
+        ]);
+        assert_eq!(value, to_value(&value).unwrap());
+        assert_eq!(value, from_value::<RuntimeValue>(value.clone()).unwrap());
+    }
+
+    #[test]
+    fn test_conversion_errors() {
+        assert_eq!(Err(ValueError::Unsupported("None")), to_value(&None::<i64>));
+        assert_eq!(Err(ValueError::Unsupported("()")), to_value(&()));
+        assert_eq!(
+            Err(ValueError::OutOfRange(u64::MAX.to_string(), "i64")),
+            to_value(&u64::MAX)
+        );
+        assert!(from_value::<Wizard>(RuntimeValue::Integer(1)).is_err());
+    }
+
+    #[test]
+    fn test_try_from() {
+        assert_eq!(Ok(7), i64::try_from(RuntimeValue::Integer(7)));
+        assert_eq!(Ok(7), u8::try_from(RuntimeValue::Integer(7)));
+        assert_eq!(
+            Ok("a".to_string()),
+            String::try_from(RuntimeValue::from("a"))
+        );
+
+        let error = u8::try_from(RuntimeValue::Integer(300)).unwrap_err();
+        assert_eq!("300 does not fit in u8", error.to_string());
+
+        let error = bool::try_from(RuntimeValue::Integer(1)).unwrap_err();
+        assert_eq!("Expected Boolean, found Integer(1)", error.to_string());
+    }
+}
@@ -0,0 +1,362 @@
+use std::collections::BTreeMap;
+
+use potterscript_parser::HogwartsHouse;
+use serde::ser::{self, Serialize};
+
+use super::ValueError;
+use crate::RuntimeValue;
+
+impl Serialize for RuntimeValue {
+    fn serialize<S: ser::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            RuntimeValue::Integer(value) => serializer.serialize_i64(*value),
+            RuntimeValue::Double(value) => serializer.serialize_f64(*value),
+            RuntimeValue::Boolean(value) => serializer.serialize_bool(*value),
+            RuntimeValue::String(value) => serializer.serialize_str(value),
+            RuntimeValue::HogwartsHouse(house) => house.serialize(serializer),
+            RuntimeValue::List(values) => serializer.collect_seq(values),
+            RuntimeValue::Map(entries) => serializer.collect_map(entries),
+        }
+    }
+}
+
+fn house(name: &str) -> Option<HogwartsHouse> {
+    HogwartsHouse::ALL
+        .into_iter()
+        .find(|house| house.to_string() == name)
+}
+
+fn integer<T: TryInto<i64> + ToString + Copy>(
+    value: T,
+) -> Result<Option<RuntimeValue>, ValueError> {
+    value
+        .try_into()
+        .map(|value| Some(RuntimeValue::Integer(value)))
+        .map_err(|_| ValueError::OutOfRange(value.to_string(), "i64"))
+}
+
+/// Wraps the content of an enum variant in a single-entry map keyed by the
+/// variant name, like serde's externally tagged representation.
+fn wrap_variant(variant: &str, value: Option<RuntimeValue>) -> Option<RuntimeValue> {
+    let entries = value.map(|value| (variant.to_string(), value));
+    Some(RuntimeValue::Map(entries.into_iter().collect()))
+}
+
+/// Produces `None` for `Option::None`, which has no PotterScript value, so
+/// that maps can leave the entry out.
+pub(super) struct Serializer;
+
+type Result<T, E = ValueError> = std::result::Result<T, E>;
+
+impl ser::Serializer for Serializer {
+    type Ok = Option<RuntimeValue>;
+    type Error = ValueError;
+    type SerializeSeq = SerializeList;
+    type SerializeTuple = SerializeList;
+    type SerializeTupleStruct = SerializeList;
+    type SerializeTupleVariant = SerializeVariant<SerializeList>;
+    type SerializeMap = SerializeMap;
+    type SerializeStruct = SerializeMap;
+    type SerializeStructVariant = SerializeVariant<SerializeMap>;
+
+    fn serialize_bool(self, value: bool) -> Result<Self::Ok> {
+        Ok(Some(RuntimeValue::Boolean(value)))
+    }
+
+    fn serialize_i8(self, value: i8) -> Result<Self::Ok> {
+        integer(value)
+    }
+
+    fn serialize_i16(self, value: i16) -> Result<Self::Ok> {
+        integer(value)
+    }
+
+    fn serialize_i32(self, value: i32) -> Result<Self::Ok> {
+        integer(value)
+    }
+
+    fn serialize_i64(self, value: i64) -> Result<Self::Ok> {
+        integer(value)
+    }
+
+    fn serialize_i128(self, value: i128) -> Result<Self::Ok> {
+        integer(value)
+    }
+
+    fn serialize_u8(self, value: u8) -> Result<Self::Ok> {
+        integer(value)
+    }
+
+    fn serialize_u16(self, value: u16) -> Result<Self::Ok> {
+        integer(value)
+    }
+
+    fn serialize_u32(self, value: u32) -> Result<Self::Ok> {
+        integer(value)
+    }
+
+    fn serialize_u64(self, value: u64) -> Result<Self::Ok> {
+        integer(value)
+    }
+
+    fn serialize_u128(self, value: u128) -> Result<Self::Ok> {
+        integer(value)
+    }
+
+    fn serialize_f32(self, value: f32) -> Result<Self::Ok> {
+        Ok(Some(RuntimeValue::Double(value.into())))
+    }
+
+    fn serialize_f64(self, value: f64) -> Result<Self::Ok> {
+        Ok(Some(RuntimeValue::Double(value)))
+    }
+
+    fn serialize_char(self, value: char) -> Result<Self::Ok> {
+        Ok(Some(RuntimeValue::String(value.to_string().into())))
+    }
+
+    fn serialize_str(self, value: &str) -> Result<Self::Ok> {
+        Ok(Some(RuntimeValue::String(value.into())))
+    }
+
+    fn serialize_bytes(self, value: &[u8]) -> Result<Self::Ok> {
+        let bytes = value
+            .iter()
+            .map(|byte| RuntimeValue::Integer((*byte).into()))
+            .collect();
+        Ok(Some(RuntimeValue::List(bytes)))
+    }
+
+    fn serialize_none(self) -> Result<Self::Ok> {
+        Ok(None)
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> Result<Self::Ok> {
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> Result<Self::Ok> {
+        Err(ValueError::Unsupported("()"))
+    }
+
+    fn serialize_unit_struct(self, name: &'static str) -> Result<Self::Ok> {
+        Err(ValueError::Unsupported(name))
+    }
+
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+    ) -> Result<Self::Ok> {
+        Ok(Some(match house(variant) {
+            Some(house) => RuntimeValue::HogwartsHouse(house),
+            None => RuntimeValue::String(variant.into()),
+        }))
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        value: &T,
+    ) -> Result<Self::Ok> {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+        value: &T,
+    ) -> Result<Self::Ok> {
+        Ok(wrap_variant(variant, value.serialize(self)?))
+    }
+
+    fn serialize_seq(self, len: Option<usize>) -> Result<Self::SerializeSeq> {
+        Ok(SerializeList(Vec::with_capacity(len.unwrap_or(0))))
+    }
+
+    fn serialize_tuple(self, len: usize) -> Result<Self::SerializeTuple> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_struct(
+        self,
+        _name: &'static str,
+        len: usize,
+    ) -> Result<Self::SerializeTupleStruct> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+        len: usize,
+    ) -> Result<Self::SerializeTupleVariant> {
+        Ok(SerializeVariant(variant, self.serialize_seq(Some(len))?))
+    }
+
+    fn serialize_map(self, _len: Option<usize>) -> Result<Self::SerializeMap> {
+        Ok(SerializeMap::default())
+    }
+
+    fn serialize_struct(self, _name: &'static str, len: usize) -> Result<Self::SerializeStruct> {
+        self.serialize_map(Some(len))
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+        len: usize,
+    ) -> Result<Self::SerializeStructVariant> {
+        Ok(SerializeVariant(variant, self.serialize_map(Some(len))?))
+    }
+}
+
+pub(super) struct SerializeList(Vec<RuntimeValue>);
+
+impl SerializeList {
+    fn push<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<()> {
+        let value = value
+            .serialize(Serializer)?
+            .ok_or(ValueError::Unsupported("None in a list"))?;
+        self.0.push(value);
+        Ok(())
+    }
+}
+
+impl ser::SerializeSeq for SerializeList {
+    type Ok = Option<RuntimeValue>;
+    type Error = ValueError;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<()> {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<Self::Ok> {
+        Ok(Some(RuntimeValue::List(self.0)))
+    }
+}
+
+impl ser::SerializeTuple for SerializeList {
+    type Ok = Option<RuntimeValue>;
+    type Error = ValueError;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<()> {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<Self::Ok> {
+        ser::SerializeSeq::end(self)
+    }
+}
+
+impl ser::SerializeTupleStruct for SerializeList {
+    type Ok = Option<RuntimeValue>;
+    type Error = ValueError;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<()> {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<Self::Ok> {
+        ser::SerializeSeq::end(self)
+    }
+}
+
+#[derive(Default)]
+pub(super) struct SerializeMap {
+    entries: BTreeMap<String, RuntimeValue>,
+    next_key: Option<String>,
+}
+
+impl SerializeMap {
+    fn insert<T: Serialize + ?Sized>(&mut self, key: String, value: &T) -> Result<()> {
+        if let Some(value) = value.serialize(Serializer)? {
+            self.entries.insert(key, value);
+        }
+        Ok(())
+    }
+}
+
+impl ser::SerializeMap for SerializeMap {
+    type Ok = Option<RuntimeValue>;
+    type Error = ValueError;
+
+    fn serialize_key<T: Serialize + ?Sized>(&mut self, key: &T) -> Result<()> {
+        let key = match key.serialize(Serializer)? {
+            Some(RuntimeValue::List(_) | RuntimeValue::Map(_)) | None => {
+                return Err(ValueError::Unsupported("map keys that are not scalars"))
+            }
+            Some(key) => key.to_string(),
+        };
+        self.next_key = Some(key);
+        Ok(())
+    }
+
+    fn serialize_value<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<()> {
+        let key = self
+            .next_key
+            .take()
+            .expect("serialize_value called before serialize_key");
+        self.insert(key, value)
+    }
+
+    fn end(self) -> Result<Self::Ok> {
+        Ok(Some(RuntimeValue::Map(self.entries)))
+    }
+}
+
+impl ser::SerializeStruct for SerializeMap {
+    type Ok = Option<RuntimeValue>;
+    type Error = ValueError;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<()> {
+        self.insert(key.to_string(), value)
+    }
+
+    fn end(self) -> Result<Self::Ok> {
+        ser::SerializeMap::end(self)
+    }
+}
+
+pub(super) struct SerializeVariant<T>(&'static str, T);
+
+impl ser::SerializeTupleVariant for SerializeVariant<SerializeList> {
+    type Ok = Option<RuntimeValue>;
+    type Error = ValueError;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<()> {
+        self.1.push(value)
+    }
+
+    fn end(self) -> Result<Self::Ok> {
+        Ok(wrap_variant(self.0, ser::SerializeSeq::end(self.1)?))
+    }
+}
+
+impl ser::SerializeStructVariant for SerializeVariant<SerializeMap> {
+    type Ok = Option<RuntimeValue>;
+    type Error = ValueError;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<()> {
+        self.1.insert(key.to_string(), value)
+    }
+
+    fn end(self) -> Result<Self::Ok> {
+        Ok(wrap_variant(self.0, ser::SerializeMap::end(self.1)?))
+    }
+}
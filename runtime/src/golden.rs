@@ -0,0 +1,200 @@
+//! Golden-file tests: every `name.potter` script in a directory is run with a
+//! [`TestAdapter`] and what it logs is compared against `name.out`. Lines in
+//! an optional `name.in` feed `~Accio`.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::{Runtime, RuntimeError, TestAdapter};
+
+/// Keeps a runaway `quidditch` loop from hanging the whole run.
+const MAX_STEPS: usize = 1_000_000;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GoldenOutcome {
+    Passed,
+    /// The output differs from the `.out` file, see [`diff`].
+    Failed {
+        expected: String,
+        actual: String,
+    },
+    /// There is no `.out` file to compare with.
+    Missing,
+    /// The `.out` file was (re)written with the actual output.
+    Blessed,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GoldenCase {
+    pub script: PathBuf,
+    pub outcome: GoldenOutcome,
+}
+
+/// Runs `code` the way golden tests do and returns what it logged, one line
+/// each. The Sorting Hat goes round the houses in order, and parse or runtime
+/// errors end the output with an `error:` line. `~AvadaKedabra` just ends it.
+pub fn golden_output(code: &str, inputs: &[String]) -> String {
+    let adapter = TestAdapter::new()
+        .with_integers((0..4).cycle())
+        .with_inputs(inputs.iter().cloned());
+    let mut runtime = Runtime::new(adapter);
+    runtime.set_max_steps(Some(MAX_STEPS));
+
+    let result = match potterscript_parser::parse(code) {
+        Ok(program) => match runtime.eval(&program) {
+            Err(RuntimeError::Exit) => Ok(()),
+            result => result.map_err(|error| error.to_string()),
+        },
+        Err(error) => Err(error.to_string()),
+    };
+
+    let mut output = runtime.adapter().output();
+    if let Err(error) = result {
+        output += &format!("error: {}\n", error);
+    }
+    output
+}
+
+/// Runs every `.potter` script in `dir`, in name order. With `bless`, `.out`
+/// files are written instead of compared.
+pub fn run_golden(dir: &Path, bless: bool) -> io::Result<Vec<GoldenCase>> {
+    let mut scripts = vec![];
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path
+            .extension()
+            .is_some_and(|extension| extension == "potter")
+        {
+            scripts.push(path);
+        }
+    }
+    scripts.sort();
+
+    scripts
+        .into_iter()
+        .map(|script| {
+            let outcome = run_case(&script, bless)?;
+            Ok(GoldenCase { script, outcome })
+        })
+        .collect()
+}
+
+fn run_case(script: &Path, bless: bool) -> io::Result<GoldenOutcome> {
+    let code = fs::read_to_string(script)?;
+    let inputs = match fs::read_to_string(script.with_extension("in")) {
+        Ok(inputs) => inputs.lines().map(String::from).collect(),
+        Err(error) if error.kind() == io::ErrorKind::NotFound => vec![],
+        Err(error) => return Err(error),
+    };
+    let actual = golden_output(&code, &inputs);
+
+    let out = script.with_extension("out");
+    if bless {
+        fs::write(out, actual)?;
+        return Ok(GoldenOutcome::Blessed);
+    }
+
+    match fs::read_to_string(out) {
+        Ok(expected) if expected == actual => Ok(GoldenOutcome::Passed),
+        Ok(expected) => Ok(GoldenOutcome::Failed { expected, actual }),
+        Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(GoldenOutcome::Missing),
+        Err(error) => Err(error),
+    }
+}
+
+/// A line diff from `expected` to `actual`, with removed lines prefixed by
+/// `-`, added ones by `+` and common ones by a space.
+pub fn diff(expected: &str, actual: &str) -> String {
+    let expected: Vec<&str> = expected.lines().collect();
+    let actual: Vec<&str> = actual.lines().collect();
+
+    // Longest common subsequence of every pair of suffixes
+    let mut lcs = vec![vec![0; actual.len() + 1]; expected.len() + 1];
+    for i in (0..expected.len()).rev() {
+        for j in (0..actual.len()).rev() {
+            lcs[i][j] = if expected[i] == actual[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+
+    let mut output = String::new();
+    let (mut i, mut j) = (0, 0);
+    while i < expected.len() || j < actual.len() {
+        if i < expected.len() && j < actual.len() && expected[i] == actual[j] {
+            output += &format!(" {}\n", expected[i]);
+            i += 1;
+            j += 1;
+        } else if i < expected.len() && (j == actual.len() || lcs[i + 1][j] >= lcs[i][j + 1]) {
+            output += &format!("-{}\n", expected[i]);
+            i += 1;
+        } else {
+            output += &format!("+{}\n", actual[j]);
+            j += 1;
+        }
+    }
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_golden_files() {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/golden");
+        for case in run_golden(&dir, false).unwrap() {
+            if let GoldenOutcome::Failed { expected, actual } = &case.outcome {
+                panic!("{}\n{}", case.script.display(), diff(expected, actual));
+            }
+            assert_eq!(GoldenOutcome::Passed, case.outcome, "{:?}", case.script);
+        }
+    }
+
+    #[test]
+    fn test_golden_output() {
+        let inputs = vec!["Dobby".to_string()];
+        assert_eq!(
+            "Gryffindor\nHufflepuff\nDobby\nerror: Variable y not found\n",
+            golden_output(
+                "~Revelio 🎩✨\n~Revelio 🎩✨\n~Revelio ~Accio\n~Revelio y",
+                &inputs
+            )
+        );
+    }
+
+    #[test]
+    fn test_avada_kedabra_ends_only_its_script() {
+        let dir = std::env::temp_dir().join(format!("potter-golden-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(
+            dir.join("a.potter"),
+            "~Revelio 1\n~AvadaKedabra\n~Revelio 2",
+        )
+        .unwrap();
+        fs::write(dir.join("a.out"), "1\n").unwrap();
+        fs::write(dir.join("b.potter"), "~Revelio 3").unwrap();
+        fs::write(dir.join("b.out"), "4\n").unwrap();
+
+        let cases = run_golden(&dir, false);
+        fs::remove_dir_all(&dir).unwrap();
+        let cases = cases.unwrap();
+        assert_eq!(GoldenOutcome::Passed, cases[0].outcome);
+        assert_eq!(
+            GoldenOutcome::Failed {
+                expected: "4\n".to_string(),
+                actual: "3\n".to_string()
+            },
+            cases[1].outcome
+        );
+    }
+
+    #[test]
+    fn test_diff() {
+        assert_eq!(" a\n-b\n+c\n d\n", diff("a\nb\nd\n", "a\nc\nd\n"));
+        assert_eq!(" a\n+b\n", diff("a\n", "a\nb\n"));
+    }
+}
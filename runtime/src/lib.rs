@@ -1,21 +1,99 @@
-use std::collections::HashMap;
-use std::{fmt, ops, panic, process, time};
+mod convert;
+pub mod golden;
+mod native;
+pub mod optimizer;
+mod random;
+mod replay;
+mod resolver;
+pub mod spells;
+mod testing;
+pub mod vm;
+pub mod vows;
+
+use std::collections::{BTreeMap, HashMap};
+#[cfg(feature = "std")]
+use std::io::{self, Write};
+use std::rc::Rc;
+#[cfg(feature = "std")]
+use std::thread;
+use std::{error, fmt, ops, time};
 
 #[cfg(feature = "std")]
 use colored::Colorize;
-use potterscript_parser::{
-    Atom, BinaryOperation, Expression, HogwartsHouse, Program, Spell, Statement,
-};
+use potterscript_parser::{Atom, BinaryOperation, Expression, HogwartsHouse, Program, Spell};
 #[cfg(feature = "std")]
 use rand::Rng;
 
+pub use crate::convert::{from_value, to_value, ValueError};
+use crate::native::NativeSpell;
+pub use crate::native::{SpellHandler, SpellTarget};
+pub use crate::random::{Random, Seeded};
+pub use crate::replay::{Choice, Recorder, Replayer};
+pub use crate::resolver::{evaluates_target, undefined_variables};
+use crate::resolver::{Expr, Interner, Slot, Stmt, Target};
+pub use crate::testing::TestAdapter;
+
 #[derive(Debug, Clone, PartialEq)]
 pub enum RuntimeValue {
     Integer(i64),
     Double(f64),
     Boolean(bool),
-    String(String),
+    /// Shared, so reading a string variable doesn't copy the string.
+    String(Rc<str>),
     HogwartsHouse(HogwartsHouse),
+    /// Has no literal syntax yet, injected by the host.
+    List(Vec<RuntimeValue>),
+    /// Has no literal syntax yet, injected by the host.
+    Map(BTreeMap<String, RuntimeValue>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ValueType {
+    Integer,
+    Double,
+    Boolean,
+    String,
+    HogwartsHouse,
+    List,
+    Map,
+}
+
+impl ValueType {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ValueType::Integer => "Integer",
+            ValueType::Double => "Double",
+            ValueType::Boolean => "Boolean",
+            ValueType::String => "String",
+            ValueType::HogwartsHouse => "HogwartsHouse",
+            ValueType::List => "List",
+            ValueType::Map => "Map",
+        }
+    }
+}
+
+impl fmt::Display for ValueType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl RuntimeValue {
+    pub fn value_type(&self) -> ValueType {
+        match self {
+            RuntimeValue::Integer(_) => ValueType::Integer,
+            RuntimeValue::Double(_) => ValueType::Double,
+            RuntimeValue::Boolean(_) => ValueType::Boolean,
+            RuntimeValue::String(_) => ValueType::String,
+            RuntimeValue::HogwartsHouse(_) => ValueType::HogwartsHouse,
+            RuntimeValue::List(_) => ValueType::List,
+            RuntimeValue::Map(_) => ValueType::Map,
+        }
+    }
+
+    pub fn type_name(&self) -> &'static str {
+        self.value_type().name()
+    }
 }
 
 impl From<Atom> for RuntimeValue {
@@ -24,7 +102,7 @@ impl From<Atom> for RuntimeValue {
             Atom::Boolean(boolean) => RuntimeValue::Boolean(boolean),
             Atom::Integer(integer) => RuntimeValue::Integer(integer),
             Atom::Double(float) => RuntimeValue::Double(float),
-            Atom::String(string) => RuntimeValue::String(string),
+            Atom::String(string) => RuntimeValue::String(string.into()),
             Atom::Variable(var) => panic!("Cannot convert variable to RuntimeValue: {}", var),
             Atom::HogwartsHouse(house) => RuntimeValue::HogwartsHouse(house),
         }
@@ -39,104 +117,333 @@ impl fmt::Display for RuntimeValue {
             RuntimeValue::Boolean(value) => write!(f, "{}", value),
             RuntimeValue::String(value) => write!(f, "{}", value),
             RuntimeValue::HogwartsHouse(value) => write!(f, "{:?}", value),
+            RuntimeValue::List(values) => {
+                write!(f, "[")?;
+                for (index, value) in values.iter().enumerate() {
+                    if index > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", value.literal())?;
+                }
+                write!(f, "]")
+            }
+            RuntimeValue::Map(entries) => {
+                write!(f, "{{")?;
+                for (index, (key, value)) in entries.iter().enumerate() {
+                    if index > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{:?}: ", key)?;
+                    write!(f, "{}", value.literal())?;
+                }
+                write!(f, "}}")
+            }
+        }
+    }
+}
+
+impl RuntimeValue {
+    /// Formats the value the way it would be written in a script, unlike
+    /// `Display` which prints strings as they are.
+    pub fn literal(&self) -> String {
+        match self {
+            RuntimeValue::String(value) => format!("{:?}", value),
+            _ => self.to_string(),
+        }
+    }
+
+    pub fn binary_operation(
+        self,
+        operation: BinaryOperation,
+        rhs: Self,
+    ) -> Result<Self, RuntimeError> {
+        match operation {
+            BinaryOperation::Plus => self + rhs,
+            BinaryOperation::Minus => self - rhs,
+            BinaryOperation::Times => self * rhs,
+            BinaryOperation::Divide => self / rhs,
+            BinaryOperation::Equal => Ok(RuntimeValue::Boolean(self == rhs)),
+            BinaryOperation::NotEqual => Ok(RuntimeValue::Boolean(self != rhs)),
         }
     }
 }
 
 impl ops::Add for RuntimeValue {
-    type Output = Self;
+    type Output = Result<Self, RuntimeError>;
 
     fn add(self, rhs: Self) -> Self::Output {
-        let self_value = self.clone();
-        let rhs_value = rhs.clone();
-
         match (self, rhs) {
             (RuntimeValue::Integer(left), RuntimeValue::Integer(right)) => {
-                RuntimeValue::Integer(left + right)
+                Ok(RuntimeValue::Integer(left.wrapping_add(right)))
             }
             (RuntimeValue::Double(left), RuntimeValue::Double(right)) => {
-                RuntimeValue::Double(left + right)
+                Ok(RuntimeValue::Double(left + right))
             }
             (RuntimeValue::String(left), RuntimeValue::String(right)) => {
-                RuntimeValue::String(left + &right)
+                Ok(RuntimeValue::String(format!("{}{}", left, right).into()))
             }
-            _ => panic!("Cannot add {:?} and {:?}", self_value, rhs_value),
+            (left, right) => Err(RuntimeError::InvalidBinaryOperation(
+                BinaryOperation::Plus,
+                left,
+                right,
+            )),
         }
     }
 }
 
 impl ops::Sub for RuntimeValue {
-    type Output = Self;
+    type Output = Result<Self, RuntimeError>;
 
     fn sub(self, rhs: Self) -> Self::Output {
-        let self_value = self.clone();
-        let rhs_value = rhs.clone();
-
         match (self, rhs) {
             (RuntimeValue::Integer(left), RuntimeValue::Integer(right)) => {
-                RuntimeValue::Integer(left - right)
+                Ok(RuntimeValue::Integer(left.wrapping_sub(right)))
             }
             (RuntimeValue::Double(left), RuntimeValue::Double(right)) => {
-                RuntimeValue::Double(left - right)
+                Ok(RuntimeValue::Double(left - right))
             }
-            _ => panic!("Cannot subtract {:?} and {:?}", self_value, rhs_value),
+            (left, right) => Err(RuntimeError::InvalidBinaryOperation(
+                BinaryOperation::Minus,
+                left,
+                right,
+            )),
         }
     }
 }
 
 impl ops::Mul for RuntimeValue {
-    type Output = Self;
+    type Output = Result<Self, RuntimeError>;
 
     fn mul(self, rhs: Self) -> Self::Output {
-        let self_value = self.clone();
-        let rhs_value = rhs.clone();
-
         match (self, rhs) {
             (RuntimeValue::Integer(left), RuntimeValue::Integer(right)) => {
-                RuntimeValue::Integer(left * right)
+                Ok(RuntimeValue::Integer(left.wrapping_mul(right)))
             }
             (RuntimeValue::Double(left), RuntimeValue::Double(right)) => {
-                RuntimeValue::Double(left * right)
+                Ok(RuntimeValue::Double(left * right))
             }
-            _ => panic!("Cannot multiply {:?} and {:?}", self_value, rhs_value),
+            (left, right) => Err(RuntimeError::InvalidBinaryOperation(
+                BinaryOperation::Times,
+                left,
+                right,
+            )),
         }
     }
 }
 
 impl ops::Div for RuntimeValue {
-    type Output = Self;
+    type Output = Result<Self, RuntimeError>;
 
     fn div(self, rhs: Self) -> Self::Output {
-        let self_value = self.clone();
-        let rhs_value = rhs.clone();
-
         match (self, rhs) {
+            (RuntimeValue::Integer(_), RuntimeValue::Integer(0)) => {
+                Err(RuntimeError::DivisionByZero)
+            }
             (RuntimeValue::Integer(left), RuntimeValue::Integer(right)) => {
-                RuntimeValue::Integer(left / right)
+                Ok(RuntimeValue::Integer(left.wrapping_div(right)))
             }
             (RuntimeValue::Double(left), RuntimeValue::Double(right)) => {
-                RuntimeValue::Double(left / right)
+                Ok(RuntimeValue::Double(left / right))
             }
-            _ => panic!("Cannot divide {:?} and {:?}", self_value, rhs_value),
+            (left, right) => Err(RuntimeError::InvalidBinaryOperation(
+                BinaryOperation::Divide,
+                left,
+                right,
+            )),
         }
     }
 }
 
 impl ops::Not for RuntimeValue {
-    type Output = Self;
+    type Output = Result<Self, RuntimeError>;
 
     fn not(self) -> Self::Output {
         match self {
-            RuntimeValue::Boolean(value) => RuntimeValue::Boolean(!value),
-            _ => panic!("Cannot negate {:?}", self),
+            RuntimeValue::Boolean(value) => Ok(RuntimeValue::Boolean(!value)),
+            _ => Err(RuntimeError::InvalidNegation(self)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum RuntimeError {
+    VariableNotFound(String),
+    ConstantReassignment(String),
+    NoneAssignment(String),
+    InvalidBinaryOperation(BinaryOperation, RuntimeValue, RuntimeValue),
+    InvalidNegation(RuntimeValue),
+    InvalidSpellTarget(Spell, RuntimeValue),
+    MissingSpellTarget(Spell),
+    UnknownSpell(String),
+    DivisionByZero,
+    StepLimitExceeded(usize),
+    /// A `~UnbreakableVow` that didn't hold, with the values of the operands
+    /// if the expression was a binary operation, or of the whole expression.
+    BrokenVow(Expression, Vec<RuntimeValue>),
+    /// Raised by host code, e.g. a native spell.
+    Custom(String),
+    /// Raised by the script with `~Confringo`.
+    Raised(RuntimeValue),
+    FieldNotFound(RuntimeValue, String),
+    /// `~AvadaKedabra` was cast. Not a failure: it stops the script, and
+    /// hosts end it like one that ran to the end. It is an error so it stops
+    /// every enclosing block and loop on its way out, and so that embedding
+    /// hosts decide what ending the script means instead of the runtime
+    /// exiting their process.
+    Exit,
+}
+
+impl RuntimeError {
+    /// Name of the variant, e.g. `VariableNotFound`, as seen by scripts in
+    /// the `kind` of a caught error.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            RuntimeError::VariableNotFound(_) => "VariableNotFound",
+            RuntimeError::ConstantReassignment(_) => "ConstantReassignment",
+            RuntimeError::NoneAssignment(_) => "NoneAssignment",
+            RuntimeError::InvalidBinaryOperation(_, _, _) => "InvalidBinaryOperation",
+            RuntimeError::InvalidNegation(_) => "InvalidNegation",
+            RuntimeError::InvalidSpellTarget(_, _) => "InvalidSpellTarget",
+            RuntimeError::MissingSpellTarget(_) => "MissingSpellTarget",
+            RuntimeError::UnknownSpell(_) => "UnknownSpell",
+            RuntimeError::DivisionByZero => "DivisionByZero",
+            RuntimeError::StepLimitExceeded(_) => "StepLimitExceeded",
+            RuntimeError::BrokenVow(_, _) => "BrokenVow",
+            RuntimeError::Custom(_) => "Custom",
+            RuntimeError::Raised(_) => "Raised",
+            RuntimeError::FieldNotFound(_, _) => "FieldNotFound",
+            RuntimeError::Exit => "Exit",
         }
     }
+
+    /// Whether `protego` can catch the error. Exceeding the step limit,
+    /// breaking a vow and `~AvadaKedabra` always stop the script.
+    pub fn is_catchable(&self) -> bool {
+        !matches!(
+            self,
+            RuntimeError::StepLimitExceeded(_) | RuntimeError::BrokenVow(_, _) | RuntimeError::Exit
+        )
+    }
+
+    /// The value `expelliarmus` binds the error to, a map with its `kind`
+    /// and `message`.
+    pub fn to_value(&self) -> RuntimeValue {
+        RuntimeValue::Map(BTreeMap::from([
+            ("kind".to_string(), RuntimeValue::from(self.kind())),
+            ("message".to_string(), RuntimeValue::from(self.to_string())),
+        ]))
+    }
 }
 
+impl fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RuntimeError::VariableNotFound(name) => write!(f, "Variable {} not found", name),
+            RuntimeError::ConstantReassignment(name) => {
+                write!(f, "Cannot re-assign constant {}", name)
+            }
+            RuntimeError::NoneAssignment(name) => {
+                write!(f, "Cannot assign None to variable {}", name)
+            }
+            RuntimeError::InvalidBinaryOperation(operation, left, right) => {
+                let verb = match operation {
+                    BinaryOperation::Plus => "add",
+                    BinaryOperation::Minus => "subtract",
+                    BinaryOperation::Times => "multiply",
+                    BinaryOperation::Divide => "divide",
+                    BinaryOperation::Equal | BinaryOperation::NotEqual => "compare",
+                };
+                write!(f, "Cannot {} {:?} and {:?}", verb, left, right)
+            }
+            RuntimeError::InvalidNegation(value) => write!(f, "Cannot negate {:?}", value),
+            RuntimeError::InvalidSpellTarget(spell, value) => {
+                write!(f, "Cannot {:?} {:?}", spell, value)
+            }
+            RuntimeError::MissingSpellTarget(spell) => write!(f, "{} needs a target", spell),
+            RuntimeError::UnknownSpell(name) => write!(f, "Wand broken: Unknown spell {}", name),
+            RuntimeError::DivisionByZero => write!(f, "Cannot divide by zero"),
+            RuntimeError::StepLimitExceeded(limit) => {
+                write!(f, "Step limit of {} statements exceeded", limit)
+            }
+            RuntimeError::BrokenVow(expression, values) => {
+                let actual = match (expression, values.as_slice()) {
+                    (Expression::BinaryOperation(operation, _, _), [left, right]) => {
+                        format!("{} {} {}", left.literal(), operation, right.literal())
+                    }
+                    _ => values
+                        .iter()
+                        .map(RuntimeValue::literal)
+                        .collect::<Vec<_>>()
+                        .join(", "),
+                };
+                write!(
+                    f,
+                    "Unbreakable Vow broken: `{}` was `{}`",
+                    expression, actual
+                )
+            }
+            RuntimeError::Custom(message) => write!(f, "{}", message),
+            RuntimeError::Raised(value) => write!(f, "{}", value),
+            RuntimeError::FieldNotFound(value, field) => {
+                write!(f, "Field {} not found in {:?}", field, value)
+            }
+            RuntimeError::Exit => write!(f, "{} ended the script", Spell::AvadaKedabra),
+        }
+    }
+}
+
+impl error::Error for RuntimeError {}
+
 pub trait RuntimeAdapter {
-    fn create_random_index(&self) -> usize;
+    /// A random integer in `range`, e.g. `0..=3` for the Sorting Hat.
+    fn random_integer(&self, range: ops::RangeInclusive<i64>) -> i64;
+    /// A random double in `0.0..1.0`.
+    fn random_double(&self) -> f64;
     fn lumos(&self, string: String) -> String;
     fn log(&self, string: &str);
+
+    /// Reads a line for `~Accio`, showing `prompt` first if given. `None`
+    /// means there is no more input, which is also the default.
+    fn read_line(&self, _prompt: Option<&str>) -> Option<String> {
+        None
+    }
+
+    /// Pauses for `~Inmobolus`. Does nothing by default.
+    fn sleep(&self, _duration: time::Duration) {}
+
+    /// The index of a house the Sorting Hat picks.
+    #[deprecated(note = "use `random_integer(0..=3)` instead")]
+    fn create_random_index(&self) -> usize {
+        self.random_integer(0..=3) as usize
+    }
+}
+
+/// Lets a runtime borrow the adapter of another, e.g. to run a program on a
+/// [`vm::Vm`] with the adapter of a [`Runtime`].
+impl<A: RuntimeAdapter + ?Sized> RuntimeAdapter for &A {
+    fn random_integer(&self, range: ops::RangeInclusive<i64>) -> i64 {
+        (**self).random_integer(range)
+    }
+
+    fn random_double(&self) -> f64 {
+        (**self).random_double()
+    }
+
+    fn lumos(&self, string: String) -> String {
+        (**self).lumos(string)
+    }
+
+    fn log(&self, string: &str) {
+        (**self).log(string)
+    }
+
+    fn read_line(&self, prompt: Option<&str>) -> Option<String> {
+        (**self).read_line(prompt)
+    }
+
+    fn sleep(&self, duration: time::Duration) {
+        (**self).sleep(duration)
+    }
 }
 
 #[cfg(feature = "std")]
@@ -144,9 +451,12 @@ pub struct DefaultRuntimeAdapter;
 
 #[cfg(feature = "std")]
 impl RuntimeAdapter for DefaultRuntimeAdapter {
-    fn create_random_index(&self) -> usize {
-        let mut rng = rand::thread_rng();
-        rng.gen_range(0..=3)
+    fn random_integer(&self, range: ops::RangeInclusive<i64>) -> i64 {
+        rand::thread_rng().gen_range(range)
+    }
+
+    fn random_double(&self) -> f64 {
+        rand::thread_rng().gen()
     }
 
     fn lumos(&self, string: String) -> String {
@@ -156,285 +466,772 @@ impl RuntimeAdapter for DefaultRuntimeAdapter {
     fn log(&self, string: &str) {
         println!("{}", string);
     }
+
+    fn read_line(&self, prompt: Option<&str>) -> Option<String> {
+        if let Some(prompt) = prompt {
+            print!("{}", prompt);
+            io::stdout().flush().ok()?;
+        }
+
+        let mut line = String::new();
+        match io::stdin().read_line(&mut line) {
+            Ok(0) | Err(_) => None,
+            Ok(_) => {
+                let len = line.trim_end_matches(['\n', '\r']).len();
+                line.truncate(len);
+                Some(line)
+            }
+        }
+    }
+
+    fn sleep(&self, duration: time::Duration) {
+        thread::sleep(duration);
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BindingKind {
+    Variable,
+    /// A variable frozen with `~PetrificusTotalus`.
+    Constant,
+}
+
+pub type Watcher = dyn FnMut(Option<&RuntimeValue>);
+
+struct Binding {
+    value: RuntimeValue,
+    kind: BindingKind,
 }
 
 pub struct Runtime<T: RuntimeAdapter> {
     runtime_adapter: T,
-    variables: HashMap<String, RuntimeValue>,
-    constants: HashMap<String, RuntimeValue>,
+    names: Interner,
+    /// Value of every variable and constant, indexed by the slot of its name.
+    slots: Vec<Option<Binding>>,
+    /// Watchers of every variable, indexed by slot.
+    watchers: Vec<Vec<Box<Watcher>>>,
+    spells: HashMap<String, NativeSpell>,
     quidditch: bool,
     is_lumos_casted: bool,
+    last_value: Option<RuntimeValue>,
+    steps: usize,
+    max_steps: Option<usize>,
 }
 
 impl<T: RuntimeAdapter> Runtime<T> {
     pub fn new(runtime_adapter: T) -> Self {
         Self {
             runtime_adapter,
-            variables: HashMap::new(),
-            constants: HashMap::new(),
+            names: Interner::default(),
+            slots: vec![],
+            watchers: vec![],
+            spells: HashMap::new(),
             quidditch: false,
             is_lumos_casted: false,
+            last_value: None,
+            steps: 0,
+            max_steps: None,
+        }
+    }
+
+    /// Limits how many statements this runtime evaluates before failing with
+    /// [`RuntimeError::StepLimitExceeded`], e.g. to stop a `quidditch` loop
+    /// that never catches the snitch. `None` means no limit.
+    pub fn set_max_steps(&mut self, max_steps: Option<usize>) {
+        self.max_steps = max_steps;
+    }
+
+    /// Registers a native spell cast as `~name`. The handler receives the
+    /// evaluated target, already checked against `target`, and returns the
+    /// value of the cast.
+    ///
+    /// # Panics
+    ///
+    /// If `name` is a built-in spell like `Revelio`, which cannot be
+    /// overridden.
+    pub fn register_spell<F>(&mut self, name: &str, target: SpellTarget, handler: F)
+    where
+        F: FnMut(Option<RuntimeValue>) -> Result<Option<RuntimeValue>, RuntimeError> + 'static,
+    {
+        let spell = NativeSpell::new(name, target, Box::new(handler));
+        self.spells.insert(name.to_string(), spell);
+    }
+
+    pub fn adapter(&self) -> &T {
+        &self.runtime_adapter
+    }
+
+    pub fn into_adapter(self) -> T {
+        self.runtime_adapter
+    }
+
+    /// Value of the variable or constant called `name`.
+    pub fn get(&self, name: &str) -> Option<&RuntimeValue> {
+        let slot = self.names.get(name)?;
+        self.slots[slot].as_ref().map(|binding| &binding.value)
+    }
+
+    /// Sets a variable before or after running a script. Watchers are only
+    /// notified of changes made by the script.
+    pub fn set(&mut self, name: &str, value: RuntimeValue) -> Result<(), RuntimeError> {
+        let slot = self.slot(name);
+        self.bind(slot, value, BindingKind::Variable)
+    }
+
+    /// Declares a constant, as if the script had cast `~PetrificusTotalus` on
+    /// it, replacing any variable with the same name.
+    pub fn declare_constant(
+        &mut self,
+        name: &str,
+        value: RuntimeValue,
+    ) -> Result<(), RuntimeError> {
+        let slot = self.slot(name);
+        self.bind(slot, value, BindingKind::Constant)
+    }
+
+    /// Removes a variable, returning its value. Constants cannot be removed.
+    pub fn remove(&mut self, name: &str) -> Option<RuntimeValue> {
+        let slot = self.names.get(name)?;
+        match &self.slots[slot] {
+            Some(binding) if binding.kind == BindingKind::Variable => {
+                self.slots[slot].take().map(|binding| binding.value)
+            }
+            _ => None,
         }
     }
 
-    pub fn eval(&mut self, program: Program) {
-        for statement in program.0 {
-            self.eval_statement(statement);
+    /// Calls `watcher` with the new value every time the script assigns,
+    /// mutates or obliviates (with `None`) the variable called `name`.
+    pub fn watch<F>(&mut self, name: &str, watcher: F)
+    where
+        F: FnMut(Option<&RuntimeValue>) + 'static,
+    {
+        let slot = self.slot(name);
+        self.watchers[slot].push(Box::new(watcher));
+    }
+
+    /// Removes every watcher of the variable called `name`.
+    pub fn unwatch(&mut self, name: &str) {
+        if let Some(slot) = self.names.get(name) {
+            self.watchers[slot].clear();
         }
     }
 
-    fn eval_atom(&self, atom: Atom) -> RuntimeValue {
-        match atom {
-            Atom::Variable(var_name) => self
-                .variables
-                .get(&var_name)
-                .cloned()
-                .expect(format!("Variable {} not found", var_name).as_str()),
-            _ => atom.into(),
+    /// Every variable and constant currently defined, in no particular order.
+    pub fn bindings(&self) -> impl Iterator<Item = (&str, &RuntimeValue, BindingKind)> {
+        self.slots.iter().enumerate().filter_map(|(slot, binding)| {
+            let binding = binding.as_ref()?;
+            Some((self.names.name(slot), &binding.value, binding.kind))
+        })
+    }
+
+    /// Names of every variable and constant currently defined.
+    pub fn variable_names(&self) -> impl Iterator<Item = &str> {
+        self.bindings().map(|(name, _, _)| name)
+    }
+
+    /// Forgets every binding and spell effect, keeping the adapter and the
+    /// step limit.
+    pub fn reset(&mut self) {
+        self.slots.fill_with(|| None);
+        self.quidditch = false;
+        self.is_lumos_casted = false;
+        self.last_value = None;
+        self.steps = 0;
+    }
+
+    /// Value of the last expression statement evaluated by the most recent
+    /// top-level statement, e.g. `7` after evaluating `3 + 4`.
+    pub fn last_value(&self) -> Option<&RuntimeValue> {
+        self.last_value.as_ref()
+    }
+
+    /// Evaluates `program`, which is only borrowed so it can be evaluated
+    /// again. Its variables are resolved to slots first, which keep their
+    /// values from earlier programs.
+    ///
+    /// A script ended with `~AvadaKedabra` returns
+    /// [`RuntimeError::Exit`], which hosts usually treat like `Ok`.
+    pub fn eval(&mut self, program: &Program) -> Result<(), RuntimeError> {
+        let resolved = resolver::resolve(program, &mut self.names);
+        self.grow();
+
+        for statement in &resolved.statements {
+            self.last_value = None;
+            self.eval_statement(statement)?;
         }
+        Ok(())
     }
 
-    fn eval_statement(&mut self, statement: Statement) {
+    /// The slot of the variable called `name`, interning it if needed.
+    fn slot(&mut self, name: &str) -> Slot {
+        let slot = self.names.intern(name);
+        self.grow();
+        slot
+    }
+
+    /// Makes room for the slots of every interned name.
+    fn grow(&mut self) {
+        self.slots.resize_with(self.names.len(), || None);
+        self.watchers.resize_with(self.names.len(), Vec::new);
+    }
+
+    fn bind(
+        &mut self,
+        slot: Slot,
+        value: RuntimeValue,
+        kind: BindingKind,
+    ) -> Result<(), RuntimeError> {
+        if let Some(Binding {
+            kind: BindingKind::Constant,
+            ..
+        }) = self.slots[slot]
+        {
+            let name = self.names.name(slot).to_string();
+            return Err(RuntimeError::ConstantReassignment(name));
+        }
+
+        self.slots[slot] = Some(Binding { value, kind });
+        Ok(())
+    }
+
+    fn get_variable(&self, slot: Slot) -> Result<RuntimeValue, RuntimeError> {
+        match &self.slots[slot] {
+            Some(binding) => Ok(binding.value.clone()),
+            None => Err(RuntimeError::VariableNotFound(
+                self.names.name(slot).to_string(),
+            )),
+        }
+    }
+
+    /// Sets a variable on behalf of the script, notifying its watchers.
+    fn assign(&mut self, slot: Slot, value: RuntimeValue) -> Result<(), RuntimeError> {
+        self.bind(slot, value, BindingKind::Variable)?;
+        self.notify(slot);
+        Ok(())
+    }
+
+    fn notify(&mut self, slot: Slot) {
+        let value = self.slots[slot].as_ref().map(|binding| &binding.value);
+        for watcher in &mut self.watchers[slot] {
+            watcher(value);
+        }
+    }
+
+    fn eval_block(&mut self, block: &[Stmt]) -> Result<(), RuntimeError> {
+        block
+            .iter()
+            .try_for_each(|statement| self.eval_statement(statement))
+    }
+
+    fn eval_statement(&mut self, statement: &Stmt) -> Result<(), RuntimeError> {
+        self.steps += 1;
+        if let Some(max_steps) = self.max_steps {
+            if self.steps > max_steps {
+                return Err(RuntimeError::StepLimitExceeded(max_steps));
+            }
+        }
+
         match statement {
-            Statement::VariableAssignment(name, value) => {
-                if self.constants.contains_key(&name) {
-                    panic!("Cannot re-assign a constant!");
+            Stmt::Assign(slot, value) => {
+                if let Some(Binding {
+                    kind: BindingKind::Constant,
+                    ..
+                }) = self.slots[*slot]
+                {
+                    let name = self.names.name(*slot).to_string();
+                    return Err(RuntimeError::ConstantReassignment(name));
                 }
 
-                // dbg!(format!("VariableAssignment: {:?} = {:?}", name, value));
-                let evaluated_value = self.eval_expression(value);
+                let evaluated_value = self.eval_expression(value)?;
 
                 if let Some(evaluated_value) = evaluated_value {
-                    self.variables.insert(name, evaluated_value);
+                    self.assign(*slot, evaluated_value)?;
                 } else {
-                    panic!("Cannot assign None to variable");
+                    let name = self.names.name(*slot).to_string();
+                    return Err(RuntimeError::NoneAssignment(name));
                 }
             }
-            Statement::ExpressionStatement(expression) => {
-                // dbg!(format!("ExpressionStatement: {:?}", expression));
-                self.eval_expression(expression);
+            Stmt::Expression(expression) => {
+                self.last_value = self.eval_expression(expression)?;
             }
-            Statement::If(condition, true_block, else_block) => {
-                // dbg!(format!("If: {:?} {{ ... }}", condition));
-                if let Some(RuntimeValue::Boolean(true)) = self.eval_expression(condition) {
-                    for statement in true_block {
-                        self.eval_statement(statement);
-                    }
+            Stmt::If(condition, true_block, else_block) => {
+                if let Some(RuntimeValue::Boolean(true)) = self.eval_expression(condition)? {
+                    self.eval_block(true_block)?;
                 } else {
-                    for statement in else_block {
-                        self.eval_statement(statement);
-                    }
+                    self.eval_block(else_block)?;
                 }
             }
-            Statement::Quidditch(block) => {
-                // dbg!(format!("Quidditch: {:?} {{ ... }}", condition));
+            Stmt::Quidditch(block) => {
                 self.quidditch = true;
 
-                // arc
                 while self.quidditch {
-                    self.eval(Program(block.clone()))
+                    self.eval_block(block)?;
                 }
             }
-            Statement::Snitch => {
-                // dbg!("Snitch");
+            Stmt::Snitch => {
                 self.quidditch = false;
             }
+            Stmt::Vow => {}
+            Stmt::Protego(block, slot, catch_block) => {
+                // A loop the error escapes from leaves the flag set
+                let quidditch = self.quidditch;
+                match self.eval_block(block) {
+                    Err(error) if error.is_catchable() => {
+                        self.quidditch = quidditch;
+                        self.assign(*slot, error.to_value())?;
+                        self.eval_block(catch_block)?;
+                    }
+                    result => result?,
+                }
+            }
         }
+
+        Ok(())
     }
 
-    fn eval_expression(&mut self, expression: Expression) -> Option<RuntimeValue> {
+    fn eval_expression(&mut self, expression: &Expr) -> Result<Option<RuntimeValue>, RuntimeError> {
         match expression {
-            Expression::SpellCast(spell, target) => self.eval_spell(spell, target),
-            Expression::BinaryOperation(operation, left, right) => {
-                match (self.eval_expression(*left), self.eval_expression(*right)) {
-                    (Some(left), Some(right)) => match operation {
-                        BinaryOperation::Plus => Some(left + right),
-                        BinaryOperation::Minus => Some(left - right),
-                        BinaryOperation::Times => Some(left * right),
-                        BinaryOperation::Divide => Some(left / right),
-                        BinaryOperation::Equal => Some(RuntimeValue::Boolean(left == right)),
-                        BinaryOperation::NotEqual => Some(RuntimeValue::Boolean(left != right)),
-                    },
-                    _ => None,
+            Expr::Spell(spell, target) => self.eval_spell(spell, target),
+            Expr::UnbreakableVow(expression, resolved) => {
+                let (value, operands) = match resolved.as_ref() {
+                    Expr::BinaryOperation(operation, left, right) => {
+                        let left = self.eval_expression(left)?;
+                        let right = self.eval_expression(right)?;
+                        spells::vow_operation(*operation, left, right)?
+                    }
+                    resolved => {
+                        let value = self.eval_expression(resolved)?;
+                        (value.clone(), value.into_iter().collect())
+                    }
+                };
+                spells::unbreakable_vow(expression, value, operands).map(|_| None)
+            }
+            Expr::BinaryOperation(operation, left, right) => {
+                match (self.eval_expression(left)?, self.eval_expression(right)?) {
+                    (Some(left), Some(right)) => left.binary_operation(*operation, right).map(Some),
+                    _ => Ok(None),
                 }
             }
-            Expression::Atom(atom) => Some(self.eval_atom(atom)),
-            Expression::Comment(_) => None,
-            Expression::SortingHat => {
-                let houses = vec![
-                    HogwartsHouse::Gryffindor,
-                    HogwartsHouse::Hufflepuff,
-                    HogwartsHouse::Ravenclaw,
-                    HogwartsHouse::Slytherin,
-                ];
-
-                let index = self.runtime_adapter.create_random_index();
-                let random_house = houses[index];
-                Some(RuntimeValue::HogwartsHouse(random_house.clone()))
+            Expr::Variable(slot) => self.get_variable(*slot).map(Some),
+            Expr::Value(value) => Ok(Some(value.clone())),
+            Expr::Field(expression, field) => match self.eval_expression(expression)? {
+                Some(RuntimeValue::Map(mut entries)) => match entries.remove(*field) {
+                    Some(value) => Ok(Some(value)),
+                    None => Err(RuntimeError::FieldNotFound(
+                        RuntimeValue::Map(entries),
+                        field.to_string(),
+                    )),
+                },
+                Some(value) => Err(RuntimeError::FieldNotFound(value, field.to_string())),
+                None => Ok(None),
+            },
+            Expr::Nothing => Ok(None),
+            Expr::SortingHat => {
+                let index = self.runtime_adapter.random_integer(0..=3);
+                let random_house = HogwartsHouse::ALL[index as usize];
+                Ok(Some(RuntimeValue::HogwartsHouse(random_house)))
             }
         }
     }
 
+    /// Evaluates the target of a spell that evaluates it, if any.
+    fn eval_target(&mut self, target: &Target) -> Result<Option<RuntimeValue>, RuntimeError> {
+        match target {
+            Target::Expression(target) => self.eval_expression(target),
+            _ => Ok(None),
+        }
+    }
+
     fn eval_spell(
         &mut self,
-        spell: Spell,
-        target: Box<Option<Expression>>,
-    ) -> Option<RuntimeValue> {
+        spell: &Spell,
+        target: &Target,
+    ) -> Result<Option<RuntimeValue>, RuntimeError> {
         match spell {
-            Spell::AvadaKedabra => process::exit(0),
-            Spell::Inmobolus => match *target {
-                Some(Expression::Atom(Atom::Integer(number))) => {
-                    let _ms = time::Duration::from_millis(number as u64);
-                    // TODO add this again when WASM compatible (need to add tokio runtime)
-                    // thread::sleep(ms);
-                    None
-                }
-                _ => None,
-            },
-            Spell::Incendio => match *target {
-                Some(Expression::Atom(Atom::Variable(var_name))) => {
-                    let value = self
-                        .variables
-                        .get(&var_name)
-                        .cloned()
-                        .expect(format!("Variable {} not found", var_name).as_str());
-                    match value {
-                        RuntimeValue::String(string) => {
-                            self.variables
-                                .insert(var_name, RuntimeValue::String(string + "🔥"));
-                        }
-                        _ => panic!("Cannot Incendio {:?}", value),
-                    }
-                    None
+            Spell::Custom(name) => {
+                let target = self.eval_target(target)?;
+                match self.spells.get_mut(name) {
+                    Some(spell) => spell.cast(name, target),
+                    None => Err(RuntimeError::UnknownSpell(name.clone())),
                 }
-                Some(Expression::Atom(Atom::String(string))) => {
-                    Some(RuntimeValue::String(string + "🔥"))
+            }
+            Spell::Accio => {
+                let prompt = self.eval_target(target)?;
+                Ok(Some(spells::accio(&self.runtime_adapter, prompt)))
+            }
+            // With a target it is resolved to `Expr::UnbreakableVow`
+            Spell::UnbreakableVow => Err(RuntimeError::MissingSpellTarget(Spell::UnbreakableVow)),
+            Spell::FelixFelicis => {
+                let target = self.eval_target(target)?;
+                spells::felix_felicis(&self.runtime_adapter, target).map(Some)
+            }
+            Spell::AvadaKedabra => Err(RuntimeError::Exit),
+            Spell::Confringo => {
+                let target = self.eval_target(target)?;
+                Err(spells::confringo(target))
+            }
+            Spell::Inmobolus => {
+                let target = self.eval_target(target)?;
+                spells::inmobolus(&self.runtime_adapter, target);
+                Ok(None)
+            }
+            Spell::Incendio => match target {
+                Target::Variable(slot) => {
+                    let value = self.get_variable(*slot)?;
+                    self.assign(*slot, spells::append(Spell::Incendio, value, "🔥")?)?;
+                    Ok(None)
                 }
-                _ => None,
+                Target::String(string) => Ok(Some(RuntimeValue::from(format!("{}🔥", string)))),
+                _ => Ok(None),
             },
-            Spell::Aguamenti => RuntimeValue::String("💦".to_string()).into(),
-            Spell::OculusReparo => RuntimeValue::String("👓".to_string()).into(),
-            Spell::Serpensortia => RuntimeValue::String("🐍".to_string()).into(),
+            Spell::Aguamenti => Ok(Some(RuntimeValue::from("💦"))),
+            Spell::OculusReparo => Ok(Some(RuntimeValue::from("👓"))),
+            Spell::Serpensortia => Ok(Some(RuntimeValue::from("🐍"))),
             Spell::Periculum => {
                 self.runtime_adapter.log("🔥🔥🔥🔥🔥🔥🔥🔥🔥");
-                None
+                Ok(None)
             }
             Spell::Lumos => {
                 self.is_lumos_casted = true;
-                None
+                Ok(None)
             }
             Spell::Nox => {
                 self.is_lumos_casted = false;
-                None
+                Ok(None)
             }
-            Spell::Engorgio => match *target {
-                Some(Expression::Atom(Atom::Variable(var_name))) => {
-                    let value = self
-                        .variables
-                        .get(&var_name)
-                        .cloned()
-                        .expect(format!("Variable {} not found", var_name).as_str());
-                    match value {
-                        RuntimeValue::Integer(value) => {
-                            self.variables
-                                .insert(var_name, RuntimeValue::Integer(value + 1));
-                        }
-                        RuntimeValue::Double(value) => {
-                            self.variables
-                                .insert(var_name, RuntimeValue::Double(value + 1.0));
-                        }
-                        RuntimeValue::String(string) => {
-                            self.variables.insert(
-                                var_name,
-                                RuntimeValue::String(string.to_ascii_uppercase()),
-                            );
-                        }
-                        _ => panic!("Cannot increment {:?}", value),
-                    }
-                    None
+            Spell::Engorgio => match target {
+                Target::Variable(slot) => {
+                    let value = self.get_variable(*slot)?;
+                    self.assign(*slot, spells::engorgio(value)?)?;
+                    Ok(None)
                 }
-                _ => None,
+                _ => Ok(None),
             },
-            Spell::Reducio => match *target {
-                Some(Expression::Atom(Atom::Variable(var_name))) => {
-                    let value = self
-                        .variables
-                        .get(&var_name)
-                        .cloned()
-                        .expect(format!("Variable {} not found", var_name).as_str());
-                    match value {
-                        RuntimeValue::Integer(value) => {
-                            self.variables
-                                .insert(var_name, RuntimeValue::Integer(value - 1));
-                        }
-                        RuntimeValue::Double(value) => {
-                            self.variables
-                                .insert(var_name, RuntimeValue::Double(value - 1.0));
-                        }
-                        RuntimeValue::String(string) => {
-                            self.variables.insert(
-                                var_name,
-                                RuntimeValue::String(string.to_ascii_lowercase()),
-                            );
-                        }
-                        _ => panic!("Cannot decrement {:?}", value),
-                    }
-                    None
+            Spell::Reducio => match target {
+                Target::Variable(slot) => {
+                    let value = self.get_variable(*slot)?;
+                    self.assign(*slot, spells::reducio(value)?)?;
+                    Ok(None)
                 }
-                _ => None,
+                _ => Ok(None),
             },
-            Spell::Obliviate => match *target {
-                Some(Expression::Atom(Atom::Variable(var_name))) => {
-                    self.variables.remove(&var_name);
-                    None
+            Spell::Obliviate => match target {
+                Target::Variable(slot) => {
+                    if let Some(Binding {
+                        kind: BindingKind::Variable,
+                        ..
+                    }) = self.slots[*slot]
+                    {
+                        self.slots[*slot] = None;
+                        self.notify(*slot);
+                    }
+                    Ok(None)
                 }
-                _ => None,
+                _ => Ok(None),
             },
-            Spell::Revelio => match *target {
-                Some(target) => {
+            Spell::Revelio => match target {
+                Target::Expression(target) => {
                     let mut string_target: String = self
-                        .eval_expression(target)
-                        .unwrap_or(RuntimeValue::String("".to_string()))
+                        .eval_expression(target)?
+                        .unwrap_or(RuntimeValue::from(""))
                         .to_string();
                     if self.is_lumos_casted {
                         string_target = self.runtime_adapter.lumos(string_target);
                     }
                     self.runtime_adapter.log(&string_target);
-                    None
+                    Ok(None)
                 }
-                None => None,
+                _ => Ok(None),
             },
-            Spell::PetrificusTotalus => match *target {
-                Some(Expression::Atom(Atom::Variable(var_name))) => {
-                    let value = self.variables.remove(&var_name);
-                    if let Some(value) = value {
-                        self.constants.insert(var_name, value);
+            Spell::PetrificusTotalus => match target {
+                Target::Variable(slot) => {
+                    if let Some(binding) = &mut self.slots[*slot] {
+                        binding.kind = BindingKind::Constant;
                     }
-                    None
+                    Ok(None)
                 }
-                _ => None,
+                _ => Ok(None),
             },
-            Spell::WingardiumLeviosa => match *target {
-                Some(Expression::Atom(Atom::Variable(var_name))) => {
-                    let value = self
-                        .variables
-                        .get(&var_name)
-                        .cloned()
-                        .expect(format!("Variable {} not found", var_name).as_str());
-                    match value {
-                        RuntimeValue::String(string) => {
-                            self.variables
-                                .insert(var_name, RuntimeValue::String(string + "\n"));
-                        }
-                        _ => panic!("Cannot WingardiumLeviosa {:?}", value),
-                    }
-                    None
-                }
-                Some(Expression::Atom(Atom::String(string))) => {
-                    Some(RuntimeValue::String(string + "\n"))
+            Spell::WingardiumLeviosa => match target {
+                Target::Variable(slot) => {
+                    let value = self.get_variable(*slot)?;
+                    let value = spells::append(Spell::WingardiumLeviosa, value, "\n")?;
+                    self.assign(*slot, value)?;
+                    Ok(None)
                 }
-                _ => None,
+                Target::String(string) => Ok(Some(RuntimeValue::from(format!("{}\n", string)))),
+                _ => Ok(None),
             },
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn eval(code: &str) -> Runtime<TestAdapter> {
+        let program = potterscript_parser::parse(code).unwrap();
+        let mut runtime = Runtime::new(TestAdapter::new());
+        runtime.eval(&program).unwrap();
+        runtime
+    }
+
+    #[test]
+    fn test_native_spell() {
+        let program = potterscript_parser::parse("x = ~Double 21\ny = ~Answer").unwrap();
+        let mut runtime = Runtime::new(TestAdapter::new());
+        runtime.register_spell("Double", SpellTarget::Type(ValueType::Integer), |target| {
+            match target {
+                Some(RuntimeValue::Integer(value)) => Ok(Some(RuntimeValue::Integer(value * 2))),
+                _ => unreachable!(),
+            }
+        });
+        runtime.register_spell("Answer", SpellTarget::None, |_| {
+            Err(RuntimeError::Custom("Ask again later".to_string()))
+        });
+
+        let error = runtime.eval(&program).unwrap_err();
+        assert_eq!(RuntimeError::Custom("Ask again later".to_string()), error);
+        assert_eq!(Some(&RuntimeValue::Integer(42)), runtime.get("x"));
+    }
+
+    #[test]
+    #[should_panic(expected = "cannot register the built-in spell ~Revelio")]
+    fn test_native_spell_cannot_override_built_in() {
+        let mut runtime = Runtime::new(TestAdapter::new());
+        runtime.register_spell("Revelio", SpellTarget::Any, Ok);
+    }
+
+    #[test]
+    fn test_native_spell_target_checks() {
+        let mut runtime = Runtime::new(TestAdapter::new());
+        runtime.register_spell("Double", SpellTarget::Type(ValueType::Integer), |target| {
+            Ok(target)
+        });
+
+        let program = potterscript_parser::parse("~Double \"two\"").unwrap();
+        assert_eq!(
+            Err(RuntimeError::InvalidSpellTarget(
+                Spell::Custom("Double".to_string()),
+                RuntimeValue::from("two")
+            )),
+            runtime.eval(&program)
+        );
+
+        let program = potterscript_parser::parse("~Double").unwrap();
+        assert_eq!(
+            Err(RuntimeError::MissingSpellTarget(Spell::Custom(
+                "Double".to_string()
+            ))),
+            runtime.eval(&program)
+        );
+
+        let program = potterscript_parser::parse("~Expecto").unwrap();
+        assert_eq!(
+            Err(RuntimeError::UnknownSpell("Expecto".to_string())),
+            runtime.eval(&program)
+        );
+    }
+
+    #[test]
+    fn test_host_bindings() {
+        let program = potterscript_parser::parse("total = price * 2\n~Engorgio total").unwrap();
+        let mut runtime = Runtime::new(TestAdapter::new());
+        runtime.set("price", RuntimeValue::Integer(21)).unwrap();
+        runtime.eval(&program).unwrap();
+
+        assert_eq!(Some(&RuntimeValue::Integer(43)), runtime.get("total"));
+        assert_eq!(Some(RuntimeValue::Integer(21)), runtime.remove("price"));
+        assert_eq!(None, runtime.get("price"));
+    }
+
+    #[test]
+    fn test_host_constants() {
+        let mut runtime = Runtime::new(TestAdapter::new());
+        runtime
+            .declare_constant(
+                "house",
+                RuntimeValue::HogwartsHouse(HogwartsHouse::Ravenclaw),
+            )
+            .unwrap();
+
+        let program = potterscript_parser::parse("same = house == Ravenclaw").unwrap();
+        runtime.eval(&program).unwrap();
+        assert_eq!(Some(&RuntimeValue::Boolean(true)), runtime.get("same"));
+
+        let program = potterscript_parser::parse("house = Slytherin").unwrap();
+        assert_eq!(
+            Err(RuntimeError::ConstantReassignment("house".to_string())),
+            runtime.eval(&program)
+        );
+        assert_eq!(
+            Err(RuntimeError::ConstantReassignment("house".to_string())),
+            runtime.set("house", RuntimeValue::Integer(1))
+        );
+    }
+
+    #[test]
+    fn test_watch() {
+        use std::cell::RefCell;
+        use std::rc::Rc;
+
+        let changes = Rc::new(RefCell::new(vec![]));
+        let mut runtime = Runtime::new(TestAdapter::new());
+        let watched = Rc::clone(&changes);
+        runtime.watch("x", move |value| watched.borrow_mut().push(value.cloned()));
+
+        let program =
+            potterscript_parser::parse("x = 1\ny = 2\n~Engorgio x\n~Obliviate x").unwrap();
+        runtime.eval(&program).unwrap();
+
+        assert_eq!(
+            vec![
+                Some(RuntimeValue::Integer(1)),
+                Some(RuntimeValue::Integer(2)),
+                None
+            ],
+            *changes.borrow()
+        );
+    }
+
+    #[test]
+    fn test_last_value() {
+        let runtime = eval("x = 3\nx + 4");
+        assert_eq!(Some(&RuntimeValue::Integer(7)), runtime.last_value());
+    }
+
+    #[test]
+    fn test_last_value_cleared_by_statements() {
+        let runtime = eval("3 + 4\nx = 3");
+        assert_eq!(None, runtime.last_value());
+
+        let runtime = eval("x = \"a\"\n~Revelio x");
+        assert_eq!(None, runtime.last_value());
+    }
+
+    #[test]
+    fn test_eval_program_again() {
+        let code =
+            "i = 0\nquidditch {\n~Engorgio i\n~Engorgio x\nif i == 3 {\nsnitch\n}\n}\n~Revelio x";
+        let program = potterscript_parser::parse(code).unwrap();
+        let mut runtime = Runtime::new(TestAdapter::new());
+        runtime.set("x", RuntimeValue::Integer(0)).unwrap();
+
+        runtime.eval(&program).unwrap();
+        runtime.eval(&program).unwrap();
+        runtime.adapter().assert_logs(&["3", "6"]);
+    }
+
+    #[test]
+    fn test_accio() {
+        let adapter = TestAdapter::new().with_inputs(["Hedwig"]);
+        let program = potterscript_parser::parse("name = ~Accio \"Owl? \"\nnext = ~Accio").unwrap();
+        let mut runtime = Runtime::new(adapter);
+        runtime.eval(&program).unwrap();
+
+        assert_eq!(Some(&RuntimeValue::from("Hedwig")), runtime.get("name"));
+        assert_eq!(Some(&RuntimeValue::Boolean(false)), runtime.get("next"));
+        assert_eq!(
+            vec![Some("Owl? ".to_string()), None],
+            *runtime.adapter().prompts()
+        );
+    }
+
+    #[test]
+    fn test_felix_felicis() {
+        let adapter = TestAdapter::new()
+            .with_integers([5, 1])
+            .with_doubles([0.25, 0.5]);
+        let program = potterscript_parser::parse(
+            "roll = ~FelixFelicis 6\nluck = ~FelixFelicis\nchance = ~FelixFelicis 10.0\npet = ~FelixFelicis pets",
+        )
+        .unwrap();
+        let mut runtime = Runtime::new(adapter);
+        let pets = vec![RuntimeValue::from("Hedwig"), RuntimeValue::from("Scabbers")];
+        runtime.set("pets", RuntimeValue::List(pets)).unwrap();
+        runtime.eval(&program).unwrap();
+
+        assert_eq!(Some(&RuntimeValue::Integer(5)), runtime.get("roll"));
+        assert_eq!(Some(&RuntimeValue::Double(0.25)), runtime.get("luck"));
+        assert_eq!(Some(&RuntimeValue::Double(5.0)), runtime.get("chance"));
+        assert_eq!(Some(&RuntimeValue::from("Scabbers")), runtime.get("pet"));
+
+        for code in [
+            "~FelixFelicis 0",
+            "~FelixFelicis -2.0",
+            "nan = 0.0 / 0.0\n~FelixFelicis nan",
+        ] {
+            let program = potterscript_parser::parse(code).unwrap();
+            assert!(matches!(
+                runtime.eval(&program),
+                Err(RuntimeError::InvalidSpellTarget(Spell::FelixFelicis, _))
+            ));
+        }
+    }
+
+    #[test]
+    fn test_seeded_felix_felicis() {
+        let rolls = |seed| {
+            let program =
+                potterscript_parser::parse("~Revelio ~FelixFelicis 100\n~Revelio ~FelixFelicis")
+                    .unwrap();
+            let mut runtime = Runtime::new(Seeded::new(TestAdapter::new(), seed));
+            runtime.eval(&program).unwrap();
+            runtime.adapter().inner().take_logs()
+        };
+
+        assert_eq!(rolls(3), rolls(3));
+        assert_ne!(rolls(3), rolls(4));
+    }
+
+    #[test]
+    fn test_protego() {
+        let runtime = eval(
+            "protego {\n  ~Revelio missing\n  ~Revelio \"unreachable\"\n} expelliarmus err {\n  kind = err.kind\n  message = err.message\n}\nprotego {\n  ~Confringo \"Mischief managed\"\n} expelliarmus err {\n  ~Revelio err.message\n}",
+        );
+
+        assert_eq!(
+            Some(&RuntimeValue::from("VariableNotFound")),
+            runtime.get("kind")
+        );
+        assert_eq!(
+            Some(&RuntimeValue::from("Variable missing not found")),
+            runtime.get("message")
+        );
+        runtime.adapter().assert_logs(&["Mischief managed"]);
+    }
+
+    #[test]
+    fn test_uncaught_errors() {
+        let program = potterscript_parser::parse("~Confringo 42").unwrap();
+        let mut runtime = Runtime::new(TestAdapter::new());
+        assert_eq!(
+            Err(RuntimeError::Raised(RuntimeValue::Integer(42))),
+            runtime.eval(&program)
+        );
+
+        let program = potterscript_parser::parse(
+            "protego {\n  ~UnbreakableVow 1 == 2\n} expelliarmus err {\n  ~Revelio err\n}",
+        )
+        .unwrap();
+        assert!(matches!(
+            runtime.eval(&program),
+            Err(RuntimeError::BrokenVow(_, _))
+        ));
+
+        let program = potterscript_parser::parse(
+            "x = 1
+~Revelio x.kind",
+        )
+        .unwrap();
+        assert_eq!(
+            Err(RuntimeError::FieldNotFound(
+                RuntimeValue::Integer(1),
+                "kind".to_string()
+            )),
+            runtime.eval(&program)
+        );
+    }
+}
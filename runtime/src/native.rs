@@ -0,0 +1,56 @@
+use potterscript_parser::Spell;
+
+use crate::{RuntimeError, RuntimeValue, ValueType};
+
+/// What a native spell can be cast on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SpellTarget {
+    /// Cast without a target, like `~Aguamenti`.
+    None,
+    /// Cast on a value of any type.
+    Any,
+    /// Cast on a value of the given type.
+    Type(ValueType),
+}
+
+pub type SpellHandler =
+    dyn FnMut(Option<RuntimeValue>) -> Result<Option<RuntimeValue>, RuntimeError>;
+
+pub(crate) struct NativeSpell {
+    pub(crate) target: SpellTarget,
+    pub(crate) handler: Box<SpellHandler>,
+}
+
+impl NativeSpell {
+    /// A native spell cast as `~name`.
+    ///
+    /// # Panics
+    ///
+    /// If `name` is a built-in spell, which scripts would never reach.
+    pub(crate) fn new(name: &str, target: SpellTarget, handler: Box<SpellHandler>) -> Self {
+        assert!(
+            !Spell::ALL.iter().any(|spell| spell.to_string() == name),
+            "cannot register the built-in spell ~{}",
+            name
+        );
+        Self { target, handler }
+    }
+
+    pub(crate) fn cast(
+        &mut self,
+        name: &str,
+        target: Option<RuntimeValue>,
+    ) -> Result<Option<RuntimeValue>, RuntimeError> {
+        let spell = || Spell::Custom(name.to_string());
+
+        match (self.target, target) {
+            (SpellTarget::None, None) => (self.handler)(None),
+            (SpellTarget::Any, Some(value)) => (self.handler)(Some(value)),
+            (SpellTarget::Type(value_type), Some(value)) if value.value_type() == value_type => {
+                (self.handler)(Some(value))
+            }
+            (_, Some(value)) => Err(RuntimeError::InvalidSpellTarget(spell(), value)),
+            (_, None) => Err(RuntimeError::MissingSpellTarget(spell())),
+        }
+    }
+}
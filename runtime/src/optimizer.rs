@@ -0,0 +1,440 @@
+//! Optimizes a program before it is run: folds binary operations on
+//! literals, drops branches and statements that can never run and comments
+//! that have no effect.
+//!
+//! An optimized program behaves the same, errors included, but evaluates
+//! fewer statements, so it counts fewer steps towards a step limit.
+
+use potterscript_parser::{Atom, Expression, Program, Spell, Statement};
+
+use crate::resolver::evaluates_target;
+use crate::RuntimeValue;
+
+pub fn optimize(program: Program) -> Program {
+    Program(strip_top_level_comments(block(program.0, true)))
+}
+
+/// Optimizes the statements of a block, or of the top level if `top`.
+fn block(statements: Vec<Statement>, top: bool) -> Vec<Statement> {
+    let mut optimized = vec![];
+    for statement in statements {
+        let diverges = diverges(&statement);
+        optimized.extend(self::statement(statement, top));
+        // Nothing after it runs
+        if diverges {
+            break;
+        }
+    }
+    optimized
+}
+
+/// Optimizes a statement, into the statements of a branch for an `if` with
+/// a literal condition.
+fn statement(statement: Statement, top: bool) -> Vec<Statement> {
+    let statement = match statement {
+        Statement::VariableAssignment(name, value) => {
+            Statement::VariableAssignment(name, expression(value))
+        }
+        Statement::ExpressionStatement(expression) => {
+            Statement::ExpressionStatement(self::expression(expression))
+        }
+        Statement::If(condition, true_block, else_block) => {
+            let condition = expression(condition);
+            let true_block = block(true_block, false);
+            let else_block = block(else_block, false);
+
+            let branch = match literal(&condition) {
+                Some(RuntimeValue::Boolean(true)) => true_block,
+                // Anything else is not `true`
+                Some(_) => else_block,
+                None => return vec![Statement::If(condition, true_block, else_block)],
+            };
+            // Every top-level statement starts without a last value, so the
+            // branch can only become top-level statements if none of them
+            // leaves one
+            if top && sets_last_value(&branch) {
+                return vec![Statement::If(
+                    Expression::Atom(Atom::Boolean(true)),
+                    branch,
+                    vec![],
+                )];
+            }
+            return branch;
+        }
+        Statement::Quidditch(body) => Statement::Quidditch(block(body, false)),
+        Statement::Snitch => Statement::Snitch,
+        // Vows run as top-level statements, see `vows`
+        Statement::Vow(name, body) => {
+            Statement::Vow(name, strip_top_level_comments(block(body, true)))
+        }
+        Statement::Protego(body, name, catch_block) => {
+            Statement::Protego(block(body, false), name, block(catch_block, false))
+        }
+    };
+    vec![statement]
+}
+
+fn expression(expression: Expression) -> Expression {
+    match expression {
+        Expression::BinaryOperation(operation, left, right) => {
+            let left = self::expression(*left);
+            let right = self::expression(*right);
+            if let (Some(left), Some(right)) = (literal(&left), literal(&right)) {
+                // Errors like a division by zero are left to happen at runtime
+                if let Ok(value) = left.binary_operation(operation, right) {
+                    if let Some(atom) = atom(value) {
+                        return Expression::Atom(atom);
+                    }
+                }
+            }
+            Expression::BinaryOperation(operation, Box::new(left), Box::new(right))
+        }
+        Expression::Field(expression, field) => {
+            Expression::Field(Box::new(self::expression(*expression)), field)
+        }
+        // The other spells use their target as written, and a broken vow
+        // reports its expression
+        Expression::SpellCast(spell, target)
+            if evaluates_target(&spell) && spell != Spell::UnbreakableVow =>
+        {
+            Expression::SpellCast(spell, Box::new(target.map(self::expression)))
+        }
+        expression => expression,
+    }
+}
+
+fn literal(expression: &Expression) -> Option<RuntimeValue> {
+    match expression {
+        Expression::Atom(Atom::Variable(_)) => None,
+        Expression::Atom(atom) => Some(atom.clone().into()),
+        _ => None,
+    }
+}
+
+fn atom(value: RuntimeValue) -> Option<Atom> {
+    match value {
+        RuntimeValue::Integer(integer) => Some(Atom::Integer(integer)),
+        RuntimeValue::Double(double) => Some(Atom::Double(double)),
+        RuntimeValue::Boolean(boolean) => Some(Atom::Boolean(boolean)),
+        RuntimeValue::String(string) => Some(Atom::String(string.to_string())),
+        RuntimeValue::HogwartsHouse(house) => Some(Atom::HogwartsHouse(house)),
+        RuntimeValue::List(_) | RuntimeValue::Map(_) => None,
+    }
+}
+
+/// Whether `statement` never finishes, by exiting, failing or looping
+/// forever.
+fn diverges(statement: &Statement) -> bool {
+    match statement {
+        Statement::VariableAssignment(_, expression)
+        | Statement::ExpressionStatement(expression) => {
+            matches!(
+                expression,
+                Expression::SpellCast(Spell::AvadaKedabra | Spell::Confringo, _)
+            )
+        }
+        Statement::If(_, true_block, else_block) => {
+            true_block.iter().any(diverges) && else_block.iter().any(diverges)
+        }
+        // The snitch of a nested loop ends this one too
+        Statement::Quidditch(body) => !catches_snitch(body),
+        _ => false,
+    }
+}
+
+fn catches_snitch(block: &[Statement]) -> bool {
+    block.iter().any(|statement| match statement {
+        Statement::Snitch => true,
+        Statement::If(_, true_block, else_block) => {
+            catches_snitch(true_block) || catches_snitch(else_block)
+        }
+        Statement::Quidditch(block) | Statement::Vow(_, block) => catches_snitch(block),
+        Statement::Protego(block, _, catch_block) => {
+            catches_snitch(block) || catches_snitch(catch_block)
+        }
+        _ => false,
+    })
+}
+
+/// Whether evaluating `expression` as a statement always leaves no last
+/// value, if it finishes.
+fn has_no_value(expression: &Expression) -> bool {
+    match expression {
+        Expression::Comment(_) => true,
+        Expression::SpellCast(spell, target) => match spell {
+            Spell::Incendio | Spell::WingardiumLeviosa => {
+                !matches!(**target, Some(Expression::Atom(Atom::String(_))))
+            }
+            Spell::AvadaKedabra
+            | Spell::Confringo
+            | Spell::Engorgio
+            | Spell::Inmobolus
+            | Spell::Lumos
+            | Spell::Nox
+            | Spell::Obliviate
+            | Spell::Periculum
+            | Spell::PetrificusTotalus
+            | Spell::Reducio
+            | Spell::Revelio
+            | Spell::UnbreakableVow => true,
+            _ => false,
+        },
+        _ => false,
+    }
+}
+
+/// Whether running `block` may leave a last value.
+fn sets_last_value(block: &[Statement]) -> bool {
+    block.iter().any(|statement| match statement {
+        Statement::ExpressionStatement(expression) => !has_no_value(expression),
+        Statement::If(_, true_block, else_block) => {
+            sets_last_value(true_block) || sets_last_value(else_block)
+        }
+        Statement::Quidditch(block) => sets_last_value(block),
+        Statement::Protego(block, _, catch_block) => {
+            sets_last_value(block) || sets_last_value(catch_block)
+        }
+        _ => false,
+    })
+}
+
+fn is_comment(statement: &Statement) -> bool {
+    matches!(
+        statement,
+        Statement::ExpressionStatement(Expression::Comment(_))
+    )
+}
+
+/// Drops the comments of a top-level block. Every top-level statement starts
+/// without a last value, so they only matter at the end, to clear the value
+/// of the statement before them.
+fn strip_top_level_comments(statements: Vec<Statement>) -> Vec<Statement> {
+    let mut stripped = vec![];
+    let mut cleared = true;
+    let mut trailing_comment = None;
+    for statement in statements {
+        if is_comment(&statement) {
+            trailing_comment = trailing_comment.or(Some(statement));
+            continue;
+        }
+
+        trailing_comment = None;
+        cleared = true;
+        stripped.push(self::strip_comments(statement, &mut cleared));
+    }
+
+    if let Some(comment) = trailing_comment.filter(|_| !cleared) {
+        stripped.push(comment);
+    }
+    stripped
+}
+
+/// Drops the comments of a nested block. `cleared` is whether the block
+/// starts without a last value, and is updated to whether it ends without.
+fn strip_block_comments(statements: Vec<Statement>, cleared: &mut bool) -> Vec<Statement> {
+    let mut stripped = vec![];
+    for statement in statements {
+        if is_comment(&statement) && *cleared {
+            continue;
+        }
+        stripped.push(strip_comments(statement, cleared));
+    }
+    stripped
+}
+
+fn strip_comments(statement: Statement, cleared: &mut bool) -> Statement {
+    match statement {
+        Statement::ExpressionStatement(expression) => {
+            *cleared = has_no_value(&expression);
+            Statement::ExpressionStatement(expression)
+        }
+        Statement::If(condition, true_block, else_block) => {
+            let (mut true_cleared, mut else_cleared) = (*cleared, *cleared);
+            let true_block = strip_block_comments(true_block, &mut true_cleared);
+            let else_block = strip_block_comments(else_block, &mut else_cleared);
+            *cleared = true_cleared && else_cleared;
+            Statement::If(condition, true_block, else_block)
+        }
+        Statement::Quidditch(body) => {
+            // Later iterations start with whatever the one before left
+            *cleared = *cleared && !sets_last_value(&body);
+            Statement::Quidditch(strip_block_comments(body, cleared))
+        }
+        Statement::Protego(body, name, catch_block) => {
+            // The catch block starts wherever the protego block failed
+            let mut catch_cleared = *cleared && !sets_last_value(&body);
+            let body = strip_block_comments(body, cleared);
+            let catch_block = strip_block_comments(catch_block, &mut catch_cleared);
+            *cleared = *cleared && catch_cleared;
+            Statement::Protego(body, name, catch_block)
+        }
+        statement => statement,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::Path;
+
+    use potterscript_parser::BinaryOperation;
+
+    use super::*;
+    use crate::{Runtime, RuntimeError, TestAdapter};
+
+    fn optimized(code: &str) -> Program {
+        optimize(potterscript_parser::parse(code).unwrap())
+    }
+
+    fn assert_optimized(code: &str, expected: &str) {
+        let expected = potterscript_parser::parse(expected).unwrap();
+        assert_eq!(expected, optimized(code), "{}", code);
+    }
+
+    #[test]
+    fn test_constant_folding() {
+        assert_optimized("y = 3 + 7", "y = 10");
+        assert_optimized("~Revelio \"a\" + \"b\"", "~Revelio \"ab\"");
+        assert_optimized("x = 2.5 * 2.0", "x = 5.0");
+        assert_optimized("x = Gryffindor == Slytherin", "x = false");
+        assert_optimized("y = x + 2", "y = x + 2");
+
+        // Nested operations, which have no syntax yet
+        let integer = |integer| Box::new(Expression::Atom(Atom::Integer(integer)));
+        let nested = Expression::BinaryOperation(
+            BinaryOperation::Equal,
+            Box::new(Expression::BinaryOperation(
+                BinaryOperation::Plus,
+                integer(1),
+                integer(2),
+            )),
+            integer(3),
+        );
+        assert_eq!(
+            Expression::Atom(Atom::Boolean(true)),
+            super::expression(nested)
+        );
+    }
+
+    #[test]
+    fn test_folding_keeps_errors() {
+        assert_optimized("y = 1 / 0", "y = 1 / 0");
+        assert_optimized("y = 1 + \"a\"", "y = 1 + \"a\"");
+    }
+
+    #[test]
+    fn test_folding_keeps_spell_targets() {
+        // Incendio only appends to a string literal or variable
+        assert_optimized("~Incendio \"a\" + \"b\"", "~Incendio \"a\" + \"b\"");
+        assert_optimized("~UnbreakableVow 1 + 1", "~UnbreakableVow 1 + 1");
+    }
+
+    #[test]
+    fn test_dead_branches() {
+        assert_optimized(
+            "if true {\n~Revelio 1\n} else {\n~Revelio 2\n}",
+            "~Revelio 1",
+        );
+        assert_optimized(
+            "if 1 == 2 {\n~Revelio 1\n} else {\n~Revelio 2\n}",
+            "~Revelio 2",
+        );
+        assert_optimized("if x {\n~Revelio 1\n}", "if x {\n~Revelio 1\n}");
+        // Would leave a last value between top-level statements
+        assert_optimized("if 2 == 2 {\n3 + 4\nx = 1\n}", "if true {\n7\nx = 1\n}");
+        assert_optimized(
+            "quidditch {\nif true {\n~Aguamenti\nsnitch\n}\n}",
+            "quidditch {\n~Aguamenti\nsnitch\n}",
+        );
+    }
+
+    #[test]
+    fn test_unreachable_statements() {
+        assert_optimized(
+            "~Revelio 1\n~AvadaKedabra\n~Revelio 2",
+            "~Revelio 1\n~AvadaKedabra",
+        );
+        assert_optimized(
+            "quidditch {\n~Revelio 1\n}\n~Revelio 2",
+            "quidditch {\n~Revelio 1\n}",
+        );
+        assert_optimized(
+            "protego {\n~Confringo 1\n~Revelio 2\n} expelliarmus e {\n~Revelio e\n}\n~Revelio 3",
+            "protego {\n~Confringo 1\n} expelliarmus e {\n~Revelio e\n}\n~Revelio 3",
+        );
+        // The iteration catching the snitch finishes
+        assert_optimized(
+            "quidditch {\nsnitch\n~Revelio 1\n}\n~Revelio 2",
+            "quidditch {\nsnitch\n~Revelio 1\n}\n~Revelio 2",
+        );
+    }
+
+    #[test]
+    fn test_comments() {
+        assert_optimized("# a\nx = 1 # b\n~Revelio x", "x = 1\n~Revelio x");
+        assert_optimized("if x {\n# a\n~Revelio x\n}", "if x {\n~Revelio x\n}");
+        // Clear the value of the statement before them
+        assert_optimized("3 + 4\n# a\n# b", "7\n# a");
+        assert_optimized("if x {\n3 + 4\n# a\n}", "if x {\n7\n# a\n}");
+    }
+
+    #[derive(Debug, PartialEq)]
+    struct Outcome {
+        logs: Vec<String>,
+        result: Result<(), RuntimeError>,
+        last_value: Option<RuntimeValue>,
+    }
+
+    fn run(program: &Program, inputs: &[String]) -> Outcome {
+        let adapter = TestAdapter::new()
+            .with_integers((0..4).cycle())
+            .with_inputs(inputs.iter().cloned());
+        let mut runtime = Runtime::new(adapter);
+        let result = runtime.eval(program);
+        Outcome {
+            logs: runtime.adapter().take_logs(),
+            result,
+            last_value: runtime.last_value().cloned(),
+        }
+    }
+
+    fn assert_same_outcome(code: &str, inputs: &[String]) {
+        let program = potterscript_parser::parse(code).unwrap();
+        let expected = run(&program, inputs);
+        let actual = run(&optimize(program), inputs);
+        assert_eq!(expected, actual, "{}", code);
+    }
+
+    #[test]
+    fn test_same_outcome() {
+        let scripts = [
+            "x = 10 / 4\n~Revelio x\n~Revelio 1.5 + 1.0",
+            "~Revelio 1 / 0",
+            "if 1 == 1 {\n  3 + 4\n  x = 1\n}",
+            "3 + 4\n# done",
+            "i = 0\nquidditch {\n  # count\n  ~Engorgio i\n  if i == 3 {\n    snitch\n  }\n  i + 1\n}",
+            "protego {\n  7\n  ~Confringo \"boom\"\n  ~Revelio \"never\"\n} expelliarmus e {\n  # caught\n}",
+        ];
+        for code in scripts {
+            assert_same_outcome(code, &[]);
+        }
+    }
+
+    #[test]
+    fn test_golden_scripts_same_outcome() {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/golden");
+        for entry in fs::read_dir(dir).unwrap() {
+            let path = entry.unwrap().path();
+            if path
+                .extension()
+                .is_some_and(|extension| extension == "potter")
+            {
+                let code = fs::read_to_string(&path).unwrap();
+                let inputs: Vec<String> = fs::read_to_string(path.with_extension("in"))
+                    .map(|inputs| inputs.lines().map(String::from).collect())
+                    .unwrap_or_default();
+                assert_same_outcome(&code, &inputs);
+            }
+        }
+    }
+}
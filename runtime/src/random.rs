@@ -0,0 +1,164 @@
+use std::cell::Cell;
+use std::ops::RangeInclusive;
+use std::time::Duration;
+
+use crate::RuntimeAdapter;
+
+/// A small seedable random number generator (SplitMix64) that gives the same
+/// numbers for the same seed on every platform, WASM included.
+#[derive(Debug, Clone)]
+pub struct Random {
+    seed: Cell<u64>,
+    state: Cell<u64>,
+}
+
+impl Random {
+    pub fn new(seed: u64) -> Self {
+        Self {
+            seed: Cell::new(seed),
+            state: Cell::new(seed),
+        }
+    }
+
+    #[cfg(feature = "std")]
+    pub fn from_entropy() -> Self {
+        Self::new(rand::random())
+    }
+
+    /// The seed this generator started from, to replay a run.
+    pub fn seed(&self) -> u64 {
+        self.seed.get()
+    }
+
+    /// Starts over from `seed`.
+    pub fn reseed(&self, seed: u64) {
+        self.seed.set(seed);
+        self.state.set(seed);
+    }
+
+    pub fn next_u64(&self) -> u64 {
+        let state = self.state.get().wrapping_add(0x9E37_79B9_7F4A_7C15);
+        self.state.set(state);
+
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    /// A number in `range`, which must not be empty.
+    pub fn integer(&self, range: RangeInclusive<i64>) -> i64 {
+        let (start, end) = range.into_inner();
+        assert!(start <= end, "cannot pick from an empty range");
+        let span = (end as i128 - start as i128 + 1) as u128;
+        let offset = (self.next_u64() as u128 * span) >> 64;
+        (start as i128 + offset as i128) as i64
+    }
+
+    /// A number in `0.0..1.0`.
+    pub fn double(&self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+/// Wraps an adapter to make its random choices, like the Sorting Hat's, with
+/// a seeded [`Random`]. Everything else is left to the wrapped adapter.
+#[derive(Debug)]
+pub struct Seeded<A> {
+    inner: A,
+    random: Random,
+    used: Cell<bool>,
+}
+
+impl<A> Seeded<A> {
+    pub fn new(inner: A, seed: u64) -> Self {
+        Self {
+            inner,
+            random: Random::new(seed),
+            used: Cell::new(false),
+        }
+    }
+
+    pub fn inner(&self) -> &A {
+        &self.inner
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.random.seed()
+    }
+
+    pub fn reseed(&self, seed: u64) {
+        self.random.reseed(seed);
+    }
+
+    /// Whether the program made any random choice, i.e. whether the seed
+    /// matters to replay it.
+    pub fn was_used(&self) -> bool {
+        self.used.get()
+    }
+}
+
+impl<A: RuntimeAdapter> RuntimeAdapter for Seeded<A> {
+    fn random_integer(&self, range: RangeInclusive<i64>) -> i64 {
+        self.used.set(true);
+        self.random.integer(range)
+    }
+
+    fn random_double(&self) -> f64 {
+        self.used.set(true);
+        self.random.double()
+    }
+
+    fn lumos(&self, string: String) -> String {
+        self.inner.lumos(string)
+    }
+
+    fn log(&self, string: &str) {
+        self.inner.log(string)
+    }
+
+    fn read_line(&self, prompt: Option<&str>) -> Option<String> {
+        self.inner.read_line(prompt)
+    }
+
+    fn sleep(&self, duration: Duration) {
+        self.inner.sleep(duration)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Runtime, TestAdapter};
+
+    #[test]
+    fn test_random_is_reproducible() {
+        let random = Random::new(42);
+        let first: Vec<i64> = (0..8).map(|_| random.integer(-2..=2)).collect();
+        assert!(first.iter().all(|integer| (-2..=2).contains(integer)));
+        let double = random.double();
+        assert!((0.0..1.0).contains(&double));
+
+        random.reseed(42);
+        let second: Vec<i64> = (0..8).map(|_| random.integer(-2..=2)).collect();
+        assert_eq!(first, second);
+        assert_eq!(double, random.double());
+        assert_eq!(42, random.seed());
+        assert_eq!(i64::MIN, Random::new(0).integer(i64::MIN..=i64::MIN));
+    }
+
+    #[test]
+    fn test_seeded_sorting_hat() {
+        let houses = |seed| {
+            let program =
+                potterscript_parser::parse("~Revelio 🎩✨\n~Revelio 🎩✨\n~Revelio 🎩✨").unwrap();
+            let mut runtime = Runtime::new(Seeded::new(TestAdapter::new(), seed));
+            runtime.eval(&program).unwrap();
+            assert!(runtime.adapter().was_used());
+            runtime.adapter().inner().take_logs()
+        };
+
+        assert_eq!(houses(7), houses(7));
+        assert!((0..8).any(|seed| houses(seed) != houses(7)));
+    }
+}
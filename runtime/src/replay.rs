@@ -0,0 +1,211 @@
+use std::cell::{Cell, RefCell};
+use std::collections::VecDeque;
+use std::ops::RangeInclusive;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use crate::RuntimeAdapter;
+
+/// A nondeterministic choice made by an adapter during evaluation.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Choice {
+    /// A random integer, e.g. the index of the house picked by the Sorting Hat.
+    Integer(i64),
+    Double(f64),
+    /// A line read by `~Accio`, `None` when there was no more input.
+    Input(Option<String>),
+}
+
+/// Wraps an adapter to remember every [`Choice`] it makes, so the run can
+/// be replayed with a [`Replayer`].
+#[derive(Debug)]
+pub struct Recorder<A> {
+    inner: A,
+    choices: RefCell<Vec<Choice>>,
+}
+
+impl<A> Recorder<A> {
+    pub fn new(inner: A) -> Self {
+        Self {
+            inner,
+            choices: RefCell::default(),
+        }
+    }
+
+    pub fn inner(&self) -> &A {
+        &self.inner
+    }
+
+    pub fn choices(&self) -> Vec<Choice> {
+        self.choices.borrow().clone()
+    }
+}
+
+impl<A: RuntimeAdapter> RuntimeAdapter for Recorder<A> {
+    fn random_integer(&self, range: RangeInclusive<i64>) -> i64 {
+        let integer = self.inner.random_integer(range);
+        self.choices.borrow_mut().push(Choice::Integer(integer));
+        integer
+    }
+
+    fn random_double(&self) -> f64 {
+        let double = self.inner.random_double();
+        self.choices.borrow_mut().push(Choice::Double(double));
+        double
+    }
+
+    fn lumos(&self, string: String) -> String {
+        self.inner.lumos(string)
+    }
+
+    fn log(&self, string: &str) {
+        self.inner.log(string)
+    }
+
+    fn read_line(&self, prompt: Option<&str>) -> Option<String> {
+        let line = self.inner.read_line(prompt);
+        self.choices.borrow_mut().push(Choice::Input(line.clone()));
+        line
+    }
+
+    fn sleep(&self, duration: Duration) {
+        self.inner.sleep(duration)
+    }
+}
+
+/// Wraps an adapter to make the choices recorded by a [`Recorder`] again.
+/// Once the recording runs out, or asks for a different kind of choice than
+/// the program does, the wrapped adapter decides.
+#[derive(Debug)]
+pub struct Replayer<A> {
+    inner: A,
+    choices: RefCell<VecDeque<Choice>>,
+    diverged: Cell<bool>,
+}
+
+impl<A> Replayer<A> {
+    pub fn new(inner: A, choices: Vec<Choice>) -> Self {
+        Self {
+            inner,
+            choices: RefCell::new(choices.into()),
+            diverged: Cell::new(false),
+        }
+    }
+
+    pub fn inner(&self) -> &A {
+        &self.inner
+    }
+
+    /// Whether the program asked for a choice the recording didn't have.
+    pub fn diverged(&self) -> bool {
+        self.diverged.get()
+    }
+
+    fn next(&self) -> Option<Choice> {
+        let choice = self.choices.borrow_mut().pop_front();
+        if choice.is_none() {
+            self.diverged.set(true);
+        }
+        choice
+    }
+}
+
+impl<A: RuntimeAdapter> RuntimeAdapter for Replayer<A> {
+    fn random_integer(&self, range: RangeInclusive<i64>) -> i64 {
+        match self.next() {
+            Some(Choice::Integer(integer)) if range.contains(&integer) => integer,
+            Some(_) => {
+                self.diverged.set(true);
+                self.inner.random_integer(range)
+            }
+            None => self.inner.random_integer(range),
+        }
+    }
+
+    fn random_double(&self) -> f64 {
+        match self.next() {
+            Some(Choice::Double(double)) => double,
+            Some(_) => {
+                self.diverged.set(true);
+                self.inner.random_double()
+            }
+            None => self.inner.random_double(),
+        }
+    }
+
+    fn lumos(&self, string: String) -> String {
+        self.inner.lumos(string)
+    }
+
+    fn log(&self, string: &str) {
+        self.inner.log(string)
+    }
+
+    fn read_line(&self, prompt: Option<&str>) -> Option<String> {
+        match self.next() {
+            Some(Choice::Input(line)) => line,
+            Some(_) => {
+                self.diverged.set(true);
+                self.inner.read_line(prompt)
+            }
+            None => self.inner.read_line(prompt),
+        }
+    }
+
+    fn sleep(&self, duration: Duration) {
+        self.inner.sleep(duration)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Runtime, TestAdapter};
+
+    const SCRIPT: &str = "~Revelio 🎩✨\nname = ~Accio\n~Revelio name\n~Revelio 🎩✨";
+
+    #[test]
+    fn test_record_and_replay() {
+        let program = potterscript_parser::parse(SCRIPT).unwrap();
+        let adapter = TestAdapter::new()
+            .with_integers([2, 3])
+            .with_inputs(["Fawkes"]);
+        let mut runtime = Runtime::new(Recorder::new(adapter));
+        runtime.eval(&program).unwrap();
+
+        let choices = runtime.adapter().choices();
+        assert_eq!(
+            vec![
+                Choice::Integer(2),
+                Choice::Input(Some("Fawkes".to_string())),
+                Choice::Integer(3)
+            ],
+            choices
+        );
+
+        let program = potterscript_parser::parse(SCRIPT).unwrap();
+        let mut runtime = Runtime::new(Replayer::new(TestAdapter::new(), choices));
+        runtime.eval(&program).unwrap();
+        assert!(!runtime.adapter().diverged());
+        runtime
+            .adapter()
+            .inner()
+            .assert_logs(&["Ravenclaw", "Fawkes", "Slytherin"]);
+    }
+
+    #[test]
+    fn test_replay_diverges() {
+        let program = potterscript_parser::parse(SCRIPT).unwrap();
+        let adapter = TestAdapter::new().with_integers([0]);
+        let choices = vec![Choice::Integer(1), Choice::Integer(1)];
+        let mut runtime = Runtime::new(Replayer::new(adapter, choices));
+        runtime.eval(&program).unwrap();
+
+        assert!(runtime.adapter().diverged());
+        runtime
+            .adapter()
+            .inner()
+            .assert_logs(&["Hufflepuff", "false", "Gryffindor"]);
+    }
+}
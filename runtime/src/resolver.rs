@@ -0,0 +1,433 @@
+//! Resolves the variables of a program to slots before it is evaluated, so
+//! the [`Runtime`](crate::Runtime) reads and writes them by index instead of
+//! hashing their names.
+
+use std::collections::HashMap;
+use std::mem;
+use std::rc::Rc;
+
+use potterscript_parser::{Atom, BinaryOperation, Expression, Program, Spell, Statement};
+
+use crate::RuntimeValue;
+
+/// Index of a variable in the storage of a runtime.
+pub(crate) type Slot = usize;
+
+/// Interned variable names, each with its slot.
+#[derive(Debug, Default)]
+pub(crate) struct Interner {
+    names: Vec<Rc<str>>,
+    slots: HashMap<Rc<str>, Slot>,
+}
+
+impl Interner {
+    pub(crate) fn intern(&mut self, name: &str) -> Slot {
+        if let Some(slot) = self.slots.get(name) {
+            return *slot;
+        }
+
+        let name: Rc<str> = name.into();
+        self.names.push(name.clone());
+        self.slots.insert(name, self.names.len() - 1);
+        self.names.len() - 1
+    }
+
+    pub(crate) fn get(&self, name: &str) -> Option<Slot> {
+        self.slots.get(name).copied()
+    }
+
+    pub(crate) fn name(&self, slot: Slot) -> &str {
+        &self.names[slot]
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.names.len()
+    }
+}
+
+/// A statement with its variables resolved, borrowing the rest from the
+/// program.
+pub(crate) enum Stmt<'a> {
+    Assign(Slot, Expr<'a>),
+    Expression(Expr<'a>),
+    If(Expr<'a>, Vec<Stmt<'a>>, Vec<Stmt<'a>>),
+    Quidditch(Vec<Stmt<'a>>),
+    Snitch,
+    /// Only run by a test runner, see `vows`.
+    Vow,
+    Protego(Vec<Stmt<'a>>, Slot, Vec<Stmt<'a>>),
+}
+
+pub(crate) enum Expr<'a> {
+    Spell(&'a Spell, Target<'a>),
+    /// The vow expression, kept to report it if the vow breaks, and its
+    /// resolved form.
+    UnbreakableVow(&'a Expression, Box<Expr<'a>>),
+    BinaryOperation(BinaryOperation, Box<Expr<'a>>, Box<Expr<'a>>),
+    Variable(Slot),
+    Value(RuntimeValue),
+    Field(Box<Expr<'a>>, &'a str),
+    Nothing,
+    SortingHat,
+}
+
+/// The target of a spell, as far as the spell cares about it.
+pub(crate) enum Target<'a> {
+    None,
+    Expression(Box<Expr<'a>>),
+    /// A variable the spell reads or changes instead of evaluating it.
+    Variable(Slot),
+    /// A string literal the spell uses as it is.
+    String(&'a str),
+}
+
+/// Whether casting `spell` evaluates its target. The other spells ignore it,
+/// or only act on a variable or string literal written as the target.
+pub fn evaluates_target(spell: &Spell) -> bool {
+    matches!(
+        spell,
+        Spell::Custom(_)
+            | Spell::Accio
+            | Spell::UnbreakableVow
+            | Spell::FelixFelicis
+            | Spell::Confringo
+            | Spell::Inmobolus
+            | Spell::Revelio
+    )
+}
+
+/// Whether casting `spell` on a variable reads its value.
+fn reads_variable(spell: &Spell) -> bool {
+    matches!(
+        spell,
+        Spell::Engorgio | Spell::Incendio | Spell::Reducio | Spell::WingardiumLeviosa
+    )
+}
+
+pub(crate) struct Resolved<'a> {
+    pub(crate) statements: Vec<Stmt<'a>>,
+    /// Variables the program may read before assigning them, in order of
+    /// first use.
+    pub(crate) undefined: Vec<Slot>,
+}
+
+/// The variables that are assigned on every path to a point of the program.
+#[derive(Clone)]
+struct Assigned {
+    slots: Vec<bool>,
+    /// No path reaches the point, e.g. right after a `snitch`.
+    unreachable: bool,
+}
+
+impl Assigned {
+    fn new() -> Self {
+        Self {
+            slots: vec![],
+            unreachable: false,
+        }
+    }
+
+    fn unreachable() -> Self {
+        Self {
+            slots: vec![],
+            unreachable: true,
+        }
+    }
+
+    fn contains(&self, slot: Slot) -> bool {
+        self.unreachable || self.slots.get(slot).copied().unwrap_or(false)
+    }
+
+    fn insert(&mut self, slot: Slot) {
+        if self.slots.len() <= slot {
+            self.slots.resize(slot + 1, false);
+        }
+        self.slots[slot] = true;
+    }
+
+    /// Where two paths meet, only what both assigned is assigned.
+    fn join(self, other: Self) -> Self {
+        match (self.unreachable, other.unreachable) {
+            (true, _) => other,
+            (_, true) => self,
+            _ => Self {
+                slots: self
+                    .slots
+                    .iter()
+                    .zip(&other.slots)
+                    .map(|(a, b)| *a && *b)
+                    .collect(),
+                unreachable: false,
+            },
+        }
+    }
+}
+
+struct Resolver<'n> {
+    names: &'n mut Interner,
+    assigned: Assigned,
+    /// For every `quidditch` loop being resolved, whether a `snitch` that
+    /// ends it, its own or one of a nested loop, may run so far.
+    snitches: Vec<bool>,
+    undefined: Vec<Slot>,
+}
+
+/// Resolves the variables of `program` to slots of `names`, interning the
+/// ones it doesn't know yet.
+pub(crate) fn resolve<'a>(program: &'a Program, names: &mut Interner) -> Resolved<'a> {
+    let mut resolver = Resolver {
+        names,
+        assigned: Assigned::new(),
+        snitches: vec![],
+        undefined: vec![],
+    };
+    let statements = resolver.block(&program.0);
+
+    // The test runner runs each vow after the statements outside of vows
+    let assigned = resolver.assigned.clone();
+    for statement in &program.0 {
+        if let Statement::Vow(_, block) = statement {
+            resolver.assigned = assigned.clone();
+            resolver.block(block);
+        }
+    }
+
+    Resolved {
+        statements,
+        undefined: resolver.undefined,
+    }
+}
+
+/// Names of the variables `program` may read before assigning them, in
+/// order of first use, including in `vow` blocks.
+///
+/// A variable counts as assigned after an `if` only if both branches assign
+/// it, and after a `quidditch` loop only if every iteration does, since the
+/// loop ends once the iteration that runs a `snitch` is over. Reading one that isn't is a `VariableNotFound` error unless the
+/// host defines it first, so `potter check` reports them without running
+/// the script.
+pub fn undefined_variables(program: &Program) -> Vec<String> {
+    let mut names = Interner::default();
+    let resolved = resolve(program, &mut names);
+    resolved
+        .undefined
+        .into_iter()
+        .map(|slot| names.name(slot).to_string())
+        .collect()
+}
+
+impl Resolver<'_> {
+    fn assign(&mut self, name: &str) -> Slot {
+        let slot = self.names.intern(name);
+        self.assigned.insert(slot);
+        slot
+    }
+
+    fn read(&mut self, name: &str) -> Slot {
+        let slot = self.names.intern(name);
+        if !self.assigned.contains(slot) && !self.undefined.contains(&slot) {
+            self.undefined.push(slot);
+        }
+        slot
+    }
+
+    fn block<'a>(&mut self, block: &'a [Statement]) -> Vec<Stmt<'a>> {
+        block
+            .iter()
+            .map(|statement| self.statement(statement))
+            .collect()
+    }
+
+    fn statement<'a>(&mut self, statement: &'a Statement) -> Stmt<'a> {
+        match statement {
+            Statement::VariableAssignment(name, value) => {
+                let value = self.expression(value);
+                Stmt::Assign(self.assign(name), value)
+            }
+            Statement::ExpressionStatement(expression) => {
+                let expression = self.expression(expression);
+                if let Expr::Spell(Spell::AvadaKedabra | Spell::Confringo, _) = expression {
+                    self.assigned.unreachable = true;
+                }
+                Stmt::Expression(expression)
+            }
+            Statement::If(condition, true_block, else_block) => {
+                let condition = self.expression(condition);
+                let before = self.assigned.clone();
+                let true_block = self.block(true_block);
+                let after_true = mem::replace(&mut self.assigned, before);
+                let else_block = self.block(else_block);
+                self.assigned = after_true.join(self.assigned.clone());
+                Stmt::If(condition, true_block, else_block)
+            }
+            Statement::Quidditch(block) => {
+                self.snitches.push(false);
+                let block = self.block(block);
+                // The loop ends with the iteration that catches the snitch,
+                // so what the iteration assigned is assigned after it.
+                // Without a `snitch` it never ends.
+                if self.snitches.pop() == Some(true) {
+                    if let Some(snitches) = self.snitches.last_mut() {
+                        *snitches = true;
+                    }
+                } else {
+                    self.assigned = Assigned::unreachable();
+                }
+                Stmt::Quidditch(block)
+            }
+            Statement::Snitch => {
+                if !self.assigned.unreachable {
+                    if let Some(snitches) = self.snitches.last_mut() {
+                        *snitches = true;
+                    }
+                }
+                Stmt::Snitch
+            }
+            // Checked for undefined variables by `resolve`, the vow runs
+            // resolved again by the test runner
+            Statement::Vow(_, _) => Stmt::Vow,
+            Statement::Protego(block, name, catch_block) => {
+                // An error can happen anywhere in the block, so the
+                // `expelliarmus` block only counts on what was assigned
+                // before it
+                let before = self.assigned.clone();
+                let block = self.block(block);
+                let after_block = mem::replace(&mut self.assigned, before);
+                let slot = self.assign(name);
+                let catch_block = self.block(catch_block);
+                self.assigned = after_block.join(self.assigned.clone());
+                Stmt::Protego(block, slot, catch_block)
+            }
+        }
+    }
+
+    fn expression<'a>(&mut self, expression: &'a Expression) -> Expr<'a> {
+        match expression {
+            Expression::SpellCast(spell, target) => self.spell(spell, target.as_ref().as_ref()),
+            Expression::BinaryOperation(operation, left, right) => Expr::BinaryOperation(
+                *operation,
+                Box::new(self.expression(left)),
+                Box::new(self.expression(right)),
+            ),
+            Expression::Atom(Atom::Variable(name)) => Expr::Variable(self.read(name)),
+            Expression::Atom(atom) => Expr::Value(atom.clone().into()),
+            Expression::Field(expression, field) => {
+                Expr::Field(Box::new(self.expression(expression)), field)
+            }
+            Expression::Comment(_) => Expr::Nothing,
+            Expression::SortingHat => Expr::SortingHat,
+        }
+    }
+
+    fn spell<'a>(&mut self, spell: &'a Spell, target: Option<&'a Expression>) -> Expr<'a> {
+        if let (Spell::UnbreakableVow, Some(target)) = (spell, target) {
+            return Expr::UnbreakableVow(target, Box::new(self.expression(target)));
+        }
+
+        let target = match target {
+            Some(target) if evaluates_target(spell) => {
+                Target::Expression(Box::new(self.expression(target)))
+            }
+            Some(Expression::Atom(Atom::Variable(name))) if reads_variable(spell) => {
+                Target::Variable(self.read(name))
+            }
+            Some(Expression::Atom(Atom::Variable(name))) => {
+                Target::Variable(self.names.intern(name))
+            }
+            Some(Expression::Atom(Atom::String(string))) => Target::String(string),
+            _ => Target::None,
+        };
+        Expr::Spell(spell, target)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn undefined(code: &str) -> Vec<String> {
+        undefined_variables(&potterscript_parser::parse(code).unwrap())
+    }
+
+    #[test]
+    fn test_undefined_variables() {
+        assert_eq!(Vec::<String>::new(), undefined("x = 1\n~Revelio x"));
+        assert_eq!(vec!["y"], undefined("x = y + 1\n~Revelio y"));
+        assert_eq!(vec!["x", "y"], undefined("~Engorgio x\n~Revelio y"));
+        // Only reads before the first assignment
+        assert_eq!(vec!["x"], undefined("~Revelio x\nx = 1\n~Revelio x"));
+        assert_eq!(
+            Vec::<String>::new(),
+            undefined("protego {\n~Confringo 1\n} expelliarmus e {\n~Revelio e\n}")
+        );
+        // Spells that don't read their target
+        assert_eq!(Vec::<String>::new(), undefined("~Obliviate x\n~Lumos y"));
+        assert_eq!(vec!["z"], undefined("vow \"z\" {\n~UnbreakableVow z\n}"));
+    }
+
+    #[test]
+    fn test_undefined_variables_along_paths() {
+        // Both branches, or a branch that never ends
+        assert_eq!(
+            Vec::<String>::new(),
+            undefined("if true {\nx = 1\n} else {\nx = 2\n}\n~Revelio x")
+        );
+        assert_eq!(
+            Vec::<String>::new(),
+            undefined("if true {\nx = 1\n} else {\n~Confringo \"no\"\n}\n~Revelio x")
+        );
+        assert_eq!(vec!["x"], undefined("if true {\nx = 1\n}\n~Revelio x"));
+
+        // Loops end with the iteration that catches the snitch
+        assert_eq!(
+            Vec::<String>::new(),
+            undefined("quidditch {\nx = 1\nsnitch\n}\n~Revelio x")
+        );
+        assert_eq!(
+            Vec::<String>::new(),
+            undefined("quidditch {\nsnitch\nx = 1\n}\n~Revelio x")
+        );
+        assert_eq!(
+            vec!["x"],
+            undefined("quidditch {\nif true {\nsnitch\n} else {\nx = 1\n}\n}\n~Revelio x")
+        );
+        assert_eq!(
+            Vec::<String>::new(),
+            undefined("quidditch {\nquidditch {\nsnitch\n}\nx = 1\n}\n~Revelio x")
+        );
+        assert_eq!(vec!["i"], undefined("quidditch {\n~Revelio i\ni = 1\n}"));
+
+        // The rest of the iteration still runs after a `snitch`
+        assert_eq!(vec!["x"], undefined("snitch\n~Revelio x"));
+        assert_eq!(vec!["x"], undefined("quidditch {\nsnitch\n~Revelio x\n}"));
+
+        // `expelliarmus` may run before anything in `protego` did
+        assert_eq!(
+            vec!["x"],
+            undefined("protego {\nx = 1\n} expelliarmus e {\n~Revelio x\n}")
+        );
+        assert_eq!(
+            vec!["x"],
+            undefined("protego {\nx = 1\n} expelliarmus e {\n~Revelio e\n}\n~Revelio x")
+        );
+
+        // Vows run after everything outside of them
+        assert_eq!(
+            Vec::<String>::new(),
+            undefined("vow \"x\" {\n~UnbreakableVow x == 1\n}\nx = 1")
+        );
+    }
+
+    #[test]
+    fn test_slots_are_shared() {
+        let mut names = Interner::default();
+        let program = potterscript_parser::parse("x = 1\ny = x").unwrap();
+        resolve(&program, &mut names);
+        let program = potterscript_parser::parse("~Revelio y\nz = y").unwrap();
+        resolve(&program, &mut names);
+
+        assert_eq!(Some(1), names.get("y"));
+        assert_eq!(Some(2), names.get("z"));
+        assert_eq!(3, names.len());
+    }
+}
@@ -0,0 +1,118 @@
+//! What built-in spells do to the values they are cast on, shared by the
+//! tree-walking [`Runtime`](crate::Runtime) and the [`Vm`](crate::vm::Vm),
+//! and called by Rust code generated from scripts.
+
+use std::time;
+
+use potterscript_parser::{BinaryOperation, Expression, Spell};
+
+use crate::{RuntimeAdapter, RuntimeError, RuntimeValue};
+
+pub fn accio(adapter: &impl RuntimeAdapter, prompt: Option<RuntimeValue>) -> RuntimeValue {
+    let prompt = prompt.map(|prompt| prompt.to_string());
+    let line = adapter.read_line(prompt.as_deref());
+    // No more input reads as `false`, there is no null value
+    line.map_or(RuntimeValue::Boolean(false), RuntimeValue::from)
+}
+
+pub fn felix_felicis(
+    adapter: &impl RuntimeAdapter,
+    target: Option<RuntimeValue>,
+) -> Result<RuntimeValue, RuntimeError> {
+    match target {
+        None => Ok(RuntimeValue::Double(adapter.random_double())),
+        Some(RuntimeValue::Integer(max)) if max > 0 => {
+            Ok(RuntimeValue::Integer(adapter.random_integer(0..=max - 1)))
+        }
+        Some(RuntimeValue::Double(max)) if max >= 0.0 && max.is_finite() => {
+            Ok(RuntimeValue::Double(adapter.random_double() * max))
+        }
+        Some(RuntimeValue::List(mut list)) if !list.is_empty() => {
+            let last = list.len() as i64 - 1;
+            let index = adapter.random_integer(0..=last);
+            Ok(list.swap_remove(index as usize))
+        }
+        Some(value) => Err(RuntimeError::InvalidSpellTarget(Spell::FelixFelicis, value)),
+    }
+}
+
+pub fn inmobolus(adapter: &impl RuntimeAdapter, target: Option<RuntimeValue>) {
+    // Like before sleeping went through the adapter, other targets do nothing
+    if let Some(RuntimeValue::Integer(ms)) = target {
+        adapter.sleep(time::Duration::from_millis(ms.max(0) as u64));
+    }
+}
+
+pub fn confringo(target: Option<RuntimeValue>) -> RuntimeError {
+    match target {
+        Some(value) => RuntimeError::Raised(value),
+        None => RuntimeError::MissingSpellTarget(Spell::Confringo),
+    }
+}
+
+/// The new value of a variable `~Engorgio` is cast on.
+pub fn engorgio(value: RuntimeValue) -> Result<RuntimeValue, RuntimeError> {
+    match value {
+        RuntimeValue::Integer(value) => Ok(RuntimeValue::Integer(value.wrapping_add(1))),
+        RuntimeValue::Double(value) => Ok(RuntimeValue::Double(value + 1.0)),
+        RuntimeValue::String(string) => Ok(string.to_ascii_uppercase().into()),
+        _ => Err(RuntimeError::InvalidSpellTarget(Spell::Engorgio, value)),
+    }
+}
+
+/// The new value of a variable `~Reducio` is cast on.
+pub fn reducio(value: RuntimeValue) -> Result<RuntimeValue, RuntimeError> {
+    match value {
+        RuntimeValue::Integer(value) => Ok(RuntimeValue::Integer(value.wrapping_sub(1))),
+        RuntimeValue::Double(value) => Ok(RuntimeValue::Double(value - 1.0)),
+        RuntimeValue::String(string) => Ok(string.to_ascii_lowercase().into()),
+        _ => Err(RuntimeError::InvalidSpellTarget(Spell::Reducio, value)),
+    }
+}
+
+/// Appends `suffix` to a string variable, for `~Incendio` and
+/// `~WingardiumLeviosa`.
+pub fn append(
+    spell: Spell,
+    value: RuntimeValue,
+    suffix: &str,
+) -> Result<RuntimeValue, RuntimeError> {
+    match value {
+        RuntimeValue::String(string) => Ok(format!("{}{}", string, suffix).into()),
+        _ => Err(RuntimeError::InvalidSpellTarget(spell, value)),
+    }
+}
+
+/// Evaluates the operands of a vow that is a binary operation, keeping them
+/// to report if the vow breaks.
+pub fn vow_operation(
+    operation: BinaryOperation,
+    left: Option<RuntimeValue>,
+    right: Option<RuntimeValue>,
+) -> Result<(Option<RuntimeValue>, Vec<RuntimeValue>), RuntimeError> {
+    match (left, right) {
+        (Some(left), Some(right)) => {
+            let operands = vec![left.clone(), right.clone()];
+            Ok((Some(left.binary_operation(operation, right)?), operands))
+        }
+        _ => Ok((None, vec![])),
+    }
+}
+
+pub fn unbreakable_vow(
+    expression: &Expression,
+    value: Option<RuntimeValue>,
+    operands: Vec<RuntimeValue>,
+) -> Result<(), RuntimeError> {
+    match value {
+        Some(RuntimeValue::Boolean(true)) => Ok(()),
+        Some(RuntimeValue::Boolean(false)) => {
+            Err(RuntimeError::BrokenVow(expression.clone(), operands))
+        }
+        Some(value) => Err(RuntimeError::InvalidSpellTarget(
+            Spell::UnbreakableVow,
+            value,
+        )),
+        None => Err(RuntimeError::MissingSpellTarget(Spell::UnbreakableVow)),
+    }
+}
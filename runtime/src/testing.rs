@@ -0,0 +1,248 @@
+use std::cell::{Cell, Ref, RefCell};
+use std::collections::VecDeque;
+use std::ops::RangeInclusive;
+use std::time::Duration;
+use std::{fmt, iter};
+
+use crate::RuntimeAdapter;
+
+/// A deterministic [`RuntimeAdapter`] for tests: it records logged lines
+/// instead of printing them, answers random choices and `~Accio` from
+/// scripted queues, and only pretends to sleep.
+///
+/// ```
+/// use potterscript_runtime::{Runtime, TestAdapter};
+///
+/// let program = potterscript_parser::parse("~Revelio ~Accio").unwrap();
+/// let mut runtime = Runtime::new(TestAdapter::new().with_inputs(["Hedwig"]));
+/// runtime.eval(&program).unwrap();
+/// runtime.adapter().assert_logs(&["Hedwig"]);
+/// ```
+pub struct TestAdapter {
+    logs: RefCell<Vec<String>>,
+    integers: RefCell<Box<dyn Iterator<Item = i64>>>,
+    doubles: RefCell<VecDeque<f64>>,
+    inputs: RefCell<VecDeque<String>>,
+    prompts: RefCell<Vec<Option<String>>>,
+    elapsed: Cell<Duration>,
+}
+
+impl Default for TestAdapter {
+    fn default() -> Self {
+        Self {
+            logs: RefCell::default(),
+            integers: RefCell::new(Box::new(iter::empty())),
+            doubles: RefCell::default(),
+            inputs: RefCell::default(),
+            prompts: RefCell::default(),
+            elapsed: Cell::default(),
+        }
+    }
+}
+
+impl fmt::Debug for TestAdapter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TestAdapter")
+            .field("logs", &self.logs)
+            .field("doubles", &self.doubles)
+            .field("inputs", &self.inputs)
+            .field("prompts", &self.prompts)
+            .field("elapsed", &self.elapsed)
+            .finish_non_exhaustive()
+    }
+}
+
+impl TestAdapter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Queues the random integers to return, in order. The Sorting Hat picks
+    /// the house at that index of [`HogwartsHouse::ALL`], so `(0..4).cycle()`
+    /// goes round the houses forever. Asking for more integers than queued,
+    /// or for one out of the queued one's range, panics.
+    ///
+    /// [`HogwartsHouse::ALL`]: potterscript_parser::HogwartsHouse::ALL
+    pub fn with_integers<I>(self, integers: I) -> Self
+    where
+        I: IntoIterator<Item = i64>,
+        I::IntoIter: 'static,
+    {
+        let queued = self.integers.replace(Box::new(iter::empty()));
+        *self.integers.borrow_mut() = Box::new(queued.chain(integers));
+        self
+    }
+
+    /// Queues the random doubles to return, in order. Asking for more
+    /// doubles than queued panics.
+    pub fn with_doubles(self, doubles: impl IntoIterator<Item = f64>) -> Self {
+        self.doubles.borrow_mut().extend(doubles);
+        self
+    }
+
+    /// Queues the lines `~Accio` reads, in order. Once they run out it reads
+    /// as no more input.
+    pub fn with_inputs<S: Into<String>>(self, inputs: impl IntoIterator<Item = S>) -> Self {
+        self.inputs
+            .borrow_mut()
+            .extend(inputs.into_iter().map(Into::into));
+        self
+    }
+
+    /// Every line logged so far.
+    pub fn logs(&self) -> Ref<'_, [String]> {
+        Ref::map(self.logs.borrow(), Vec::as_slice)
+    }
+
+    /// Returns and forgets the lines logged so far.
+    pub fn take_logs(&self) -> Vec<String> {
+        self.logs.take()
+    }
+
+    /// Everything logged so far, one line each.
+    pub fn output(&self) -> String {
+        self.logs
+            .borrow()
+            .iter()
+            .map(|line| line.clone() + "\n")
+            .collect()
+    }
+
+    /// The prompt of every `~Accio` cast so far.
+    pub fn prompts(&self) -> Ref<'_, [Option<String>]> {
+        Ref::map(self.prompts.borrow(), Vec::as_slice)
+    }
+
+    /// Total time the program asked to sleep for.
+    pub fn elapsed(&self) -> Duration {
+        self.elapsed.get()
+    }
+
+    pub fn assert_logs(&self, expected: &[&str]) {
+        let logs = self.logs.borrow();
+        assert!(
+            logs.iter().eq(expected),
+            "logged lines differ\n  expected: {:?}\n    actual: {:?}",
+            expected,
+            *logs
+        );
+    }
+
+    pub fn assert_output(&self, expected: &str) {
+        let output = self.output();
+        assert!(
+            output == expected,
+            "output differs\n--- expected\n{}--- actual\n{}",
+            expected,
+            output
+        );
+    }
+}
+
+impl RuntimeAdapter for TestAdapter {
+    fn random_integer(&self, range: RangeInclusive<i64>) -> i64 {
+        let integer = self
+            .integers
+            .borrow_mut()
+            .next()
+            .expect("TestAdapter ran out of random integers, queue more with `with_integers`");
+        assert!(
+            range.contains(&integer),
+            "TestAdapter queued {} but {:?} was asked for",
+            integer,
+            range
+        );
+        integer
+    }
+
+    fn random_double(&self) -> f64 {
+        self.doubles
+            .borrow_mut()
+            .pop_front()
+            .expect("TestAdapter ran out of random doubles, queue more with `with_doubles`")
+    }
+
+    fn lumos(&self, string: String) -> String {
+        string
+    }
+
+    fn log(&self, string: &str) {
+        self.logs.borrow_mut().push(string.to_string());
+    }
+
+    fn read_line(&self, prompt: Option<&str>) -> Option<String> {
+        self.prompts.borrow_mut().push(prompt.map(String::from));
+        self.inputs.borrow_mut().pop_front()
+    }
+
+    fn sleep(&self, duration: Duration) {
+        self.elapsed.set(self.elapsed.get() + duration);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Runtime, RuntimeValue};
+    use potterscript_parser::HogwartsHouse;
+
+    fn run(adapter: TestAdapter, code: &str) -> Runtime<TestAdapter> {
+        let program = potterscript_parser::parse(code).unwrap();
+        let mut runtime = Runtime::new(adapter);
+        runtime.eval(&program).unwrap();
+        runtime
+    }
+
+    #[test]
+    fn test_logs() {
+        let runtime = run(TestAdapter::new(), "~Revelio 1\n~Lumos\n~Revelio \"a\"");
+        runtime.adapter().assert_logs(&["1", "a"]);
+        runtime.adapter().assert_output("1\na\n");
+        assert_eq!(vec!["1", "a"], runtime.adapter().take_logs());
+        assert!(runtime.adapter().logs().is_empty());
+    }
+
+    #[test]
+    fn test_scripted_houses_and_inputs() {
+        let adapter = TestAdapter::new()
+            .with_integers([2, 0])
+            .with_inputs(["Luna"]);
+        let runtime = run(
+            adapter,
+            "a = SortingHat\nb = SortingHat\nname = ~Accio\nrest = ~Accio",
+        );
+
+        assert_eq!(
+            Some(&RuntimeValue::HogwartsHouse(HogwartsHouse::Ravenclaw)),
+            runtime.get("a")
+        );
+        assert_eq!(
+            Some(&RuntimeValue::HogwartsHouse(HogwartsHouse::Gryffindor)),
+            runtime.get("b")
+        );
+        assert_eq!(Some(&RuntimeValue::from("Luna")), runtime.get("name"));
+        assert_eq!(Some(&RuntimeValue::Boolean(false)), runtime.get("rest"));
+    }
+
+    #[test]
+    fn test_virtual_clock() {
+        let runtime = run(
+            TestAdapter::new(),
+            "ms = 1500\n~Inmobolus ms\n~Inmobolus 500",
+        );
+        assert_eq!(Duration::from_secs(2), runtime.adapter().elapsed());
+    }
+
+    #[test]
+    fn test_inmobolus_ignores_other_targets() {
+        let runtime = run(TestAdapter::new(), "~Inmobolus \"soon\"\n~Inmobolus");
+        assert_eq!(Duration::ZERO, runtime.adapter().elapsed());
+    }
+
+    #[test]
+    #[should_panic(expected = "logged lines differ")]
+    fn test_assert_logs() {
+        let runtime = run(TestAdapter::new(), "~Revelio 1");
+        runtime.adapter().assert_logs(&["2"]);
+    }
+}
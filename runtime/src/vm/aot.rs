@@ -0,0 +1,84 @@
+//! Compiles a [`Chunk`] ahead of time to an object file, which `potter build`
+//! links with the support library in `rt` into a standalone executable.
+//!
+//! The object file defines:
+//!
+//! - `potter_code`, the [`NativeCode`] of the chunk, calling
+//!   `potter_execute` for every instruction, which the support library
+//!   defines with [`execute`].
+//! - `potter_chunk`, the chunk in the bytecode file format, and
+//!   `potter_chunk_len`, its length as a pointer-sized integer, which the
+//!   support library loads into a [`Vm`] before calling
+//!   [`Vm::set_native_code`].
+
+use cranelift_module::{DataDescription, Linkage, Module};
+use cranelift_object::{ObjectBuilder, ObjectModule};
+
+use super::native;
+pub use super::native::{execute, Context, NativeCode};
+use super::{Chunk, Vm};
+use crate::RuntimeAdapter;
+
+/// Compiles `chunk` to an object file for the host.
+pub fn compile_object(chunk: &Chunk) -> Result<Vec<u8>, String> {
+    let builder = ObjectBuilder::new(
+        native::host_isa(true)?,
+        "potter",
+        cranelift_module::default_libcall_names(),
+    )
+    .map_err(|error| error.to_string())?;
+    let mut module = ObjectModule::new(builder);
+
+    native::define(&mut module, chunk, "potter_code", Linkage::Export)?;
+
+    let bytes = chunk.to_bytes();
+    let pointer_bytes = module.target_config().pointer_bytes() as usize;
+    let len = (bytes.len() as u64).to_le_bytes()[..pointer_bytes].to_vec();
+    for (name, contents) in [("potter_chunk", bytes), ("potter_chunk_len", len)] {
+        let data = module
+            .declare_data(name, Linkage::Export, false, false)
+            .map_err(|error| error.to_string())?;
+        let mut description = DataDescription::new();
+        description.define(contents.into_boxed_slice());
+        module
+            .define_data(data, &description)
+            .map_err(|error| error.to_string())?;
+    }
+
+    module.finish().emit().map_err(|error| error.to_string())
+}
+
+impl<T: RuntimeAdapter> Vm<T> {
+    /// Runs `code` instead of interpreting the chunk, except for the
+    /// instructions it leaves to the interpreter.
+    ///
+    /// # Safety
+    ///
+    /// `code` must be compiled from the chunk of this VM by
+    /// [`compile_object`], calling [`execute::<T>`](execute).
+    pub unsafe fn set_native_code(&mut self, code: NativeCode<T>) {
+        self.native = Some(native::Native::new(code, &self.chunk));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vm::compile;
+
+    #[test]
+    fn test_compile_object() {
+        let program = potterscript_parser::parse("x = 1\n~Revelio x").unwrap();
+        let object = compile_object(&compile(&program)).unwrap();
+
+        for symbol in ["potter_code", "potter_execute", "potter_chunk_len"] {
+            assert!(
+                object
+                    .windows(symbol.len())
+                    .any(|window| window == symbol.as_bytes()),
+                "{}",
+                symbol
+            );
+        }
+    }
+}
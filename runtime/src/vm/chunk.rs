@@ -0,0 +1,142 @@
+use potterscript_parser::{BinaryOperation, Expression, Span};
+
+use crate::RuntimeValue;
+
+/// Index into one of the tables of a [`Chunk`].
+pub type Index = u32;
+
+/// A single instruction of the [`Vm`](super::Vm).
+///
+/// Every expression leaves exactly one entry on the stack, which is empty
+/// (`None`) for expressions without a value like `~Revelio x`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Op {
+    /// Counts a statement towards the step limit.
+    Step,
+    /// Forgets the last value, before every top-level statement.
+    ClearLastValue,
+    /// Pops the value of an expression statement into the last value.
+    SetLastValue,
+    /// Pushes a value of the constant pool.
+    Constant(Index),
+    /// Pushes an empty entry.
+    Nothing,
+    /// Pushes the value of a variable slot.
+    Load(Index),
+    /// Fails if the slot holds a constant, checked before the value of an
+    /// assignment is evaluated.
+    CheckAssignable(Index),
+    /// Pops a value into a variable slot.
+    Assign(Index),
+    /// Replaces a map with its field named by a string.
+    Field(Index),
+    BinaryOperation(BinaryOperation),
+    SortingHat,
+    Jump(Index),
+    /// Pops a condition and jumps unless it is `true`.
+    JumpUnlessTrue(Index),
+    /// Starts a `quidditch` loop.
+    Quidditch,
+    /// Jumps back to the start of the loop body until the snitch is caught.
+    LoopWhileQuidditch(Index),
+    Snitch,
+    /// Starts a `protego` block whose `expelliarmus` block starts at the
+    /// given instruction, with the error value on the stack.
+    Protego(Index),
+    EndProtego,
+    /// Casts the native spell named by a string on the popped target.
+    CastNative(Index),
+    Accio,
+    AvadaKedabra,
+    Confringo,
+    /// Engorgio of a variable slot.
+    Engorgio(Index),
+    FelixFelicis,
+    /// Incendio of a variable slot.
+    Incendio(Index),
+    Inmobolus,
+    Lumos,
+    Nox,
+    Obliviate(Index),
+    Periculum,
+    PetrificusTotalus(Index),
+    Reducio(Index),
+    Revelio,
+    /// Pops the value of the vow expression at the given index.
+    UnbreakableVow(Index),
+    /// Pops both operands of the vow expression at the given index, a
+    /// binary operation.
+    UnbreakableVowOperation(Index, BinaryOperation),
+    /// WingardiumLeviosa of a variable slot.
+    WingardiumLeviosa(Index),
+}
+
+impl Op {
+    /// How many values the instruction pops, and then pushes, on its way to
+    /// the next instruction or the one it jumps to.
+    pub fn stack_effect(&self) -> (usize, usize) {
+        match self {
+            Op::Step
+            | Op::ClearLastValue
+            | Op::CheckAssignable(_)
+            | Op::Jump(_)
+            | Op::Quidditch
+            | Op::LoopWhileQuidditch(_)
+            | Op::Snitch
+            | Op::Protego(_)
+            | Op::EndProtego => (0, 0),
+            Op::SetLastValue | Op::Assign(_) | Op::JumpUnlessTrue(_) => (1, 0),
+            Op::Constant(_)
+            | Op::Nothing
+            | Op::Load(_)
+            | Op::SortingHat
+            | Op::AvadaKedabra
+            | Op::Engorgio(_)
+            | Op::Incendio(_)
+            | Op::Lumos
+            | Op::Nox
+            | Op::Obliviate(_)
+            | Op::Periculum
+            | Op::PetrificusTotalus(_)
+            | Op::Reducio(_)
+            | Op::WingardiumLeviosa(_) => (0, 1),
+            Op::Field(_)
+            | Op::CastNative(_)
+            | Op::Accio
+            | Op::Confringo
+            | Op::FelixFelicis
+            | Op::Inmobolus
+            | Op::Revelio
+            | Op::UnbreakableVow(_) => (1, 1),
+            Op::BinaryOperation(_) | Op::UnbreakableVowOperation(_, _) => (2, 1),
+        }
+    }
+}
+
+/// A compiled program.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Chunk {
+    pub code: Vec<Op>,
+    /// Every literal value of the program, once each.
+    pub constants: Vec<RuntimeValue>,
+    /// Name of every variable slot.
+    pub names: Vec<String>,
+    /// Names of native spells and map fields.
+    pub strings: Vec<String>,
+    /// Expressions of every `~UnbreakableVow`, to report broken ones.
+    pub vows: Vec<Expression>,
+    /// Debug info: the first instruction of every top-level statement and
+    /// where it is in the source, empty if compiled without spans.
+    pub spans: Vec<(Index, Span)>,
+}
+
+impl Chunk {
+    /// Span of the top-level statement the instruction at `pc` belongs to.
+    pub fn span_at(&self, pc: usize) -> Option<Span> {
+        self.spans
+            .iter()
+            .take_while(|(start, _)| *start as usize <= pc)
+            .last()
+            .map(|(_, span)| *span)
+    }
+}
@@ -0,0 +1,309 @@
+use std::collections::HashMap;
+
+use potterscript_parser::{Atom, Expression, Program, Span, Spell, Statement};
+
+use super::chunk::{Chunk, Index, Op};
+use crate::RuntimeValue;
+
+/// Compiles `program` to bytecode for the [`Vm`](super::Vm).
+pub fn compile(program: &Program) -> Chunk {
+    let mut compiler = Compiler::default();
+    for statement in &program.0 {
+        compiler.top_level_statement(statement);
+    }
+    compiler.chunk
+}
+
+/// Like [`compile`], keeping the spans of
+/// [`parse_spanned`](potterscript_parser::parse_spanned) as debug info.
+pub fn compile_spanned(statements: &[(Statement, Span)]) -> Chunk {
+    let mut compiler = Compiler::default();
+    for (statement, span) in statements {
+        let start = compiler.here();
+        compiler.chunk.spans.push((start, *span));
+        compiler.top_level_statement(statement);
+    }
+    compiler.chunk
+}
+
+#[derive(Default)]
+struct Compiler {
+    chunk: Chunk,
+    slots: HashMap<String, Index>,
+}
+
+impl Compiler {
+    fn emit(&mut self, op: Op) -> usize {
+        self.chunk.code.push(op);
+        self.chunk.code.len() - 1
+    }
+
+    /// Where the next instruction goes, to jump to it.
+    fn here(&self) -> Index {
+        self.chunk.code.len() as Index
+    }
+
+    /// Points the jump at `at` to the next instruction.
+    fn patch(&mut self, at: usize) {
+        let target = self.here();
+        match &mut self.chunk.code[at] {
+            Op::Jump(to) | Op::JumpUnlessTrue(to) | Op::Protego(to) => *to = target,
+            op => unreachable!("{:?} is not a jump", op),
+        }
+    }
+
+    fn slot(&mut self, name: &str) -> Index {
+        if let Some(slot) = self.slots.get(name) {
+            return *slot;
+        }
+
+        let slot = self.chunk.names.len() as Index;
+        self.chunk.names.push(name.to_string());
+        self.slots.insert(name.to_string(), slot);
+        slot
+    }
+
+    fn constant(&mut self, value: RuntimeValue) -> Index {
+        // By bits for doubles, `-0.0 == 0.0` but they print differently
+        let same = |constant: &RuntimeValue| match (constant, &value) {
+            (RuntimeValue::Double(a), RuntimeValue::Double(b)) => a.to_bits() == b.to_bits(),
+            (constant, value) => constant == value,
+        };
+        let index = match self.chunk.constants.iter().position(same) {
+            Some(index) => index,
+            None => {
+                self.chunk.constants.push(value);
+                self.chunk.constants.len() - 1
+            }
+        };
+        index as Index
+    }
+
+    fn string(&mut self, string: &str) -> Index {
+        let index = match self.chunk.strings.iter().position(|s| s == string) {
+            Some(index) => index,
+            None => {
+                self.chunk.strings.push(string.to_string());
+                self.chunk.strings.len() - 1
+            }
+        };
+        index as Index
+    }
+
+    fn top_level_statement(&mut self, statement: &Statement) {
+        self.emit(Op::ClearLastValue);
+        self.statement(statement);
+    }
+
+    fn block(&mut self, block: &[Statement]) {
+        for statement in block {
+            self.statement(statement);
+        }
+    }
+
+    fn statement(&mut self, statement: &Statement) {
+        self.emit(Op::Step);
+
+        match statement {
+            Statement::VariableAssignment(name, value) => {
+                let slot = self.slot(name);
+                self.emit(Op::CheckAssignable(slot));
+                self.expression(value);
+                self.emit(Op::Assign(slot));
+            }
+            Statement::ExpressionStatement(expression) => {
+                self.expression(expression);
+                self.emit(Op::SetLastValue);
+            }
+            Statement::If(condition, true_block, else_block) => {
+                self.expression(condition);
+                let to_else = self.emit(Op::JumpUnlessTrue(0));
+                self.block(true_block);
+                let to_end = self.emit(Op::Jump(0));
+                self.patch(to_else);
+                self.block(else_block);
+                self.patch(to_end);
+            }
+            Statement::Quidditch(block) => {
+                self.emit(Op::Quidditch);
+                let start = self.here();
+                self.block(block);
+                self.emit(Op::LoopWhileQuidditch(start));
+            }
+            Statement::Snitch => {
+                self.emit(Op::Snitch);
+            }
+            // Only run by a test runner, see `vows`
+            Statement::Vow(_, _) => {}
+            Statement::Protego(block, name, catch_block) => {
+                let to_catch = self.emit(Op::Protego(0));
+                self.block(block);
+                self.emit(Op::EndProtego);
+                let to_end = self.emit(Op::Jump(0));
+                self.patch(to_catch);
+                let slot = self.slot(name);
+                self.emit(Op::Assign(slot));
+                self.block(catch_block);
+                self.patch(to_end);
+            }
+        }
+    }
+
+    fn expression(&mut self, expression: &Expression) {
+        match expression {
+            Expression::SpellCast(spell, target) => self.spell(spell, target.as_ref().as_ref()),
+            Expression::BinaryOperation(operation, left, right) => {
+                self.expression(left);
+                self.expression(right);
+                self.emit(Op::BinaryOperation(*operation));
+            }
+            Expression::Atom(Atom::Variable(name)) => {
+                let slot = self.slot(name);
+                self.emit(Op::Load(slot));
+            }
+            Expression::Atom(atom) => {
+                let constant = self.constant(atom.clone().into());
+                self.emit(Op::Constant(constant));
+            }
+            Expression::Field(expression, field) => {
+                self.expression(expression);
+                let field = self.string(field);
+                self.emit(Op::Field(field));
+            }
+            Expression::Comment(_) => {
+                self.emit(Op::Nothing);
+            }
+            Expression::SortingHat => {
+                self.emit(Op::SortingHat);
+            }
+        }
+    }
+
+    /// Compiles the target of a spell that evaluates it, if any.
+    fn target(&mut self, target: Option<&Expression>) {
+        match target {
+            Some(target) => self.expression(target),
+            None => {
+                self.emit(Op::Nothing);
+            }
+        }
+    }
+
+    /// Pushes `value`, for spells whose result is known ahead of time.
+    fn value(&mut self, value: RuntimeValue) {
+        let constant = self.constant(value);
+        self.emit(Op::Constant(constant));
+    }
+
+    fn spell(&mut self, spell: &Spell, target: Option<&Expression>) {
+        // Most spells only act on variables or literals written as their
+        // target and ignore anything else
+        let variable = match target {
+            Some(Expression::Atom(Atom::Variable(name))) => Some(self.slot(name)),
+            _ => None,
+        };
+        let string = match target {
+            Some(Expression::Atom(Atom::String(string))) => Some(string.clone()),
+            _ => None,
+        };
+
+        match spell {
+            Spell::Custom(name) => {
+                self.target(target);
+                let name = self.string(name);
+                self.emit(Op::CastNative(name));
+            }
+            Spell::Accio => {
+                self.target(target);
+                self.emit(Op::Accio);
+            }
+            Spell::UnbreakableVow => match target {
+                Some(Expression::BinaryOperation(operation, left, right)) => {
+                    self.expression(left);
+                    self.expression(right);
+                    let vow = self.vow(target);
+                    self.emit(Op::UnbreakableVowOperation(vow, *operation));
+                }
+                _ => {
+                    self.target(target);
+                    let vow = self.vow(target);
+                    self.emit(Op::UnbreakableVow(vow));
+                }
+            },
+            Spell::FelixFelicis => {
+                self.target(target);
+                self.emit(Op::FelixFelicis);
+            }
+            Spell::AvadaKedabra => {
+                self.emit(Op::AvadaKedabra);
+            }
+            Spell::Confringo => {
+                self.target(target);
+                self.emit(Op::Confringo);
+            }
+            Spell::Inmobolus => {
+                self.target(target);
+                self.emit(Op::Inmobolus);
+            }
+            Spell::Incendio => match (variable, string) {
+                (Some(slot), _) => {
+                    self.emit(Op::Incendio(slot));
+                }
+                (_, Some(string)) => self.value(RuntimeValue::from(string + "🔥")),
+                _ => {
+                    self.emit(Op::Nothing);
+                }
+            },
+            Spell::Aguamenti => self.value(RuntimeValue::from("💦")),
+            Spell::OculusReparo => self.value(RuntimeValue::from("👓")),
+            Spell::Serpensortia => self.value(RuntimeValue::from("🐍")),
+            Spell::Periculum => {
+                self.emit(Op::Periculum);
+            }
+            Spell::Lumos => {
+                self.emit(Op::Lumos);
+            }
+            Spell::Nox => {
+                self.emit(Op::Nox);
+            }
+            Spell::Engorgio => self.variable_spell(variable, Op::Engorgio),
+            Spell::Reducio => self.variable_spell(variable, Op::Reducio),
+            Spell::Obliviate => self.variable_spell(variable, Op::Obliviate),
+            Spell::PetrificusTotalus => self.variable_spell(variable, Op::PetrificusTotalus),
+            Spell::Revelio => match target {
+                Some(target) => {
+                    self.expression(target);
+                    self.emit(Op::Revelio);
+                }
+                None => {
+                    self.emit(Op::Nothing);
+                }
+            },
+            Spell::WingardiumLeviosa => match (variable, string) {
+                (Some(slot), _) => {
+                    self.emit(Op::WingardiumLeviosa(slot));
+                }
+                (_, Some(string)) => self.value(RuntimeValue::from(string + "\n")),
+                _ => {
+                    self.emit(Op::Nothing);
+                }
+            },
+        }
+    }
+
+    fn variable_spell(&mut self, variable: Option<Index>, op: fn(Index) -> Op) {
+        match variable {
+            Some(slot) => self.emit(op(slot)),
+            None => self.emit(Op::Nothing),
+        };
+    }
+
+    fn vow(&mut self, target: Option<&Expression>) -> Index {
+        // A vow without target fails before its expression is needed
+        let expression = target
+            .cloned()
+            .unwrap_or(Expression::Comment(String::new()));
+        self.chunk.vows.push(expression);
+        (self.chunk.vows.len() - 1) as Index
+    }
+}
@@ -0,0 +1,730 @@
+//! The binary file format of precompiled scripts.
+//!
+//! All numbers are little-endian. A file starts with [`MAGIC`], a `u16`
+//! [`VERSION`] and a `u8` of flags, followed by the tables of the [`Chunk`]
+//! in order: constants, slot names, strings, vow expressions, code and, if
+//! the `DEBUG_INFO` flag is set, spans. Tables start with a `u32` length and
+//! strings are a `u32` byte length followed by UTF-8.
+
+use std::collections::BTreeMap;
+use std::{error, fmt};
+
+use potterscript_parser::{Atom, BinaryOperation, Expression, HogwartsHouse, Span, Spell};
+
+use super::chunk::{Chunk, Index, Op};
+use crate::RuntimeValue;
+
+pub const MAGIC: [u8; 4] = *b"PTRC";
+/// Bumped on every change to the format, files of other versions are
+/// rejected.
+pub const VERSION: u16 = 1;
+
+const DEBUG_INFO: u8 = 1;
+/// Keeps deeply nested values and expressions from overflowing the stack.
+const MAX_DEPTH: usize = 256;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum LoadError {
+    /// The file doesn't start with [`MAGIC`].
+    NotBytecode,
+    UnsupportedVersion(u16),
+    UnknownFlags(u8),
+    UnexpectedEnd,
+    TrailingBytes(usize),
+    InvalidTag(&'static str, u8),
+    InvalidUtf8,
+    TooDeep,
+    /// An instruction refers past the end of a table.
+    IndexOutOfBounds {
+        pc: usize,
+        table: &'static str,
+    },
+    /// An instruction pops more values than there are on the stack.
+    StackUnderflow(usize),
+    /// Two paths reach an instruction with different stack sizes.
+    UnbalancedStack(usize),
+    InvalidSpan(usize),
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LoadError::NotBytecode => write!(f, "Not a PotterScript bytecode file"),
+            LoadError::UnsupportedVersion(version) => write!(
+                f,
+                "Bytecode version {} is not supported, expected version {}",
+                version, VERSION
+            ),
+            LoadError::UnknownFlags(flags) => write!(f, "Unknown flags {:#04x}", flags),
+            LoadError::UnexpectedEnd => write!(f, "Unexpected end of bytecode"),
+            LoadError::TrailingBytes(count) => {
+                write!(f, "{} unexpected bytes after the end of bytecode", count)
+            }
+            LoadError::InvalidTag(kind, tag) => write!(f, "Invalid {} tag {}", kind, tag),
+            LoadError::InvalidUtf8 => write!(f, "Invalid UTF-8 in a string"),
+            LoadError::TooDeep => write!(f, "Values nested too deep"),
+            LoadError::IndexOutOfBounds { pc, table } => {
+                write!(f, "Instruction {} refers past the end of the {}", pc, table)
+            }
+            LoadError::StackUnderflow(pc) => write!(f, "Instruction {} underflows the stack", pc),
+            LoadError::UnbalancedStack(pc) => {
+                write!(
+                    f,
+                    "Instruction {} is reached with different stack sizes",
+                    pc
+                )
+            }
+            LoadError::InvalidSpan(index) => write!(f, "Span {} is invalid", index),
+        }
+    }
+}
+
+impl error::Error for LoadError {}
+
+impl Chunk {
+    /// Encodes the chunk in the bytecode file format, with its spans if any.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut writer = Writer::default();
+        writer.bytes.extend(MAGIC);
+        writer.u16(VERSION);
+        writer.u8(if self.spans.is_empty() { 0 } else { DEBUG_INFO });
+
+        writer.u32(self.constants.len() as u32);
+        for value in &self.constants {
+            writer.value(value);
+        }
+        writer.u32(self.names.len() as u32);
+        for name in &self.names {
+            writer.string(name);
+        }
+        writer.u32(self.strings.len() as u32);
+        for string in &self.strings {
+            writer.string(string);
+        }
+        writer.u32(self.vows.len() as u32);
+        for vow in &self.vows {
+            writer.expression(vow);
+        }
+        writer.u32(self.code.len() as u32);
+        for op in &self.code {
+            writer.op(*op);
+        }
+
+        if !self.spans.is_empty() {
+            writer.u32(self.spans.len() as u32);
+            for (pc, span) in &self.spans {
+                writer.u32(*pc);
+                writer.u32(span.start as u32);
+                writer.u32(span.end as u32);
+            }
+        }
+        writer.bytes
+    }
+
+    /// Decodes a chunk written by [`to_bytes`](Self::to_bytes), checking
+    /// that the [`Vm`](super::Vm) can run it.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, LoadError> {
+        let mut reader = Reader { bytes, depth: 0 };
+        if reader.take(MAGIC.len()).ok() != Some(&MAGIC[..]) {
+            return Err(LoadError::NotBytecode);
+        }
+        let version = reader.u16()?;
+        if version != VERSION {
+            return Err(LoadError::UnsupportedVersion(version));
+        }
+        let flags = reader.u8()?;
+        if flags & !DEBUG_INFO != 0 {
+            return Err(LoadError::UnknownFlags(flags));
+        }
+
+        let mut chunk = Chunk::default();
+        for _ in 0..reader.u32()? {
+            chunk.constants.push(reader.value()?);
+        }
+        for _ in 0..reader.u32()? {
+            chunk.names.push(reader.string()?);
+        }
+        for _ in 0..reader.u32()? {
+            chunk.strings.push(reader.string()?);
+        }
+        for _ in 0..reader.u32()? {
+            chunk.vows.push(reader.expression()?);
+        }
+        for _ in 0..reader.u32()? {
+            chunk.code.push(reader.op()?);
+        }
+
+        if flags & DEBUG_INFO != 0 {
+            for index in 0..reader.u32()? as usize {
+                let pc = reader.u32()?;
+                let start = reader.u32()? as usize;
+                let end = reader.u32()? as usize;
+                if pc as usize > chunk.code.len() || start > end {
+                    return Err(LoadError::InvalidSpan(index));
+                }
+                chunk.spans.push((pc, Span { start, end }));
+            }
+        }
+        if !reader.bytes.is_empty() {
+            return Err(LoadError::TrailingBytes(reader.bytes.len()));
+        }
+
+        verify(&chunk)?;
+        Ok(chunk)
+    }
+}
+
+const HOUSES: [HogwartsHouse; 4] = HogwartsHouse::ALL;
+
+const OPERATIONS: [BinaryOperation; 6] = [
+    BinaryOperation::Plus,
+    BinaryOperation::Minus,
+    BinaryOperation::Times,
+    BinaryOperation::Divide,
+    BinaryOperation::Equal,
+    BinaryOperation::NotEqual,
+];
+
+#[derive(Default)]
+struct Writer {
+    bytes: Vec<u8>,
+}
+
+impl Writer {
+    fn u8(&mut self, value: u8) {
+        self.bytes.push(value);
+    }
+
+    fn u16(&mut self, value: u16) {
+        self.bytes.extend(value.to_le_bytes());
+    }
+
+    fn u32(&mut self, value: u32) {
+        self.bytes.extend(value.to_le_bytes());
+    }
+
+    fn string(&mut self, string: &str) {
+        self.u32(string.len() as u32);
+        self.bytes.extend(string.as_bytes());
+    }
+
+    fn house(&mut self, house: HogwartsHouse) {
+        self.u8(HOUSES.iter().position(|h| *h == house).unwrap() as u8);
+    }
+
+    fn operation(&mut self, operation: BinaryOperation) {
+        self.u8(OPERATIONS.iter().position(|o| *o == operation).unwrap() as u8);
+    }
+
+    fn value(&mut self, value: &RuntimeValue) {
+        match value {
+            RuntimeValue::Integer(integer) => {
+                self.u8(0);
+                self.bytes.extend(integer.to_le_bytes());
+            }
+            RuntimeValue::Double(double) => {
+                self.u8(1);
+                self.bytes.extend(double.to_le_bytes());
+            }
+            RuntimeValue::Boolean(boolean) => {
+                self.u8(2);
+                self.u8(*boolean as u8);
+            }
+            RuntimeValue::String(string) => {
+                self.u8(3);
+                self.string(string);
+            }
+            RuntimeValue::HogwartsHouse(house) => {
+                self.u8(4);
+                self.house(*house);
+            }
+            RuntimeValue::List(values) => {
+                self.u8(5);
+                self.u32(values.len() as u32);
+                for value in values {
+                    self.value(value);
+                }
+            }
+            RuntimeValue::Map(entries) => {
+                self.u8(6);
+                self.u32(entries.len() as u32);
+                for (key, value) in entries {
+                    self.string(key);
+                    self.value(value);
+                }
+            }
+        }
+    }
+
+    fn expression(&mut self, expression: &Expression) {
+        match expression {
+            Expression::SpellCast(spell, target) => {
+                self.u8(0);
+                self.string(&spell.to_string());
+                match target.as_ref() {
+                    Some(target) => {
+                        self.u8(1);
+                        self.expression(target);
+                    }
+                    None => self.u8(0),
+                }
+            }
+            Expression::BinaryOperation(operation, left, right) => {
+                self.u8(1);
+                self.operation(*operation);
+                self.expression(left);
+                self.expression(right);
+            }
+            Expression::Atom(atom) => {
+                self.u8(2);
+                match atom {
+                    Atom::Variable(name) => {
+                        self.u8(0);
+                        self.string(name);
+                    }
+                    atom => {
+                        self.u8(1);
+                        self.value(&atom.clone().into());
+                    }
+                }
+            }
+            Expression::Field(expression, field) => {
+                self.u8(3);
+                self.expression(expression);
+                self.string(field);
+            }
+            Expression::Comment(comment) => {
+                self.u8(4);
+                self.string(comment);
+            }
+            Expression::SortingHat => self.u8(5),
+        }
+    }
+
+    fn op(&mut self, op: Op) {
+        let (opcode, operand) = match op {
+            Op::Step => (0, None),
+            Op::ClearLastValue => (1, None),
+            Op::SetLastValue => (2, None),
+            Op::Constant(index) => (3, Some(index)),
+            Op::Nothing => (4, None),
+            Op::Load(slot) => (5, Some(slot)),
+            Op::CheckAssignable(slot) => (6, Some(slot)),
+            Op::Assign(slot) => (7, Some(slot)),
+            Op::Field(field) => (8, Some(field)),
+            Op::BinaryOperation(operation) => {
+                self.u8(9);
+                self.operation(operation);
+                return;
+            }
+            Op::SortingHat => (10, None),
+            Op::Jump(to) => (11, Some(to)),
+            Op::JumpUnlessTrue(to) => (12, Some(to)),
+            Op::Quidditch => (13, None),
+            Op::LoopWhileQuidditch(start) => (14, Some(start)),
+            Op::Snitch => (15, None),
+            Op::Protego(catch) => (16, Some(catch)),
+            Op::EndProtego => (17, None),
+            Op::CastNative(name) => (18, Some(name)),
+            Op::Accio => (19, None),
+            Op::AvadaKedabra => (20, None),
+            Op::Confringo => (21, None),
+            Op::Engorgio(slot) => (22, Some(slot)),
+            Op::FelixFelicis => (23, None),
+            Op::Incendio(slot) => (24, Some(slot)),
+            Op::Inmobolus => (25, None),
+            Op::Lumos => (26, None),
+            Op::Nox => (27, None),
+            Op::Obliviate(slot) => (28, Some(slot)),
+            Op::Periculum => (29, None),
+            Op::PetrificusTotalus(slot) => (30, Some(slot)),
+            Op::Reducio(slot) => (31, Some(slot)),
+            Op::Revelio => (32, None),
+            Op::UnbreakableVow(vow) => (33, Some(vow)),
+            Op::UnbreakableVowOperation(vow, operation) => {
+                self.u8(34);
+                self.u32(vow);
+                self.operation(operation);
+                return;
+            }
+            Op::WingardiumLeviosa(slot) => (35, Some(slot)),
+        };
+
+        self.u8(opcode);
+        if let Some(operand) = operand {
+            self.u32(operand);
+        }
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    depth: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], LoadError> {
+        if self.bytes.len() < len {
+            return Err(LoadError::UnexpectedEnd);
+        }
+        let (taken, rest) = self.bytes.split_at(len);
+        self.bytes = rest;
+        Ok(taken)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], LoadError> {
+        Ok(self.take(N)?.try_into().unwrap())
+    }
+
+    fn u8(&mut self) -> Result<u8, LoadError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, LoadError> {
+        self.array().map(u16::from_le_bytes)
+    }
+
+    fn u32(&mut self) -> Result<u32, LoadError> {
+        self.array().map(u32::from_le_bytes)
+    }
+
+    fn string(&mut self) -> Result<String, LoadError> {
+        let len = self.u32()? as usize;
+        let bytes = self.take(len)?;
+        String::from_utf8(bytes.to_vec()).map_err(|_| LoadError::InvalidUtf8)
+    }
+
+    fn house(&mut self) -> Result<HogwartsHouse, LoadError> {
+        let tag = self.u8()?;
+        HOUSES
+            .get(tag as usize)
+            .copied()
+            .ok_or(LoadError::InvalidTag("house", tag))
+    }
+
+    fn operation(&mut self) -> Result<BinaryOperation, LoadError> {
+        let tag = self.u8()?;
+        OPERATIONS
+            .get(tag as usize)
+            .copied()
+            .ok_or(LoadError::InvalidTag("operation", tag))
+    }
+
+    /// Reads something that can nest, failing if it nests too deep.
+    fn nested<T>(&mut self, read: fn(&mut Self) -> Result<T, LoadError>) -> Result<T, LoadError> {
+        if self.depth == MAX_DEPTH {
+            return Err(LoadError::TooDeep);
+        }
+        self.depth += 1;
+        let result = read(self);
+        self.depth -= 1;
+        result
+    }
+
+    fn value(&mut self) -> Result<RuntimeValue, LoadError> {
+        self.nested(|reader| match reader.u8()? {
+            0 => Ok(RuntimeValue::Integer(i64::from_le_bytes(reader.array()?))),
+            1 => Ok(RuntimeValue::Double(f64::from_le_bytes(reader.array()?))),
+            2 => match reader.u8()? {
+                0 => Ok(RuntimeValue::Boolean(false)),
+                1 => Ok(RuntimeValue::Boolean(true)),
+                tag => Err(LoadError::InvalidTag("boolean", tag)),
+            },
+            3 => Ok(RuntimeValue::from(reader.string()?)),
+            4 => Ok(RuntimeValue::HogwartsHouse(reader.house()?)),
+            5 => {
+                let mut values = vec![];
+                for _ in 0..reader.u32()? {
+                    values.push(reader.value()?);
+                }
+                Ok(RuntimeValue::List(values))
+            }
+            6 => {
+                let mut entries = BTreeMap::new();
+                for _ in 0..reader.u32()? {
+                    let key = reader.string()?;
+                    entries.insert(key, reader.value()?);
+                }
+                Ok(RuntimeValue::Map(entries))
+            }
+            tag => Err(LoadError::InvalidTag("value", tag)),
+        })
+    }
+
+    fn atom(&mut self) -> Result<Atom, LoadError> {
+        match self.u8()? {
+            0 => Ok(Atom::Variable(self.string()?)),
+            1 => match self.value()? {
+                RuntimeValue::Integer(integer) => Ok(Atom::Integer(integer)),
+                RuntimeValue::Double(double) => Ok(Atom::Double(double)),
+                RuntimeValue::Boolean(boolean) => Ok(Atom::Boolean(boolean)),
+                RuntimeValue::String(string) => Ok(Atom::String(string.to_string())),
+                RuntimeValue::HogwartsHouse(house) => Ok(Atom::HogwartsHouse(house)),
+                // Lists and maps have no literal syntax
+                RuntimeValue::List(_) => Err(LoadError::InvalidTag("atom", 5)),
+                RuntimeValue::Map(_) => Err(LoadError::InvalidTag("atom", 6)),
+            },
+            tag => Err(LoadError::InvalidTag("atom", tag)),
+        }
+    }
+
+    fn expression(&mut self) -> Result<Expression, LoadError> {
+        self.nested(|reader| match reader.u8()? {
+            0 => {
+                let name = reader.string()?;
+                let spell = Spell::ALL
+                    .into_iter()
+                    .find(|spell| spell.to_string() == name)
+                    .unwrap_or(Spell::Custom(name));
+                let target = match reader.u8()? {
+                    0 => None,
+                    1 => Some(reader.expression()?),
+                    tag => return Err(LoadError::InvalidTag("spell target", tag)),
+                };
+                Ok(Expression::SpellCast(spell, Box::new(target)))
+            }
+            1 => {
+                let operation = reader.operation()?;
+                let left = reader.expression()?;
+                let right = reader.expression()?;
+                Ok(Expression::BinaryOperation(
+                    operation,
+                    Box::new(left),
+                    Box::new(right),
+                ))
+            }
+            2 => Ok(Expression::Atom(reader.atom()?)),
+            3 => {
+                let expression = reader.expression()?;
+                Ok(Expression::Field(Box::new(expression), reader.string()?))
+            }
+            4 => Ok(Expression::Comment(reader.string()?)),
+            5 => Ok(Expression::SortingHat),
+            tag => Err(LoadError::InvalidTag("expression", tag)),
+        })
+    }
+
+    fn op(&mut self) -> Result<Op, LoadError> {
+        let op = match self.u8()? {
+            0 => Op::Step,
+            1 => Op::ClearLastValue,
+            2 => Op::SetLastValue,
+            3 => Op::Constant(self.u32()?),
+            4 => Op::Nothing,
+            5 => Op::Load(self.u32()?),
+            6 => Op::CheckAssignable(self.u32()?),
+            7 => Op::Assign(self.u32()?),
+            8 => Op::Field(self.u32()?),
+            9 => Op::BinaryOperation(self.operation()?),
+            10 => Op::SortingHat,
+            11 => Op::Jump(self.u32()?),
+            12 => Op::JumpUnlessTrue(self.u32()?),
+            13 => Op::Quidditch,
+            14 => Op::LoopWhileQuidditch(self.u32()?),
+            15 => Op::Snitch,
+            16 => Op::Protego(self.u32()?),
+            17 => Op::EndProtego,
+            18 => Op::CastNative(self.u32()?),
+            19 => Op::Accio,
+            20 => Op::AvadaKedabra,
+            21 => Op::Confringo,
+            22 => Op::Engorgio(self.u32()?),
+            23 => Op::FelixFelicis,
+            24 => Op::Incendio(self.u32()?),
+            25 => Op::Inmobolus,
+            26 => Op::Lumos,
+            27 => Op::Nox,
+            28 => Op::Obliviate(self.u32()?),
+            29 => Op::Periculum,
+            30 => Op::PetrificusTotalus(self.u32()?),
+            31 => Op::Reducio(self.u32()?),
+            32 => Op::Revelio,
+            33 => Op::UnbreakableVow(self.u32()?),
+            34 => Op::UnbreakableVowOperation(self.u32()?, self.operation()?),
+            35 => Op::WingardiumLeviosa(self.u32()?),
+            tag => return Err(LoadError::InvalidTag("instruction", tag)),
+        };
+        Ok(op)
+    }
+}
+
+/// Checks that every index of the code is in bounds and that the stack never
+/// underflows, so the [`Vm`](super::Vm) cannot panic running the chunk.
+/// Returns the stack size at every instruction and at the end, `None` where
+/// no path reaches.
+pub(super) fn verify(chunk: &Chunk) -> Result<Vec<Option<usize>>, LoadError> {
+    let len = chunk.code.len();
+    let check = |pc: usize, index: Index, table: &'static str, table_len: usize| {
+        if index as usize >= table_len {
+            return Err(LoadError::IndexOutOfBounds { pc, table });
+        }
+        Ok(())
+    };
+
+    // Stack size at every instruction, found by following every path
+    let mut depths: Vec<Option<usize>> = vec![None; len + 1];
+    let mut pending = vec![(0, 0)];
+    while let Some((pc, depth)) = pending.pop() {
+        match depths[pc] {
+            Some(known) if known == depth => continue,
+            Some(_) => return Err(LoadError::UnbalancedStack(pc)),
+            None => depths[pc] = Some(depth),
+        }
+        let Some(op) = chunk.code.get(pc) else {
+            continue;
+        };
+
+        let mut jump = None;
+        match *op {
+            Op::Constant(index) => check(pc, index, "constants", chunk.constants.len())?,
+            Op::Load(slot)
+            | Op::CheckAssignable(slot)
+            | Op::Assign(slot)
+            | Op::Engorgio(slot)
+            | Op::Incendio(slot)
+            | Op::Obliviate(slot)
+            | Op::PetrificusTotalus(slot)
+            | Op::Reducio(slot)
+            | Op::WingardiumLeviosa(slot) => check(pc, slot, "names", chunk.names.len())?,
+            Op::Field(string) | Op::CastNative(string) => {
+                check(pc, string, "strings", chunk.strings.len())?
+            }
+            Op::Jump(to) => {
+                check(pc, to, "code", len + 1)?;
+                pending.push((to as usize, depth));
+                continue;
+            }
+            Op::JumpUnlessTrue(to) => {
+                check(pc, to, "code", len + 1)?;
+                jump = Some((to, 1, 0));
+            }
+            Op::LoopWhileQuidditch(to) => {
+                check(pc, to, "code", len + 1)?;
+                jump = Some((to, 0, 0));
+            }
+            Op::Protego(catch) => {
+                check(pc, catch, "code", len + 1)?;
+                // The error value is pushed for the `expelliarmus` block
+                jump = Some((catch, 0, 1));
+            }
+            Op::UnbreakableVow(vow) | Op::UnbreakableVowOperation(vow, _) => {
+                check(pc, vow, "vows", chunk.vows.len())?
+            }
+            _ => {}
+        }
+        let (pops, pushes) = op.stack_effect();
+
+        if depth < pops {
+            return Err(LoadError::StackUnderflow(pc));
+        }
+        if let Some((to, pops, pushes)) = jump {
+            pending.push((to as usize, depth - pops + pushes));
+        }
+        pending.push((pc + 1, depth - pops + pushes));
+    }
+
+    Ok(depths)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vm::{compile_spanned, Vm};
+    use crate::TestAdapter;
+
+    const SCRIPT: &str = r#"
+total = 1.5 + 2.5
+protego {
+  ~Revelio missing
+} expelliarmus err {
+  ~Revelio err.kind
+}
+~UnbreakableVow total == 4.0
+~UnbreakableVow ~Expecto 🎩✨
+"#;
+
+    fn chunk() -> Chunk {
+        compile_spanned(&potterscript_parser::parse_spanned(SCRIPT).unwrap())
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let chunk = chunk();
+        let bytes = chunk.to_bytes();
+        assert_eq!(MAGIC, bytes[..4]);
+        assert_eq!(chunk, Chunk::from_bytes(&bytes).unwrap());
+
+        let adapter = TestAdapter::new().with_integers([0]);
+        let mut vm = Vm::new(Chunk::from_bytes(&bytes).unwrap(), adapter);
+        assert_eq!(
+            Err(crate::RuntimeError::UnknownSpell("Expecto".to_string())),
+            vm.run()
+        );
+        vm.adapter().assert_logs(&["VariableNotFound"]);
+        let span = vm.chunk().span_at(vm.pc()).unwrap();
+        assert_eq!(
+            "~UnbreakableVow ~Expecto 🎩✨",
+            &SCRIPT[span.start..span.end]
+        );
+    }
+
+    #[test]
+    fn test_rejects_malformed_files() {
+        let bytes = chunk().to_bytes();
+
+        assert_eq!(
+            Err(LoadError::NotBytecode),
+            Chunk::from_bytes(b"~Revelio 1")
+        );
+
+        let mut other_version = bytes.clone();
+        other_version[4] = 99;
+        assert_eq!(
+            Err(LoadError::UnsupportedVersion(99)),
+            Chunk::from_bytes(&other_version)
+        );
+
+        assert_eq!(
+            Err(LoadError::UnexpectedEnd),
+            Chunk::from_bytes(&bytes[..bytes.len() - 1])
+        );
+
+        let mut trailing = bytes.clone();
+        trailing.push(0);
+        assert_eq!(
+            Err(LoadError::TrailingBytes(1)),
+            Chunk::from_bytes(&trailing)
+        );
+    }
+
+    #[test]
+    fn test_rejects_invalid_code() {
+        let mut chunk = Chunk {
+            code: vec![Op::Load(0)],
+            ..Chunk::default()
+        };
+        assert_eq!(
+            Err(LoadError::IndexOutOfBounds {
+                pc: 0,
+                table: "names"
+            }),
+            Chunk::from_bytes(&chunk.to_bytes())
+        );
+
+        chunk.code = vec![Op::Nothing, Op::BinaryOperation(BinaryOperation::Plus)];
+        assert_eq!(
+            Err(LoadError::StackUnderflow(1)),
+            Chunk::from_bytes(&chunk.to_bytes())
+        );
+
+        chunk.code = vec![Op::Nothing, Op::Jump(0)];
+        assert_eq!(
+            Err(LoadError::UnbalancedStack(0)),
+            Chunk::from_bytes(&chunk.to_bytes())
+        );
+        assert_eq!(
+            "Instruction 0 is reached with different stack sizes",
+            LoadError::UnbalancedStack(0).to_string()
+        );
+    }
+}
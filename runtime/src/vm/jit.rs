@@ -0,0 +1,92 @@
+//! Compiles the chunk of a [`Vm`] to native code in memory once its loops
+//! are hot, see [`Vm::set_jit_threshold`].
+
+use std::mem;
+use std::rc::Rc;
+
+use cranelift_jit::{JITBuilder, JITModule};
+use cranelift_module::Linkage;
+
+use super::native::{self, NativeCode};
+use super::{Chunk, Vm};
+use crate::RuntimeAdapter;
+
+/// Memory of the native code, freed when dropped.
+struct Code(Option<JITModule>);
+
+impl Drop for Code {
+    fn drop(&mut self) {
+        if let Some(module) = self.0.take() {
+            // SAFETY: the VM that calls the code is being dropped too
+            unsafe { module.free_memory() };
+        }
+    }
+}
+
+/// JIT state of a [`Vm`].
+#[derive(Default)]
+pub(super) struct Jit {
+    threshold: Option<usize>,
+    /// Times a loop jumped back in the interpreter.
+    loops: usize,
+    code: Option<Rc<Code>>,
+    /// Whether compiling failed, e.g. on a host Cranelift doesn't support.
+    failed: bool,
+}
+
+impl Jit {
+    pub(super) fn set_threshold(&mut self, threshold: Option<usize>) {
+        self.threshold = threshold;
+    }
+
+    pub(super) fn is_compiled(&self) -> bool {
+        self.code.is_some()
+    }
+
+    pub(super) fn count_loop(&mut self) {
+        self.loops += 1;
+    }
+
+    fn is_hot(&self) -> bool {
+        !self.failed
+            && self
+                .threshold
+                .is_some_and(|threshold| self.loops >= threshold)
+    }
+}
+
+impl<T: RuntimeAdapter> Vm<T> {
+    /// Compiles the chunk to native code the first time it is hot.
+    pub(super) fn compile_if_hot(&mut self) {
+        if self.jit.code.is_some() || !self.jit.is_hot() {
+            return;
+        }
+
+        match compile::<T>(&self.chunk) {
+            Ok((code, function)) => {
+                self.jit.code = Some(Rc::new(code));
+                self.native = Some(native::Native::new(function, &self.chunk));
+            }
+            Err(_) => self.jit.failed = true,
+        }
+    }
+}
+
+fn compile<T: RuntimeAdapter>(chunk: &Chunk) -> Result<(Code, NativeCode<T>), String> {
+    let mut builder = JITBuilder::with_isa(
+        native::host_isa(false)?,
+        cranelift_module::default_libcall_names(),
+    );
+    builder.symbol(native::EXECUTE, native::execute::<T> as *const u8);
+    let mut module = JITModule::new(builder);
+
+    let function = native::define(&mut module, chunk, "run", Linkage::Local)?;
+    module
+        .finalize_definitions()
+        .map_err(|error| error.to_string())?;
+    // SAFETY: the code was compiled with `execute::<T>`
+    let function: NativeCode<T> =
+        unsafe { mem::transmute(module.get_finalized_function(function)) };
+
+    Ok((Code(Some(module)), function))
+}
@@ -0,0 +1,662 @@
+//! A bytecode compiler and stack-based virtual machine, a faster alternative
+//! to walking the AST with [`Runtime`](crate::Runtime).
+//!
+//! [`compile`] turns a [`Program`](potterscript_parser::Program) into a
+//! [`Chunk`] where literals live in a constant pool and variables are
+//! resolved to slots, and a [`Vm`] runs it with the same semantics and
+//! [`RuntimeAdapter`] calls as the tree walker. Watching variables is only
+//! supported by the tree walker.
+//!
+//! ```
+//! use potterscript_runtime::vm::{compile, Vm};
+//! use potterscript_runtime::TestAdapter;
+//!
+//! let program = potterscript_parser::parse("x = 1\n~Engorgio x\n~Revelio x").unwrap();
+//! let mut vm = Vm::new(compile(&program), TestAdapter::new());
+//! vm.run().unwrap();
+//! vm.adapter().assert_logs(&["2"]);
+//! ```
+
+#[cfg(feature = "aot")]
+pub mod aot;
+mod chunk;
+mod compiler;
+mod file;
+#[cfg(feature = "jit")]
+mod jit;
+#[cfg(any(feature = "jit", feature = "aot"))]
+mod native;
+
+use std::collections::HashMap;
+
+use potterscript_parser::{HogwartsHouse, Spell};
+
+pub use self::chunk::{Chunk, Index, Op};
+pub use self::compiler::{compile, compile_spanned};
+pub use self::file::{LoadError, MAGIC, VERSION};
+use crate::native::NativeSpell;
+use crate::{spells, BindingKind, RuntimeAdapter, RuntimeError, RuntimeValue, SpellTarget};
+
+/// The `expelliarmus` block to run if an error happens in a `protego` block.
+struct Handler {
+    catch: usize,
+    depth: usize,
+    /// The `quidditch` flag when the `protego` block started, put back if a
+    /// loop in it fails.
+    quidditch: bool,
+}
+
+pub struct Vm<T: RuntimeAdapter> {
+    chunk: Chunk,
+    runtime_adapter: T,
+    slots: Vec<Option<(RuntimeValue, BindingKind)>>,
+    spells: HashMap<String, NativeSpell>,
+    stack: Vec<Option<RuntimeValue>>,
+    handlers: Vec<Handler>,
+    pc: usize,
+    quidditch: bool,
+    is_lumos_casted: bool,
+    last_value: Option<RuntimeValue>,
+    steps: usize,
+    max_steps: Option<usize>,
+    #[cfg(feature = "jit")]
+    jit: jit::Jit,
+    #[cfg(any(feature = "jit", feature = "aot"))]
+    native: Option<native::Native<T>>,
+}
+
+impl<T: RuntimeAdapter> Vm<T> {
+    pub fn new(chunk: Chunk, runtime_adapter: T) -> Self {
+        Self {
+            slots: vec![None; chunk.names.len()],
+            chunk,
+            runtime_adapter,
+            spells: HashMap::new(),
+            stack: vec![],
+            handlers: vec![],
+            pc: 0,
+            quidditch: false,
+            is_lumos_casted: false,
+            last_value: None,
+            steps: 0,
+            max_steps: None,
+            #[cfg(feature = "jit")]
+            jit: jit::Jit::default(),
+            #[cfg(any(feature = "jit", feature = "aot"))]
+            native: None,
+        }
+    }
+
+    /// Like [`Runtime::set_max_steps`](crate::Runtime::set_max_steps).
+    pub fn set_max_steps(&mut self, max_steps: Option<usize>) {
+        self.max_steps = max_steps;
+    }
+
+    /// Compiles the chunk to native code with Cranelift once `quidditch`
+    /// loops have jumped back `threshold` times, or before running it with
+    /// `Some(0)`. The default, `None`, only interprets. Native spells, and
+    /// the whole chunk if the host isn't supported, are still interpreted.
+    #[cfg(feature = "jit")]
+    pub fn set_jit_threshold(&mut self, threshold: Option<usize>) {
+        self.jit.set_threshold(threshold);
+    }
+
+    /// Whether the chunk has been compiled to native code.
+    #[cfg(feature = "jit")]
+    pub fn is_jit_compiled(&self) -> bool {
+        self.jit.is_compiled()
+    }
+
+    /// Like [`Runtime::register_spell`](crate::Runtime::register_spell).
+    pub fn register_spell<F>(&mut self, name: &str, target: SpellTarget, handler: F)
+    where
+        F: FnMut(Option<RuntimeValue>) -> Result<Option<RuntimeValue>, RuntimeError> + 'static,
+    {
+        let spell = NativeSpell::new(name, target, Box::new(handler));
+        self.spells.insert(name.to_string(), spell);
+    }
+
+    pub fn adapter(&self) -> &T {
+        &self.runtime_adapter
+    }
+
+    pub fn into_adapter(self) -> T {
+        self.runtime_adapter
+    }
+
+    pub fn chunk(&self) -> &Chunk {
+        &self.chunk
+    }
+
+    /// Value of the variable or constant called `name`.
+    pub fn get(&self, name: &str) -> Option<&RuntimeValue> {
+        let slot = self.chunk.names.iter().position(|n| n == name)?;
+        self.slots[slot].as_ref().map(|(value, _)| value)
+    }
+
+    /// Sets a variable before or after running the program. Only names the
+    /// program uses have a slot, any other fails with
+    /// [`RuntimeError::VariableNotFound`].
+    pub fn set(&mut self, name: &str, value: RuntimeValue) -> Result<(), RuntimeError> {
+        match self.chunk.names.iter().position(|n| n == name) {
+            Some(slot) => self.assign(slot as Index, value),
+            None => Err(RuntimeError::VariableNotFound(name.to_string())),
+        }
+    }
+
+    /// Like [`Runtime::declare_constant`](crate::Runtime::declare_constant),
+    /// only for names the program uses like [`set`](Self::set).
+    pub fn declare_constant(
+        &mut self,
+        name: &str,
+        value: RuntimeValue,
+    ) -> Result<(), RuntimeError> {
+        match self.chunk.names.iter().position(|n| n == name) {
+            Some(slot) => {
+                self.slots[slot] = Some((value, BindingKind::Constant));
+                Ok(())
+            }
+            None => Err(RuntimeError::VariableNotFound(name.to_string())),
+        }
+    }
+
+    /// Like [`Runtime::bindings`](crate::Runtime::bindings).
+    pub fn bindings(&self) -> impl Iterator<Item = (&str, &RuntimeValue, BindingKind)> {
+        self.slots.iter().enumerate().filter_map(|(slot, binding)| {
+            let (value, kind) = binding.as_ref()?;
+            Some((self.chunk.names[slot].as_str(), value, *kind))
+        })
+    }
+
+    /// Like [`Runtime::last_value`](crate::Runtime::last_value).
+    pub fn last_value(&self) -> Option<&RuntimeValue> {
+        self.last_value.as_ref()
+    }
+
+    /// Index of the instruction being executed, or of the one that failed
+    /// after [`run`](Self::run) returns an error.
+    pub fn pc(&self) -> usize {
+        self.pc
+    }
+
+    /// Runs the whole program. Bindings are kept, so running it again sees
+    /// the values the previous run left.
+    ///
+    /// Like [`Runtime::eval`](crate::Runtime::eval), a script ended with
+    /// `~AvadaKedabra` returns [`RuntimeError::Exit`].
+    pub fn run(&mut self) -> Result<(), RuntimeError> {
+        self.stack.clear();
+        self.handlers.clear();
+
+        self.pc = 0;
+        while self.pc < self.chunk.code.len() {
+            #[cfg(any(feature = "jit", feature = "aot"))]
+            {
+                self.run_native()?;
+                if self.pc >= self.chunk.code.len() {
+                    break;
+                }
+            }
+
+            let pc = self.pc;
+            self.pc = match self.execute(pc) {
+                Ok(next) => next,
+                Err(error) => self.catch(error)?,
+            };
+            #[cfg(feature = "jit")]
+            if self.pc < pc {
+                self.jit.count_loop();
+            }
+        }
+        Ok(())
+    }
+
+    /// Jumps to the innermost `expelliarmus` block with the error on the
+    /// stack, returning its first instruction, or fails if there is none.
+    fn catch(&mut self, error: RuntimeError) -> Result<usize, RuntimeError> {
+        match self.handlers.pop() {
+            Some(handler) if error.is_catchable() => {
+                self.stack.truncate(handler.depth);
+                self.stack.push(Some(error.to_value()));
+                self.quidditch = handler.quidditch;
+                Ok(handler.catch)
+            }
+            _ => Err(error),
+        }
+    }
+
+    fn pop(&mut self) -> Option<RuntimeValue> {
+        self.stack
+            .pop()
+            .expect("the stack is never empty when popped")
+    }
+
+    fn name(&self, slot: Index) -> String {
+        self.chunk.names[slot as usize].clone()
+    }
+
+    fn load(&self, slot: Index) -> Result<RuntimeValue, RuntimeError> {
+        match &self.slots[slot as usize] {
+            Some((value, _)) => Ok(value.clone()),
+            None => Err(RuntimeError::VariableNotFound(self.name(slot))),
+        }
+    }
+
+    fn assign(&mut self, slot: Index, value: RuntimeValue) -> Result<(), RuntimeError> {
+        self.check_assignable(slot)?;
+        self.slots[slot as usize] = Some((value, BindingKind::Variable));
+        Ok(())
+    }
+
+    fn check_assignable(&self, slot: Index) -> Result<(), RuntimeError> {
+        match self.slots[slot as usize] {
+            Some((_, BindingKind::Constant)) => {
+                Err(RuntimeError::ConstantReassignment(self.name(slot)))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Executes the instruction at `pc`, returning the next one.
+    fn execute(&mut self, pc: usize) -> Result<usize, RuntimeError> {
+        let mut value = None;
+
+        match self.chunk.code[pc] {
+            Op::Step => {
+                self.steps += 1;
+                if let Some(max_steps) = self.max_steps {
+                    if self.steps > max_steps {
+                        return Err(RuntimeError::StepLimitExceeded(max_steps));
+                    }
+                }
+                return Ok(pc + 1);
+            }
+            Op::ClearLastValue => {
+                self.last_value = None;
+                return Ok(pc + 1);
+            }
+            Op::SetLastValue => {
+                self.last_value = self.pop();
+                return Ok(pc + 1);
+            }
+            Op::Constant(index) => value = Some(self.chunk.constants[index as usize].clone()),
+            Op::Nothing => {}
+            Op::Load(slot) => value = Some(self.load(slot)?),
+            Op::CheckAssignable(slot) => {
+                self.check_assignable(slot)?;
+                return Ok(pc + 1);
+            }
+            Op::Assign(slot) => {
+                match self.pop() {
+                    Some(value) => self.assign(slot, value)?,
+                    None => return Err(RuntimeError::NoneAssignment(self.name(slot))),
+                }
+                return Ok(pc + 1);
+            }
+            Op::Field(field) => {
+                let target = self.pop();
+                let field = &self.chunk.strings[field as usize];
+                value = match target {
+                    Some(RuntimeValue::Map(mut entries)) => match entries.remove(field) {
+                        Some(value) => Some(value),
+                        None => {
+                            return Err(RuntimeError::FieldNotFound(
+                                RuntimeValue::Map(entries),
+                                field.clone(),
+                            ))
+                        }
+                    },
+                    Some(value) => return Err(RuntimeError::FieldNotFound(value, field.clone())),
+                    None => None,
+                };
+            }
+            Op::BinaryOperation(operation) => {
+                let right = self.pop();
+                let left = self.pop();
+                if let (Some(left), Some(right)) = (left, right) {
+                    value = Some(left.binary_operation(operation, right)?);
+                }
+            }
+            Op::SortingHat => {
+                let index = self.runtime_adapter.random_integer(0..=3);
+                value = Some(RuntimeValue::HogwartsHouse(
+                    HogwartsHouse::ALL[index as usize],
+                ));
+            }
+            Op::Jump(to) => return Ok(to as usize),
+            Op::JumpUnlessTrue(to) => {
+                return match self.pop() {
+                    Some(RuntimeValue::Boolean(true)) => Ok(pc + 1),
+                    _ => Ok(to as usize),
+                };
+            }
+            Op::Quidditch => {
+                self.quidditch = true;
+                return Ok(pc + 1);
+            }
+            Op::LoopWhileQuidditch(start) => {
+                return match self.quidditch {
+                    true => Ok(start as usize),
+                    false => Ok(pc + 1),
+                };
+            }
+            Op::Snitch => {
+                self.quidditch = false;
+                return Ok(pc + 1);
+            }
+            Op::Protego(catch) => {
+                self.handlers.push(Handler {
+                    catch: catch as usize,
+                    depth: self.stack.len(),
+                    quidditch: self.quidditch,
+                });
+                return Ok(pc + 1);
+            }
+            Op::EndProtego => {
+                self.handlers.pop();
+                return Ok(pc + 1);
+            }
+            Op::CastNative(name) => {
+                let target = self.pop();
+                let name = &self.chunk.strings[name as usize];
+                value = match self.spells.get_mut(name) {
+                    Some(spell) => spell.cast(name, target)?,
+                    None => return Err(RuntimeError::UnknownSpell(name.clone())),
+                };
+            }
+            Op::Accio => {
+                let prompt = self.pop();
+                value = Some(spells::accio(&self.runtime_adapter, prompt));
+            }
+            Op::AvadaKedabra => return Err(RuntimeError::Exit),
+            Op::Confringo => return Err(spells::confringo(self.pop())),
+            Op::Engorgio(slot) => {
+                let engorged = spells::engorgio(self.load(slot)?)?;
+                self.assign(slot, engorged)?;
+            }
+            Op::FelixFelicis => {
+                let target = self.pop();
+                value = Some(spells::felix_felicis(&self.runtime_adapter, target)?);
+            }
+            Op::Incendio(slot) => {
+                let string = spells::append(Spell::Incendio, self.load(slot)?, "🔥")?;
+                self.assign(slot, string)?;
+            }
+            Op::Inmobolus => {
+                let target = self.pop();
+                spells::inmobolus(&self.runtime_adapter, target);
+            }
+            Op::Lumos => self.is_lumos_casted = true,
+            Op::Nox => self.is_lumos_casted = false,
+            Op::Obliviate(slot) => {
+                if let Some((_, BindingKind::Variable)) = self.slots[slot as usize] {
+                    self.slots[slot as usize] = None;
+                }
+            }
+            Op::Periculum => self.runtime_adapter.log("🔥🔥🔥🔥🔥🔥🔥🔥🔥"),
+            Op::PetrificusTotalus(slot) => {
+                if let Some((_, kind)) = &mut self.slots[slot as usize] {
+                    *kind = BindingKind::Constant;
+                }
+            }
+            Op::Reducio(slot) => {
+                let reduced = spells::reducio(self.load(slot)?)?;
+                self.assign(slot, reduced)?;
+            }
+            Op::Revelio => {
+                let mut string = self.pop().unwrap_or(RuntimeValue::from("")).to_string();
+                if self.is_lumos_casted {
+                    string = self.runtime_adapter.lumos(string);
+                }
+                self.runtime_adapter.log(&string);
+            }
+            Op::UnbreakableVow(vow) => {
+                let value = self.pop();
+                let operands = value.clone().into_iter().collect();
+                spells::unbreakable_vow(&self.chunk.vows[vow as usize], value, operands)?;
+            }
+            Op::UnbreakableVowOperation(vow, operation) => {
+                let right = self.pop();
+                let left = self.pop();
+                let (value, operands) = spells::vow_operation(operation, left, right)?;
+                spells::unbreakable_vow(&self.chunk.vows[vow as usize], value, operands)?;
+            }
+            Op::WingardiumLeviosa(slot) => {
+                let string = spells::append(Spell::WingardiumLeviosa, self.load(slot)?, "\n")?;
+                self.assign(slot, string)?;
+            }
+        }
+
+        self.stack.push(value);
+        Ok(pc + 1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::Path;
+
+    use super::*;
+    use crate::{Runtime, TestAdapter, ValueType};
+
+    /// Snippets covering every statement, spell and error, run by both the
+    /// tree walker and the VM.
+    const SCRIPTS: &[&str] = &[
+        "x = 1\ny = x + 2\nz = y * 3.0",
+        "x = 1\ny = 2.5\nz = x / 0",
+        "x = \"a\" + \"b\"\ny = x == \"ab\"\nz = x != Gryffindor\nx + \"c\"",
+        "x = 3\nx + 4\ny = 1",
+        "if 1 == 1 {\n  ~Revelio \"yes\"\n} else {\n  ~Revelio \"no\"\n}\nif 2 {\n  ~Revelio \"no\"\n}",
+        "i = 0\nquidditch {\n  ~Engorgio i\n  if i == 3 {\n    snitch\n  }\n  ~Revelio i\n}",
+        "i = 0\nquidditch {\n  j = 0\n  quidditch {\n    ~Engorgio j\n    snitch\n  }\n  ~Engorgio i\n  if i != 2 {\n    quidditch {\n      snitch\n    }\n  }\n}",
+        "s = ~Serpensortia\n~WingardiumLeviosa s\n~Incendio s\n~Revelio s\n~Revelio ~Incendio \"a\"\n~Revelio ~WingardiumLeviosa \"b\"\n~Revelio ~Incendio 1",
+        "s = \"Ab\"\n~Engorgio s\nt = s\n~Reducio s\nd = 1.5\n~Reducio d\n~Engorgio 4",
+        "~Revelio ~Aguamenti\n~Revelio ~OculusReparo\n~Periculum\n~Lumos\n~Revelio 1\n~Nox\n~Revelio",
+        "x = 1\n~PetrificusTotalus x\n~Obliviate x\n~Revelio x\n~Engorgio x",
+        "x = 1\n~Obliviate x\n~Revelio x",
+        "x = 1\n~PetrificusTotalus x\nx = y",
+        "x = ~Revelio 1",
+        "x = true\ny = ~Incendio x",
+        "b = true\n~Engorgio b",
+        "~Revelio 🎩✨\nh = SortingHat\n~Revelio h == Hufflepuff",
+        "name = ~Accio \"Name? \"\nrest = ~Accio\n~Revelio name",
+        "a = ~FelixFelicis 6\nb = ~FelixFelicis\nc = ~FelixFelicis 2.0\n~FelixFelicis \"x\"",
+        "~Inmobolus 10\n~Inmobolus \"10\"",
+        "~Inmobolus",
+        "~Confringo",
+        "x = ~Twice 21\n~Revelio x\n~Revelio ~Answer",
+        "~Twice \"a\"",
+        "~Expecto",
+        "~UnbreakableVow 2 == 2\n~UnbreakableVow 1 + 1",
+        "x = 2\n~UnbreakableVow x == 3",
+        "done = false\n~UnbreakableVow done",
+        "~UnbreakableVow",
+        "~UnbreakableVow ~Revelio 1",
+        "vow \"skipped\" {\n  ~Revelio \"never\"\n}\n~Revelio \"ran\"",
+        "protego {\n  ~Revelio 1\n  ~Revelio 1 / 0\n  ~Revelio 2\n} expelliarmus err {\n  ~Revelio err.kind\n  ~Revelio err.message\n}\n~Revelio err",
+        "protego {\n  protego {\n    ~Confringo \"inner\"\n  } expelliarmus e {\n    ~Confringo e.message + \"!\"\n  }\n} expelliarmus e {\n  ~Revelio e\n}",
+        "protego {\n  ~Revelio 1\n} expelliarmus e {\n  ~Revelio \"unreachable\"\n}\n~Revelio e",
+        "protego {\n  ~Revelio missing\n} expelliarmus e {\n  ~Revelio e.code\n}",
+        "protego {\n  ~UnbreakableVow false\n} expelliarmus e {\n  ~Revelio e\n}",
+        "i = 0\nprotego {\n  quidditch {\n    ~Engorgio i\n    if i == 3 {\n      ~Confringo i\n    }\n  }\n} expelliarmus e {\n  ~Revelio e.message\n}",
+        "quidditch {\n  protego {\n    ~Revelio 1\n  } expelliarmus e {\n    ~Revelio e\n  }\n}",
+        "i = 0\nquidditch {\n  snitch\n  protego {\n    quidditch {\n      ~Confringo i\n    }\n  } expelliarmus e {\n    ~Engorgio i\n  }\n}\nquidditch {\n  ~Engorgio i\n  if i == 3 {\n    snitch\n  }\n}",
+        "x = 1\n~Revelio x.kind",
+        "# just a comment\nx = 1 # and another",
+        "a = 0.0\nb = -0.0\n~Revelio b\n~Revelio 1.0 / b",
+    ];
+
+    #[derive(Debug, PartialEq)]
+    struct Outcome {
+        logs: Vec<String>,
+        result: Result<(), RuntimeError>,
+        last_value: Option<RuntimeValue>,
+        bindings: Vec<(String, Option<RuntimeValue>)>,
+    }
+
+    fn adapter(inputs: &[String]) -> TestAdapter {
+        TestAdapter::new()
+            .with_integers((0..4).cycle())
+            .with_doubles([0.25, 0.5, 0.75])
+            .with_inputs(inputs.iter().cloned())
+    }
+
+    fn twice(target: Option<RuntimeValue>) -> Result<Option<RuntimeValue>, RuntimeError> {
+        match target {
+            Some(RuntimeValue::Integer(value)) => Ok(Some(RuntimeValue::Integer(value * 2))),
+            _ => unreachable!(),
+        }
+    }
+
+    fn answer(_: Option<RuntimeValue>) -> Result<Option<RuntimeValue>, RuntimeError> {
+        Ok(Some(RuntimeValue::Integer(42)))
+    }
+
+    fn assert_same_outcome(code: &str, inputs: &[String]) {
+        let program = potterscript_parser::parse(code).unwrap();
+        let chunk = compile(&program);
+
+        let mut runtime = Runtime::new(adapter(inputs));
+        runtime.set_max_steps(Some(1000));
+        runtime.register_spell("Twice", SpellTarget::Type(ValueType::Integer), twice);
+        runtime.register_spell("Answer", SpellTarget::None, answer);
+        let result = runtime.eval(&program);
+        let expected = Outcome {
+            logs: runtime.adapter().take_logs(),
+            result,
+            last_value: runtime.last_value().cloned(),
+            bindings: chunk
+                .names
+                .iter()
+                .map(|name| (name.clone(), runtime.get(name).cloned()))
+                .collect(),
+        };
+
+        // Through the file format, to check its loader accepts compiled code
+        let chunk = Chunk::from_bytes(&chunk.to_bytes()).unwrap();
+        let actual = run_vm(Vm::new(chunk.clone(), adapter(inputs)));
+        assert_eq!(expected, actual, "{}", code);
+
+        // Compiled before running, and once a loop jumped back twice
+        #[cfg(feature = "jit")]
+        for threshold in [0, 2] {
+            let mut vm = Vm::new(chunk.clone(), adapter(inputs));
+            vm.set_jit_threshold(Some(threshold));
+            let actual = run_vm(vm);
+            assert_eq!(expected, actual, "JIT after {} loops: {}", threshold, code);
+        }
+    }
+
+    fn run_vm(mut vm: Vm<TestAdapter>) -> Outcome {
+        vm.set_max_steps(Some(1000));
+        vm.register_spell("Twice", SpellTarget::Type(ValueType::Integer), twice);
+        vm.register_spell("Answer", SpellTarget::None, answer);
+        let result = vm.run();
+        Outcome {
+            logs: vm.adapter().take_logs(),
+            result,
+            last_value: vm.last_value().cloned(),
+            bindings: vm
+                .chunk()
+                .names
+                .iter()
+                .map(|name| (name.clone(), vm.get(name).cloned()))
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn test_same_as_tree_walker() {
+        let inputs = vec!["Dobby".to_string()];
+        for code in SCRIPTS {
+            assert_same_outcome(code, &inputs);
+        }
+    }
+
+    #[test]
+    fn test_golden_scripts_same_as_tree_walker() {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/golden");
+        for entry in fs::read_dir(dir).unwrap() {
+            let path = entry.unwrap().path();
+            if path
+                .extension()
+                .is_some_and(|extension| extension == "potter")
+            {
+                let code = fs::read_to_string(&path).unwrap();
+                let inputs: Vec<String> = fs::read_to_string(path.with_extension("in"))
+                    .map(|inputs| inputs.lines().map(String::from).collect())
+                    .unwrap_or_default();
+                assert_same_outcome(&code, &inputs);
+            }
+        }
+    }
+
+    #[test]
+    fn test_step_limit() {
+        let program = potterscript_parser::parse("quidditch {\n  x = 1\n}").unwrap();
+        let mut vm = Vm::new(compile(&program), TestAdapter::new());
+        vm.set_max_steps(Some(10));
+        assert_eq!(Err(RuntimeError::StepLimitExceeded(10)), vm.run());
+    }
+
+    #[test]
+    fn test_host_bindings() {
+        let program = potterscript_parser::parse("total = price * 2").unwrap();
+        let chunk = compile(&program);
+        assert_eq!(vec!["total", "price"], chunk.names);
+        assert_eq!(vec![RuntimeValue::Integer(2)], chunk.constants);
+
+        let mut vm = Vm::new(chunk, TestAdapter::new());
+        vm.set("price", RuntimeValue::Integer(21)).unwrap();
+        vm.run().unwrap();
+        assert_eq!(Some(&RuntimeValue::Integer(42)), vm.get("total"));
+        assert_eq!(
+            Err(RuntimeError::VariableNotFound("other".to_string())),
+            vm.set("other", RuntimeValue::Integer(1))
+        );
+
+        vm.declare_constant("price", RuntimeValue::Integer(1))
+            .unwrap();
+        vm.run().unwrap();
+        let mut bindings: Vec<_> = vm.bindings().collect();
+        bindings.sort_by_key(|(name, _, _)| *name);
+        assert_eq!(
+            vec![
+                ("price", &RuntimeValue::Integer(1), BindingKind::Constant),
+                ("total", &RuntimeValue::Integer(2), BindingKind::Variable),
+            ],
+            bindings
+        );
+    }
+
+    #[cfg(feature = "jit")]
+    #[test]
+    fn test_jit_hot_loop() {
+        let program = potterscript_parser::parse(
+            "i = 0\nquidditch {\n  ~Engorgio i\n  if i == 5 {\n    snitch\n  }\n}\n~Revelio i",
+        )
+        .unwrap();
+        let mut vm = Vm::new(compile(&program), TestAdapter::new());
+        vm.set_jit_threshold(Some(3));
+        vm.run().unwrap();
+        assert!(vm.is_jit_compiled());
+        vm.adapter().assert_logs(&["5"]);
+
+        let mut vm = Vm::new(compile(&program), TestAdapter::new());
+        vm.set_jit_threshold(Some(10));
+        vm.run().unwrap();
+        assert!(!vm.is_jit_compiled());
+    }
+
+    #[cfg(feature = "jit")]
+    #[test]
+    #[should_panic(expected = "random integer")]
+    fn test_jit_adapter_panic_unwinds() {
+        // The adapter has no integers queued for the Sorting Hat
+        let program = potterscript_parser::parse("i = 1 + 2\n~Revelio 🎩✨").unwrap();
+        let mut vm = Vm::new(compile(&program), TestAdapter::new());
+        vm.set_jit_threshold(Some(0));
+        let _ = vm.run();
+    }
+}
@@ -0,0 +1,852 @@
+//! Native code for a [`Chunk`], generated with Cranelift by the JIT and by
+//! the ahead-of-time compiler.
+//!
+//! Integers, booleans and empty values are unboxed to a tag and a payload:
+//! on the stack they live in registers, and in variables in a table the
+//! native code reads and writes directly. Loading constants and variables,
+//! assigning them, integer arithmetic, comparisons, `~Engorgio`, `~Reducio`,
+//! the step limit and control flow run as native code on them. Every other
+//! instruction, and one whose operands are of another type, calls back into
+//! the VM, which runs it with the same semantics and [`RuntimeAdapter`]
+//! calls as the interpreter. Casting a native spell is left to the
+//! interpreter.
+
+use std::any::Any;
+use std::mem::{self, offset_of};
+use std::panic::{self, AssertUnwindSafe};
+
+use cranelift_codegen::ir::condcodes::IntCC;
+use cranelift_codegen::ir::{self, types, AbiParam, Block, FuncRef, InstBuilder, MemFlags};
+use cranelift_codegen::isa::OwnedTargetIsa;
+use cranelift_codegen::settings::{self, Configurable};
+use cranelift_frontend::{FunctionBuilder, FunctionBuilderContext, Switch, Variable};
+use cranelift_module::{FuncId, Linkage, Module};
+use potterscript_parser::BinaryOperation;
+
+use super::{file, Chunk, Index, Op, Vm};
+use crate::{BindingKind, RuntimeAdapter, RuntimeError, RuntimeValue};
+
+/// Name of the function native code calls to execute an instruction.
+pub(super) const EXECUTE: &str = "potter_execute";
+
+/// Returned by [`execute`] when the instruction failed.
+const FAILED: usize = usize::MAX;
+
+/// Tags of an [`Unboxed`] value. A boxed one is left on the stack of the VM,
+/// or in the slot of a constant or an unset variable. A variable holding a
+/// boxed value is tagged [`VARIABLE`] instead, so it can be assigned to.
+const VARIABLE: i64 = -1;
+const BOXED: i64 = 0;
+const EMPTY: i64 = 1;
+const INTEGER: i64 = 2;
+const BOOLEAN: i64 = 3;
+
+/// A value as native code sees it.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+struct Unboxed {
+    tag: i64,
+    payload: i64,
+}
+
+impl Unboxed {
+    const BOXED: Self = Self {
+        tag: BOXED,
+        payload: 0,
+    };
+
+    fn new(value: &Option<RuntimeValue>) -> Self {
+        match value {
+            Some(value) => Self::of(value),
+            None => Self {
+                tag: EMPTY,
+                payload: 0,
+            },
+        }
+    }
+
+    fn of(value: &RuntimeValue) -> Self {
+        match value {
+            RuntimeValue::Integer(value) => Self {
+                tag: INTEGER,
+                payload: *value,
+            },
+            RuntimeValue::Boolean(value) => Self {
+                tag: BOOLEAN,
+                payload: *value as i64,
+            },
+            _ => Self::BOXED,
+        }
+    }
+
+    /// The value, unless it is boxed.
+    fn value(self) -> Option<RuntimeValue> {
+        match self.tag {
+            INTEGER => Some(RuntimeValue::Integer(self.payload)),
+            BOOLEAN => Some(RuntimeValue::Boolean(self.payload != 0)),
+            _ => None,
+        }
+    }
+}
+
+/// The part of the [`Context`] native code reads and writes directly.
+#[repr(C)]
+struct State {
+    steps: u64,
+    /// `u64::MAX` without a limit.
+    max_steps: u64,
+    quidditch: u64,
+    /// The last value, unless it is boxed and then on the VM.
+    last_value: Unboxed,
+    /// Stack size at the instruction native code calls back for or stops
+    /// at.
+    depth: u64,
+    /// Unboxed variables, by slot. Only variables (not constants) holding
+    /// integers or booleans are unboxed, and then this is their value.
+    /// Other variables are tagged [`VARIABLE`].
+    slots: *mut Unboxed,
+    /// The stack, where the boxed values are the ones left on the stack of
+    /// the VM, in order.
+    stack: *mut Unboxed,
+}
+
+/// What native code and [`execute`] share while it runs.
+#[repr(C)]
+pub struct Context<'v, T: RuntimeAdapter> {
+    state: State,
+    vm: &'v mut Vm<T>,
+    error: Option<RuntimeError>,
+    /// A panic caught in [`execute`], resumed once native code returned.
+    panic: Option<Box<dyn Any + Send>>,
+}
+
+/// Native code of a chunk. Runs it from an instruction until it ends, fails,
+/// or reaches one left to the interpreter, returning the index of that
+/// instruction.
+pub type NativeCode<T> = unsafe extern "C-unwind" fn(*mut Context<T>, usize) -> usize;
+
+/// Native code with the memory it keeps unboxed values in.
+pub(super) struct Native<T: RuntimeAdapter> {
+    code: NativeCode<T>,
+    slots: Vec<Unboxed>,
+    stack: Vec<Unboxed>,
+}
+
+/// How native code returned.
+enum Return {
+    /// At the end of the chunk or an instruction to interpret.
+    Stopped(usize),
+    /// At an instruction that failed.
+    Failed(usize, RuntimeError),
+    Panicked(Box<dyn Any + Send>),
+}
+
+impl<T: RuntimeAdapter> Native<T> {
+    pub(super) fn new(code: NativeCode<T>, chunk: &Chunk) -> Self {
+        let depth = file::verify(chunk)
+            .ok()
+            .and_then(|depths| depths.into_iter().flatten().max())
+            .unwrap_or(chunk.code.len());
+        Self {
+            code,
+            slots: vec![Unboxed::BOXED; chunk.names.len()],
+            stack: vec![Unboxed::BOXED; depth],
+        }
+    }
+}
+
+/// The variable slot an instruction reads or writes, if any.
+fn slot_of(op: &Op) -> Option<Index> {
+    match *op {
+        Op::Load(slot)
+        | Op::CheckAssignable(slot)
+        | Op::Assign(slot)
+        | Op::Engorgio(slot)
+        | Op::Incendio(slot)
+        | Op::Obliviate(slot)
+        | Op::PetrificusTotalus(slot)
+        | Op::Reducio(slot)
+        | Op::WingardiumLeviosa(slot) => Some(slot),
+        _ => None,
+    }
+}
+
+impl<T: RuntimeAdapter> Context<'_, T> {
+    /// Writes an unboxed variable back to the VM.
+    fn box_slot(&mut self, slot: usize) {
+        // SAFETY: the table has a value per slot
+        let unboxed = unsafe { *self.state.slots.add(slot) };
+        if let Some(value) = unboxed.value() {
+            self.vm.slots[slot] = Some((value, BindingKind::Variable));
+        }
+    }
+
+    fn unbox_slot(&mut self, slot: usize) {
+        let unboxed = match &self.vm.slots[slot] {
+            Some((value, BindingKind::Variable)) => match Unboxed::of(value) {
+                Unboxed { tag: BOXED, .. } => Unboxed {
+                    tag: VARIABLE,
+                    payload: 0,
+                },
+                unboxed => unboxed,
+            },
+            _ => Unboxed::BOXED,
+        };
+        // SAFETY: the table has a value per slot
+        unsafe { *self.state.slots.add(slot) = unboxed };
+    }
+
+    /// Writes an unboxed last value back to the VM.
+    fn box_last_value(&mut self) {
+        if self.state.last_value.tag != BOXED {
+            self.vm.last_value = self.state.last_value.value();
+            self.state.last_value = Unboxed::BOXED;
+        }
+    }
+
+    /// Moves the stack from entry `from` up onto the stack of the VM,
+    /// returning how many values the VM keeps for the entries below it.
+    fn box_stack(&mut self, from: usize) -> usize {
+        let depth = self.state.depth as usize;
+        // SAFETY: the stack is never deeper than the memory for it
+        let unboxed = |index| unsafe { *self.state.stack.add(index) };
+        let above = (from..depth)
+            .filter(|&index| unboxed(index).tag == BOXED)
+            .count();
+        let below = self.vm.stack.len() - above;
+        // The boxed values are already in place, in order
+        for (position, index) in (below..).zip(from..depth) {
+            let unboxed = unboxed(index);
+            if unboxed.tag != BOXED {
+                self.vm.stack.insert(position, unboxed.value());
+            }
+        }
+        below
+    }
+
+    /// Moves the integers, booleans and empty values off the stack of the
+    /// VM from entry `from` up, leaving the other values there. `below` is
+    /// how many values the VM keeps for the entries below it.
+    fn unbox_stack(&mut self, from: usize, below: usize) {
+        let mut boxed = below;
+        for index in below..self.vm.stack.len() {
+            let unboxed = Unboxed::new(&self.vm.stack[index]);
+            if unboxed.tag == BOXED {
+                self.vm.stack.swap(boxed, index);
+                boxed += 1;
+            }
+            // SAFETY: the stack is never deeper than the memory for it
+            unsafe { *self.state.stack.add(from + index - below) = unboxed };
+        }
+        self.state.depth = (from + self.vm.stack.len() - below) as u64;
+        self.vm.stack.truncate(boxed);
+    }
+}
+
+/// Executes the instruction at `pc` for native code, returning the next one,
+/// or `usize::MAX` if it failed or panicked.
+///
+/// # Safety
+///
+/// `context` must be the one the native code was called with.
+pub unsafe extern "C-unwind" fn execute<T: RuntimeAdapter>(
+    context: *mut Context<T>,
+    pc: usize,
+) -> usize {
+    // SAFETY: nothing else uses the context during the call
+    let context = unsafe { &mut *context };
+    let slot = slot_of(&context.vm.chunk.code[pc]).map(|slot| slot as usize);
+
+    context.vm.steps = context.state.steps as usize;
+    context.vm.quidditch = context.state.quidditch != 0;
+    context.box_last_value();
+    if let Some(slot) = slot {
+        context.box_slot(slot);
+    }
+    // Only the operands move to the VM, except for `protego`, which keeps
+    // the size of the whole stack for the `expelliarmus` block
+    let op = &context.vm.chunk.code[pc];
+    let from = match op {
+        Op::Protego(_) => 0,
+        _ => context.state.depth as usize - op.stack_effect().0,
+    };
+    let below = context.box_stack(from);
+
+    // Native code has no unwind info, so a panicking adapter unwinds from
+    // `Vm::run_native` instead
+    match panic::catch_unwind(AssertUnwindSafe(|| context.vm.execute(pc))) {
+        Ok(Ok(next)) => {
+            if let Some(slot) = slot {
+                context.unbox_slot(slot);
+            }
+            context.unbox_stack(from, below);
+            context.state.steps = context.vm.steps as u64;
+            context.state.quidditch = context.vm.quidditch as u64;
+            next
+        }
+        Ok(Err(error)) => {
+            // The `expelliarmus` block may need the entries below as well
+            context.state.depth = from as u64;
+            let above = context.vm.stack.split_off(below);
+            context.box_stack(0);
+            context.vm.stack.extend(above);
+            context.error = Some(error);
+            FAILED
+        }
+        Err(payload) => {
+            context.panic = Some(payload);
+            FAILED
+        }
+    }
+}
+
+impl<T: RuntimeAdapter> Vm<T> {
+    /// Runs native code from the current instruction, compiling the chunk
+    /// first if the JIT finds it hot. Returns with the VM at the end of the
+    /// chunk or at an instruction to interpret, right away if there is no
+    /// native code.
+    pub(super) fn run_native(&mut self) -> Result<(), RuntimeError> {
+        #[cfg(feature = "jit")]
+        self.compile_if_hot();
+        let Some(mut native) = self.native.take() else {
+            return Ok(());
+        };
+
+        let result = loop {
+            match self.call_native(&mut native) {
+                Return::Stopped(stop) => {
+                    self.pc = stop;
+                    break Ok(());
+                }
+                Return::Failed(stop, error) => {
+                    self.pc = stop;
+                    match self.catch(error) {
+                        Ok(catch) => self.pc = catch,
+                        Err(error) => break Err(error),
+                    }
+                }
+                Return::Panicked(payload) => {
+                    self.native = Some(native);
+                    panic::resume_unwind(payload);
+                }
+            }
+        };
+        self.native = Some(native);
+        result
+    }
+
+    /// Calls the native code from the current instruction with the VM
+    /// unboxed, boxing it again once it returns.
+    fn call_native(&mut self, native: &mut Native<T>) -> Return {
+        let pc = self.pc;
+        let slots = self.slots.len();
+        let mut context = Context {
+            state: State {
+                steps: self.steps as u64,
+                max_steps: self
+                    .max_steps
+                    .map_or(u64::MAX, |max_steps| max_steps as u64),
+                quidditch: self.quidditch as u64,
+                last_value: Unboxed::BOXED,
+                depth: 0,
+                slots: native.slots.as_mut_ptr(),
+                stack: native.stack.as_mut_ptr(),
+            },
+            vm: &mut *self,
+            error: None,
+            panic: None,
+        };
+        for slot in 0..slots {
+            context.unbox_slot(slot);
+        }
+        context.unbox_stack(0, 0);
+
+        // SAFETY: the code was compiled for this chunk, and the context
+        // outlives the call
+        let stop = unsafe { (native.code)(&mut context, pc) };
+
+        // A failed instruction left the whole stack on the VM
+        if context.error.is_none() && context.panic.is_none() {
+            context.box_stack(0);
+        }
+        for slot in 0..slots {
+            context.box_slot(slot);
+        }
+        context.box_last_value();
+        context.vm.steps = context.state.steps as usize;
+        context.vm.quidditch = context.state.quidditch != 0;
+        match (context.error, context.panic) {
+            (_, Some(payload)) => Return::Panicked(payload),
+            (Some(error), None) => Return::Failed(stop, error),
+            (None, None) => Return::Stopped(stop),
+        }
+    }
+}
+
+/// Cranelift target for the host, or for the object files linked on it
+/// when `pic`.
+pub(super) fn host_isa(pic: bool) -> Result<OwnedTargetIsa, String> {
+    let mut flags = settings::builder();
+    flags
+        .set("opt_level", "speed")
+        .map_err(|error| error.to_string())?;
+    if pic {
+        flags.enable("is_pic").map_err(|error| error.to_string())?;
+    }
+    cranelift_native::builder()?
+        .finish(settings::Flags::new(flags))
+        .map_err(|error| error.to_string())
+}
+
+/// Defines the [`NativeCode`] of `chunk` in `module` as `name`, calling
+/// [`EXECUTE`] for the instructions it doesn't run itself.
+pub(super) fn define<M: Module>(
+    module: &mut M,
+    chunk: &Chunk,
+    name: &str,
+    linkage: Linkage,
+) -> Result<FuncId, String> {
+    let depths = file::verify(chunk).map_err(|error| error.to_string())?;
+    let pointer = module.target_config().pointer_type();
+
+    // Native code and `execute` both take the context and an instruction
+    // index, and return an instruction index
+    let mut signature = module.make_signature();
+    signature.params.push(AbiParam::new(pointer));
+    signature.params.push(AbiParam::new(pointer));
+    signature.returns.push(AbiParam::new(pointer));
+    let function = module
+        .declare_function(name, linkage, &signature)
+        .map_err(|error| error.to_string())?;
+    let execute = module
+        .declare_function(EXECUTE, Linkage::Import, &signature)
+        .map_err(|error| error.to_string())?;
+
+    let mut context = module.make_context();
+    context.func.signature = signature;
+    let mut builder_context = FunctionBuilderContext::new();
+    let mut builder = FunctionBuilder::new(&mut context.func, &mut builder_context);
+    let execute = module.declare_func_in_func(execute, builder.func);
+
+    // A block per instruction, and one past the last
+    let code = &chunk.code;
+    let blocks: Vec<_> = (0..=code.len()).map(|_| builder.create_block()).collect();
+    let exit = builder.create_block();
+    builder.append_block_param(exit, pointer);
+
+    // A tag and a payload variable for every entry of the stack
+    let max_depth = depths.iter().flatten().max().copied().unwrap_or(0);
+    let stack: Vec<_> = (0..max_depth)
+        .map(|index| {
+            let tag = Variable::from_u32(2 * index as u32);
+            let payload = Variable::from_u32(2 * index as u32 + 1);
+            builder.declare_var(tag, types::I64);
+            builder.declare_var(payload, types::I64);
+            (tag, payload)
+        })
+        .collect();
+
+    let entry = builder.create_block();
+    builder.append_block_params_for_function_params(entry);
+    builder.switch_to_block(entry);
+    let context_pointer = builder.block_params(entry)[0];
+    let start = builder.block_params(entry)[1];
+    let flags = MemFlags::trusted();
+    let slots = builder.ins().load(
+        pointer,
+        flags,
+        context_pointer,
+        offset_of!(State, slots) as i32,
+    );
+    let stack_pointer = builder.ins().load(
+        pointer,
+        flags,
+        context_pointer,
+        offset_of!(State, stack) as i32,
+    );
+
+    let mut translator = Translator {
+        builder,
+        chunk,
+        depths: &depths,
+        blocks,
+        exit,
+        execute,
+        pointer,
+        context: context_pointer,
+        slots,
+        stack_pointer,
+        stack,
+    };
+    translator.reload(max_depth);
+    let mut switch = Switch::new();
+    for (pc, block) in translator.blocks.iter().enumerate() {
+        switch.set_entry(pc as u128, *block);
+    }
+    switch.emit(
+        &mut translator.builder,
+        start,
+        translator.blocks[code.len()],
+    );
+
+    for pc in 0..code.len() {
+        translator.builder.switch_to_block(translator.blocks[pc]);
+        translator.translate(pc);
+    }
+    translator
+        .builder
+        .switch_to_block(translator.blocks[code.len()]);
+    translator.stop(code.len());
+
+    let mut builder = translator.builder;
+    builder.switch_to_block(exit);
+    let stop = builder.block_params(exit)[0];
+    builder.ins().return_(&[stop]);
+
+    builder.seal_all_blocks();
+    builder.finalize();
+
+    module
+        .define_function(function, &mut context)
+        .map_err(|error| error.to_string())?;
+    module.clear_context(&mut context);
+    Ok(function)
+}
+
+/// Translates the instructions of a chunk to Cranelift IR.
+struct Translator<'a, 'f> {
+    builder: FunctionBuilder<'f>,
+    chunk: &'a Chunk,
+    depths: &'a [Option<usize>],
+    blocks: Vec<Block>,
+    exit: Block,
+    execute: FuncRef,
+    pointer: ir::Type,
+    context: ir::Value,
+    slots: ir::Value,
+    stack_pointer: ir::Value,
+    /// The tag and payload variables of every entry of the stack.
+    stack: Vec<(Variable, Variable)>,
+}
+
+impl Translator<'_, '_> {
+    fn translate(&mut self, pc: usize) {
+        let Some(depth) = self.depths[pc] else {
+            // Unreachable
+            return self.stop(pc);
+        };
+        let next = self.blocks[pc + 1];
+
+        match self.chunk.code[pc] {
+            Op::ClearLastValue => {
+                let tag = self.builder.ins().iconst(types::I64, EMPTY);
+                self.store_state(offset_of!(State, last_value), tag);
+                self.builder.ins().jump(next, &[]);
+            }
+            Op::SetLastValue => {
+                let (tag, payload) = self.peek(depth - 1);
+                let boxed = self.builder.ins().icmp_imm(IntCC::Equal, tag, BOXED);
+                self.fast_path_then(pc, boxed);
+                let last_value = offset_of!(State, last_value);
+                self.store_state(last_value, tag);
+                self.store_state(last_value + 8, payload);
+                self.builder.ins().jump(next, &[]);
+            }
+            Op::Step => {
+                let steps = self.load_state(offset_of!(State, steps));
+                let max_steps = self.load_state(offset_of!(State, max_steps));
+                let steps = self.builder.ins().iadd_imm(steps, 1);
+                let exceeded =
+                    self.builder
+                        .ins()
+                        .icmp(IntCC::UnsignedGreaterThan, steps, max_steps);
+                self.fast_path_then(pc, exceeded);
+                self.store_state(offset_of!(State, steps), steps);
+                self.builder.ins().jump(next, &[]);
+            }
+            Op::Constant(index) => match self.chunk.constants[index as usize] {
+                RuntimeValue::Integer(value) => {
+                    let payload = self.builder.ins().iconst(types::I64, value);
+                    self.push_tagged(depth, INTEGER, payload);
+                    self.builder.ins().jump(next, &[]);
+                }
+                RuntimeValue::Boolean(value) => {
+                    let payload = self.builder.ins().iconst(types::I64, value as i64);
+                    self.push_tagged(depth, BOOLEAN, payload);
+                    self.builder.ins().jump(next, &[]);
+                }
+                _ => self.fall_back(pc),
+            },
+            Op::Nothing => {
+                let payload = self.builder.ins().iconst(types::I64, 0);
+                self.push_tagged(depth, EMPTY, payload);
+                self.builder.ins().jump(next, &[]);
+            }
+            Op::Load(slot) => {
+                let (tag, payload) = self.load_slot(slot);
+                let boxed = self
+                    .builder
+                    .ins()
+                    .icmp_imm(IntCC::SignedLessThan, tag, INTEGER);
+                self.fast_path_then(pc, boxed);
+                self.push(depth, tag, payload);
+                self.builder.ins().jump(next, &[]);
+            }
+            Op::CheckAssignable(slot) => {
+                let (tag, _) = self.load_slot(slot);
+                let boxed = self.builder.ins().icmp_imm(IntCC::Equal, tag, BOXED);
+                self.fast_path_then(pc, boxed);
+                self.builder.ins().jump(next, &[]);
+            }
+            Op::Assign(slot) => {
+                let (tag, payload) = self.peek(depth - 1);
+                let (slot_tag, _) = self.load_slot(slot);
+                let boxed_slot = self.builder.ins().icmp_imm(IntCC::Equal, slot_tag, BOXED);
+                let not_unboxed = self
+                    .builder
+                    .ins()
+                    .icmp_imm(IntCC::SignedLessThan, tag, INTEGER);
+                let slow = self.builder.ins().bor(boxed_slot, not_unboxed);
+                self.fast_path_then(pc, slow);
+                self.store_slot(slot, tag, payload);
+                self.builder.ins().jump(next, &[]);
+            }
+            Op::BinaryOperation(operation) => {
+                let (left_tag, left) = self.peek(depth - 2);
+                let (right_tag, right) = self.peek(depth - 1);
+                match operation {
+                    BinaryOperation::Plus | BinaryOperation::Minus | BinaryOperation::Times => {
+                        let left_integer =
+                            self.builder.ins().icmp_imm(IntCC::Equal, left_tag, INTEGER);
+                        let right_integer =
+                            self.builder
+                                .ins()
+                                .icmp_imm(IntCC::Equal, right_tag, INTEGER);
+                        let integers = self.builder.ins().band(left_integer, right_integer);
+                        let slow = self.builder.ins().bxor_imm(integers, 1);
+                        self.fast_path_then(pc, slow);
+                        // Integer arithmetic wraps like in the interpreter
+                        let value = match operation {
+                            BinaryOperation::Plus => self.builder.ins().iadd(left, right),
+                            BinaryOperation::Minus => self.builder.ins().isub(left, right),
+                            _ => self.builder.ins().imul(left, right),
+                        };
+                        self.push_tagged(depth - 2, INTEGER, value);
+                        self.builder.ins().jump(next, &[]);
+                    }
+                    BinaryOperation::Equal | BinaryOperation::NotEqual => {
+                        let left_boxed =
+                            self.builder
+                                .ins()
+                                .icmp_imm(IntCC::SignedLessThan, left_tag, INTEGER);
+                        let right_boxed =
+                            self.builder
+                                .ins()
+                                .icmp_imm(IntCC::SignedLessThan, right_tag, INTEGER);
+                        let slow = self.builder.ins().bor(left_boxed, right_boxed);
+                        self.fast_path_then(pc, slow);
+                        // An integer is never equal to a boolean
+                        let same_tag = self.builder.ins().icmp(IntCC::Equal, left_tag, right_tag);
+                        let same_payload = self.builder.ins().icmp(IntCC::Equal, left, right);
+                        let mut equal = self.builder.ins().band(same_tag, same_payload);
+                        if operation == BinaryOperation::NotEqual {
+                            equal = self.builder.ins().bxor_imm(equal, 1);
+                        }
+                        let value = self.builder.ins().uextend(types::I64, equal);
+                        self.push_tagged(depth - 2, BOOLEAN, value);
+                        self.builder.ins().jump(next, &[]);
+                    }
+                    BinaryOperation::Divide => self.fall_back(pc),
+                }
+            }
+            Op::Jump(to) => {
+                self.builder.ins().jump(self.blocks[to as usize], &[]);
+            }
+            Op::JumpUnlessTrue(to) => {
+                let target = self.blocks[to as usize];
+                let (tag, payload) = self.peek(depth - 1);
+                let boxed = self.builder.ins().icmp_imm(IntCC::Equal, tag, BOXED);
+                let slow = self.builder.create_block();
+                let fast = self.builder.create_block();
+                self.builder.ins().brif(boxed, slow, &[], fast, &[]);
+
+                self.builder.switch_to_block(slow);
+                let after = self.call_back(pc);
+                let goes_on = self
+                    .builder
+                    .ins()
+                    .icmp_imm(IntCC::Equal, after, pc as i64 + 1);
+                self.builder.ins().brif(goes_on, next, &[], target, &[]);
+
+                self.builder.switch_to_block(fast);
+                let boolean = self.builder.ins().icmp_imm(IntCC::Equal, tag, BOOLEAN);
+                let set = self.builder.ins().icmp_imm(IntCC::NotEqual, payload, 0);
+                let is_true = self.builder.ins().band(boolean, set);
+                self.builder.ins().brif(is_true, next, &[], target, &[]);
+            }
+            Op::Quidditch | Op::Snitch => {
+                let flag = matches!(self.chunk.code[pc], Op::Quidditch) as i64;
+                let flag = self.builder.ins().iconst(types::I64, flag);
+                self.store_state(offset_of!(State, quidditch), flag);
+                self.builder.ins().jump(next, &[]);
+            }
+            Op::LoopWhileQuidditch(start) => {
+                let flag = self.load_state(offset_of!(State, quidditch));
+                self.builder
+                    .ins()
+                    .brif(flag, self.blocks[start as usize], &[], next, &[]);
+            }
+            Op::Engorgio(slot) | Op::Reducio(slot) => {
+                let (tag, payload) = self.load_slot(slot);
+                let integer = self.builder.ins().icmp_imm(IntCC::Equal, tag, INTEGER);
+                let slow = self.builder.ins().bxor_imm(integer, 1);
+                self.fast_path_then(pc, slow);
+                let step = match self.chunk.code[pc] {
+                    Op::Engorgio(_) => 1,
+                    _ => -1,
+                };
+                let value = self.builder.ins().iadd_imm(payload, step);
+                self.store_slot(slot, tag, value);
+                let empty = self.builder.ins().iconst(types::I64, 0);
+                self.push_tagged(depth, EMPTY, empty);
+                self.builder.ins().jump(next, &[]);
+            }
+            Op::CastNative(_) => self.stop(pc),
+            _ => self.fall_back(pc),
+        }
+    }
+
+    /// Calls back into the VM for the instruction at `pc` if `slow`, going
+    /// on with the native code for it otherwise.
+    fn fast_path_then(&mut self, pc: usize, slow: ir::Value) {
+        let slow_block = self.builder.create_block();
+        let fast = self.builder.create_block();
+        self.builder.ins().brif(slow, slow_block, &[], fast, &[]);
+        self.builder.switch_to_block(slow_block);
+        self.fall_back(pc);
+        self.builder.switch_to_block(fast);
+    }
+
+    /// Calls back into the VM for the instruction at `pc` and goes on with
+    /// the next one.
+    fn fall_back(&mut self, pc: usize) {
+        self.call_back(pc);
+        self.builder.ins().jump(self.blocks[pc + 1], &[]);
+    }
+
+    /// Calls [`execute`] for the instruction at `pc` with the stack in
+    /// memory, returning the next instruction. Stops if it failed.
+    fn call_back(&mut self, pc: usize) -> ir::Value {
+        let depth = self.depths[pc].unwrap_or(0);
+        self.spill(depth);
+        let here = self.builder.ins().iconst(self.pointer, pc as i64);
+        let call = self.builder.ins().call(self.execute, &[self.context, here]);
+        let next = self.builder.inst_results(call)[0];
+
+        let failed = self
+            .builder
+            .ins()
+            .icmp_imm(IntCC::Equal, next, FAILED as i64);
+        let done = self.builder.create_block();
+        self.builder
+            .ins()
+            .brif(failed, self.exit, &[here], done, &[]);
+        self.builder.switch_to_block(done);
+        // Both paths of a jump have the same stack size
+        self.reload(self.depths[pc + 1].unwrap_or(0));
+        next
+    }
+
+    /// Returns to the interpreter at `pc` with the stack in memory.
+    fn stop(&mut self, pc: usize) {
+        self.spill(self.depths[pc].unwrap_or(0));
+        let here = self.builder.ins().iconst(self.pointer, pc as i64);
+        self.builder.ins().jump(self.exit, &[here]);
+    }
+
+    /// Writes the top `depth` entries of the stack to memory.
+    fn spill(&mut self, depth: usize) {
+        let flags = MemFlags::trusted();
+        for index in 0..depth {
+            let (tag, payload) = self.peek(index);
+            let offset = (index * mem::size_of::<Unboxed>()) as i32;
+            self.builder
+                .ins()
+                .store(flags, tag, self.stack_pointer, offset);
+            self.builder
+                .ins()
+                .store(flags, payload, self.stack_pointer, offset + 8);
+        }
+        let depth = self.builder.ins().iconst(types::I64, depth as i64);
+        self.store_state(offset_of!(State, depth), depth);
+    }
+
+    /// Reads the bottom `depth` entries of the stack from memory.
+    fn reload(&mut self, depth: usize) {
+        let flags = MemFlags::trusted();
+        for index in 0..depth {
+            let offset = (index * mem::size_of::<Unboxed>()) as i32;
+            let tag = self
+                .builder
+                .ins()
+                .load(types::I64, flags, self.stack_pointer, offset);
+            let payload =
+                self.builder
+                    .ins()
+                    .load(types::I64, flags, self.stack_pointer, offset + 8);
+            self.push(index, tag, payload);
+        }
+    }
+
+    fn peek(&mut self, index: usize) -> (ir::Value, ir::Value) {
+        let (tag, payload) = self.stack[index];
+        (self.builder.use_var(tag), self.builder.use_var(payload))
+    }
+
+    /// Sets the entry of the stack at `index`.
+    fn push(&mut self, index: usize, tag: ir::Value, payload: ir::Value) {
+        let (tag_variable, payload_variable) = self.stack[index];
+        self.builder.def_var(tag_variable, tag);
+        self.builder.def_var(payload_variable, payload);
+    }
+
+    fn push_tagged(&mut self, index: usize, tag: i64, payload: ir::Value) {
+        let tag = self.builder.ins().iconst(types::I64, tag);
+        self.push(index, tag, payload);
+    }
+
+    fn load_slot(&mut self, slot: Index) -> (ir::Value, ir::Value) {
+        let offset = (slot as usize * mem::size_of::<Unboxed>()) as i32;
+        let flags = MemFlags::trusted();
+        let tag = self
+            .builder
+            .ins()
+            .load(types::I64, flags, self.slots, offset);
+        let payload = self
+            .builder
+            .ins()
+            .load(types::I64, flags, self.slots, offset + 8);
+        (tag, payload)
+    }
+
+    fn store_slot(&mut self, slot: Index, tag: ir::Value, payload: ir::Value) {
+        let offset = (slot as usize * mem::size_of::<Unboxed>()) as i32;
+        let flags = MemFlags::trusted();
+        self.builder.ins().store(flags, tag, self.slots, offset);
+        self.builder
+            .ins()
+            .store(flags, payload, self.slots, offset + 8);
+    }
+
+    fn load_state(&mut self, offset: usize) -> ir::Value {
+        self.builder
+            .ins()
+            .load(types::I64, MemFlags::trusted(), self.context, offset as i32)
+    }
+
+    fn store_state(&mut self, offset: usize, value: ir::Value) {
+        self.builder
+            .ins()
+            .store(MemFlags::trusted(), value, self.context, offset as i32);
+    }
+}
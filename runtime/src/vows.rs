@@ -0,0 +1,173 @@
+//! Runs the `vow "name" { ... }` blocks of a script, each in a fresh runtime.
+//!
+//! A vow is set up by running every top-level statement outside of vows
+//! first, so a script can define values its vows check with
+//! `~UnbreakableVow`.
+
+use potterscript_parser::{Program, Statement};
+
+use crate::{Runtime, RuntimeAdapter, RuntimeError, TestAdapter};
+
+#[derive(Debug)]
+pub struct VowResult {
+    pub name: String,
+    /// What the vow logged, one line each.
+    pub output: String,
+    pub error: Option<RuntimeError>,
+}
+
+impl VowResult {
+    pub fn passed(&self) -> bool {
+        self.error.is_none()
+    }
+}
+
+/// Names of the vows in `program`, in order.
+pub fn vow_names(program: &Program) -> Vec<&str> {
+    program
+        .0
+        .iter()
+        .filter_map(|statement| match statement {
+            Statement::Vow(name, _) => Some(name.as_str()),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Runs the vow called `name` on `runtime` after the statements outside of
+/// vows, which are all that runs if there is no such vow. `~AvadaKedabra`
+/// ends the vow without failing it.
+pub fn run_vow<T: RuntimeAdapter>(
+    runtime: &mut Runtime<T>,
+    program: &Program,
+    name: &str,
+) -> Result<(), RuntimeError> {
+    let mut statements: Vec<Statement> = program
+        .0
+        .iter()
+        .filter(|statement| !matches!(statement, Statement::Vow(_, _)))
+        .cloned()
+        .collect();
+
+    for statement in &program.0 {
+        if let Statement::Vow(vow, block) = statement {
+            if vow == name {
+                statements.extend(block.iter().cloned());
+            }
+        }
+    }
+
+    match runtime.eval(&Program(statements)) {
+        Err(RuntimeError::Exit) => Ok(()),
+        result => result,
+    }
+}
+
+/// Runs every vow in `program` whose name contains `filter`, each with its
+/// own [`TestAdapter`] where the Sorting Hat goes round the houses in order.
+pub fn run_vows(program: &Program, filter: Option<&str>) -> Vec<VowResult> {
+    vow_names(program)
+        .into_iter()
+        .filter(|name| filter.is_none_or(|filter| name.contains(filter)))
+        .map(|name| {
+            let mut runtime = Runtime::new(TestAdapter::new().with_integers((0..4).cycle()));
+            let error = run_vow(&mut runtime, program, name).err();
+            VowResult {
+                name: name.to_string(),
+                output: runtime.adapter().output(),
+                error,
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SCRIPT: &str = r#"
+total = 10
+
+vow "total starts at ten" {
+  ~UnbreakableVow total == 10
+}
+
+vow "vows run in isolation" {
+  ~Engorgio total
+  ~Revelio total
+  ~UnbreakableVow total == 10
+}
+
+vow "plain booleans" {
+  done = false
+  ~UnbreakableVow done
+}
+"#;
+
+    #[test]
+    fn test_run_vows() {
+        let program = potterscript_parser::parse(SCRIPT).unwrap();
+        assert_eq!(
+            vec![
+                "total starts at ten",
+                "vows run in isolation",
+                "plain booleans"
+            ],
+            vow_names(&program)
+        );
+
+        let results = run_vows(&program, None);
+        assert!(results[0].passed());
+
+        assert_eq!("11\n", results[1].output);
+        assert_eq!(
+            "Unbreakable Vow broken: `total == 10` was `11 == 10`",
+            results[1].error.as_ref().unwrap().to_string()
+        );
+
+        assert_eq!(
+            "Unbreakable Vow broken: `done` was `false`",
+            results[2].error.as_ref().unwrap().to_string()
+        );
+    }
+
+    #[test]
+    fn test_filter_vows() {
+        let program = potterscript_parser::parse(SCRIPT).unwrap();
+        let results = run_vows(&program, Some("isolation"));
+        assert_eq!(1, results.len());
+        assert_eq!("vows run in isolation", results[0].name);
+    }
+
+    #[test]
+    fn test_avada_kedabra_ends_only_its_vow() {
+        let program = potterscript_parser::parse(
+            r#"
+vow "ends early" {
+  ~Revelio 1
+  ~AvadaKedabra
+  ~Revelio 2
+}
+
+vow "still runs" {
+  ~UnbreakableVow 1 == 2
+}
+"#,
+        )
+        .unwrap();
+
+        let results = run_vows(&program, None);
+        assert_eq!(2, results.len());
+        assert!(results[0].passed());
+        assert_eq!("1\n", results[0].output);
+        assert!(!results[1].passed());
+    }
+
+    #[test]
+    fn test_vows_are_skipped_by_eval() {
+        let program = potterscript_parser::parse(SCRIPT).unwrap();
+        let mut runtime = Runtime::new(TestAdapter::new());
+        runtime.eval(&program).unwrap();
+        assert!(runtime.adapter().logs().is_empty());
+    }
+}
@@ -1,6 +1,9 @@
 use potterscript_parser;
-use potterscript_runtime::{self, RuntimeAdapter};
-use wasm_bindgen::prelude::wasm_bindgen;
+use potterscript_runtime::{self, RuntimeAdapter, RuntimeError, Seeded};
+use std::ops::RangeInclusive;
+
+use js_sys::Function;
+use wasm_bindgen::prelude::{wasm_bindgen, JsError, JsValue};
 use web_sys::console;
 
 #[wasm_bindgen]
@@ -9,11 +12,27 @@ pub fn parse(code: &str) -> String {
     serde_json::to_string(&ast).unwrap()
 }
 
-struct WasmRuntimeAdapter;
+/// Transpiles `code` to JavaScript, see `potterscript_codegen::js`.
+#[wasm_bindgen]
+pub fn to_javascript(code: &str) -> Result<String, JsError> {
+    let program = potterscript_parser::parse(code)?;
+    Ok(potterscript_codegen::js::transpile(&program))
+}
+
+struct WasmRuntimeAdapter {
+    /// Called with the prompt (or `undefined`) for `~Accio`, returns the line
+    /// or `null`/`undefined` when there is no more input.
+    read_line: Option<Function>,
+}
 
 impl RuntimeAdapter for WasmRuntimeAdapter {
-    fn create_random_index(&self) -> usize {
-        js_sys::Math::floor(js_sys::Math::random() * 4.0) as usize
+    fn random_integer(&self, range: RangeInclusive<i64>) -> i64 {
+        let span = (range.end() - range.start() + 1) as f64;
+        range.start() + js_sys::Math::floor(js_sys::Math::random() * span) as i64
+    }
+
+    fn random_double(&self) -> f64 {
+        js_sys::Math::random()
     }
 
     fn lumos(&self, string: String) -> String {
@@ -23,11 +42,37 @@ impl RuntimeAdapter for WasmRuntimeAdapter {
     fn log(&self, string: &str) {
         console::log_1(&string.into());
     }
+
+    fn read_line(&self, prompt: Option<&str>) -> Option<String> {
+        let read_line = self.read_line.as_ref()?;
+        let prompt = prompt.map_or(JsValue::UNDEFINED, JsValue::from);
+        read_line.call1(&JsValue::NULL, &prompt).ok()?.as_string()
+    }
 }
 
+/// Runs `code` and returns the seed the Sorting Hat used, which can be
+/// passed back as `seed` to replay the run.
+///
+/// Seeds are any `u64`, more than a JavaScript number holds exactly, so they
+/// cross into JavaScript as decimal strings.
 #[wasm_bindgen]
-pub fn parse_and_run(code: &str) {
-    let (_, program) = potterscript_parser::parse_program(code).unwrap();
-    let mut runtime = potterscript_runtime::Runtime::new(WasmRuntimeAdapter);
-    runtime.eval(program);
+pub fn parse_and_run(
+    code: &str,
+    read_line: Option<Function>,
+    seed: Option<String>,
+) -> Result<String, JsError> {
+    let program = potterscript_parser::parse(code)?;
+    let seed = match seed {
+        Some(seed) => seed
+            .trim()
+            .parse::<u64>()
+            .map_err(|_| JsError::new(&format!("invalid seed {seed:?}")))?,
+        None => (js_sys::Math::random() * u64::MAX as f64) as u64,
+    };
+    let adapter = Seeded::new(WasmRuntimeAdapter { read_line }, seed);
+    let mut runtime = potterscript_runtime::Runtime::new(adapter);
+    match runtime.eval(&program) {
+        Ok(()) | Err(RuntimeError::Exit) => Ok(seed.to_string()),
+        Err(error) => Err(error.into()),
+    }
 }